@@ -1,21 +1,274 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs::File,
+    hash::{Hash, Hasher},
+    io::BufReader,
+    mem,
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
-use actix_web::{dev, get, web, App, HttpServer, Responder};
+use actix_web::{
+    delete, dev,
+    error::{
+        ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized,
+    },
+    get,
+    http::header::{
+        self, CacheControl, CacheDirective, ContentDisposition, ContentEncoding, ETag, EntityTag,
+        IfNoneMatch,
+    },
+    middleware::Compress,
+    post, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder,
+};
+use anyhow::Context;
 use chrono::{DateTime, Utc};
+use figment::value::magic::RelativePathBuf;
+use futures::stream;
+use hickory_server::proto::{
+    op::Query,
+    rr::{self, Name, RecordType},
+};
+use rustls::{crypto::ring, server::WebPkiClientVerifier, RootCertStore, ServerConfig};
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use tokio::{
+    sync::{watch, Mutex},
+    time::sleep,
+};
+use uuid::Uuid;
 
-use crate::{dns::Record, sources::SourceRecords, ServerId, ServerInner};
+use crate::{
+    config::Zones,
+    dns::{Fqdn, QueryResult, RecentQuery, Record, ServerState, UpstreamStatus, ZoneStats},
+    replication::SourceChange,
+    sources::{
+        file::{add_record, remove_records, NewRecord, RecordFilter},
+        mesh::{MeshPeer, MESH_SOURCE},
+        SourceId, SourceRecords, SourceStatus, SourceType,
+    },
+    util::secrets_match,
+    Error, ServerId, ServerInner, OPTIONAL_FEATURES,
+};
+
+/// The name of the source serving the records managed through the API.
+pub(crate) const API_RECORDS_SOURCE: &str = "static";
+/// How often an idle record stream sends a comment to keep the connection open.
+const STREAM_KEEPALIVE: Duration = Duration::from_secs(15);
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub(crate) struct ApiConfig {
     pub(crate) address: SocketAddr,
+    /// The bearer token that requests changing records must include.
+    #[serde(default)]
+    pub(crate) token: Option<String>,
+    /// Further tokens that may only change records in the zones listed for
+    /// them.
+    #[serde(default)]
+    pub(crate) tokens: HashMap<String, Vec<Fqdn>>,
+    /// Where records added through the API are stored.
+    #[serde(default)]
+    pub(crate) records_file: Option<RelativePathBuf>,
+    #[serde(default)]
+    pub(crate) tls: Option<ApiTls>,
+}
+
+/// Serves the API over HTTPS. With a client CA only clients presenting a
+/// certificate signed by it can connect.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct ApiTls {
+    certificate: RelativePathBuf,
+    key: RelativePathBuf,
+    #[serde(default)]
+    client_ca: Option<RelativePathBuf>,
+}
+
+impl ApiTls {
+    pub(crate) fn files(&self) -> Vec<PathBuf> {
+        [
+            Some(&self.certificate),
+            Some(&self.key),
+            self.client_ca.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(RelativePathBuf::relative)
+        .collect()
+    }
+
+    fn server_config(&self) -> Result<ServerConfig, Error> {
+        let open = |path: &RelativePathBuf| {
+            let path = path.relative();
+            File::open(&path)
+                .map(BufReader::new)
+                .with_context(|| format!("Failed to read {}", path.display()))
+        };
+
+        let certificates =
+            rustls_pemfile::certs(&mut open(&self.certificate)?).collect::<Result<Vec<_>, _>>()?;
+        let key =
+            rustls_pemfile::private_key(&mut open(&self.key)?)?.context("No private key found")?;
+
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = match self.client_ca {
+            Some(ref client_ca) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in rustls_pemfile::certs(&mut open(client_ca)?) {
+                    roots.add(certificate?)?;
+                }
+
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        Ok(builder.with_single_cert(certificates, key)?)
+    }
+}
+
+fn bearer_token(request: &HttpRequest) -> Option<&str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Who made a request changing records.
+struct Caller {
+    /// Identifies the token in audit logs without revealing it.
+    token_id: String,
+    /// The zones the caller may change records in, any when `None`.
+    zones: Option<Vec<Fqdn>>,
+}
+
+impl Caller {
+    fn new(token: &str, zones: Option<&Vec<Fqdn>>) -> Self {
+        Self {
+            token_id: format!("{:08x}", hash_of(token) >> 32),
+            zones: zones.cloned(),
+        }
+    }
+
+    fn may_change(&self, name: &Fqdn) -> bool {
+        self.zones
+            .as_ref()
+            .map_or(true, |zones| zones.iter().any(|zone| zone.zone_of(name)))
+    }
+
+    /// Checks that the caller may change records for the name, logging the
+    /// attempt either way.
+    fn check(&self, action: &str, record: &str, name: &Fqdn) -> actix_web::Result<()> {
+        if self.may_change(name) {
+            tracing::info!(token = self.token_id, action, record, "API write accepted");
+            Ok(())
+        } else {
+            tracing::info!(
+                token = self.token_id,
+                action,
+                record,
+                "API write rejected, the name is outside of the token's zones"
+            );
+            Err(ErrorForbidden("The name is outside of the token's zones"))
+        }
+    }
 }
 
 #[derive(Clone)]
 struct AppData {
     server_id: ServerId,
     server_inner: Arc<Mutex<ServerInner>>,
+    server_state: ServerState<Zones>,
+    config: ApiConfig,
+    /// Held while changing the records file so concurrent requests aren't lost.
+    write_lock: Arc<Mutex<()>>,
+    /// Changes when the API server is shutting down so record streams end.
+    closing: watch::Receiver<bool>,
+}
+
+impl AppData {
+    /// Checks that the request may change records and returns the file to change
+    /// along with who is changing it.
+    fn authorize(&self, request: &HttpRequest) -> actix_web::Result<(PathBuf, Caller)> {
+        let Some(records_file) = self.config.records_file.as_ref() else {
+            return Err(ErrorNotFound("No records file is configured"));
+        };
+
+        if self.config.token.is_none() && self.config.tokens.is_empty() {
+            return Err(ErrorForbidden("No API token is configured"));
+        }
+
+        let given = bearer_token(request).unwrap_or_default();
+        let caller = self
+            .config
+            .token
+            .iter()
+            .map(|token| (token, None))
+            .chain(
+                self.config
+                    .tokens
+                    .iter()
+                    .map(|(token, zones)| (token, Some(zones))),
+            )
+            .find(|(token, _)| secrets_match(given, token))
+            .map(|(token, zones)| Caller::new(token, zones));
+
+        match caller {
+            Some(caller) => Ok((records_file.relative(), caller)),
+            None => {
+                tracing::info!("API write rejected, missing or incorrect API token");
+                Err(ErrorUnauthorized("Missing or incorrect API token"))
+            }
+        }
+    }
+
+    /// Checks that the request gives the API token. Tokens limited to some zones
+    /// aren't accepted.
+    fn check_token(&self, request: &HttpRequest) -> actix_web::Result<()> {
+        let Some(token) = self.config.token.as_deref() else {
+            return Err(ErrorForbidden("No API token is configured"));
+        };
+
+        if !bearer_token(request).is_some_and(|given| secrets_match(given, token)) {
+            return Err(ErrorUnauthorized("Missing or incorrect API token"));
+        }
+
+        Ok(())
+    }
+
+    async fn mesh_peers(&self) -> ApiMeshPeers {
+        ApiMeshPeers {
+            server_id: self.server_id,
+            peers: self.server_inner.lock().await.mesh_peers.peers(),
+        }
+    }
+
+    async fn api_records(&self) -> ApiRecords {
+        let source_records = {
+            self.server_inner
+                .lock()
+                .await
+                .records
+                .values()
+                .cloned()
+                .collect()
+        };
+
+        ApiRecords {
+            server_id: self.server_id,
+            timestamp: Utc::now(),
+            source_records,
+            next_offset: None,
+        }
+    }
 }
 
 #[get("/records")]
@@ -46,42 +299,795 @@ pub(crate) struct ApiRecords {
     pub(crate) server_id: ServerId,
     pub(crate) timestamp: DateTime<Utc>,
     pub(crate) source_records: Vec<SourceRecords>,
+    /// The offset of the next page when the records were paginated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) next_offset: Option<usize>,
+}
+
+#[derive(Deserialize, Hash)]
+struct RecordsParams {
+    #[serde(default)]
+    zone: Option<Fqdn>,
+    #[serde(rename = "type", default)]
+    record_type: Option<String>,
+    #[serde(default)]
+    offset: Option<usize>,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+/// Trims the records to a page of at most `limit` records starting at `offset`.
+/// Sources are ordered by id and their records by name so pages are stable.
+/// Sources with no records on the page are left out.
+fn paginate(source_records: &mut Vec<SourceRecords>, offset: usize, limit: usize) -> Option<usize> {
+    source_records.sort_by_cached_key(|source| source.source_id.to_string());
+
+    let end = offset.saturating_add(limit);
+    let mut position = 0;
+    for source in source_records.iter_mut() {
+        let mut records: Vec<Record> = mem::take(&mut source.records).into();
+        records
+            .sort_by_cached_key(|record| (record.name().clone(), format!("{:?}", record.rdata())));
+
+        let start = offset.saturating_sub(position).min(records.len());
+        let stop = end.saturating_sub(position).min(records.len());
+        position += records.len();
+
+        source.records = records.drain(start..stop).collect();
+    }
+
+    source_records.retain(|source| !source.records.is_empty());
+
+    (position > end).then_some(end)
+}
+
+fn hash_of<T: Hash>(value: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Identifies the records in a response along with the page and query that
+/// chose them. Timestamps are left out as sources report the same records again
+/// regularly.
+fn records_etag(api_records: &ApiRecords, params: &RecordsParams) -> EntityTag {
+    let mut sources: Vec<u64> = api_records
+        .source_records
+        .iter()
+        .map(|source| {
+            // Records are in no particular order.
+            let records = source
+                .records
+                .records()
+                .map(hash_of)
+                .fold(0_u64, u64::wrapping_add);
+
+            hash_of((&source.source_id, records))
+        })
+        .collect();
+    sources.sort();
+
+    EntityTag::new_strong(format!(
+        "{:016x}",
+        hash_of((&sources, api_records.next_offset, params))
+    ))
 }
 
 #[get("/v2/records")]
-async fn v2_records(app_data: web::Data<AppData>) -> impl Responder {
-    let source_records = {
+async fn v2_records(
+    app_data: web::Data<AppData>,
+    request: HttpRequest,
+    params: web::Query<RecordsParams>,
+) -> actix_web::Result<HttpResponse> {
+    let record_type = params
+        .record_type
+        .as_deref()
+        .map(|record_type| RecordType::from_str(&record_type.to_uppercase()))
+        .transpose()
+        .map_err(ErrorBadRequest)?;
+
+    let mut api_records = app_data.api_records().await;
+
+    if params.zone.is_some() || record_type.is_some() {
+        for source in api_records.source_records.iter_mut() {
+            source.records = mem::take(&mut source.records)
+                .into_iter()
+                .filter(|record| {
+                    params
+                        .zone
+                        .as_ref()
+                        .map_or(true, |zone| zone.zone_of(record.name()))
+                        && record_type.map_or(true, |record_type| {
+                            record.rdata().data_type() == record_type
+                        })
+                })
+                .collect();
+        }
+    }
+
+    if let Some(limit) = params.limit {
+        api_records.next_offset = paginate(
+            &mut api_records.source_records,
+            params.offset.unwrap_or_default(),
+            limit,
+        );
+    }
+
+    let etag = records_etag(&api_records, &params);
+    let unchanged = match request.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        None => false,
+    };
+
+    if unchanged {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(api_records))
+}
+
+/// The changes to the records since a generation, or every record when the
+/// changes aren't known.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiRecordChanges {
+    pub(crate) server_id: ServerId,
+    pub(crate) epoch: Uuid,
+    pub(crate) generation: u64,
+    /// Every source's records, sent in place of the changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) snapshot: Option<Vec<SourceRecords>>,
+    #[serde(default)]
+    pub(crate) changes: Vec<SourceChange>,
+}
+
+#[derive(Deserialize)]
+struct ChangesParams {
+    #[serde(default)]
+    epoch: Option<Uuid>,
+    #[serde(default)]
+    since: Option<u64>,
+}
+
+#[get("/v3/records")]
+async fn v3_records(
+    app_data: web::Data<AppData>,
+    params: web::Query<ChangesParams>,
+) -> impl Responder {
+    let inner = app_data.server_inner.lock().await;
+
+    let changes = params
+        .epoch
+        .zip(params.since)
+        .and_then(|(epoch, since)| inner.replication.since(epoch, since));
+
+    let (snapshot, changes) = match changes {
+        Some(changes) => (None, changes),
+        None => (Some(inner.records.values().cloned().collect()), Vec::new()),
+    };
+
+    web::Json(ApiRecordChanges {
+        server_id: app_data.server_id,
+        epoch: inner.replication.epoch(),
+        generation: inner.replication.generation(),
+        snapshot,
+        changes,
+    })
+}
+
+/// A record being served along with the sources that gave it.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiServedRecord {
+    #[serde(flatten)]
+    pub(crate) record: Record,
+    pub(crate) sources: Vec<SourceId>,
+}
+
+#[get("/v2/records/served")]
+async fn v2_served_records(app_data: web::Data<AppData>) -> impl Responder {
+    let mut served: Vec<ApiServedRecord> = {
+        let inner = app_data.server_inner.lock().await;
+        let records = app_data.server_state.records.read().await;
+
+        records
+            .records()
+            .map(|record| {
+                let sources = records
+                    .provenance(record.name())
+                    .map(|provenance| {
+                        provenance
+                            .sources
+                            .iter()
+                            .filter(|source_id| {
+                                inner.records.get(source_id).is_some_and(|source| {
+                                    source.records.contains(record.name(), record.rdata())
+                                })
+                            })
+                            .cloned()
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                ApiServedRecord {
+                    record: record.clone(),
+                    sources,
+                }
+            })
+            .collect()
+    };
+    served.sort_by(|a, b| a.record.name().cmp(b.record.name()));
+
+    web::Json(served)
+}
+
+/// Sends the current records as a server-sent event and then again every time
+/// they change.
+#[get("/v2/records/stream")]
+async fn v2_records_stream(app_data: web::Data<AppData>) -> impl Responder {
+    let changes = app_data
+        .server_inner
+        .lock()
+        .await
+        .records_changed
+        .subscribe();
+    let closing = app_data.closing.clone();
+
+    let events = stream::unfold(
+        (app_data, changes, closing, true),
+        |(app_data, mut changes, mut closing, first)| async move {
+            if !first {
+                tokio::select! {
+                    result = changes.changed() => {
+                        if result.is_err() {
+                            return None;
+                        }
+                    }
+                    _ = closing.changed() => return None,
+                    _ = sleep(STREAM_KEEPALIVE) => {
+                        let keepalive = Ok(web::Bytes::from_static(b": keepalive\n\n"));
+                        return Some((keepalive, (app_data, changes, closing, false)));
+                    }
+                }
+            }
+
+            changes.borrow_and_update();
+            let event = serde_json::to_string(&app_data.api_records().await)
+                .map(|data| web::Bytes::from(format!("event: records\ndata: {data}\n\n")));
+
+            Some((event, (app_data, changes, closing, false)))
+        },
+    );
+
+    // Compression would buffer the events.
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(ContentEncoding::Identity)
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(events)
+}
+
+#[post("/v2/records/static")]
+async fn v2_add_record(
+    app_data: web::Data<AppData>,
+    request: HttpRequest,
+    new_record: web::Json<NewRecord>,
+) -> actix_web::Result<HttpResponse> {
+    let (records_file, caller) = app_data.authorize(&request)?;
+    let record = new_record.record().map_err(ErrorBadRequest)?;
+    caller.check("add", &format!("{record:?}"), record.name())?;
+
+    let record = {
+        let _lock = app_data.write_lock.lock().await;
+        add_record(&records_file, &new_record).map_err(ErrorInternalServerError)?
+    };
+
+    Ok(HttpResponse::Created().json(record))
+}
+
+#[derive(Deserialize)]
+struct RemoveParams {
+    name: String,
+    #[serde(rename = "type", default)]
+    record_type: Option<String>,
+    #[serde(default)]
+    value: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiRemoved {
+    pub(crate) removed: usize,
+}
+
+#[delete("/v2/records/static")]
+async fn v2_remove_records(
+    app_data: web::Data<AppData>,
+    request: HttpRequest,
+    params: web::Query<RemoveParams>,
+) -> actix_web::Result<impl Responder> {
+    let (records_file, caller) = app_data.authorize(&request)?;
+    let filter = RecordFilter::new(
+        &params.name,
+        params.record_type.as_deref(),
+        params.value.as_deref(),
+    )
+    .map_err(ErrorBadRequest)?;
+    caller.check("remove", &format!("{filter:?}"), filter.name())?;
+
+    let removed = {
+        let _lock = app_data.write_lock.lock().await;
+        remove_records(&records_file, &filter).map_err(ErrorInternalServerError)?
+    };
+
+    if removed > 0 {
+        tracing::info!(
+            name = params.name,
+            removed,
+            "Records removed through the API"
+        );
+    }
+
+    Ok(web::Json(ApiRemoved { removed }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiMeshPeers {
+    pub(crate) server_id: ServerId,
+    pub(crate) peers: Vec<MeshPeer>,
+}
+
+#[get("/v2/mesh/peers")]
+async fn v2_mesh_peers(app_data: web::Data<AppData>) -> impl Responder {
+    web::Json(app_data.mesh_peers().await)
+}
+
+/// Registers another server in the mesh and responds with the peers that it
+/// can replicate from. Only servers that are part of a mesh accept
+/// registrations and only with the API token, otherwise anyone able to reach
+/// the API could have their records served.
+#[post("/v2/mesh/peers")]
+async fn v2_mesh_register(
+    app_data: web::Data<AppData>,
+    request: HttpRequest,
+    peer: web::Json<MeshPeer>,
+) -> actix_web::Result<impl Responder> {
+    app_data.check_token(&request)?;
+
+    {
+        let mut inner = app_data.server_inner.lock().await;
+        if !inner.config.sources.mesh.contains_key(MESH_SOURCE) {
+            return Err(ErrorNotFound("No mesh is configured"));
+        }
+
+        let peer = peer.into_inner();
+        if peer.server_id != app_data.server_id {
+            tracing::debug!(peer = %peer.server_id, url = peer.url, "Mesh peer registered");
+            inner.mesh_peers.register(peer);
+        }
+    }
+
+    Ok(web::Json(app_data.mesh_peers().await))
+}
+
+#[get("/")]
+async fn dashboard() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("api/dashboard.html"))
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiStatus {
+    pub(crate) version: String,
+    pub(crate) warming_up: bool,
+    pub(crate) config_error: Option<String>,
+    pub(crate) recent_queries: Vec<RecentQuery>,
+    #[serde(default)]
+    pub(crate) upstreams: Vec<UpstreamStatus>,
+}
+
+#[get("/v2/status")]
+async fn v2_status(app_data: web::Data<AppData>) -> impl Responder {
+    let config_error = app_data.server_inner.lock().await.config_error.clone();
+
+    web::Json(ApiStatus {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        warming_up: app_data.server_state.is_warming_up(),
+        config_error,
+        recent_queries: app_data.server_state.recent.queries(),
+        upstreams: app_data.server_state.upstream_health.statuses(),
+    })
+}
+
+/// Downloads the captured queries. These reveal every client's lookups so the
+/// API token is needed.
+#[get("/v2/capture")]
+async fn v2_capture(
+    app_data: web::Data<AppData>,
+    request: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    app_data.check_token(&request)?;
+
+    let capture = &app_data.server_state.capture;
+    if !capture.is_enabled() {
+        return Err(ErrorNotFound("Query capture is not enabled"));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/vnd.tcpdump.pcap")
+        .insert_header(ContentDisposition::attachment("localns.pcap"))
+        .body(capture.pcap()))
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiSource {
+    pub(crate) source_id: SourceId,
+    pub(crate) records: usize,
+    /// When the source last reported its records.
+    pub(crate) timestamp: Option<DateTime<Utc>>,
+    pub(crate) error: Option<String>,
+    #[serde(default)]
+    pub(crate) status: SourceStatus,
+}
+
+#[get("/v2/sources")]
+async fn v2_sources(app_data: web::Data<AppData>) -> impl Responder {
+    let mut sources: Vec<ApiSource> = {
+        let inner = app_data.server_inner.lock().await;
+
+        let mut source_ids = inner.config.sources.local_source_ids(&app_data.server_id);
+        source_ids.extend(inner.records.keys().cloned());
+        source_ids.extend(inner.source_errors.keys().cloned());
+        source_ids.extend(inner.source_status.keys().cloned());
+
+        source_ids
+            .into_iter()
+            .map(|source_id| {
+                let source_records = inner.records.get(&source_id);
+
+                ApiSource {
+                    records: source_records.map_or(0, |s| s.records.len()),
+                    timestamp: source_records.map(|s| s.timestamp),
+                    error: inner.source_errors.get(&source_id).cloned(),
+                    status: inner
+                        .source_status
+                        .get(&source_id)
+                        .cloned()
+                        .unwrap_or_default(),
+                    source_id,
+                }
+            })
+            .collect()
+    };
+    sources.sort_by_key(|source| source.source_id.to_string());
+
+    web::Json(sources)
+}
+
+#[get("/v2/sources/{source_type}/{source_name}/records")]
+async fn v2_source_records(
+    app_data: web::Data<AppData>,
+    path: web::Path<(String, String)>,
+) -> actix_web::Result<impl Responder> {
+    let (source_type, source_name) = path.into_inner();
+    let source_type: SourceType = serde_plain::from_str(&source_type)
+        .map_err(|_| ErrorNotFound(format!("Unknown source type '{source_type}'")))?;
+
+    // Sources from remote instances keep the type and name they have there so
+    // more than one may match.
+    let (known, source_records) = {
+        let inner = app_data.server_inner.lock().await;
+        let matches = |source_id: &SourceId| {
+            source_id.source_type == source_type && source_id.source_name == source_name
+        };
+
+        let source_records: Vec<SourceRecords> = inner
+            .records
+            .values()
+            .filter(|source_records| matches(&source_records.source_id))
+            .cloned()
+            .collect();
+
+        // A configured source that has not reported any records yet still exists.
+        let known = !source_records.is_empty()
+            || inner
+                .config
+                .sources
+                .local_source_ids(&app_data.server_id)
+                .iter()
+                .chain(inner.source_errors.keys())
+                .chain(inner.source_status.keys())
+                .any(matches);
+
+        (known, source_records)
+    };
+
+    if !known {
+        return Err(ErrorNotFound(format!(
+            "Unknown {source_type} source named '{source_name}'"
+        )));
+    }
+
+    let found: Vec<Record> = source_records
+        .into_iter()
+        .flat_map(|source_records| source_records.records)
+        .collect();
+
+    Ok(web::Json(found))
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiSourceMemory {
+    pub(crate) source_id: SourceId,
+    pub(crate) records: usize,
+    pub(crate) bytes: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiMemory {
+    pub(crate) total_bytes: usize,
+    pub(crate) sources: Vec<ApiSourceMemory>,
+}
+
+#[get("/v2/memory")]
+async fn v2_memory(app_data: web::Data<AppData>) -> impl Responder {
+    let sources: Vec<ApiSourceMemory> = {
         app_data
             .server_inner
             .lock()
             .await
             .records
             .values()
-            .cloned()
+            .map(|source_records| ApiSourceMemory {
+                source_id: source_records.source_id.clone(),
+                records: source_records.records.len(),
+                bytes: source_records.records.estimated_size(),
+            })
             .collect()
     };
 
-    let api_records = ApiRecords {
-        server_id: app_data.server_id,
-        timestamp: Utc::now(),
-        source_records,
+    web::Json(ApiMemory {
+        total_bytes: sources.iter().map(|s| s.bytes).sum(),
+        sources,
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiMissingAddress {
+    pub(crate) name: Fqdn,
+    pub(crate) missing: String,
+}
+
+/// A name that several sources gave different records.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiConflict {
+    pub(crate) name: Fqdn,
+    pub(crate) sources: Vec<SourceId>,
+    /// The sources whose records are answered with.
+    pub(crate) used: Vec<SourceId>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiLint {
+    pub(crate) missing_addresses: Vec<ApiMissingAddress>,
+    #[serde(default)]
+    pub(crate) conflicts: Vec<ApiConflict>,
+}
+
+#[get("/v2/lint")]
+async fn v2_lint(app_data: web::Data<AppData>) -> impl Responder {
+    let (missing, mut conflicts) = {
+        let inner = app_data.server_inner.lock().await;
+        let records = app_data.server_state.records.read().await;
+
+        let conflicts: Vec<ApiConflict> = records
+            .conflicts()
+            .map(|(name, provenance)| ApiConflict {
+                name: name.clone(),
+                sources: provenance.conflicting.clone(),
+                used: provenance.sources.clone(),
+            })
+            .collect();
+
+        (
+            records.missing_address_families(&inner.config.zones),
+            conflicts,
+        )
     };
+    conflicts.sort_by(|a, b| a.name.cmp(&b.name));
+
+    web::Json(ApiLint {
+        missing_addresses: missing
+            .into_iter()
+            .map(|(name, record_type)| ApiMissingAddress {
+                name,
+                missing: record_type.to_string(),
+            })
+            .collect(),
+        conflicts,
+    })
+}
+
+#[get("/v2/learned")]
+async fn v2_learned(app_data: web::Data<AppData>) -> impl Responder {
+    let learned: Vec<Record> = app_data
+        .server_state
+        .learned
+        .records()
+        .into_iter()
+        .collect();
+
+    web::Json(learned)
+}
+
+#[get("/v2/upstreams")]
+async fn v2_upstreams(app_data: web::Data<AppData>) -> impl Responder {
+    let upstreams: Vec<UpstreamStatus> = app_data.server_state.upstream_health.statuses();
+
+    web::Json(upstreams)
+}
+
+#[get("/v2/stats")]
+async fn v2_stats(app_data: web::Data<AppData>) -> impl Responder {
+    let stats: Vec<ZoneStats> = app_data.server_state.stats.zones();
 
-    web::Json(api_records)
+    web::Json(stats)
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiFeatures {
+    pub(crate) version: String,
+    pub(crate) features: Vec<String>,
+}
+
+#[get("/v2/features")]
+async fn v2_features() -> impl Responder {
+    web::Json(ApiFeatures {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        features: OPTIONAL_FEATURES
+            .iter()
+            .filter(|(_, included)| *included)
+            .map(|(feature, _)| feature.to_string())
+            .collect(),
+    })
+}
+
+#[derive(Deserialize)]
+struct QueryParams {
+    name: String,
+    #[serde(rename = "type", default)]
+    record_type: Option<String>,
+    #[serde(default)]
+    recurse: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiQueryRecord {
+    pub(crate) name: String,
+    pub(crate) ttl: u32,
+    #[serde(rename = "type")]
+    pub(crate) record_type: String,
+    pub(crate) data: String,
+}
+
+impl From<&rr::Record> for ApiQueryRecord {
+    fn from(record: &rr::Record) -> Self {
+        Self {
+            name: record.name().to_string(),
+            ttl: record.ttl(),
+            record_type: record.record_type().to_string(),
+            data: record
+                .data()
+                .map(|rdata| rdata.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApiQuery {
+    pub(crate) response_code: String,
+    pub(crate) authoritative: bool,
+    pub(crate) answers: Vec<ApiQueryRecord>,
+    pub(crate) authority: Vec<ApiQueryRecord>,
+    pub(crate) additionals: Vec<ApiQueryRecord>,
+    pub(crate) upstreams: Vec<String>,
+}
+
+/// Parses the name and optional record type of a query, the type defaults to `A`.
+pub(crate) fn build_query(name: &str, record_type: Option<&str>) -> Result<Query, String> {
+    let mut name = Name::from_str(name).map_err(|e| format!("Invalid name '{name}': {e}"))?;
+    name.set_fqdn(true);
+
+    let record_type = match record_type {
+        Some(record_type) => RecordType::from_str(&record_type.to_uppercase())
+            .map_err(|e| format!("Invalid type '{record_type}': {e}"))?,
+        None => RecordType::A,
+    };
+
+    Ok(Query::query(name, record_type))
+}
+
+impl From<QueryResult> for ApiQuery {
+    fn from(result: QueryResult) -> Self {
+        let convert = |list: &[rr::Record]| list.iter().map(ApiQueryRecord::from).collect();
+
+        Self {
+            response_code: result.response_code.to_string(),
+            authoritative: result.authoritative,
+            answers: convert(&result.answers),
+            authority: convert(&result.name_servers),
+            additionals: convert(&result.additionals),
+            upstreams: result.upstreams,
+        }
+    }
+}
+
+#[get("/v2/query")]
+async fn v2_query(
+    app_data: web::Data<AppData>,
+    params: web::Query<QueryParams>,
+) -> actix_web::Result<impl Responder> {
+    let query =
+        build_query(&params.name, params.record_type.as_deref()).map_err(ErrorBadRequest)?;
+
+    let result = app_data
+        .server_state
+        .locked()
+        .await
+        .query(query, params.recurse.unwrap_or(true))
+        .await;
+
+    Ok(web::Json(ApiQuery::from(result)))
 }
 
 fn create_server(config: &ApiConfig, app_data: AppData) -> Option<(dev::Server, u16)> {
     tracing::trace!(address = %config.address, "Starting API server");
 
-    let api_server = match HttpServer::new(move || {
+    let api_server = HttpServer::new(move || {
+        // Record lists compress well, remote sources negotiate gzip, deflate or zstd.
         App::new()
+            .wrap(Compress::default())
             .app_data(web::Data::new(app_data.clone()))
+            .service(dashboard)
             .service(records)
             .service(v2_records)
+            .service(v2_records_stream)
+            .service(v2_served_records)
+            .service(v3_records)
+            .service(v2_add_record)
+            .service(v2_remove_records)
+            .service(v2_status)
+            .service(v2_capture)
+            .service(v2_sources)
+            .service(v2_source_records)
+            .service(v2_memory)
+            .service(v2_lint)
+            .service(v2_learned)
+            .service(v2_upstreams)
+            .service(v2_stats)
+            .service(v2_mesh_peers)
+            .service(v2_mesh_register)
+            .service(v2_query)
+            .service(v2_features)
     })
-    .disable_signals()
-    .bind(config.address)
-    {
+    .disable_signals();
+
+    let api_server = match config.tls {
+        Some(ref tls) => match tls.server_config() {
+            Ok(tls_config) => api_server.bind_rustls_0_23(config.address, tls_config),
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to load the API's TLS configuration");
+                return None;
+            }
+        },
+        None => api_server.bind(config.address),
+    };
+
+    let api_server = match api_server {
         Ok(server) => server,
         Err(e) => {
             tracing::error!(error=%e, "Failed to create API server");
@@ -98,6 +1104,7 @@ pub(crate) struct ApiServer {
     #[cfg(test)]
     pub(crate) port: u16,
     api_server: dev::ServerHandle,
+    closing: watch::Sender<bool>,
 }
 
 impl ApiServer {
@@ -105,10 +1112,17 @@ impl ApiServer {
         config: &ApiConfig,
         server_id: ServerId,
         server_inner: Arc<Mutex<ServerInner>>,
+        server_state: ServerState<Zones>,
     ) -> Option<Self> {
+        let (closing, closing_receiver) = watch::channel(false);
+
         let data = AppData {
             server_id,
             server_inner,
+            server_state,
+            config: config.clone(),
+            write_lock: Default::default(),
+            closing: closing_receiver,
         };
 
         create_server(config, data).map(|(api_server, _port)| {
@@ -119,11 +1133,708 @@ impl ApiServer {
                 #[cfg(test)]
                 port: _port,
                 api_server: handle,
+                closing,
             }
         })
     }
 
     pub(crate) async fn shutdown(&self) {
+        // Record streams never finish by themselves and would hold up a graceful
+        // stop.
+        self.closing.send_replace(true);
         self.api_server.stop(true).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::{header, Client, StatusCode};
+    use serde_json::{json, Value};
+    use tempfile::TempDir;
+    use tokio::time::sleep;
+
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use crate::{
+        api::{
+            records_etag, ApiLint, ApiQuery, ApiRecordChanges, ApiRecords, ApiRemoved,
+            ApiServedRecord, ApiSource, RecordsParams,
+        },
+        dns::{RData, Record, RecordSet},
+        sources::{SourceId, SourceRecords, SourceStatus, SourceType},
+        test::{fqdn, name, write_file},
+        Server,
+    };
+
+    async fn wait_for_records<F>(server: &Server, cb: F) -> RecordSet
+    where
+        F: Fn(&RecordSet) -> bool,
+    {
+        for _ in 0..50 {
+            let current = server.records().await;
+            if cb(&current) {
+                return current;
+            }
+
+            sleep(Duration::from_millis(100)).await;
+        }
+
+        panic!("Timed out waiting for records");
+    }
+
+    async fn post(url: &str, token: Option<&str>, body: Value) -> StatusCode {
+        let mut request = Client::new().post(url).json(&body);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+
+        request.send().await.unwrap().status()
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn write_records() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53534
+
+api:
+  address: 127.0.0.1:0
+  token: secret
+  records_file: api.yml
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let port = server.api_server.inner.lock().await.as_ref().unwrap().port;
+        let url = format!("http://localhost:{port}/v2/records/static");
+
+        let status = post(
+            &url,
+            None,
+            json!({ "name": "nas.home.local", "value": "10.10.4.6" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let status = post(
+            &url,
+            Some("secret"),
+            json!({ "name": "nas.home.local", "value": "not-an-address", "type": "A" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let status = post(
+            &url,
+            Some("secret"),
+            json!({
+                "name": "nas.home.local",
+                "value": "10.10.4.6",
+                "ttl": 60,
+                "metadata": { "owner": "ansible" },
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let status = post(
+            &url,
+            Some("secret"),
+            json!({ "name": "backup.home.local", "value": "nas.home.local" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let records = wait_for_records(&server, |records| {
+            records.has_name(&name("backup.home.local."))
+        })
+        .await;
+        assert!(records.contains(
+            &fqdn("nas.home.local"),
+            &RData::A("10.10.4.6".parse().unwrap())
+        ));
+        let nas = records
+            .records()
+            .find(|record| record.name() == &fqdn("nas.home.local"))
+            .unwrap();
+        assert_eq!(nas.ttl, Some(60));
+        assert_eq!(
+            nas.metadata.get("owner").map(String::as_str),
+            Some("ansible")
+        );
+
+        let removed: ApiRemoved = Client::new()
+            .delete(&url)
+            .bearer_auth("secret")
+            .query(&[("name", "nas.home.local"), ("type", "A")])
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(removed.removed, 1);
+
+        let records = wait_for_records(&server, |records| {
+            !records.has_name(&name("nas.home.local."))
+        })
+        .await;
+        assert_eq!(records.len(), 1);
+
+        server.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delegated_tokens() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53550
+
+api:
+  address: 127.0.0.1:0
+  tokens:
+    ci-secret:
+      - dev.home.local
+  records_file: api.yml
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let port = server.api_server.inner.lock().await.as_ref().unwrap().port;
+        let url = format!("http://localhost:{port}/v2/records/static");
+
+        let status = post(
+            &url,
+            Some("ci-secret"),
+            json!({ "name": "build.dev.home.local", "value": "10.10.5.2" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(logs_contain("API write accepted"));
+        assert!(logs_contain("action=\"add\""));
+        assert!(logs_contain("build.dev.home.local"));
+
+        let status = post(
+            &url,
+            Some("ci-secret"),
+            json!({ "name": "router.home.local", "value": "10.10.5.3" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(logs_contain(
+            "API write rejected, the name is outside of the token's zones"
+        ));
+
+        let status = Client::new()
+            .delete(&url)
+            .bearer_auth("ci-secret")
+            .query(&[("name", "router.home.local")])
+            .send()
+            .await
+            .unwrap()
+            .status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let status = post(
+            &url,
+            Some("wrong"),
+            json!({ "name": "build.dev.home.local", "value": "10.10.5.2" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(logs_contain(
+            "API write rejected, missing or incorrect API token"
+        ));
+
+        let records = wait_for_records(&server, |records| {
+            records.has_name(&name("build.dev.home.local."))
+        })
+        .await;
+        assert_eq!(records.len(), 1);
+
+        // The token is never logged.
+        assert!(!logs_contain("ci-secret"));
+
+        server.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sources() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &temp_dir.path().join("zone.yml"),
+            r#"
+www.home.local: 10.10.10.5
+"#,
+        )
+        .await;
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53535
+
+api:
+  address: 127.0.0.1:0
+
+sources:
+  file:
+    zone: zone.yml
+    missing: missing.yml
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let port = server.api_server.inner.lock().await.as_ref().unwrap().port;
+
+        let sources: Vec<ApiSource> = reqwest::get(format!("http://localhost:{port}/v2/sources"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(sources.len(), 2);
+
+        let missing = &sources[0];
+        assert_eq!(missing.source_id.source_name, "missing");
+        assert_eq!(missing.records, 0);
+        assert!(missing.timestamp.is_none());
+        assert!(missing
+            .error
+            .as_deref()
+            .unwrap()
+            .starts_with("Failed to read zone file"));
+        assert_eq!(missing.status, SourceStatus::Ok);
+
+        let zone = &sources[1];
+        assert_eq!(zone.source_id.source_name, "zone");
+        assert_eq!(zone.records, 1);
+        assert!(zone.timestamp.is_some());
+        assert!(zone.error.is_none());
+
+        let found: Vec<Record> = reqwest::get(format!(
+            "http://localhost:{port}/v2/sources/file/zone/records"
+        ))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name(), &fqdn("www.home.local"));
+
+        // A configured source with no records has an empty list.
+        let found: Vec<Record> = reqwest::get(format!(
+            "http://localhost:{port}/v2/sources/file/missing/records"
+        ))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+        assert!(found.is_empty());
+
+        for path in ["file/other", "bogus/zone"] {
+            let response =
+                reqwest::get(format!("http://localhost:{port}/v2/sources/{path}/records"))
+                    .await
+                    .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+        }
+
+        server.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn conflicts() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &temp_dir.path().join("main.yml"),
+            r#"
+www.home.local: 10.10.10.5
+db.home.local: 10.10.10.6
+"#,
+        )
+        .await;
+
+        write_file(
+            &temp_dir.path().join("old.yml"),
+            r#"
+www.home.local: 10.10.10.8
+db.home.local: 10.10.10.6
+"#,
+        )
+        .await;
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53542
+
+api:
+  address: 127.0.0.1:0
+
+conflicts:
+  policy: prefer_source_order
+  source_order:
+    - file.main
+
+sources:
+  file:
+    main: main.yml
+    old: old.yml
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let port = server.api_server.inner.lock().await.as_ref().unwrap().port;
+
+        let records = wait_for_records(&server, |records| {
+            records
+                .provenance(&fqdn("db.home.local"))
+                .is_some_and(|provenance| provenance.sources.len() == 2)
+        })
+        .await;
+        assert_eq!(records.len(), 2);
+        assert!(records.contains(
+            &fqdn("www.home.local"),
+            &RData::A("10.10.10.5".parse().unwrap())
+        ));
+
+        let served: Vec<ApiServedRecord> =
+            reqwest::get(format!("http://localhost:{port}/v2/records/served"))
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+        assert_eq!(served.len(), 2);
+
+        let db = &served[0];
+        assert_eq!(db.record.name(), &fqdn("db.home.local"));
+        let mut sources: Vec<&str> = db
+            .sources
+            .iter()
+            .map(|source_id| source_id.source_name.as_str())
+            .collect();
+        sources.sort();
+        assert_eq!(sources, vec!["main", "old"]);
+
+        let www = &served[1];
+        assert_eq!(www.record.name(), &fqdn("www.home.local"));
+        assert_eq!(www.sources.len(), 1);
+        assert_eq!(www.sources[0].source_name, "main");
+
+        let lint: ApiLint = reqwest::get(format!("http://localhost:{port}/v2/lint"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(lint.conflicts.len(), 1);
+        assert_eq!(lint.conflicts[0].name, fqdn("www.home.local"));
+        assert_eq!(lint.conflicts[0].sources.len(), 2);
+        assert_eq!(lint.conflicts[0].used.len(), 1);
+        assert_eq!(lint.conflicts[0].used[0].source_name, "main");
+
+        server.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn list_records() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &temp_dir.path().join("zone.yml"),
+            r#"
+www.home.local: 10.10.10.5
+db.home.local: 10.10.10.6
+nas.home.local: fd00::7
+web.apps.local: www.home.local
+"#,
+        )
+        .await;
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53543
+
+api:
+  address: 127.0.0.1:0
+
+sources:
+  file:
+    zone: zone.yml
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let port = server.api_server.inner.lock().await.as_ref().unwrap().port;
+        wait_for_records(&server, |records| records.len() == 4).await;
+
+        let list = |query: &'static str| async move {
+            let response = reqwest::get(format!("http://localhost:{port}/v2/records{query}"))
+                .await
+                .unwrap();
+            let etag = response.headers()[header::ETAG]
+                .to_str()
+                .unwrap()
+                .to_owned();
+            let records: ApiRecords = response.json().await.unwrap();
+
+            let mut names: Vec<String> = records
+                .source_records
+                .iter()
+                .flat_map(|source| source.records.records())
+                .map(|record| record.name().to_string())
+                .collect();
+            names.sort();
+
+            (names, records.next_offset, etag)
+        };
+
+        let (names, next_offset, etag) = list("").await;
+        assert_eq!(names.len(), 4);
+        assert_eq!(next_offset, None);
+
+        let (names, _, _) = list("?zone=home.local").await;
+        assert_eq!(
+            names,
+            vec!["db.home.local.", "nas.home.local.", "www.home.local."]
+        );
+
+        let (names, _, _) = list("?zone=home.local&type=aaaa").await;
+        assert_eq!(names, vec!["nas.home.local."]);
+
+        let (names, next_offset, page_etag) = list("?limit=3").await;
+        assert_eq!(
+            names,
+            vec!["db.home.local.", "nas.home.local.", "web.apps.local."]
+        );
+        assert_eq!(next_offset, Some(3));
+
+        // The same records on a page that isn't the last.
+        let (_, next_offset, other_etag) = list("?limit=3&offset=0").await;
+        assert_eq!(next_offset, Some(3));
+        assert_ne!(page_etag, other_etag);
+
+        let (names, next_offset, _) = list("?limit=3&offset=3").await;
+        assert_eq!(names, vec!["www.home.local."]);
+        assert_eq!(next_offset, None);
+
+        let response = reqwest::get(format!("http://localhost:{port}/v2/records?type=bogus"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = Client::new()
+            .get(format!("http://localhost:{port}/v2/records"))
+            .header(header::IF_NONE_MATCH, &etag)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        server.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn etag() {
+        let server_id = Uuid::new_v4();
+        let source_id = SourceId::new(&server_id, SourceType::File, "zone");
+        let mut api_records = ApiRecords {
+            server_id,
+            timestamp: Utc::now(),
+            source_records: vec![SourceRecords::new(
+                &source_id,
+                None,
+                RecordSet::builder()
+                    .a("www.home.local", "10.10.1.5")
+                    .build(),
+            )],
+            next_offset: None,
+        };
+        let params = RecordsParams {
+            zone: None,
+            record_type: None,
+            offset: None,
+            limit: Some(1),
+        };
+
+        let last_page = records_etag(&api_records, &params);
+
+        // More records were added after the page.
+        api_records.next_offset = Some(1);
+        assert_ne!(records_etag(&api_records, &params), last_page);
+
+        let params = RecordsParams {
+            zone: Some(fqdn("home.local")),
+            ..params
+        };
+        api_records.next_offset = None;
+        assert_ne!(records_etag(&api_records, &params), last_page);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn query() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &temp_dir.path().join("zone.yml"),
+            r#"
+www.test.local:
+  - 10.5.23.43
+  - 10.4.2.4
+"#,
+        )
+        .await;
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53546
+
+api:
+  address: 127.0.0.1:0
+
+sources:
+  file:
+    zone: zone.yml
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let port = server.api_server.inner.lock().await.as_ref().unwrap().port;
+        wait_for_records(&server, |records| records.len() == 2).await;
+
+        let query: ApiQuery = reqwest::get(format!(
+            "http://localhost:{port}/v2/query?name=www.test.local&type=a"
+        ))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+        assert_eq!(query.response_code, "No Error");
+        assert_eq!(query.answers.len(), 2);
+        assert!(query.answers.iter().all(|r| r.name == "www.test.local."));
+        assert!(query.upstreams.is_empty());
+
+        let response = reqwest::get(format!(
+            "http://localhost:{port}/v2/query?name=www.test.local&type=bogus"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        server.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn compression() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &temp_dir.path().join("zone.yml"),
+            "www.home.local: 10.10.10.5",
+        )
+        .await;
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53547
+
+api:
+  address: 127.0.0.1:0
+
+sources:
+  file:
+    zone: zone.yml
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let port = server.api_server.inner.lock().await.as_ref().unwrap().port;
+        wait_for_records(&server, |records| records.len() == 1).await;
+
+        let response = Client::builder()
+            .no_gzip()
+            .build()
+            .unwrap()
+            .get(format!("http://localhost:{port}/v2/records"))
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+
+        let response = Client::builder()
+            .no_zstd()
+            .build()
+            .unwrap()
+            .get(format!("http://localhost:{port}/v3/records"))
+            .header(header::ACCEPT_ENCODING, "zstd")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "zstd");
+
+        // A client that only accepts zstd has to decode it to read the records.
+        let response = Client::builder()
+            .no_gzip()
+            .no_deflate()
+            .build()
+            .unwrap()
+            .get(format!("http://localhost:{port}/v3/records"))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        let changes: ApiRecordChanges = response.json().await.unwrap();
+        assert_eq!(changes.snapshot.unwrap()[0].records.len(), 1);
+
+        server.shutdown().await;
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, fmt};
+use std::{collections::HashMap, fmt, net::IpAddr};
 
 use figment::value::magic::RelativePathBuf;
 use reqwest::Url;
@@ -7,10 +7,14 @@ use serde::{
     Deserialize, Deserializer,
 };
 
+use super::{AnswerOrder, ConflictConfig, UpstreamStrategy};
 use crate::{
     api::ApiConfig,
     dns::{Fqdn, ServerConfig, Upstream},
-    sources::SourcesConfig,
+    logging::{LoggingConfig, TelemetryConfig},
+    outputs::OutputsConfig,
+    sources::{mesh::MeshConfig, SourcesConfig},
+    util::Subnet,
 };
 
 struct UrlVisitor;
@@ -37,6 +41,23 @@ where
     de.deserialize_str(UrlVisitor)
 }
 
+pub(crate) fn deserialize_urls<'de, D>(de: D) -> Result<Vec<Url>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(de)?
+        .iter()
+        .map(|url| Url::parse(url).map_err(de::Error::custom))
+        .collect()
+}
+
+pub(crate) fn deserialize_optional_url<'de, D>(de: D) -> Result<Option<Url>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_url(de).map(Some)
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
 pub(super) struct DefaultZoneConfig {
     #[serde(default)]
@@ -44,6 +65,74 @@ pub(super) struct DefaultZoneConfig {
 
     #[serde(default)]
     pub(super) ttl: Option<u32>,
+
+    #[serde(default)]
+    pub(super) answer_order: Option<AnswerOrder>,
+
+    #[serde(default)]
+    pub(super) upstream_strategy: Option<UpstreamStrategy>,
+
+    #[serde(default)]
+    pub(super) max_cname_depth: Option<usize>,
+
+    #[serde(default)]
+    pub(super) max_stale: Option<u32>,
+}
+
+/// A name server that a zone is delegated to. A bare address is given a name
+/// within the delegated zone.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub(super) enum NameServer {
+    Address(IpAddr),
+    Name(Fqdn),
+    Full {
+        name: Fqdn,
+        #[serde(default)]
+        addresses: Vec<IpAddr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub(super) enum NameServers {
+    One(NameServer),
+    Many(Vec<NameServer>),
+}
+
+impl NameServers {
+    pub(super) fn list(&self) -> &[NameServer] {
+        match self {
+            NameServers::One(name_server) => std::slice::from_ref(name_server),
+            NameServers::Many(list) => list,
+        }
+    }
+}
+
+/// Other configuration files merged into the main one.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub(super) enum Includes {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Includes {
+    pub(super) fn list(&self) -> &[String] {
+        match self {
+            Includes::One(include) => std::slice::from_ref(include),
+            Includes::Many(list) => list,
+        }
+    }
+}
+
+/// Clients that are not given the zone's local records.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(super) struct View {
+    pub(super) clients: Vec<Subnet>,
+    /// Where their queries go instead, without one they get NXDOMAIN.
+    #[serde(default)]
+    pub(super) upstream: Option<Upstream>,
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
@@ -53,6 +142,25 @@ pub(super) struct PartialZoneConfig {
 
     #[serde(default)]
     pub(super) authoritative: Option<bool>,
+
+    #[serde(default)]
+    pub(super) dual_stack: Option<bool>,
+
+    #[serde(default)]
+    pub(super) learn: Option<bool>,
+
+    #[serde(default)]
+    pub(super) nodata: Option<bool>,
+
+    #[serde(default)]
+    pub(super) delegate_to: Option<NameServers>,
+
+    #[serde(default)]
+    pub(super) views: Vec<View>,
+
+    /// Set for the zones generated from `reverse_zones`.
+    #[serde(skip)]
+    pub(super) reverse: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -60,6 +168,15 @@ pub(super) struct ConfigFile {
     #[serde(default)]
     pub(super) pid_file: Option<RelativePathBuf>,
 
+    #[serde(default)]
+    pub(super) control_socket: Option<RelativePathBuf>,
+
+    #[serde(default)]
+    pub(super) logging: LoggingConfig,
+
+    #[serde(default)]
+    pub(super) telemetry: Option<TelemetryConfig>,
+
     #[serde(default)]
     pub(super) defaults: DefaultZoneConfig,
 
@@ -72,6 +189,26 @@ pub(super) struct ConfigFile {
     #[serde(default)]
     pub(super) sources: SourcesConfig,
 
+    /// How to answer for names given different records by several sources.
+    #[serde(default)]
+    pub(super) conflicts: ConflictConfig,
+
+    #[serde(default)]
+    pub(super) mesh: Option<MeshConfig>,
+
+    #[serde(default)]
+    pub(super) outputs: OutputsConfig,
+
     #[serde(default)]
     pub(super) zones: HashMap<Fqdn, PartialZoneConfig>,
+
+    #[serde(default)]
+    pub(super) reverse_zones: Vec<Subnet>,
+
+    #[serde(default)]
+    pub(super) reverse_upstreams: HashMap<Subnet, Upstream>,
+
+    /// The clients that can see records with each tag.
+    #[serde(default)]
+    pub(super) tags: HashMap<String, Vec<Subnet>>,
 }
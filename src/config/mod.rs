@@ -1,34 +1,180 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     fmt, fs,
-    path::Path,
+    net::IpAddr,
+    path::{Path, PathBuf},
     process,
 };
 
 use figment::{
-    providers::{Env, Format, Yaml},
-    value::{Uncased, UncasedStr},
+    providers::{Env, Format, Serialized, Yaml},
+    value::{magic::RelativePathBuf, Dict, Uncased, UncasedStr, Value},
     Figment,
 };
 use hickory_server::proto::{rr, rr::rdata::SOA};
+use serde::Deserialize;
 use tracing::instrument;
 
 use crate::{
-    api::ApiConfig,
+    api::{ApiConfig, API_RECORDS_SOURCE},
     dns::{Fqdn, ServerConfig, Upstream},
-    sources::SourcesConfig,
-    Error,
+    logging::{LoggingConfig, TelemetryConfig},
+    outputs::OutputsConfig,
+    sources::{
+        file::ApiRecordsConfig, interfaces::InterfacesConfig, mesh::MESH_SOURCE, SourceId,
+        SourcesConfig, OPTIONAL_SOURCE_TYPES,
+    },
+    util::Subnet,
+    Error, OPTIONAL_FEATURES,
 };
 
 mod file;
+mod validate;
+
+pub(crate) use file::{deserialize_optional_url, deserialize_url, deserialize_urls};
+
+/// The name of the self source that publishes the server's hostname.
+const SELF_SOURCE: &str = "localns";
+/// How many CNAME records are followed when answering a query by default.
+const DEFAULT_MAX_CNAME_DEPTH: usize = 8;
+
+/// A zone that is served by other name servers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Delegation {
+    /// The zone being delegated.
+    pub(crate) origin: Fqdn,
+    /// The name servers and any addresses known for them.
+    pub(crate) name_servers: Vec<(Fqdn, Vec<IpAddr>)>,
+}
+
+impl Delegation {
+    fn new(origin: Fqdn, name_servers: &file::NameServers) -> Self {
+        let name_servers = name_servers
+            .list()
+            .iter()
+            .enumerate()
+            .filter_map(|(index, name_server)| match name_server {
+                file::NameServer::Address(ip) => {
+                    match origin.child(format!("ns{}", index + 1).as_str()) {
+                        Ok(name) => Some((name, vec![*ip])),
+                        Err(e) => {
+                            tracing::warn!(error = %e, %origin, "Unable to name delegated name server");
+                            None
+                        }
+                    }
+                }
+                file::NameServer::Name(name) => Some((name.clone(), Vec::new())),
+                file::NameServer::Full { name, addresses } => {
+                    Some((name.clone(), addresses.clone()))
+                }
+            })
+            .collect();
+
+        Self {
+            origin,
+            name_servers,
+        }
+    }
+}
+
+/// The order that a name's local records are given in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AnswerOrder {
+    /// Whatever order the records are stored in.
+    #[default]
+    Fixed,
+    /// Each answer starts with the record after the one the last answer started
+    /// with.
+    Rotate,
+    /// A random order for every answer.
+    Shuffle,
+}
+
+/// How a zone's upstream servers are used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum UpstreamStrategy {
+    /// Each upstream is asked in turn until one answers.
+    #[default]
+    Failover,
+    /// Every upstream is asked at once and the first answer is used.
+    Race,
+    /// Like failover but starting with a different upstream for each lookup.
+    RoundRobin,
+}
+
+/// How a name is answered when sources give it different records.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ConflictPolicy {
+    /// The records from every source are given.
+    #[default]
+    Merge,
+    /// Only the records from the most preferred source are given.
+    PreferSourceOrder,
+    /// The name isn't answered until the sources agree.
+    Error,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub(crate) struct ConflictConfig {
+    #[serde(default)]
+    pub(crate) policy: ConflictPolicy,
+    /// Sources in order of preference, either `type.name` or just `type` for
+    /// every source of that type.
+    #[serde(default)]
+    pub(crate) source_order: Vec<String>,
+}
+
+impl ConflictConfig {
+    /// Where a source comes in the order of preference, unlisted sources come
+    /// after all of the listed ones.
+    pub(crate) fn rank(&self, source_id: &SourceId) -> usize {
+        let source_type = source_id.source_type.to_string();
 
-pub(crate) use file::deserialize_url;
+        self.source_order
+            .iter()
+            .position(|entry| match entry.split_once('.') {
+                Some((entry_type, entry_name)) => {
+                    entry_type == source_type && entry_name == source_id.source_name
+                }
+                None => *entry == source_type,
+            })
+            .unwrap_or(self.source_order.len())
+    }
+}
 
 pub(crate) struct ZoneConfig {
     pub(crate) origin: Option<Fqdn>,
     pub(crate) upstreams: VecDeque<Upstream>,
+    pub(crate) upstream_strategy: UpstreamStrategy,
     pub(crate) ttl: u32,
     pub(crate) authoritative: bool,
+    /// Names in the zone are expected to have both A and AAAA records.
+    pub(crate) dual_stack: bool,
+    /// Answers from upstream servers for names in the zone are recorded.
+    pub(crate) learn: bool,
+    /// Queries for a type of record that a local name doesn't have get an empty
+    /// answer rather than being forwarded. Always the case for authoritative
+    /// zones.
+    pub(crate) nodata: bool,
+    /// Queries for names in the zone are referred to other name servers.
+    pub(crate) delegation: Option<Delegation>,
+    /// The addresses published for this server in authoritative zones.
+    pub(crate) name_server_addresses: Vec<IpAddr>,
+    /// The forward zone that names this server in reverse zones.
+    pub(crate) forward_zone: Option<Fqdn>,
+    /// The client is in one of the zone's views so is not given local records.
+    pub(crate) hide_records: bool,
+    /// Records with only these tags are not given to the client.
+    pub(crate) hidden_tags: HashSet<String>,
+    pub(crate) answer_order: AnswerOrder,
+    /// Queries that need more CNAME records than this followed fail.
+    pub(crate) max_cname_depth: usize,
+    /// How many seconds past their TTL upstream answers may be served while the
+    /// upstreams can't be reached, 0 never serves them.
+    pub(crate) max_stale: u32,
 }
 
 impl Default for ZoneConfig {
@@ -36,8 +182,20 @@ impl Default for ZoneConfig {
         Self {
             origin: None,
             upstreams: VecDeque::new(),
+            upstream_strategy: UpstreamStrategy::Failover,
             ttl: 300,
             authoritative: false,
+            dual_stack: false,
+            learn: false,
+            nodata: false,
+            delegation: None,
+            name_server_addresses: Vec::new(),
+            forward_zone: None,
+            hide_records: false,
+            hidden_tags: HashSet::new(),
+            answer_order: AnswerOrder::Fixed,
+            max_cname_depth: DEFAULT_MAX_CNAME_DEPTH,
+            max_stale: 0,
         }
     }
 }
@@ -47,45 +205,136 @@ impl From<&file::DefaultZoneConfig> for ZoneConfig {
         Self {
             origin: None,
             upstreams: VecDeque::from_iter(defaults.upstream.iter().cloned()),
+            upstream_strategy: defaults.upstream_strategy.unwrap_or_default(),
             ttl: defaults.ttl.unwrap_or(300),
             authoritative: false,
+            dual_stack: false,
+            learn: false,
+            nodata: false,
+            delegation: None,
+            name_server_addresses: Vec::new(),
+            forward_zone: None,
+            hide_records: false,
+            hidden_tags: HashSet::new(),
+            answer_order: defaults.answer_order.unwrap_or_default(),
+            max_cname_depth: defaults.max_cname_depth.unwrap_or(DEFAULT_MAX_CNAME_DEPTH),
+            max_stale: defaults.max_stale.unwrap_or_default(),
         }
     }
 }
 
 impl ZoneConfig {
-    pub(crate) fn soa(&self) -> Option<rr::Record> {
+    /// The zone that holds this server's name in an authoritative zone. Names in
+    /// a reverse zone can only be addresses so a forward zone is used there.
+    fn name_server_zone(&self) -> Option<&Fqdn> {
         if !self.authoritative {
             return None;
         }
 
+        let origin = self.origin.as_ref()?;
+        if origin.parse_arpa_name().is_ok() {
+            if let Some(ref forward_zone) = self.forward_zone {
+                return Some(forward_zone);
+            }
+        }
+
+        Some(origin)
+    }
+
+    /// The name that this server uses for itself in an authoritative zone.
+    pub(crate) fn name_server(&self) -> Option<Fqdn> {
+        self.name_server_zone()?.child("ns").ok()
+    }
+
+    pub(crate) fn soa(&self, serial: u32) -> Option<rr::Record> {
+        self.build_soa(self.ttl, 60, serial)
+    }
+
+    /// An SOA record that will cause resolvers to only cache a negative answer
+    /// for `ttl` seconds.
+    pub(crate) fn short_soa(&self, ttl: u32, serial: u32) -> Option<rr::Record> {
+        self.build_soa(ttl, ttl, serial)
+    }
+
+    fn build_soa(&self, ttl: u32, minimum: u32, serial: u32) -> Option<rr::Record> {
+        let name_server = self.name_server()?;
         let origin = self.origin.clone()?;
+        let hostmaster = self.name_server_zone()?.child("hostmaster").ok()?;
 
         Some(rr::Record::from_rdata(
             origin.name(),
-            self.ttl,
+            ttl,
             rr::RData::SOA(SOA::new(
-                origin.child("ns").ok()?.name(),
-                origin.child("hostmaster").ok()?.name(),
-                0,
-                self.ttl.try_into().unwrap(),
-                self.ttl.try_into().unwrap(),
-                (self.ttl * 10).try_into().unwrap(),
-                60,
+                name_server.name(),
+                hostmaster.name(),
+                serial,
+                ttl.try_into().unwrap(),
+                ttl.try_into().unwrap(),
+                (ttl * 10).try_into().unwrap(),
+                minimum,
             )),
         ))
     }
 
-    fn apply_config(&mut self, origin: Fqdn, config: &file::PartialZoneConfig) {
+    fn apply_config(
+        &mut self,
+        origin: Fqdn,
+        config: &file::PartialZoneConfig,
+        client: Option<IpAddr>,
+    ) {
+        if let Some(ref delegate_to) = config.delegate_to {
+            self.delegation = Some(Delegation::new(origin.clone(), delegate_to));
+        }
+
         self.origin = Some(origin);
 
+        // Reverse zones answer for their addresses rather than asking upstream.
+        if config.reverse {
+            self.upstreams.clear();
+        }
+        // Once a view hides the records of a parent zone only the view's upstream
+        // is used, unless the client is also in one of this zone's views.
         if let Some(ref upstream) = config.config.upstream {
-            self.upstreams.push_front(upstream.clone());
+            if !self.hide_records {
+                self.upstreams.push_front(upstream.clone());
+            }
         }
         if let Some(ttl) = config.config.ttl {
             self.ttl = ttl;
         }
+        if let Some(answer_order) = config.config.answer_order {
+            self.answer_order = answer_order;
+        }
+        if let Some(upstream_strategy) = config.config.upstream_strategy {
+            self.upstream_strategy = upstream_strategy;
+        }
+        if let Some(max_cname_depth) = config.config.max_cname_depth {
+            self.max_cname_depth = max_cname_depth;
+        }
+        if let Some(max_stale) = config.config.max_stale {
+            self.max_stale = max_stale;
+        }
         self.authoritative = config.authoritative.unwrap_or(true);
+        if let Some(dual_stack) = config.dual_stack {
+            self.dual_stack = dual_stack;
+        }
+        if let Some(learn) = config.learn {
+            self.learn = learn;
+        }
+        if let Some(nodata) = config.nodata {
+            self.nodata = nodata;
+        }
+
+        let view = client.and_then(|ip| {
+            config
+                .views
+                .iter()
+                .find(|view| view.clients.iter().any(|subnet| subnet.contains(&ip)))
+        });
+        if let Some(view) = view {
+            self.hide_records = true;
+            self.upstreams = view.upstream.iter().cloned().collect();
+        }
     }
 }
 
@@ -98,10 +347,38 @@ impl fmt::Debug for ZoneConfig {
 
         parts.push(format!("ttl={}", self.ttl));
         parts.push(format!("authoritative={}", self.authoritative));
+        if self.dual_stack {
+            parts.push("dual_stack".to_string());
+        }
+        if self.learn {
+            parts.push("learn".to_string());
+        }
+        if self.nodata {
+            parts.push("nodata".to_string());
+        }
+        if let Some(ref delegation) = self.delegation {
+            parts.push(format!("delegation={}", delegation.origin));
+        }
+        if self.hide_records {
+            parts.push("hide_records".to_string());
+        }
+        if self.answer_order != AnswerOrder::Fixed {
+            parts.push(format!("answer_order={:?}", self.answer_order));
+        }
+        if self.max_cname_depth != DEFAULT_MAX_CNAME_DEPTH {
+            parts.push(format!("max_cname_depth={}", self.max_cname_depth));
+        }
+        if self.max_stale != 0 {
+            parts.push(format!("max_stale={}", self.max_stale));
+        }
 
         if !self.upstreams.is_empty() {
             let strings: Vec<String> = self.upstreams.iter().map(|u| format!("{u:?}")).collect();
             parts.push(format!("upstream={:?}", strings.join(",")));
+
+            if self.upstream_strategy != UpstreamStrategy::Failover {
+                parts.push(format!("upstream_strategy={:?}", self.upstream_strategy));
+            }
         }
 
         f.pad(&format!("[{}]", parts.join(" ")))
@@ -112,31 +389,136 @@ impl fmt::Debug for ZoneConfig {
 pub(crate) struct Zones {
     defaults: file::DefaultZoneConfig,
     zones: Vec<(Fqdn, file::PartialZoneConfig)>,
+    /// Sorted so the most specific subnet comes first.
+    reverse_upstreams: Vec<(Subnet, Upstream)>,
+    tags: Vec<(String, Vec<Subnet>)>,
+    name_server_addresses: Vec<IpAddr>,
+    forward_zone: Option<Fqdn>,
 }
 
 impl Zones {
     fn new(
         defaults: file::DefaultZoneConfig,
         mut zones: HashMap<Fqdn, file::PartialZoneConfig>,
+        reverse_zones: &[Subnet],
+        reverse_upstreams: HashMap<Subnet, Upstream>,
+        tags: HashMap<String, Vec<Subnet>>,
+        name_server_addresses: Vec<IpAddr>,
     ) -> Self {
+        for origin in reverse_zones.iter().flat_map(Subnet::reverse_zones) {
+            zones.entry(origin).or_default().reverse = true;
+        }
+
         let mut zones: Vec<(Fqdn, file::PartialZoneConfig)> = zones.drain().collect();
         zones.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
 
-        Self { defaults, zones }
+        let forward_zone = zones
+            .iter()
+            .find(|(origin, config)| {
+                origin.parse_arpa_name().is_err()
+                    && config.delegate_to.is_none()
+                    && config.authoritative.unwrap_or(true)
+            })
+            .map(|(origin, _)| origin.clone());
+
+        let mut reverse_upstreams: Vec<(Subnet, Upstream)> =
+            reverse_upstreams.into_iter().collect();
+        reverse_upstreams.sort_by(|(s1, _), (s2, _)| {
+            s2.prefix()
+                .cmp(&s1.prefix())
+                .then_with(|| s1.to_string().cmp(&s2.to_string()))
+        });
+
+        let mut tags: Vec<(String, Vec<Subnet>)> = tags.into_iter().collect();
+        tags.sort_by(|(t1, _), (t2, _)| t1.cmp(t2));
+
+        Self {
+            defaults,
+            zones,
+            reverse_upstreams,
+            tags,
+            name_server_addresses,
+            forward_zone,
+        }
+    }
+
+    /// These zones along with authoritative reverse zones for more subnets, such
+    /// as those that sources find.
+    pub(crate) fn with_reverse_zones<'a>(
+        &self,
+        subnets: impl IntoIterator<Item = &'a Subnet>,
+    ) -> Self {
+        let mut zones = self.clone();
+
+        for origin in subnets.into_iter().flat_map(Subnet::reverse_zones) {
+            match zones.zones.iter_mut().find(|(name, _)| *name == origin) {
+                Some((_, config)) => config.reverse = true,
+                None => zones.zones.push((
+                    origin,
+                    file::PartialZoneConfig {
+                        reverse: true,
+                        ..Default::default()
+                    },
+                )),
+            }
+        }
+
+        zones.zones.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
+        zones
+    }
+
+    /// The upstream for reverse lookups of addresses in the most specific
+    /// matching subnet.
+    fn reverse_upstream(&self, name: &Fqdn) -> Option<&Upstream> {
+        if self.reverse_upstreams.is_empty() {
+            return None;
+        }
+
+        let ip = name.parse_arpa_name().ok()?.addr();
+        self.reverse_upstreams
+            .iter()
+            .find(|(subnet, _)| subnet.contains(&ip))
+            .map(|(_, upstream)| upstream)
     }
 }
 
 pub(crate) trait ZoneConfigProvider {
     fn zone_config(&self, fqdn: &Fqdn) -> ZoneConfig;
+
+    /// The configuration for a query from `client`, which may be in one of the
+    /// zone's views.
+    fn client_zone_config(&self, fqdn: &Fqdn, _client: Option<IpAddr>) -> ZoneConfig {
+        self.zone_config(fqdn)
+    }
 }
 
 impl ZoneConfigProvider for Zones {
     fn zone_config(&self, name: &Fqdn) -> ZoneConfig {
+        self.client_zone_config(name, None)
+    }
+
+    fn client_zone_config(&self, name: &Fqdn, client: Option<IpAddr>) -> ZoneConfig {
         let mut config = ZoneConfig::from(&self.defaults);
+        config.name_server_addresses = self.name_server_addresses.clone();
+        config.forward_zone = self.forward_zone.clone();
+
+        if let Some(ip) = client {
+            config.hidden_tags = self
+                .tags
+                .iter()
+                .filter(|(_, clients)| !clients.iter().any(|subnet| subnet.contains(&ip)))
+                .map(|(tag, _)| tag.clone())
+                .collect();
+        }
+
+        // Replaces the default upstream but zones can still override it.
+        if let Some(upstream) = self.reverse_upstream(name) {
+            config.upstreams = VecDeque::from([upstream.clone()]);
+        }
 
         for (n, c) in &self.zones {
             if n.zone_of(name) {
-                config.apply_config(n.clone(), c);
+                config.apply_config(n.clone(), c, client);
             }
         }
 
@@ -161,23 +543,335 @@ fn map_env(key: &UncasedStr) -> Uncased<'_> {
         .into()
 }
 
+/// Fills in any settings missing from a source with those given for its source
+/// type in `source_defaults`.
+fn apply_source_defaults(figment: Figment) -> Figment {
+    let Some(source_defaults) = figment
+        .find_value("source_defaults")
+        .ok()
+        .and_then(Value::into_dict)
+    else {
+        return figment;
+    };
+
+    let sources = figment
+        .find_value("sources")
+        .ok()
+        .and_then(Value::into_dict)
+        .unwrap_or_default();
+
+    let mut defaults = Dict::new();
+    for (source_type, type_defaults) in source_defaults {
+        let Some(names) = sources.get(&source_type).and_then(Value::as_dict) else {
+            continue;
+        };
+
+        let type_sources: Dict = names
+            .keys()
+            .map(|name| (name.clone(), type_defaults.clone()))
+            .collect();
+        defaults.insert(source_type, type_sources.into());
+    }
+
+    let mut wrapped = Dict::new();
+    wrapped.insert("sources".to_owned(), defaults.into());
+
+    figment.join(Serialized::defaults(wrapped))
+}
+
+/// Matches a file name against a pattern where `*` matches any characters.
+pub(crate) fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+
+            (0..=name.len())
+                .filter(|index| name.is_char_boundary(*index))
+                .any(|index| matches_wildcard(rest, &name[index..]))
+        }
+    }
+}
+
+/// The files in a directory with names that match, sorted so that they are
+/// always merged in the same order.
+fn list_directory(
+    dir: &Path,
+    matches: impl Fn(&str) -> bool,
+) -> Result<Vec<PathBuf>, figment::Error> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("Unable to read {}: {e}", dir.display()))?;
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(&matches)
+        })
+        .collect();
+    files.sort();
+
+    Ok(files)
+}
+
+/// Finds the files that the configuration file includes. An include can be a
+/// file, a directory whose YAML files are all included or a file name using `*`
+/// wildcards. Returns the files to merge in order along with every file,
+/// directory and wildcard pattern that must be watched to notice changes.
+fn resolve_includes(
+    config_file: &Path,
+    includes: &file::Includes,
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>), figment::Error> {
+    let base = config_file.parent().unwrap_or(Path::new("."));
+
+    let mut files = Vec::new();
+    let mut directories = Vec::new();
+
+    for include in includes.list() {
+        let path = base.join(include);
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        if file_name.contains('*') {
+            let dir = path.parent().unwrap_or(base);
+            files.extend(list_directory(dir, |name| {
+                matches_wildcard(file_name, name)
+            })?);
+            directories.push(path.clone());
+        } else if path.is_dir() {
+            files.extend(list_directory(&path, |name| {
+                name.ends_with(".yaml") || name.ends_with(".yml")
+            })?);
+            directories.push(path);
+        } else {
+            files.push(path);
+        }
+    }
+
+    // A wildcard may well match the main file.
+    files.retain(|file| file != config_file);
+
+    let mut watched = files.clone();
+    watched.extend(directories);
+
+    Ok((files, watched))
+}
+
+/// A problem found when checking a configuration file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// The dotted path to the setting with the problem, empty for problems with
+    /// the file as a whole.
+    pub location: String,
+    pub message: String,
+    /// A likely mistake that doesn't stop the file from loading.
+    pub warning: bool,
+}
+
+impl ConfigProblem {
+    fn new(location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            location: location.into(),
+            message: message.into(),
+            warning: false,
+        }
+    }
+
+    fn warning(location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            warning: true,
+            ..Self::new(location, message)
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.location.is_empty() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{}: {}", self.location, self.message)
+        }
+    }
+}
+
 #[derive(Clone, Default, Debug, PartialEq)]
 pub(crate) struct Config {
     pub server: ServerConfig,
     pub api: Option<ApiConfig>,
+    pub(crate) control_socket: Option<PathBuf>,
+    pub(crate) logging: LoggingConfig,
+    pub(crate) telemetry: Option<TelemetryConfig>,
     pub sources: SourcesConfig,
+    pub(crate) conflicts: ConflictConfig,
+    pub(crate) outputs: OutputsConfig,
     pub(crate) zones: Zones,
+    /// The included files and directories, watched for changes along with the
+    /// main file.
+    pub(crate) included_paths: Vec<PathBuf>,
 }
 
 impl Config {
+    /// Builds the figment for the configuration file and any files it includes.
+    /// Settings in the main file override those in included files and earlier
+    /// included files override later ones. Also returns the included paths.
+    fn figment(config_file: &Path) -> Result<(Figment, Vec<PathBuf>), figment::Error> {
+        let mut figment = Figment::new()
+            .join(Env::prefixed("LOCALNS_").map(map_env).lowercase(false))
+            .join(Yaml::file_exact(config_file));
+
+        let main = Figment::from(Yaml::file_exact(config_file));
+        if main.find_value("include").is_err() {
+            return Ok((figment, Vec::new()));
+        }
+
+        let includes: file::Includes = main.extract_inner("include")?;
+        let (files, watched) = resolve_includes(config_file, &includes)?;
+        for file in files {
+            figment = figment.join(Yaml::file_exact(file));
+        }
+
+        Ok((figment, watched))
+    }
+
+    /// Reads only the location of the control socket. Used by the CLI commands
+    /// which must not write the PID file like loading the full configuration does.
+    pub(crate) fn control_socket(config_file: &Path) -> Result<Option<PathBuf>, Error> {
+        let (figment, _) = Self::figment(config_file)?;
+
+        if figment.find_value("control_socket").is_err() {
+            return Ok(None);
+        }
+
+        let path: RelativePathBuf = figment.extract_inner("control_socket")?;
+        Ok(Some(path.relative()))
+    }
+
+    fn parse(config_file: &Path) -> Result<(file::ConfigFile, Vec<PathBuf>), Box<figment::Error>> {
+        let (figment, included_paths) = Self::figment(config_file)?;
+
+        for (source_type, _) in OPTIONAL_FEATURES
+            .iter()
+            .filter(|(feature, included)| !included && OPTIONAL_SOURCE_TYPES.contains(feature))
+        {
+            if figment
+                .find_value(&format!("sources.{source_type}"))
+                .is_ok()
+            {
+                tracing::warn!(
+                    source_type,
+                    "This build does not support the configured source type, ignoring"
+                );
+            }
+        }
+
+        Ok((apply_source_defaults(figment).extract()?, included_paths))
+    }
+
+    /// Checks a configuration file without applying it. Beyond the problems that
+    /// would stop the file from loading this also looks for files that are
+    /// referenced but don't exist and likely mistakes such as forwarding loops.
+    pub(crate) fn check(config_file: &Path) -> Vec<ConfigProblem> {
+        if !config_file.is_file() {
+            return vec![ConfigProblem::new(
+                "",
+                format!("{} does not exist", config_file.display()),
+            )];
+        }
+
+        let config = match Self::parse(config_file) {
+            Ok((config, _)) => config,
+            Err(e) => {
+                return (*e)
+                    .into_iter()
+                    .map(|error| ConfigProblem::new(error.path.join("."), error.kind.to_string()))
+                    .collect();
+            }
+        };
+
+        let mut problems = Vec::new();
+
+        let mut required_files: Vec<(String, PathBuf)> = Vec::new();
+        if let Some(tls) = config.api.as_ref().and_then(|api| api.tls.as_ref()) {
+            required_files.extend(tls.files().into_iter().map(|path| ("api.tls".into(), path)));
+        }
+        if let Some(mesh) = &config.mesh {
+            required_files.extend(
+                mesh.tls_files()
+                    .into_iter()
+                    .map(|path| ("mesh.tls".into(), path)),
+            );
+        }
+        required_files.extend(config.sources.required_files());
+
+        for (location, path) in required_files {
+            if !path.exists() {
+                problems.push(ConfigProblem::new(
+                    location,
+                    format!("{} does not exist", path.display()),
+                ));
+            }
+        }
+
+        // These files are created by LocalNS but only in an existing directory.
+        let created_files = [
+            ("pid_file", config.pid_file.as_ref()),
+            ("control_socket", config.control_socket.as_ref()),
+            (
+                "api.records_file",
+                config
+                    .api
+                    .as_ref()
+                    .and_then(|api| api.records_file.as_ref()),
+            ),
+        ];
+
+        let created_files = created_files
+            .into_iter()
+            .filter_map(|(location, path)| {
+                path.map(|path| (location.to_owned(), RelativePathBuf::relative(path)))
+            })
+            .chain(config.outputs.created_files());
+
+        for (location, path) in created_files {
+            let parent = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty());
+            if parent.is_some_and(|parent| !parent.is_dir()) {
+                problems.push(ConfigProblem::new(
+                    location,
+                    format!("The directory for {} does not exist", path.display()),
+                ));
+            }
+        }
+
+        problems.extend(validate::validate(&config));
+        problems.extend(validate::conflicting_records(&config));
+
+        problems
+    }
+
     #[instrument(fields(config_file = %config_file.display()), err)]
     pub(crate) fn from_file(config_file: &Path) -> Result<Config, Error> {
         tracing::info!("Reading configuration");
 
-        let config: file::ConfigFile = Figment::new()
-            .join(Env::prefixed("LOCALNS_").map(map_env).lowercase(false))
-            .join(Yaml::file_exact(config_file))
-            .extract()?;
+        let (config, included_paths) = Self::parse(config_file)?;
+
+        for problem in validate::validate(&config) {
+            tracing::warn!(
+                location = problem.location,
+                problem = problem.message,
+                "Possible mistake in the configuration"
+            );
+        }
 
         if let Some(path) = config.pid_file {
             let id = process::id();
@@ -186,22 +880,68 @@ impl Config {
             }
         }
 
+        let zones = Zones::new(
+            config.defaults,
+            config.zones,
+            &config.reverse_zones,
+            config.reverse_upstreams,
+            config.tags,
+            config.server.addresses.clone(),
+        );
+
+        let mut sources = config.sources;
+        if let Some(records_file) = config
+            .api
+            .as_ref()
+            .and_then(|api| api.records_file.as_ref())
+        {
+            sources.api.insert(
+                API_RECORDS_SOURCE.to_owned(),
+                ApiRecordsConfig {
+                    records_file: records_file.relative(),
+                },
+            );
+        }
+
+        if let Some(mesh) = config.mesh {
+            sources.mesh.insert(MESH_SOURCE.to_owned(), mesh);
+        }
+
+        // A self source named after the server unless one is already configured.
+        if let Some(hostname) = &config.server.hostname {
+            sources
+                .interfaces
+                .entry(SELF_SOURCE.to_owned())
+                .or_insert_with(|| InterfacesConfig::new(vec![hostname.clone()]));
+        }
+
         Ok(Config {
             server: config.server,
             api: config.api,
-            sources: config.sources,
-            zones: Zones::new(config.defaults, config.zones),
+            control_socket: config.control_socket.map(|path| path.relative()),
+            logging: config.logging,
+            telemetry: config.telemetry,
+            sources,
+            conflicts: config.conflicts,
+            outputs: config.outputs,
+            zones,
+            included_paths,
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
+    use hickory_server::proto::rr;
     use tempfile::TempDir;
 
+    #[cfg(feature = "docker")]
+    use crate::sources::docker;
     use crate::{
-        config::{Config, ZoneConfigProvider},
-        sources::docker,
+        config::{Config, ConfigProblem, ZoneConfigProvider},
+        sources::interfaces::InterfacesConfig,
         test::{fqdn, write_file},
     };
 
@@ -234,6 +974,12 @@ zones:
   home.local: {}
   other.local:
     upstream: 10.10.15.250:5353
+  corp.local:
+    upstream:
+      address: "[fd00::53]"
+      tsig:
+        name: localns-key
+        secret: c2VjcmV0IGtleSBmb3IgdGVzdGluZw==
 "#,
         )
         .await;
@@ -262,9 +1008,505 @@ zones:
             "10.10.14.250:5324"
         );
 
-        assert_eq!(config.sources.docker.len(), 1);
-        let (name, docker_config) = config.sources.docker.iter().next().unwrap();
-        assert_eq!(name, "local");
-        assert!(matches!(docker_config, docker::DockerConfig::Local {}));
+        let zone_config = config.zones.zone_config(&fqdn("www.corp.local"));
+        assert_eq!(
+            format!("{:?}", zone_config.upstreams.front().unwrap()),
+            "fd00::53 (key localns-key)"
+        );
+
+        #[cfg(feature = "docker")]
+        {
+            assert_eq!(config.sources.docker.len(), 1);
+            let (name, docker_config) = config.sources.docker.iter().next().unwrap();
+            assert_eq!(name, "local");
+            assert!(matches!(docker_config, docker::DockerConfig::Local { .. }));
+        }
+        #[cfg(not(feature = "docker"))]
+        assert!(logs_contain("does not support the configured source type"));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn source_defaults() {
+        let temp = TempDir::new().unwrap();
+
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+source_defaults:
+  remote:
+    interval_ms: 5000
+  traefik:
+    address: 10.10.1.5
+
+sources:
+  file:
+    zone: zone.yaml
+  remote:
+    first:
+      url: https://first.local/
+    second:
+      url: https://second.local/
+      interval_ms: 100
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+
+        write_file(
+            &config_file,
+            r#"
+sources:
+  file:
+    zone: zone.yaml
+  remote:
+    first:
+      url: https://first.local/
+      interval_ms: 5000
+    second:
+      url: https://second.local/
+      interval_ms: 100
+"#,
+        )
+        .await;
+
+        let expected = Config::from_file(&config_file).unwrap();
+
+        assert_eq!(config.sources, expected.sources);
+        assert!(config.sources.traefik.is_empty());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn server_hostname() {
+        let temp = TempDir::new().unwrap();
+
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+server:
+  hostname: dns.home.local
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+        assert_eq!(
+            config.sources.interfaces.get("localns"),
+            Some(&InterfacesConfig::new(vec![fqdn("dns.home.local")]))
+        );
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  hostname: dns.home.local
+
+sources:
+  self:
+    localns:
+      hostnames:
+        - router.home.local
+      interfaces:
+        - eth0
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+        assert_eq!(config.sources.interfaces.len(), 1);
+        assert_ne!(
+            config.sources.interfaces.get("localns"),
+            Some(&InterfacesConfig::new(vec![fqdn("dns.home.local")]))
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn reverse_upstreams() {
+        let temp = TempDir::new().unwrap();
+
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+defaults:
+  upstream: 10.10.14.250
+
+reverse_zones:
+  - 10.10.0.0/16
+
+reverse_upstreams:
+  0.0.0.0/0: 1.1.1.1
+  10.0.0.0/8: 10.20.0.1
+  "::/0": 2606:4700:4700::1111
+
+zones:
+  20.10.in-addr.arpa:
+    authoritative: false
+    upstream: 10.20.0.53
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+
+        let upstreams = |name: &str| -> Vec<String> {
+            config
+                .zones
+                .zone_config(&fqdn(name))
+                .upstreams
+                .iter()
+                .map(|upstream| upstream.config.address(53))
+                .collect()
+        };
+
+        assert_eq!(upstreams("www.home.local"), vec!["10.10.14.250:53"]);
+        assert_eq!(upstreams("4.3.2.1.in-addr.arpa"), vec!["1.1.1.1:53"]);
+        assert_eq!(upstreams("4.3.2.10.in-addr.arpa"), vec!["10.20.0.1:53"]);
+        assert!(upstreams("4.3.10.10.in-addr.arpa").is_empty());
+        assert_eq!(
+            upstreams("4.3.20.10.in-addr.arpa"),
+            vec!["10.20.0.53:53", "10.20.0.1:53"]
+        );
+        assert_eq!(
+            upstreams("1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.d.f.ip6.arpa"),
+            vec!["[2606:4700:4700::1111]:53"]
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn nested_zone_views() {
+        let temp = TempDir::new().unwrap();
+
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+zones:
+  home.local:
+    views:
+      - clients:
+          - 10.30.0.0/16
+      - clients:
+          - 10.40.0.0/16
+        upstream: 1.1.1.1
+  lab.home.local:
+    upstream: 10.10.14.250
+  dev.home.local:
+    upstream: 10.10.15.250
+    views:
+      - clients:
+          - 10.30.5.0/24
+        upstream: 10.10.15.251
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+
+        let upstreams = |name: &str, client: &str| -> Vec<String> {
+            config
+                .zones
+                .client_zone_config(&fqdn(name), Some(client.parse().unwrap()))
+                .upstreams
+                .iter()
+                .map(|upstream| upstream.config.address(53))
+                .collect()
+        };
+
+        assert_eq!(
+            upstreams("www.lab.home.local", "10.10.1.5"),
+            vec!["10.10.14.250:53"]
+        );
+        assert!(upstreams("www.lab.home.local", "10.30.1.5").is_empty());
+        assert_eq!(
+            upstreams("www.lab.home.local", "10.40.1.5"),
+            vec!["1.1.1.1:53"]
+        );
+
+        // A view of the nested zone replaces the parent's.
+        assert!(upstreams("www.dev.home.local", "10.30.1.5").is_empty());
+        assert_eq!(
+            upstreams("www.dev.home.local", "10.30.5.5"),
+            vec!["10.10.15.251:53"]
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn reverse_zone_name_server() {
+        let temp = TempDir::new().unwrap();
+
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+reverse_zones:
+  - 10.10.0.0/16
+
+zones:
+  delegated.local:
+    delegate_to: 10.10.0.53
+  home.local: {}
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+
+        let reverse = config.zones.zone_config(&fqdn("10.10.in-addr.arpa"));
+        assert_eq!(reverse.name_server(), Some(fqdn("ns.home.local")));
+
+        let soa = reverse.soa(1).unwrap();
+        assert_eq!(soa.name(), &fqdn("10.10.in-addr.arpa").name());
+        let Some(rr::RData::SOA(soa)) = soa.data() else {
+            panic!("Expected an SOA record");
+        };
+        assert_eq!(soa.mname(), &fqdn("ns.home.local").name());
+        assert_eq!(soa.rname(), &fqdn("hostmaster.home.local").name());
+
+        let forward = config.zones.zone_config(&fqdn("www.home.local"));
+        assert_eq!(forward.name_server(), Some(fqdn("ns.home.local")));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn includes() {
+        let temp = TempDir::new().unwrap();
+        let conf_dir = temp.path().join("conf.d");
+        fs::create_dir(&conf_dir).unwrap();
+
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+include:
+  - conf.d
+  - extra-*.yml
+
+zones:
+  home.local:
+    ttl: 60
+"#,
+        )
+        .await;
+
+        write_file(
+            &conf_dir.join("10-zones.yaml"),
+            r#"
+zones:
+  home.local:
+    ttl: 300
+  other.local:
+    ttl: 120
+"#,
+        )
+        .await;
+        write_file(
+            &conf_dir.join("20-zones.yml"),
+            r#"
+zones:
+  other.local:
+    ttl: 240
+"#,
+        )
+        .await;
+        write_file(&conf_dir.join("notes.txt"), "ignored").await;
+        write_file(
+            &temp.path().join("extra-files.yml"),
+            r#"
+sources:
+  file:
+    home: home.yml
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+
+        assert_eq!(config.zones.zone_config(&fqdn("www.home.local")).ttl, 60);
+        assert_eq!(config.zones.zone_config(&fqdn("www.other.local")).ttl, 120);
+
+        // Relative paths are relative to the included file.
+        assert_eq!(
+            config.sources.file.get("home").unwrap().relative(),
+            temp.path().join("home.yml")
+        );
+
+        assert_eq!(
+            config.included_paths,
+            vec![
+                conf_dir.join("10-zones.yaml"),
+                conf_dir.join("20-zones.yml"),
+                temp.path().join("extra-files.yml"),
+                conf_dir.clone(),
+                temp.path().join("extra-*.yml"),
+            ]
+        );
+
+        write_file(
+            &config_file,
+            r#"
+include: missing.yml
+"#,
+        )
+        .await;
+
+        assert!(Config::from_file(&config_file).is_err());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn check_config() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+
+        assert_eq!(
+            Config::check(&config_file),
+            vec![ConfigProblem::new(
+                "",
+                format!("{} does not exist", config_file.display())
+            )]
+        );
+
+        write_file(
+            &config_file,
+            r#"
+zones:
+  home.local:
+    ttl: soon
+"#,
+        )
+        .await;
+
+        let problems = Config::check(&config_file);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].location, "zones.home.local.ttl");
+
+        write_file(&temp.path().join("zone.yml"), "www.home.local: 10.10.10.10").await;
+        write_file(
+            &config_file,
+            r#"
+control_socket: missing/localns.sock
+
+sources:
+  file:
+    exists: zone.yml
+    missing: other.yml
+  dhcp:
+    leases:
+      lease_file: dnsmasq.leases
+      zone: home.local
+"#,
+        )
+        .await;
+
+        let problems: Vec<String> = Config::check(&config_file)
+            .iter()
+            .map(ConfigProblem::to_string)
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                format!(
+                    "sources.dhcp.leases: {} does not exist",
+                    temp.path().join("dnsmasq.leases").display()
+                ),
+                format!(
+                    "sources.file.missing: {} does not exist",
+                    temp.path().join("other.yml").display()
+                ),
+                format!(
+                    "control_socket: The directory for {} does not exist",
+                    temp.path().join("missing/localns.sock").display()
+                ),
+            ]
+        );
+
+        write_file(&temp.path().join("dnsmasq.leases"), "").await;
+        write_file(&temp.path().join("other.yml"), "").await;
+        fs::create_dir(temp.path().join("missing")).unwrap();
+
+        assert!(Config::check(&config_file).is_empty());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn validate_config() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+
+        write_file(
+            &temp.path().join("first.yml"),
+            r#"
+www.home.local: 10.10.10.10
+nas.home.local: 10.10.10.11
+db.home.local: 10.10.10.13
+"#,
+        )
+        .await;
+        write_file(
+            &temp.path().join("second.yml"),
+            r#"
+www.home.local: nas.home.local
+nas.home.local: 10.10.10.12
+db.home.local: 10.10.10.13
+"#,
+        )
+        .await;
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 5353
+  listen:
+    - 127.0.0.1
+    - 10.10.1.1
+
+defaults:
+  upstream: 127.0.0.1:5353
+
+zones:
+  home.local:
+    upstream: 10.10.1.1:5353
+  lab.home.local:
+    delegate_to: 10.10.5.2
+  test.lab.home.local:
+    ttl: 60
+  other.local:
+    upstream: 10.10.1.1
+  work.local:
+    upstream: 10.10.1.2:5353
+
+sources:
+  file:
+    first: first.yml
+    second: second.yml
+"#,
+        )
+        .await;
+
+        let problems = Config::check(&config_file);
+        assert!(problems.iter().all(|problem| problem.warning));
+
+        let problems: Vec<String> = problems.iter().map(ConfigProblem::to_string).collect();
+        assert_eq!(
+            problems,
+            vec![
+                "defaults.upstream: 127.0.0.1:5353 is this server so forwarding to it would loop".to_owned(),
+                "zones.home.local.upstream: 10.10.1.1:5353 is this server so forwarding to it would loop".to_owned(),
+                "zones.test.lab.home.local: The zone is within the delegated zone lab.home.local. so is never used".to_owned(),
+                "sources.file.second: The A records for nas.home.local. differ from those from sources.file.first".to_owned(),
+                "sources.file.second: The records for www.home.local. conflict with those from sources.file.first, a CNAME can't be combined with other records".to_owned(),
+            ]
+        );
+
+        // The configuration still loads.
+        Config::from_file(&config_file).unwrap();
     }
 }
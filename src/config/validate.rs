@@ -0,0 +1,238 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
+
+use if_addrs::get_if_addrs;
+
+use crate::{
+    config::{file::ConfigFile, ConfigProblem},
+    dns::{Fqdn, RData, RecordSet, Upstream},
+    sources::file::parse_records,
+};
+
+/// How a zone appears in the location of a problem.
+fn zone_location(origin: &Fqdn) -> String {
+    format!("zones.{}", origin.to_string().trim_end_matches('.'))
+}
+
+/// Upstreams that are this server, forwarding to them would send queries
+/// straight back.
+fn forwarding_loops(config: &ConfigFile) -> Vec<ConfigProblem> {
+    let listening = config.server.listen_addresses();
+    let local_addresses: Vec<IpAddr> = get_if_addrs()
+        .map(|interfaces| interfaces.iter().map(|interface| interface.ip()).collect())
+        .unwrap_or_default();
+
+    // A server listening on every address receives queries sent to any of the
+    // host's own addresses.
+    let is_server = |upstream: SocketAddr| {
+        listening.iter().any(|listen| {
+            if listen.port() != upstream.port() {
+                false
+            } else if listen.ip().is_unspecified() {
+                listen.is_ipv4() == upstream.is_ipv4()
+                    && (upstream.ip().is_loopback()
+                        || upstream.ip().is_unspecified()
+                        || local_addresses.contains(&upstream.ip()))
+            } else {
+                listen.ip() == upstream.ip()
+            }
+        })
+    };
+
+    let mut upstreams: Vec<(String, &Upstream)> = Vec::new();
+    upstreams.extend(
+        config
+            .defaults
+            .upstream
+            .iter()
+            .map(|upstream| ("defaults.upstream".to_owned(), upstream)),
+    );
+
+    for (origin, zone) in &config.zones {
+        let location = zone_location(origin);
+
+        upstreams.extend(
+            zone.config
+                .upstream
+                .iter()
+                .map(|upstream| (format!("{location}.upstream"), upstream)),
+        );
+
+        for (index, view) in zone.views.iter().enumerate() {
+            upstreams.extend(
+                view.upstream
+                    .iter()
+                    .map(|upstream| (format!("{location}.views.{index}.upstream"), upstream)),
+            );
+        }
+    }
+
+    upstreams.extend(
+        config
+            .reverse_upstreams
+            .iter()
+            .map(|(subnet, upstream)| (format!("reverse_upstreams.{subnet}"), upstream)),
+    );
+
+    upstreams
+        .into_iter()
+        .filter(|(_, upstream)| is_server(upstream.config.to_socket_address(53)))
+        .map(|(location, upstream)| {
+            ConfigProblem::warning(
+                location,
+                format!(
+                    "{} is this server so forwarding to it would loop",
+                    upstream.config
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Zones within a delegated zone, their settings are never used as queries are
+/// referred to the delegated name servers.
+fn shadowed_zones(config: &ConfigFile) -> Vec<ConfigProblem> {
+    let delegated: Vec<&Fqdn> = config
+        .zones
+        .iter()
+        .filter(|(_, zone)| zone.delegate_to.is_some())
+        .map(|(origin, _)| origin)
+        .collect();
+
+    config
+        .zones
+        .keys()
+        .filter_map(|origin| {
+            delegated
+                .iter()
+                .find(|parent| *parent != &origin && parent.zone_of(origin))
+                .map(|parent| {
+                    ConfigProblem::warning(
+                        zone_location(origin),
+                        format!("The zone is within the delegated zone {parent} so is never used"),
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Names given a CNAME by one source and other records by another, or different
+/// addresses or pointers by different sources. Only the sources with records
+/// known before starting, file sources and the records added through the API,
+/// can be checked. This reads every file source so is left to explicit checks,
+/// the server reports conflicts as it merges records.
+pub(super) fn conflicting_records(config: &ConfigFile) -> Vec<ConfigProblem> {
+    let read = |path: &Path| -> Option<RecordSet> {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| parse_records(&contents).ok())
+    };
+
+    let mut sources: Vec<(String, RecordSet)> = config
+        .sources
+        .file
+        .iter()
+        .filter_map(|(name, path)| {
+            read(&path.relative()).map(|records| (format!("sources.file.{name}"), records))
+        })
+        .collect();
+
+    if let Some(records) = config
+        .api
+        .as_ref()
+        .and_then(|api| api.records_file.as_ref())
+        .and_then(|path| read(&path.relative()))
+    {
+        sources.push(("api.records_file".to_owned(), records));
+    }
+
+    sources.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut names: BTreeMap<&Fqdn, Vec<(&str, &RData)>> = BTreeMap::new();
+    for (location, records) in &sources {
+        for record in records.records() {
+            names
+                .entry(record.name())
+                .or_default()
+                .push((location.as_str(), record.rdata()));
+        }
+    }
+
+    let mut problems = Vec::new();
+
+    for (name, entries) in names {
+        // Blame the later source for conflicting with an earlier one.
+        let mut reported: Vec<&str> = Vec::new();
+        for (index, (location, rdata)) in entries.iter().enumerate() {
+            if reported.contains(location) {
+                continue;
+            }
+
+            let earlier = entries[..index].iter().find(|(other, other_rdata)| {
+                other != location
+                    && other_rdata != rdata
+                    && (matches!(other_rdata, RData::Cname(_)) || matches!(rdata, RData::Cname(_)))
+            });
+
+            if let Some((other, _)) = earlier {
+                reported.push(*location);
+                problems.push(ConfigProblem::warning(
+                    *location,
+                    format!(
+                        "The records for {name} conflict with those from {other}, a CNAME can't be combined with other records"
+                    ),
+                ));
+                continue;
+            }
+
+            if matches!(rdata, RData::Cname(_)) {
+                continue;
+            }
+
+            // The data of a type that each source gives for the name.
+            let data_type = rdata.data_type();
+            let data = |source: &str| -> Vec<&RData> {
+                entries
+                    .iter()
+                    .filter(|(other, other_rdata)| {
+                        *other == source && other_rdata.data_type() == data_type
+                    })
+                    .map(|(_, other_rdata)| *other_rdata)
+                    .collect()
+            };
+            let same = |a: &[&RData], b: &[&RData]| {
+                a.iter().all(|rdata| b.contains(rdata)) && b.iter().all(|rdata| a.contains(rdata))
+            };
+
+            let ours = data(*location);
+            let earlier = entries[..index].iter().find(|(other, other_rdata)| {
+                other != location
+                    && other_rdata.data_type() == data_type
+                    && !same(&ours, &data(*other))
+            });
+
+            if let Some((other, _)) = earlier {
+                reported.push(*location);
+                problems.push(ConfigProblem::warning(
+                    *location,
+                    format!("The {data_type} records for {name} differ from those from {other}"),
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Looks for mistakes that don't stop the configuration from loading.
+pub(super) fn validate(config: &ConfigFile) -> Vec<ConfigProblem> {
+    let mut problems = forwarding_loops(config);
+    problems.extend(shadowed_zones(config));
+    problems.sort_by(|a, b| a.location.cmp(&b.location));
+
+    problems
+}
@@ -0,0 +1,335 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    task::JoinHandle,
+};
+
+use crate::{
+    api::{build_query, ApiQuery, ApiRecords},
+    sources::SourceId,
+    Error, Server,
+};
+
+/// A command sent to a running server over its control socket. Each request and
+/// response is a single line of JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum ControlRequest {
+    /// Reports on the state of the server and its sources.
+    Status,
+    /// Lists the records from every source.
+    Dump,
+    /// Reloads the configuration file.
+    Reload,
+    /// Resolves a name in the same way as a DNS request would.
+    Query {
+        name: String,
+        #[serde(rename = "type", default)]
+        record_type: Option<String>,
+        #[serde(default)]
+        recurse: Option<bool>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ControlResponse {
+    Ok(Value),
+    Error(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct ControlSourceStatus {
+    source_id: SourceId,
+    records: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ControlStatus {
+    version: String,
+    server_id: String,
+    warming_up: bool,
+    records: usize,
+    sources: Vec<ControlSourceStatus>,
+}
+
+async fn status(server: &Server) -> ControlStatus {
+    let inner = server.inner.lock().await;
+
+    let mut sources: Vec<ControlSourceStatus> = inner
+        .records
+        .values()
+        .map(|source_records| ControlSourceStatus {
+            source_id: source_records.source_id.clone(),
+            records: source_records.records.len(),
+        })
+        .collect();
+    sources.sort_by_key(|source| source.source_id.to_string());
+
+    ControlStatus {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        server_id: server.server_id.to_string(),
+        warming_up: server.server_state.is_warming_up(),
+        records: server.server_state.records.read().await.len(),
+        sources,
+    }
+}
+
+async fn dump(server: &Server) -> ApiRecords {
+    let inner = server.inner.lock().await;
+
+    ApiRecords {
+        server_id: server.server_id,
+        timestamp: chrono::Utc::now(),
+        source_records: inner.records.values().cloned().collect(),
+        next_offset: None,
+    }
+}
+
+async fn handle_request(server: &Server, request: ControlRequest) -> Result<Value, Error> {
+    tracing::debug!(?request, "Received control request");
+
+    let value = match request {
+        ControlRequest::Status => serde_json::to_value(status(server).await)?,
+        ControlRequest::Dump => serde_json::to_value(dump(server).await)?,
+        ControlRequest::Reload => {
+            server.reload_config().await?;
+            Value::Null
+        }
+        ControlRequest::Query {
+            name,
+            record_type,
+            recurse,
+        } => {
+            let query = match build_query(&name, record_type.as_deref()) {
+                Ok(query) => query,
+                Err(e) => bail!(e),
+            };
+
+            let result = server
+                .server_state
+                .locked()
+                .await
+                .query(query, recurse.unwrap_or(true))
+                .await;
+
+            serde_json::to_value(ApiQuery::from(result))?
+        }
+    };
+
+    Ok(value)
+}
+
+async fn handle_connection(server: Server, stream: UnixStream) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => match handle_request(&server, request).await {
+                Ok(value) => ControlResponse::Ok(value),
+                Err(e) => ControlResponse::Error(e.to_string()),
+            },
+            Err(e) => ControlResponse::Error(format!("Invalid request: {e}")),
+        };
+
+        let mut data = serde_json::to_vec(&response)?;
+        data.push(b'\n');
+        writer.write_all(&data).await?;
+    }
+
+    Ok(())
+}
+
+/// Listens on a local unix socket so that CLI subcommands can talk to the server
+/// even when the HTTP API is disabled.
+pub(crate) struct ControlServer {
+    path: PathBuf,
+    handle: JoinHandle<()>,
+}
+
+impl ControlServer {
+    pub(crate) fn new(path: &Path, server: Server) -> Option<Self> {
+        tracing::trace!(path = %path.display(), "Starting control socket");
+
+        // A socket left behind by a previous instance would stop the bind.
+        if path.exists() {
+            if let Err(e) = fs::remove_file(path) {
+                tracing::error!(error = %e, path = %path.display(), "Failed to remove old control socket");
+                return None;
+            }
+        }
+
+        let listener = match UnixListener::bind(path) {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!(error = %e, path = %path.display(), "Failed to create control socket");
+                return None;
+            }
+        };
+
+        let handle = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let server = server.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = handle_connection(server, stream).await {
+                                tracing::warn!(error = %e, "Control connection failed");
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        tracing::error!(error = %e, "Failed to accept control connection");
+                    }
+                }
+            }
+        });
+
+        Some(Self {
+            path: path.to_owned(),
+            handle,
+        })
+    }
+
+    pub(crate) fn shutdown(self) {
+        self.handle.abort();
+
+        if let Err(e) = fs::remove_file(&self.path) {
+            tracing::warn!(error = %e, path = %self.path.display(), "Failed to remove control socket");
+        }
+    }
+}
+
+/// Sends a request to the server listening on the given control socket and
+/// returns its response.
+pub async fn send_control_request(socket: &Path, request: &ControlRequest) -> Result<Value, Error> {
+    let stream = UnixStream::connect(socket).await?;
+    let (reader, mut writer) = stream.into_split();
+
+    let mut data = serde_json::to_vec(request)?;
+    data.push(b'\n');
+    writer.write_all(&data).await?;
+
+    let Some(line) = BufReader::new(reader).lines().next_line().await? else {
+        bail!("The server closed the connection without responding");
+    };
+
+    match serde_json::from_str(&line)? {
+        ControlResponse::Ok(value) => Ok(value),
+        ControlResponse::Error(message) => bail!(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+    use tokio::time::sleep;
+
+    use crate::{
+        control::{send_control_request, ControlRequest},
+        test::write_file,
+        Server,
+    };
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn control_socket() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+        let socket = temp_dir.path().join("control.sock");
+
+        write_file(
+            &temp_dir.path().join("zone.yml"),
+            r#"
+www.home.local: 10.10.10.5
+"#,
+        )
+        .await;
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53533
+
+control_socket: control.sock
+
+sources:
+  file:
+    zone: zone.yml
+
+zones:
+  home.local:
+    authoritative: true
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+
+        let mut status = send_control_request(&socket, &ControlRequest::Status)
+            .await
+            .unwrap();
+        for _ in 0..50 {
+            if status["records"] == 1 {
+                break;
+            }
+
+            sleep(Duration::from_millis(100)).await;
+            status = send_control_request(&socket, &ControlRequest::Status)
+                .await
+                .unwrap();
+        }
+        assert_eq!(status["records"], 1);
+        assert_eq!(status["sources"][0]["source_id"]["source_name"], "zone");
+
+        let result = send_control_request(
+            &socket,
+            &ControlRequest::Query {
+                name: "www.home.local".to_owned(),
+                record_type: None,
+                recurse: Some(false),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["response_code"], "No Error");
+        assert_eq!(result["answers"][0]["data"], "10.10.10.5");
+
+        let error = send_control_request(
+            &socket,
+            &ControlRequest::Query {
+                name: "www.home.local".to_owned(),
+                record_type: Some("BAD".to_owned()),
+                recurse: None,
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("Invalid type"));
+
+        let dump = send_control_request(&socket, &ControlRequest::Dump)
+            .await
+            .unwrap();
+        assert_eq!(dump["source_records"].as_array().unwrap().len(), 1);
+
+        let reload = send_control_request(&socket, &ControlRequest::Reload)
+            .await
+            .unwrap();
+        assert!(reload.is_null());
+
+        server.shutdown().await;
+        assert!(!socket.exists());
+    }
+}
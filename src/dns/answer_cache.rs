@@ -0,0 +1,186 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hickory_server::proto::rr::{self, DNSClass, Name, RecordType};
+
+use crate::dns::upstream::UpstreamKey;
+
+/// Identifies a lookup sent upstream. Answers are only given to lookups that
+/// would go to the same upstreams with the same client subnet.
+pub(super) type AnswerKey = (UpstreamKey, Name, DNSClass, RecordType);
+
+#[derive(Debug)]
+struct CachedAnswers {
+    answers: Vec<rr::Record>,
+    cached: Instant,
+    expires: Instant,
+}
+
+impl CachedAnswers {
+    fn usable(&self, stale_for: Duration, now: Instant) -> bool {
+        self.expires + stale_for > now
+    }
+}
+
+/// Answers from upstream servers, kept for their shortest TTL and then for as
+/// long as the caller allows them to be used once stale.
+#[derive(Clone, Debug)]
+pub(super) struct AnswerCache {
+    entries: Arc<Mutex<HashMap<AnswerKey, CachedAnswers>>>,
+    /// The most lookups that are kept at once.
+    limit: usize,
+    /// The lowest TTL given to answers taken from the cache.
+    min_ttl: u32,
+}
+
+impl AnswerCache {
+    pub(super) fn new(limit: usize, min_ttl: u32) -> Self {
+        Self {
+            entries: Default::default(),
+            limit,
+            min_ttl,
+        }
+    }
+
+    /// The cached answers if they expired no more than `stale_for` ago. Their
+    /// TTLs are reduced by the time since they were cached.
+    pub(super) fn get(&self, key: &AnswerKey, stale_for: Duration) -> Option<Vec<rr::Record>> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        let entry = entries.get(key)?;
+        if !entry.usable(stale_for, now) {
+            entries.remove(key);
+            return None;
+        }
+
+        let elapsed = (now - entry.cached).as_secs() as u32;
+        Some(
+            entry
+                .answers
+                .iter()
+                .cloned()
+                .map(|mut answer| {
+                    answer.set_ttl(answer.ttl().saturating_sub(elapsed).max(self.min_ttl));
+                    answer
+                })
+                .collect(),
+        )
+    }
+
+    /// Remembers the answers for a lookup. Answers that could never be used
+    /// replace any older answers without being kept.
+    pub(super) fn insert(&self, key: AnswerKey, answers: &[rr::Record], stale_for: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+
+        let ttl = answers.iter().map(rr::Record::ttl).min().unwrap_or(0);
+        if answers.is_empty() || (ttl == 0 && stale_for.is_zero()) {
+            entries.remove(&key);
+            return;
+        }
+
+        let now = Instant::now();
+
+        if entries.len() >= self.limit && !entries.contains_key(&key) {
+            entries.retain(|_, entry| entry.usable(stale_for, now));
+            if entries.len() >= self.limit {
+                entries.clear();
+            }
+        }
+
+        entries.insert(
+            key,
+            CachedAnswers {
+                answers: answers.to_vec(),
+                cached: now,
+                expires: now + Duration::from_secs(ttl.into()),
+            },
+        );
+    }
+
+    pub(super) fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hickory_server::proto::rr::{self, DNSClass, RecordType};
+
+    use crate::{
+        dns::{
+            answer_cache::{AnswerCache, AnswerKey},
+            upstream::{Upstream, UpstreamKey},
+        },
+        test::{name, rdata_a},
+    };
+
+    fn key(target: &str) -> AnswerKey {
+        let upstream: Upstream = serde_yaml::from_str("10.10.1.1").unwrap();
+        (
+            UpstreamKey::new(&[upstream], None),
+            name(target),
+            DNSClass::IN,
+            RecordType::A,
+        )
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn limit() {
+        let cache = AnswerCache::new(2, 10);
+        let answer = |target: &str, ttl: u32| {
+            rr::Record::from_rdata(name(target), ttl, rdata_a("10.10.1.1"))
+        };
+
+        cache.insert(
+            key("a.example.org."),
+            &[answer("a.example.org.", 0)],
+            Duration::ZERO,
+        );
+        assert!(cache.get(&key("a.example.org."), Duration::ZERO).is_none());
+
+        // Answers that have expired are still given while stale ones are allowed,
+        // with the lowest TTL.
+        cache.insert(
+            key("a.example.org."),
+            &[answer("a.example.org.", 0)],
+            Duration::from_secs(60),
+        );
+        let answers = cache
+            .get(&key("a.example.org."), Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(answers[0].ttl(), 10);
+
+        // Expired answers make way for new ones once the cache is full.
+        cache.insert(
+            key("b.example.org."),
+            &[answer("b.example.org.", 300)],
+            Duration::ZERO,
+        );
+        cache.insert(
+            key("c.example.org."),
+            &[answer("c.example.org.", 300)],
+            Duration::ZERO,
+        );
+        assert!(cache
+            .get(&key("a.example.org."), Duration::from_secs(60))
+            .is_none());
+        assert!(cache.get(&key("b.example.org."), Duration::ZERO).is_some());
+        assert!(cache.get(&key("c.example.org."), Duration::ZERO).is_some());
+
+        // Otherwise everything is forgotten.
+        cache.insert(
+            key("d.example.org."),
+            &[answer("d.example.org.", 300)],
+            Duration::ZERO,
+        );
+        assert!(cache.get(&key("b.example.org."), Duration::ZERO).is_none());
+        assert!(cache.get(&key("d.example.org."), Duration::ZERO).is_some());
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use crate::dns::{Fqdn, RData, Record, RecordSet};
+
+/// Builds a record set without the boilerplate of creating each record. Names
+/// and addresses must be valid, the builder panics otherwise.
+#[cfg_attr(not(feature = "record-builder"), allow(unreachable_pub))]
+#[derive(Default)]
+pub struct RecordSetBuilder {
+    records: RecordSet,
+}
+
+fn fqdn(name: &str) -> Fqdn {
+    Fqdn::try_from(name).unwrap_or_else(|e| panic!("Invalid name {name}: {e}"))
+}
+
+#[cfg_attr(not(feature = "record-builder"), allow(unreachable_pub))]
+impl RecordSetBuilder {
+    fn record(mut self, name: &str, rdata: RData) -> Self {
+        self.records.insert(Record::new(fqdn(name), rdata));
+        self
+    }
+
+    pub fn a(self, name: &str, ip: &str) -> Self {
+        let ip = Ipv4Addr::from_str(ip).unwrap_or_else(|e| panic!("Invalid address {ip}: {e}"));
+        self.record(name, RData::A(ip))
+    }
+
+    pub fn aaaa(self, name: &str, ip: &str) -> Self {
+        let ip = Ipv6Addr::from_str(ip).unwrap_or_else(|e| panic!("Invalid address {ip}: {e}"));
+        self.record(name, RData::Aaaa(ip))
+    }
+
+    pub fn cname(self, name: &str, target: &str) -> Self {
+        let target = fqdn(target);
+        self.record(name, RData::Cname(target))
+    }
+
+    pub fn ptr(self, name: &str, target: &str) -> Self {
+        let target = fqdn(target);
+        self.record(name, RData::Ptr(target))
+    }
+
+    pub fn build(self) -> RecordSet {
+        self.records
+    }
+}
+
+#[cfg_attr(not(feature = "record-builder"), allow(unreachable_pub))]
+impl RecordSet {
+    pub fn builder() -> RecordSetBuilder {
+        RecordSetBuilder::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        dns::{RData, RecordSet},
+        test::fqdn,
+    };
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn builder() {
+        let records = RecordSet::builder()
+            .a("www.home.local", "10.10.1.5")
+            .aaaa("www.home.local.", "fd00::5")
+            .cname("web.home.local", "www.home.local")
+            .ptr("6.1.10.10.in-addr.arpa", "other.home.local")
+            .build();
+
+        assert_eq!(records.len(), 4);
+        assert!(records.contains(
+            &fqdn("www.home.local"),
+            &RData::A("10.10.1.5".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("web.home.local"),
+            &RData::Cname(fqdn("www.home.local"))
+        ));
+    }
+}
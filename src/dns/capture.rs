@@ -0,0 +1,290 @@
+use std::{
+    collections::VecDeque,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+
+/// pcap's link type for packets that start with a raw IP header.
+const LINKTYPE_RAW: u32 = 101;
+/// The largest DNS message that fits in a single UDP packet.
+const MAX_PAYLOAD: usize = u16::MAX as usize - 48;
+
+#[derive(Debug)]
+struct CapturedQuery {
+    timestamp: DateTime<Utc>,
+    client: SocketAddr,
+    server_port: u16,
+    query: Vec<u8>,
+    response: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct CaptureState {
+    limit: usize,
+    queries: VecDeque<CapturedQuery>,
+}
+
+/// Keeps the wire format of the most recent queries and their responses so that
+/// they can be downloaded as a pcap file.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct QueryCapture {
+    state: Arc<Mutex<CaptureState>>,
+}
+
+impl QueryCapture {
+    /// Sets how many queries to keep, capturing is disabled when there is no
+    /// limit.
+    pub(super) fn set_limit(&self, limit: Option<usize>) {
+        let mut state = self.state.lock().unwrap();
+        state.limit = limit.unwrap_or_default();
+        let limit = state.limit;
+        state.queries.truncate(limit);
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.state.lock().unwrap().limit > 0
+    }
+
+    pub(super) fn record(
+        &self,
+        client: SocketAddr,
+        server_port: u16,
+        query: Vec<u8>,
+        response: Vec<u8>,
+    ) {
+        if query.len() > MAX_PAYLOAD || response.len() > MAX_PAYLOAD {
+            tracing::trace!(%client, "Not capturing a query too large for a UDP packet");
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.limit == 0 {
+            return;
+        }
+
+        state.queries.push_front(CapturedQuery {
+            timestamp: Utc::now(),
+            client,
+            server_port,
+            query,
+            response,
+        });
+
+        let limit = state.limit;
+        state.queries.truncate(limit);
+    }
+
+    /// Builds a pcap file holding each query and its response, oldest first.
+    /// Everything is presented as UDP to and from the address the server listens
+    /// on.
+    pub(crate) fn pcap(&self) -> Vec<u8> {
+        let state = self.state.lock().unwrap();
+
+        let mut pcap = Vec::new();
+        pcap.extend_from_slice(&0xa1b2c3d4_u32.to_le_bytes());
+        pcap.extend_from_slice(&2_u16.to_le_bytes());
+        pcap.extend_from_slice(&4_u16.to_le_bytes());
+        pcap.extend_from_slice(&0_i32.to_le_bytes());
+        pcap.extend_from_slice(&0_u32.to_le_bytes());
+        pcap.extend_from_slice(&(u16::MAX as u32).to_le_bytes());
+        pcap.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+
+        for captured in state.queries.iter().rev() {
+            let server = SocketAddr::new(
+                match captured.client.ip() {
+                    IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+                    IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+                },
+                captured.server_port,
+            );
+
+            write_packet(
+                &mut pcap,
+                captured.timestamp,
+                captured.client,
+                server,
+                &captured.query,
+            );
+            write_packet(
+                &mut pcap,
+                captured.timestamp,
+                server,
+                captured.client,
+                &captured.response,
+            );
+        }
+
+        pcap
+    }
+}
+
+/// The internet checksum of some data, as used by IP and UDP headers.
+fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+
+    for chunk in chunks {
+        for pair in chunk.chunks(2) {
+            let word = u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]);
+            sum += u32::from(word);
+        }
+    }
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+/// UDP uses a zero checksum to mean that there isn't one.
+fn udp_checksum(chunks: &[&[u8]]) -> [u8; 2] {
+    match checksum(chunks) {
+        0 => u16::MAX,
+        sum => sum,
+    }
+    .to_be_bytes()
+}
+
+fn write_packet(
+    pcap: &mut Vec<u8>,
+    timestamp: DateTime<Utc>,
+    source: SocketAddr,
+    destination: SocketAddr,
+    payload: &[u8],
+) {
+    let udp_length = (payload.len() + 8) as u16;
+    let mut udp = Vec::with_capacity(udp_length as usize);
+    udp.extend_from_slice(&source.port().to_be_bytes());
+    udp.extend_from_slice(&destination.port().to_be_bytes());
+    udp.extend_from_slice(&udp_length.to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+
+    let mut packet = match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            let pseudo_header = [0, 17, (udp_length >> 8) as u8, udp_length as u8];
+            let udp_checksum = udp_checksum(&[
+                &source.octets(),
+                &destination.octets(),
+                &pseudo_header,
+                &udp,
+            ]);
+            udp[6..8].copy_from_slice(&udp_checksum);
+
+            let mut header = Vec::with_capacity(20);
+            header.extend_from_slice(&[0x45, 0]);
+            header.extend_from_slice(&(udp_length + 20).to_be_bytes());
+            header.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+            header.extend_from_slice(&source.octets());
+            header.extend_from_slice(&destination.octets());
+            let header_checksum = checksum(&[&header]);
+            header[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+
+            header
+        }
+        (source, destination) => {
+            let source = to_ipv6(source);
+            let destination = to_ipv6(destination);
+
+            let pseudo_header = [0, 0, (udp_length >> 8) as u8, udp_length as u8, 0, 0, 0, 17];
+            let udp_checksum = udp_checksum(&[
+                &source.octets(),
+                &destination.octets(),
+                &pseudo_header,
+                &udp,
+            ]);
+            udp[6..8].copy_from_slice(&udp_checksum);
+
+            let mut header = Vec::with_capacity(40);
+            header.extend_from_slice(&[0x60, 0, 0, 0]);
+            header.extend_from_slice(&udp_length.to_be_bytes());
+            header.extend_from_slice(&[17, 64]);
+            header.extend_from_slice(&source.octets());
+            header.extend_from_slice(&destination.octets());
+
+            header
+        }
+    };
+    packet.extend_from_slice(&udp);
+
+    let length = packet.len() as u32;
+    pcap.extend_from_slice(&(timestamp.timestamp() as u32).to_le_bytes());
+    pcap.extend_from_slice(&timestamp.timestamp_subsec_micros().to_le_bytes());
+    pcap.extend_from_slice(&length.to_le_bytes());
+    pcap.extend_from_slice(&length.to_le_bytes());
+    pcap.extend_from_slice(&packet);
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::dns::capture::{checksum, QueryCapture};
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn internet_checksum() {
+        // A commonly used worked example of an IPv4 header checksum.
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(checksum(&[&header]), 0xb861);
+
+        let mut header = header;
+        header[10..12].copy_from_slice(&0xb861_u16.to_be_bytes());
+        assert_eq!(checksum(&[&header]), 0);
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn capture() {
+        let capture = QueryCapture::default();
+        let client: SocketAddr = "10.10.1.5:41234".parse().unwrap();
+
+        capture.record(client, 53, vec![1, 2, 3], vec![4, 5, 6, 7]);
+        assert!(!capture.is_enabled());
+        assert_eq!(capture.pcap().len(), 24);
+
+        capture.set_limit(Some(2));
+        assert!(capture.is_enabled());
+
+        for i in 0..3 {
+            capture.record(client, 53, vec![i; 12], vec![i; 20]);
+        }
+
+        let pcap = capture.pcap();
+        assert_eq!(&pcap[0..4], &0xa1b2c3d4_u32.to_le_bytes());
+        assert_eq!(&pcap[20..24], &101_u32.to_le_bytes());
+
+        // Two queries each with a request and a response, the oldest was dropped.
+        let mut offset = 24;
+        let mut payloads = Vec::new();
+        while offset < pcap.len() {
+            let length = u32::from_le_bytes(pcap[offset + 8..offset + 12].try_into().unwrap());
+            let packet = &pcap[offset + 16..offset + 16 + length as usize];
+            assert_eq!(packet[0], 0x45);
+            assert_eq!(checksum(&[&packet[0..20]]), 0);
+            payloads.push(packet[28..].to_vec());
+            offset += 16 + length as usize;
+        }
+        assert_eq!(offset, pcap.len());
+        assert_eq!(
+            payloads,
+            vec![vec![1; 12], vec![1; 20], vec![2; 12], vec![2; 20]]
+        );
+
+        capture.set_limit(None);
+        assert!(!capture.is_enabled());
+        assert_eq!(capture.pcap().len(), 24);
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{sync::watch, time::timeout};
+
+/// How long shutdown waits for queries already being handled by default.
+pub(super) const DEFAULT_GRACE_MS: u64 = 5000;
+
+/// Counts the queries being handled so that shutting down can wait for them to
+/// complete rather than aborting them.
+#[derive(Clone, Debug)]
+pub(crate) struct QueryTracker {
+    draining: Arc<AtomicBool>,
+    in_flight: Arc<watch::Sender<usize>>,
+}
+
+impl Default for QueryTracker {
+    fn default() -> Self {
+        Self {
+            draining: Default::default(),
+            in_flight: Arc::new(watch::Sender::new(0)),
+        }
+    }
+}
+
+/// Held for as long as a query is being handled.
+pub(super) struct InFlight {
+    in_flight: Arc<watch::Sender<usize>>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.in_flight.send_modify(|count| *count -= 1);
+    }
+}
+
+impl QueryTracker {
+    /// Starts tracking a query. Returns `None` once draining has begun in which
+    /// case the query should be dropped.
+    pub(super) fn start(&self) -> Option<InFlight> {
+        if self.draining.load(Ordering::Acquire) {
+            return None;
+        }
+
+        self.in_flight.send_modify(|count| *count += 1);
+
+        Some(InFlight {
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    /// Stops accepting new queries and waits up to `grace` for those already
+    /// being handled to complete. Returns how many were still outstanding.
+    pub(super) async fn drain(&self, grace: Duration) -> usize {
+        self.draining.store(true, Ordering::Release);
+
+        let mut receiver = self.in_flight.subscribe();
+        match timeout(grace, receiver.wait_for(|count| *count == 0)).await {
+            Ok(_) => 0,
+            Err(_) => *self.in_flight.borrow(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::sleep;
+
+    use crate::dns::drain::QueryTracker;
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn drain() {
+        let tracker = QueryTracker::default();
+
+        let first = tracker.start().unwrap();
+        let second = tracker.start().unwrap();
+        drop(first);
+
+        let handle = tokio::spawn(async move {
+            sleep(Duration::from_millis(50)).await;
+            drop(second);
+        });
+
+        assert_eq!(tracker.drain(Duration::from_secs(5)).await, 0);
+        assert!(tracker.start().is_none());
+        handle.await.unwrap();
+
+        let tracker = QueryTracker::default();
+        let _stuck = tracker.start().unwrap();
+        assert_eq!(tracker.drain(Duration::from_millis(50)).await, 1);
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use hickory_server::proto::{
+    op::Edns,
+    rr::rdata::opt::{EdnsCode, EdnsOption},
+};
+
+/// A private use EDNS option added to forwarded queries listing the LocalNS
+/// servers that the query has passed through.
+const FORWARDED_OPTION: u16 = 65401;
+/// Queries that have already been forwarded by this many LocalNS servers are
+/// refused.
+const MAX_FORWARD_HOPS: usize = 4;
+/// How many looped queries are remembered until the lookup that sent them sees
+/// its response.
+const LOOP_LIMIT: usize = 100;
+
+/// The LocalNS servers that a query has been forwarded through.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct ForwardPath {
+    /// Identifies the lookup that first forwarded the query.
+    nonce: u64,
+    servers: Vec<u64>,
+}
+
+impl ForwardPath {
+    /// The path from a query received by the server, if it was forwarded by
+    /// another LocalNS server.
+    pub(super) fn from_edns(edns: &Edns) -> Option<Self> {
+        let Some(EdnsOption::Unknown(_, data)) = edns.option(EdnsCode::from(FORWARDED_OPTION))
+        else {
+            return None;
+        };
+
+        if data.len() < 8 || data.len() % 8 != 0 {
+            return None;
+        }
+
+        let mut values = data
+            .chunks_exact(8)
+            .map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap()));
+
+        Some(Self {
+            nonce: values.next()?,
+            servers: values.collect(),
+        })
+    }
+
+    pub(super) fn option(&self) -> EdnsOption {
+        let data = [self.nonce]
+            .iter()
+            .chain(self.servers.iter())
+            .flat_map(|value| value.to_be_bytes())
+            .collect();
+
+        EdnsOption::Unknown(FORWARDED_OPTION, data)
+    }
+}
+
+/// Why a forwarded query was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Refusal {
+    /// The query was forwarded by this server so answering would loop.
+    Loop,
+    /// The query passed through too many other servers.
+    TooManyHops,
+}
+
+/// Detects queries that this server forwarded coming back to it, directly or
+/// through other LocalNS servers.
+#[derive(Clone, Debug)]
+pub(crate) struct ForwardingLoops {
+    server: u64,
+    /// The lookups whose queries came back.
+    looped: Arc<Mutex<HashSet<u64>>>,
+}
+
+impl Default for ForwardingLoops {
+    fn default() -> Self {
+        Self {
+            server: rand::random(),
+            looped: Default::default(),
+        }
+    }
+}
+
+impl ForwardingLoops {
+    /// The path to send to an upstream, continuing the path of the query being
+    /// answered if it was forwarded to this server.
+    pub(super) fn forward(&self, received: Option<&ForwardPath>) -> ForwardPath {
+        let mut path = received.cloned().unwrap_or_else(|| ForwardPath {
+            nonce: rand::random(),
+            servers: Vec::new(),
+        });
+
+        path.servers.push(self.server);
+        path
+    }
+
+    /// Checks a query received from another server, noting the lookup that sent
+    /// it if it came from here.
+    pub(super) fn check(&self, received: &ForwardPath) -> Option<Refusal> {
+        if received.servers.contains(&self.server) {
+            let mut looped = self.looped.lock().unwrap();
+            if looped.len() >= LOOP_LIMIT {
+                looped.clear();
+            }
+            looped.insert(received.nonce);
+
+            return Some(Refusal::Loop);
+        }
+
+        if received.servers.len() >= MAX_FORWARD_HOPS {
+            return Some(Refusal::TooManyHops);
+        }
+
+        None
+    }
+
+    /// Whether the query sent by a lookup came back to this server.
+    pub(super) fn take_loop(&self, path: &ForwardPath) -> bool {
+        self.looped.lock().unwrap().remove(&path.nonce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_server::proto::op::Edns;
+
+    use crate::dns::forwarding::{ForwardPath, ForwardingLoops, Refusal};
+
+    fn round_trip(path: &ForwardPath) -> ForwardPath {
+        let mut edns = Edns::new();
+        edns.options_mut().insert(path.option());
+        ForwardPath::from_edns(&edns).unwrap()
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn loops() {
+        let first = ForwardingLoops::default();
+        let second = ForwardingLoops::default();
+
+        assert!(ForwardPath::from_edns(&Edns::new()).is_none());
+
+        // first -> second -> first
+        let path = round_trip(&first.forward(None));
+        assert_eq!(second.check(&path), None);
+        let path = round_trip(&second.forward(Some(&path)));
+        assert_eq!(path.servers.len(), 2);
+
+        assert_eq!(first.check(&path), Some(Refusal::Loop));
+        assert!(!second.take_loop(&path));
+        assert!(first.take_loop(&path));
+        assert!(!first.take_loop(&path));
+
+        let mut path = first.forward(None);
+        for _ in 0..3 {
+            path = ForwardingLoops::default().forward(Some(&path));
+        }
+        assert_eq!(second.check(&path), Some(Refusal::TooManyHops));
+    }
+}
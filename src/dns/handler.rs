@@ -1,24 +1,257 @@
-use hickory_client::op::{Edns, Header, MessageType, OpCode, ResponseCode};
+use std::{io, sync::Arc, time::Instant};
+
+use anyhow::bail;
+use chrono::Utc;
+use hickory_client::{
+    op::{Edns, Header, Message, MessageType, OpCode, ResponseCode},
+    rr::dnssec::{
+        rdata::tsig::{make_tsig_record, TSIG},
+        tsig::TSigner,
+    },
+};
 use hickory_server::{
     authority::MessageResponseBuilder,
-    server::{Request, RequestHandler, ResponseHandler, ResponseInfo},
+    proto::{
+        rr::{self, RecordType},
+        serialize::binary::{BinEncodable, BinEncoder},
+    },
+    server::{Protocol, Request, RequestHandler, ResponseHandler, ResponseInfo},
 };
 use tracing::instrument;
 
 use crate::{
-    config::Zones,
-    dns::{query::QueryState, ServerState},
+    config::{ZoneConfigProvider, Zones},
+    dns::{
+        drain::QueryTracker,
+        forwarding::{ForwardPath, Refusal},
+        journal::soa_serial,
+        query::QueryState,
+        rate_limit::RateLimiter,
+        upstream::TsigKey,
+        ServerState,
+    },
+    util::Subnet,
+    Error,
 };
 
+/// How many records are sent in each message of a zone transfer.
+const TRANSFER_CHUNK: usize = 100;
+
+/// The largest UDP response that clients which don't use EDNS accept.
+const UDP_PAYLOAD: usize = 512;
+
+/// The largest response that the client accepts. Responses over TCP can be
+/// any size.
+fn payload_limit(request: &Request) -> Option<usize> {
+    if !matches!(request.request_info().protocol, Protocol::Udp) {
+        return None;
+    }
+
+    Some(request.edns().map_or(UDP_PAYLOAD, |edns| {
+        usize::from(edns.max_payload()).max(UDP_PAYLOAD)
+    }))
+}
+
 fn serve_failed() -> ResponseInfo {
     let mut header = Header::new();
     header.set_response_code(ResponseCode::ServFail);
     header.into()
 }
 
+/// Signs the messages of a zone transfer requested with a TSIG key. Each MAC
+/// covers the one before it, starting with the request's.
+struct TransferSigner {
+    signer: TSigner,
+    mac: Vec<u8>,
+    time: u64,
+    first: bool,
+}
+
+impl TransferSigner {
+    /// Checks the request's signature, unsigned requests give `None`.
+    fn verify(request: &Request, keys: &[TsigKey]) -> Result<Option<Self>, Error> {
+        let Some(record) = request
+            .sig0()
+            .last()
+            .filter(|record| record.record_type() == RecordType::TSIG)
+        else {
+            return Ok(None);
+        };
+
+        let Some(key) = keys.iter().find(|key| key.name() == record.name()) else {
+            bail!("Unknown TSIG key {}", record.name());
+        };
+
+        let signer = key.signer()?;
+        let (mac, valid, _) = signer.verify_message_byte(None, &request.to_bytes()?, true)?;
+
+        let time = Utc::now().timestamp().try_into()?;
+        if !valid.contains(&time) {
+            bail!("TSIG signature is outside of the allowed time");
+        }
+
+        Ok(Some(Self {
+            signer,
+            mac,
+            time,
+            first: true,
+        }))
+    }
+
+    /// Creates the TSIG record to add to the end of an encoded message.
+    fn sign(&mut self, id: u16, message: &[u8]) -> Result<rr::Record, Error> {
+        let tsig = TSIG::new(
+            self.signer.algorithm().clone(),
+            self.time,
+            self.signer.fudge(),
+            Vec::new(),
+            id,
+            0,
+            Vec::new(),
+        );
+
+        let mut tbs = Vec::new();
+        let mut encoder = BinEncoder::new(&mut tbs);
+        encoder.emit_u16(self.mac.len().try_into()?)?;
+        encoder.emit_vec(&self.mac)?;
+        encoder.emit_vec(message)?;
+        if self.first {
+            tsig.emit_tsig_for_mac(&mut encoder, self.signer.signer_name())?;
+        } else {
+            // Later messages only include the timers.
+            encoder.emit_u16((self.time >> 32) as u16)?;
+            encoder.emit_u32(self.time as u32)?;
+            encoder.emit_u16(self.signer.fudge())?;
+        }
+
+        self.mac = self.signer.sign(&tbs)?;
+        self.first = false;
+
+        Ok(make_tsig_record(
+            self.signer.signer_name().clone(),
+            tsig.set_mac(self.mac.clone()),
+        ))
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct Handler {
     pub server_state: ServerState<Zones>,
+    pub port: u16,
+    pub warmup_ttl: Option<u32>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub queries: QueryTracker,
+    pub transfer_clients: Vec<Subnet>,
+    pub transfer_keys: Vec<TsigKey>,
+}
+
+impl Handler {
+    /// Sends a zone transfer over as many messages as needed. Transfers are only
+    /// allowed over TCP from permitted clients, signed with a TSIG key when any
+    /// are configured.
+    async fn transfer<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        mut response_handle: R,
+    ) -> io::Result<ResponseInfo> {
+        let query = request.query().original();
+        let client = request.request_info().src.ip();
+
+        let mut signer = match TransferSigner::verify(request, &self.transfer_keys) {
+            Ok(signer) => signer,
+            Err(e) => {
+                tracing::warn!(
+                    %client,
+                    zone = %query.name(),
+                    error = %e,
+                    "Refused zone transfer with a bad signature"
+                );
+                let builder = MessageResponseBuilder::from_message_request(request);
+                return response_handle
+                    .send_response(builder.error_msg(request.header(), ResponseCode::NotAuth))
+                    .await;
+            }
+        };
+
+        let allowed = matches!(request.request_info().protocol, Protocol::Tcp)
+            && self
+                .transfer_clients
+                .iter()
+                .any(|subnet| subnet.contains(&client))
+            && (self.transfer_keys.is_empty() || signer.is_some());
+
+        let records = if allowed {
+            // An incremental transfer gives the serial the client already has.
+            let serial = if query.query_type() == RecordType::IXFR {
+                request.name_servers().iter().find_map(soa_serial)
+            } else {
+                None
+            };
+
+            self.server_state
+                .transfer(&query.name().clone().into(), serial)
+                .await
+        } else {
+            tracing::warn!(%client, zone = %query.name(), "Refused zone transfer");
+            None
+        };
+
+        let Some(records) = records else {
+            let builder = MessageResponseBuilder::from_message_request(request);
+            return response_handle
+                .send_response(builder.error_msg(request.header(), ResponseCode::Refused))
+                .await;
+        };
+
+        tracing::debug!(%client, zone = %query.name(), records = records.len(), "Transferring zone");
+
+        let mut header = Header::response_from_request(request.header());
+        header.set_authoritative(true);
+
+        let none: &[rr::Record] = &[];
+        let mut info = serve_failed();
+        for chunk in records.chunks(TRANSFER_CHUNK) {
+            // The signature covers the message exactly as it is encoded without it.
+            let tsig = signer.as_mut().map(|signer| -> Result<rr::Record, Error> {
+                let mut message = Vec::new();
+                MessageResponseBuilder::from_message_request(request)
+                    .build(header, chunk, none, none, none)
+                    .destructive_emit(&mut BinEncoder::new(&mut message))?;
+
+                signer.sign(header.id(), &message)
+            });
+
+            let tsig = match tsig.transpose() {
+                Ok(tsig) => tsig,
+                Err(e) => {
+                    tracing::error!(error = %e, "Failed to sign zone transfer");
+                    return Ok(serve_failed());
+                }
+            };
+
+            let builder = MessageResponseBuilder::from_message_request(request);
+            info = response_handle
+                .send_response(builder.build(header, chunk, none, none, tsig.iter()))
+                .await?;
+        }
+
+        Ok(info)
+    }
+
+    /// Keeps the wire format of a query and the response sent for it.
+    fn capture(&self, request: &Request, response: Message) {
+        match (request.to_bytes(), response.to_vec()) {
+            (Ok(query), Ok(response)) => self.server_state.capture.record(
+                request.request_info().src,
+                self.port,
+                query,
+                response,
+            ),
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::warn!(error = %e, "Failed to encode query for capture");
+            }
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -35,7 +268,21 @@ impl RequestHandler for Handler {
         request: &Request,
         mut response_handle: R,
     ) -> ResponseInfo {
+        // While shutting down new queries are dropped, clients will retry.
+        let Some(_in_flight) = self.queries.start() else {
+            return serve_failed();
+        };
+
+        // Shed UDP load as early as possible. Spoofed source addresses make UDP
+        // the likely target for abuse and clients will simply retry.
+        if let Some(ref rate_limiter) = self.rate_limiter {
+            if matches!(request.request_info().protocol, Protocol::Udp) && !rate_limiter.accept() {
+                return serve_failed();
+            }
+        }
+
         let mut builder = MessageResponseBuilder::from_message_request(request);
+        let mut response_edns = None;
 
         // check if it's edns
         if let Some(req_edns) = request.edns() {
@@ -47,7 +294,8 @@ impl RequestHandler for Handler {
             resp_edns.set_dnssec_ok(false);
             resp_edns.set_max_payload(req_edns.max_payload().max(512));
             resp_edns.set_version(our_version);
-            builder.edns(resp_edns);
+            builder.edns(resp_edns.clone());
+            response_edns = Some(resp_edns);
 
             if req_edns.version() > our_version {
                 tracing::warn!(
@@ -72,19 +320,88 @@ impl RequestHandler for Handler {
             }
         }
 
+        let forwarded = request.edns().and_then(ForwardPath::from_edns);
+        if let Some(refusal) = forwarded
+            .as_ref()
+            .and_then(|path| self.server_state.upstream_health.loops().check(path))
+        {
+            match refusal {
+                Refusal::Loop => tracing::warn!(
+                    name = %request.query().name(),
+                    "Refused a query that this server forwarded, an upstream forwards back to LocalNS"
+                ),
+                Refusal::TooManyHops => tracing::warn!(
+                    name = %request.query().name(),
+                    "Refused a query forwarded through too many LocalNS servers"
+                ),
+            }
+
+            let result = response_handle
+                .send_response(builder.error_msg(request.header(), ResponseCode::Refused))
+                .await;
+
+            return match result {
+                Err(e) => {
+                    tracing::error!(error = %e, "Request error");
+                    serve_failed()
+                }
+                Ok(info) => info,
+            };
+        }
+
         let result = match request.message_type() {
             MessageType::Query => match request.op_code() {
+                OpCode::Query
+                    if matches!(
+                        request.query().query_type(),
+                        RecordType::AXFR | RecordType::IXFR
+                    ) =>
+                {
+                    self.transfer(request, response_handle).await
+                }
                 OpCode::Query => {
+                    let start = Instant::now();
                     let server_state = self.server_state.locked().await;
                     let mut query_state = QueryState::new(
                         request.query().original().clone(),
                         request.recursion_desired(),
                     );
+                    query_state.client = Some(request.request_info().src.ip());
+                    query_state.forwarded = forwarded;
                     server_state.perform_query(&mut query_state).await;
+                    self.server_state
+                        .recent
+                        .record(request.request_info().src.ip(), &query_state);
+
+                    if let Some(ttl) = self.warmup_ttl {
+                        if self.server_state.is_warming_up() {
+                            server_state.apply_warmup(&mut query_state, ttl);
+                        }
+                    }
+
+                    let zone = server_state
+                        .zones
+                        .zone_config(&query_state.query.name().clone().into())
+                        .origin;
+                    self.server_state
+                        .stats
+                        .record(zone, &query_state, start.elapsed());
+
+                    if let Some(limit) = payload_limit(request) {
+                        query_state.fit_payload(request.header(), response_edns.as_ref(), limit);
+                    }
+
+                    let header = query_state.header(request.header());
+                    if self.server_state.capture.is_enabled() {
+                        self.capture(
+                            request,
+                            query_state.message(request.header(), response_edns.as_ref()),
+                        );
+                    }
 
                     response_handle
                         .send_response(builder.build(
-                            query_state.header(request.header()),
+                            header,
                             query_state.answers(),
                             query_state.name_servers(),
                             query_state.soa(),
@@ -116,3 +433,209 @@ impl RequestHandler for Handler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use futures::StreamExt;
+    use hickory_client::{
+        client::AsyncClient,
+        op::{DnsResponse, Query, ResponseCode},
+        proto::{
+            error::ProtoError,
+            iocompat::AsyncIoTokioAsStd,
+            xfer::{DnsHandle, DnsMultiplexer, DnsRequestOptions},
+        },
+        rr::RecordType,
+        tcp::TcpClientStream,
+        udp::UdpClientStream,
+    };
+    use hickory_server::ServerFuture;
+    use tempfile::TempDir;
+    use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+    use crate::{
+        config::{Config, Zones},
+        dns::{
+            drain::QueryTracker, handler::Handler, upstream::TsigKey, RData, Record, RecordSet,
+            ServerState,
+        },
+        test::{fqdn, name, write_file},
+    };
+
+    async fn lookup(client: AsyncClient, host: &str, use_edns: bool) -> DnsResponse {
+        let mut options = DnsRequestOptions::default();
+        options.use_edns = use_edns;
+        options.recursion_desired = false;
+
+        client
+            .lookup(Query::query(name(host), RecordType::A), options)
+            .next()
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn udp_client(address: SocketAddr) -> AsyncClient {
+        let stream = UdpClientStream::<UdpSocket>::new(address);
+        let (client, bg) = AsyncClient::connect(stream).await.unwrap();
+        tokio::spawn(bg);
+        client
+    }
+
+    async fn tcp_client(address: SocketAddr) -> AsyncClient {
+        let (stream, sender) = TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::new(address);
+        let (client, bg) = AsyncClient::new(stream, sender, None).await.unwrap();
+        tokio::spawn(bg);
+        client
+    }
+
+    fn tsig_key(secret: &str) -> TsigKey {
+        serde_yaml::from_str(&format!("name: transfer-key\nsecret: {secret}")).unwrap()
+    }
+
+    async fn transfer(
+        address: SocketAddr,
+        key: Option<&TsigKey>,
+    ) -> Result<DnsResponse, ProtoError> {
+        let (stream, sender) = TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::new(address);
+        let signer = key.map(|key| Arc::new(key.signer().unwrap()));
+        let multiplexer = DnsMultiplexer::new(stream, sender, signer);
+        let (client, bg) = AsyncClient::connect(multiplexer).await.unwrap();
+        tokio::spawn(bg);
+
+        client
+            .lookup(
+                Query::query(name("home.local."), RecordType::AXFR),
+                DnsRequestOptions::default(),
+            )
+            .next()
+            .await
+            .unwrap()
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn signed_transfers() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+zones:
+  home.local: {}
+"#,
+        )
+        .await;
+        let config = Config::from_file(&config_file).unwrap();
+
+        let records = RecordSet::builder()
+            .a("www.home.local.", "10.10.1.5")
+            .build();
+
+        let key = tsig_key("c2VjcmV0IGtleSBmb3IgdGVzdGluZw==");
+        let handler = Handler {
+            server_state: ServerState::new(records, config.zones),
+            port: 0,
+            warmup_ttl: None,
+            rate_limiter: None,
+            queries: QueryTracker::default(),
+            transfer_clients: vec!["127.0.0.0/8".parse().unwrap()],
+            transfer_keys: vec![key.clone()],
+        };
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let mut server = ServerFuture::new(handler);
+        server.register_listener(listener, Duration::from_millis(500));
+
+        // The response is checked against the key by the client.
+        let response = transfer(address, Some(&key)).await.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        let types: Vec<RecordType> = response
+            .answers()
+            .iter()
+            .map(|record| record.record_type())
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                RecordType::SOA,
+                RecordType::NS,
+                RecordType::A,
+                RecordType::SOA
+            ]
+        );
+
+        let response = transfer(address, None).await.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+
+        // The unsigned refusal can't be verified by the client.
+        let other_key = tsig_key("b3RoZXIgc2VjcmV0");
+        assert!(transfer(address, Some(&other_key)).await.is_err());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn large_answers() {
+        let mut records = RecordSet::new();
+        for i in 1..=50 {
+            records.insert(Record::new(
+                fqdn("pool.home.local"),
+                RData::A(Ipv4Addr::new(10, 10, 1, i)),
+            ));
+        }
+        for i in 1..=200 {
+            records.insert(Record::new(
+                fqdn("big.home.local"),
+                RData::A(Ipv4Addr::new(10, 10, 2, i)),
+            ));
+        }
+
+        let handler = Handler {
+            server_state: ServerState::new(records, Zones::default()),
+            port: 0,
+            warmup_ttl: None,
+            rate_limiter: None,
+            queries: QueryTracker::default(),
+            transfer_clients: Vec::new(),
+            transfer_keys: Vec::new(),
+        };
+
+        let socket = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let listener = TcpListener::bind(address).await.unwrap();
+
+        let mut server = ServerFuture::new(handler);
+        server.register_socket(socket);
+        server.register_listener(listener, Duration::from_millis(500));
+
+        // Without EDNS UDP responses are limited to 512 bytes.
+        let response = lookup(udp_client(address).await, "pool.home.local.", false).await;
+        assert!(response.truncated());
+        assert!(response.answers().is_empty());
+
+        let response = lookup(udp_client(address).await, "pool.home.local.", true).await;
+        assert!(!response.truncated());
+        assert_eq!(response.answers().len(), 50);
+
+        let response = lookup(udp_client(address).await, "big.home.local.", true).await;
+        assert!(response.truncated());
+        assert!(response.answers().is_empty());
+
+        // The full response is sent over TCP.
+        let response = lookup(tcp_client(address).await, "pool.home.local.", false).await;
+        assert!(!response.truncated());
+        assert_eq!(response.answers().len(), 50);
+
+        let response = lookup(tcp_client(address).await, "big.home.local.", true).await;
+        assert!(!response.truncated());
+        assert_eq!(response.answers().len(), 200);
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+#[cfg(feature = "quic")]
+use hickory_client::client::AsyncClient;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::dns::forwarding::ForwardingLoops;
+#[cfg(feature = "quic")]
+use crate::dns::upstream::Connections;
+
+/// How many lookups in a row must fail before an upstream is considered down.
+const FAILURE_THRESHOLD: u32 = 3;
+/// How long an unhealthy upstream is skipped for by default.
+const DEFAULT_COOLDOWN_MS: u64 = 30000;
+/// How much each lookup contributes to the average latency.
+const LATENCY_WEIGHT: f64 = 0.2;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct UpstreamStatus {
+    pub(crate) upstream: String,
+    pub(crate) healthy: bool,
+    /// When the upstream last became healthy or unhealthy.
+    pub(crate) since: DateTime<Utc>,
+    pub(crate) consecutive_failures: u32,
+    pub(crate) last_error: Option<String>,
+    #[serde(default)]
+    pub(crate) successes: u64,
+    #[serde(default)]
+    pub(crate) failures: u64,
+    /// A moving average of how long successful lookups took.
+    #[serde(default)]
+    pub(crate) latency_ms: Option<f64>,
+    /// While unhealthy the upstream is skipped until this time.
+    #[serde(default)]
+    pub(crate) retry_at: Option<DateTime<Utc>>,
+}
+
+impl UpstreamStatus {
+    fn new(upstream: &str, now: DateTime<Utc>) -> Self {
+        Self {
+            upstream: upstream.to_owned(),
+            healthy: true,
+            since: now,
+            consecutive_failures: 0,
+            last_error: None,
+            successes: 0,
+            failures: 0,
+            latency_ms: None,
+            retry_at: None,
+        }
+    }
+
+    fn is_available(&self, now: DateTime<Utc>) -> bool {
+        self.healthy || self.retry_at.is_none_or(|retry_at| now >= retry_at)
+    }
+}
+
+/// Sent to the webhook when an upstream becomes healthy or unhealthy.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HealthEvent {
+    pub(crate) upstream: String,
+    pub(crate) healthy: bool,
+    /// The most recent error from the upstream.
+    pub(crate) reason: Option<String>,
+    /// How long the upstream was in its previous state.
+    pub(crate) previous_duration_secs: i64,
+    pub(crate) timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    upstreams: HashMap<String, UpstreamStatus>,
+    webhook: Option<Url>,
+    cooldown_ms: Option<u64>,
+}
+
+impl HealthState {
+    fn cooldown(&self) -> TimeDelta {
+        TimeDelta::milliseconds(self.cooldown_ms.unwrap_or(DEFAULT_COOLDOWN_MS) as i64)
+    }
+}
+
+/// Tracks whether upstream servers are answering based on the outcome of the
+/// lookups forwarded to them. Unhealthy upstreams are skipped until a cooldown
+/// has passed, then the next lookup tries them again.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct UpstreamHealth {
+    state: Arc<Mutex<HealthState>>,
+    client: Client,
+    loops: ForwardingLoops,
+    #[cfg(feature = "quic")]
+    connections: Connections<AsyncClient>,
+}
+
+impl UpstreamHealth {
+    pub(super) fn set_webhook(&self, webhook: Option<Url>) {
+        self.state.lock().unwrap().webhook = webhook;
+    }
+
+    pub(super) fn set_cooldown(&self, cooldown_ms: Option<u64>) {
+        self.state.lock().unwrap().cooldown_ms = cooldown_ms;
+    }
+
+    pub(super) fn loops(&self) -> &ForwardingLoops {
+        &self.loops
+    }
+
+    #[cfg(feature = "quic")]
+    pub(super) fn connections(&self) -> &Connections<AsyncClient> {
+        &self.connections
+    }
+
+    /// Whether lookups should be sent to the upstream.
+    pub(super) fn is_available(&self, upstream: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .upstreams
+            .get(upstream)
+            .is_none_or(|status| status.is_available(Utc::now()))
+    }
+
+    pub(super) fn success(&self, upstream: &str, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let now = Utc::now();
+
+        let status = state
+            .upstreams
+            .entry(upstream.to_owned())
+            .or_insert_with(|| UpstreamStatus::new(upstream, now));
+        status.consecutive_failures = 0;
+        status.successes += 1;
+        status.retry_at = None;
+
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        status.latency_ms = Some(match status.latency_ms {
+            Some(average) => average + (latency_ms - average) * LATENCY_WEIGHT,
+            None => latency_ms,
+        });
+
+        if status.healthy {
+            return;
+        }
+
+        let down_for = now - status.since;
+        status.healthy = true;
+        status.since = now;
+
+        tracing::warn!(
+            upstream,
+            reason = ?status.last_error,
+            down_for_secs = down_for.num_seconds(),
+            "Upstream DNS server has recovered"
+        );
+
+        let event = HealthEvent {
+            upstream: upstream.to_owned(),
+            healthy: true,
+            reason: status.last_error.clone(),
+            previous_duration_secs: down_for.num_seconds(),
+            timestamp: now,
+        };
+        self.notify(state.webhook.clone(), event);
+    }
+
+    pub(super) fn failure(&self, upstream: &str, reason: String) {
+        let mut state = self.state.lock().unwrap();
+        let now = Utc::now();
+        let cooldown = state.cooldown();
+
+        let status = state
+            .upstreams
+            .entry(upstream.to_owned())
+            .or_insert_with(|| UpstreamStatus::new(upstream, now));
+        status.consecutive_failures += 1;
+        status.failures += 1;
+        status.last_error = Some(reason.clone());
+
+        if !status.healthy {
+            // A retry after the cooldown failed so skip it for another.
+            status.retry_at = Some(now + cooldown);
+            return;
+        }
+
+        if status.consecutive_failures < FAILURE_THRESHOLD {
+            return;
+        }
+
+        let up_for = now - status.since;
+        status.healthy = false;
+        status.since = now;
+        status.retry_at = Some(now + cooldown);
+
+        tracing::warn!(
+            upstream,
+            reason = %reason,
+            up_for_secs = up_for.num_seconds(),
+            skip_for_secs = cooldown.num_seconds(),
+            "Upstream DNS server is down"
+        );
+
+        let event = HealthEvent {
+            upstream: upstream.to_owned(),
+            healthy: false,
+            reason: Some(reason),
+            previous_duration_secs: up_for.num_seconds(),
+            timestamp: now,
+        };
+        self.notify(state.webhook.clone(), event);
+    }
+
+    fn notify(&self, webhook: Option<Url>, event: HealthEvent) {
+        let Some(webhook) = webhook else {
+            return;
+        };
+
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let result = client
+                .post(webhook.clone())
+                .json(&event)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            if let Err(e) = result {
+                tracing::warn!(error = %e, %webhook, "Failed to send upstream health event");
+            }
+        });
+    }
+
+    /// The state of every upstream that has been used, sorted by name.
+    pub(crate) fn statuses(&self) -> Vec<UpstreamStatus> {
+        let mut statuses: Vec<UpstreamStatus> = self
+            .state
+            .lock()
+            .unwrap()
+            .upstreams
+            .values()
+            .cloned()
+            .collect();
+        statuses.sort_by(|a, b| a.upstream.cmp(&b.upstream));
+        statuses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread::sleep, time::Duration};
+
+    use crate::dns::health::UpstreamHealth;
+
+    const LATENCY: Duration = Duration::from_millis(20);
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn transitions() {
+        let health = UpstreamHealth::default();
+
+        health.success("10.0.0.1", LATENCY);
+        health.failure("10.0.0.1", "timed out".to_owned());
+        health.failure("10.0.0.1", "timed out".to_owned());
+
+        let status = health.statuses().remove(0);
+        assert!(status.healthy);
+        assert_eq!(status.consecutive_failures, 2);
+        assert!(!logs_contain("Upstream DNS server is down"));
+
+        health.failure("10.0.0.1", "connection refused".to_owned());
+
+        let status = health.statuses().remove(0);
+        assert!(!status.healthy);
+        assert_eq!(status.last_error.as_deref(), Some("connection refused"));
+        assert!(logs_contain("Upstream DNS server is down"));
+        assert!(logs_contain("connection refused"));
+
+        health.failure("10.0.0.1", "timed out".to_owned());
+        health.success("10.0.0.1", LATENCY);
+
+        let status = health.statuses().remove(0);
+        assert!(status.healthy);
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(status.successes, 2);
+        assert_eq!(status.failures, 4);
+        assert!(logs_contain("Upstream DNS server has recovered"));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn circuit_breaking() {
+        let health = UpstreamHealth::default();
+        health.set_cooldown(Some(50));
+
+        assert!(health.is_available("10.0.0.1"));
+
+        health.success("10.0.0.1", Duration::from_millis(10));
+        health.success("10.0.0.1", Duration::from_millis(60));
+        let latency = health.statuses()[0].latency_ms.unwrap();
+        assert!((latency - 20.0).abs() < 0.001);
+
+        for _ in 0..3 {
+            health.failure("10.0.0.1", "timed out".to_owned());
+        }
+        assert!(!health.is_available("10.0.0.1"));
+        assert!(health.statuses()[0].retry_at.is_some());
+
+        // Tried again after the cooldown and skipped again if it still fails.
+        sleep(Duration::from_millis(60));
+        assert!(health.is_available("10.0.0.1"));
+        health.failure("10.0.0.1", "timed out".to_owned());
+        assert!(!health.is_available("10.0.0.1"));
+
+        sleep(Duration::from_millis(60));
+        health.success("10.0.0.1", LATENCY);
+        assert!(health.is_available("10.0.0.1"));
+        assert!(health.statuses()[0].retry_at.is_none());
+    }
+}
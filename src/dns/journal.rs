@@ -0,0 +1,171 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use hickory_server::proto::rr;
+
+use crate::{
+    config::ZoneConfigProvider,
+    dns::{Fqdn, LockedServerState},
+};
+
+/// How many changes are kept for each zone. Secondaries further behind than
+/// this receive the full zone.
+const JOURNAL_LIMIT: usize = 50;
+
+pub(super) fn soa_serial(soa: &rr::Record) -> Option<u32> {
+    match soa.data() {
+        Some(rr::RData::SOA(soa)) => Some(soa.serial()),
+        _ => None,
+    }
+}
+
+/// The differences between two versions of a zone.
+#[derive(Debug)]
+struct Change {
+    from: rr::Record,
+    to: rr::Record,
+    removed: Vec<rr::Record>,
+    added: Vec<rr::Record>,
+}
+
+#[derive(Debug)]
+struct ZoneHistory {
+    soa: rr::Record,
+    serial: u32,
+    records: BTreeSet<rr::Record>,
+    /// The most recent changes, oldest first.
+    changes: VecDeque<Change>,
+}
+
+impl ZoneHistory {
+    fn new(soa: rr::Record, serial: u32, records: BTreeSet<rr::Record>) -> Self {
+        Self {
+            soa,
+            serial,
+            records,
+            changes: VecDeque::new(),
+        }
+    }
+
+    /// The changes needed to bring a secondary at `serial` up to date, if they
+    /// are all still known.
+    fn changes_since(&self, serial: u32) -> Option<impl Iterator<Item = &Change>> {
+        let start = self
+            .changes
+            .iter()
+            .position(|change| soa_serial(&change.from) == Some(serial))?;
+
+        Some(self.changes.iter().skip(start))
+    }
+}
+
+/// Splits the records of a zone into its SOA record, the serial and the rest.
+fn split_zone(mut records: Vec<rr::Record>) -> Option<(rr::Record, u32, BTreeSet<rr::Record>)> {
+    if records.is_empty() {
+        return None;
+    }
+
+    let soa = records.remove(0);
+    let serial = soa_serial(&soa)?;
+
+    Some((soa, serial, records.into_iter().collect()))
+}
+
+/// Remembers how authoritative zones change so that secondary servers can be
+/// sent just the differences. Zones are only tracked once they have been
+/// transferred so there is no cost for zones without secondaries.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ZoneJournal {
+    zones: Arc<Mutex<HashMap<Fqdn, ZoneHistory>>>,
+}
+
+impl ZoneJournal {
+    /// Records the changes to every tracked zone whose serial has changed.
+    pub(super) fn update<Z: ZoneConfigProvider>(&self, state: &LockedServerState<Z>) {
+        let mut zones = self.zones.lock().unwrap();
+
+        zones.retain(|origin, history| {
+            // Gathering the zone's records is only worth it when it has changed.
+            match state.zone_serial(origin) {
+                Some(serial) if serial == history.serial => return true,
+                Some(_) => {}
+                None => return false,
+            }
+
+            let Some((soa, serial, records)) = state.zone_records(origin).and_then(split_zone)
+            else {
+                return false;
+            };
+
+            let change = Change {
+                from: history.soa.clone(),
+                to: soa.clone(),
+                removed: history.records.difference(&records).cloned().collect(),
+                added: records.difference(&history.records).cloned().collect(),
+            };
+
+            history.changes.push_back(change);
+            while history.changes.len() > JOURNAL_LIMIT {
+                history.changes.pop_front();
+            }
+
+            history.soa = soa;
+            history.serial = serial;
+            history.records = records;
+
+            true
+        });
+    }
+
+    /// The records to send for a transfer of the zone. With the serial of the
+    /// secondary's copy of the zone just the changes since are sent when they
+    /// are known, otherwise the full zone is. `None` if the server isn't
+    /// authoritative for the zone.
+    pub(super) fn transfer<Z: ZoneConfigProvider>(
+        &self,
+        state: &LockedServerState<Z>,
+        origin: &Fqdn,
+        serial: Option<u32>,
+    ) -> Option<Vec<rr::Record>> {
+        let zone = state.zone_records(origin)?;
+        let (soa, current, records) = split_zone(zone.clone())?;
+
+        let mut zones = self.zones.lock().unwrap();
+        let history = zones
+            .entry(origin.clone())
+            .or_insert_with(|| ZoneHistory::new(soa.clone(), current, records));
+
+        // The journal is behind if the records changed since it was updated.
+        if history.serial != current {
+            return Some(full_transfer(zone, soa));
+        }
+
+        match serial {
+            Some(serial) if serial == current => Some(vec![soa]),
+            Some(serial) => match history.changes_since(serial) {
+                Some(changes) => {
+                    let mut transfer = vec![soa.clone()];
+                    for change in changes {
+                        transfer.push(change.from.clone());
+                        transfer.extend(change.removed.iter().cloned());
+                        transfer.push(change.to.clone());
+                        transfer.extend(change.added.iter().cloned());
+                    }
+                    transfer.push(soa);
+
+                    Some(transfer)
+                }
+                None => Some(full_transfer(zone, soa)),
+            },
+            None => Some(full_transfer(zone, soa)),
+        }
+    }
+}
+
+/// A full zone transfer ends with the SOA record that it started with.
+fn full_transfer(mut zone: Vec<rr::Record>, soa: rr::Record) -> Vec<rr::Record> {
+    zone.push(soa);
+    zone
+}
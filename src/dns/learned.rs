@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use hickory_server::proto::rr::{self, RecordType};
+
+use crate::dns::{Fqdn, Record, RecordSet};
+
+/// The most names and types that are remembered at once.
+const LEARNED_LIMIT: usize = 10000;
+
+type LearnedKey = (Fqdn, RecordType);
+
+#[derive(Debug, Default)]
+struct LearnedMap {
+    /// Counts up as records are learned so the oldest can be found.
+    generation: u64,
+    records: HashMap<LearnedKey, (u64, Vec<Record>)>,
+}
+
+impl LearnedMap {
+    /// Forgets the oldest tenth of the names when there are too many.
+    fn evict(&mut self) {
+        if self.records.len() <= LEARNED_LIMIT {
+            return;
+        }
+
+        let mut generations: Vec<u64> = self
+            .records
+            .values()
+            .map(|(generation, _)| *generation)
+            .collect();
+        generations.sort_unstable();
+        let oldest = generations[generations.len() - LEARNED_LIMIT * 9 / 10];
+
+        self.records
+            .retain(|_, (generation, _)| *generation >= oldest);
+    }
+}
+
+/// Answers from upstream servers for names in zones with learning enabled. These
+/// are never served, they only show which names are still only known upstream.
+/// The names learned longest ago are forgotten once there are too many.
+#[derive(Clone, Default, Debug)]
+pub(crate) struct LearnedRecords {
+    records: Arc<Mutex<LearnedMap>>,
+}
+
+impl LearnedRecords {
+    /// Replaces anything previously learned for the names and types of these
+    /// answers.
+    pub(super) fn learn<'a, I>(&self, answers: I)
+    where
+        I: IntoIterator<Item = &'a rr::Record>,
+    {
+        let mut learned: HashMap<LearnedKey, Vec<Record>> = HashMap::new();
+
+        for answer in answers {
+            if let Some(record) = Record::from_raw(answer) {
+                learned
+                    .entry((record.name().clone(), answer.record_type()))
+                    .or_default()
+                    .push(record);
+            }
+        }
+
+        if learned.is_empty() {
+            return;
+        }
+
+        for (name, _) in learned.keys() {
+            tracing::debug!(%name, "Learned records from upstream");
+        }
+
+        let mut map = self.records.lock().unwrap();
+        map.generation += 1;
+        let generation = map.generation;
+        map.records.extend(
+            learned
+                .into_iter()
+                .map(|(key, records)| (key, (generation, records))),
+        );
+        map.evict();
+    }
+
+    pub(crate) fn records(&self) -> RecordSet {
+        self.records
+            .lock()
+            .unwrap()
+            .records
+            .values()
+            .flat_map(|(_, records)| records)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_server::proto::rr;
+
+    use crate::{
+        dns::learned::{LearnedRecords, LEARNED_LIMIT},
+        test::{name, rdata_a},
+    };
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn limit() {
+        let learned = LearnedRecords::default();
+
+        for i in 0..=LEARNED_LIMIT {
+            learned.learn(&[rr::Record::from_rdata(
+                name(&format!("host{i}.example.org.")),
+                300,
+                rdata_a("10.10.1.1"),
+            )]);
+        }
+
+        let records = learned.records();
+        assert!(records.len() <= LEARNED_LIMIT);
+        assert!(records.has_name(&name(&format!("host{LEARNED_LIMIT}.example.org."))));
+        assert!(!records.has_name(&name("host0.example.org.")));
+    }
+}
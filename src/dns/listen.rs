@@ -0,0 +1,175 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV6},
+    thread::available_parallelism,
+};
+
+use if_addrs::get_if_addrs;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::util::Bind;
+
+/// The addresses to listen on, every IPv4 address when none are configured.
+/// Interfaces are listened to on each of the addresses they have when the server
+/// starts.
+pub(super) fn listen_addresses(listen: &[Bind], port: u16) -> Vec<SocketAddr> {
+    if listen.is_empty() {
+        return vec![SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port)];
+    }
+
+    let mut addresses = Vec::new();
+
+    for bind in listen {
+        match bind {
+            Bind::Address(ip) => addresses.push(SocketAddr::new(*ip, port)),
+            Bind::Interface(name) => {
+                let interfaces = match get_if_addrs() {
+                    Ok(interfaces) => interfaces,
+                    Err(e) => {
+                        tracing::error!(error = %e, "Failed to list network interfaces");
+                        continue;
+                    }
+                };
+
+                let known = addresses.len();
+                addresses.extend(
+                    interfaces
+                        .iter()
+                        .filter(|interface| &interface.name == name)
+                        .map(|interface| match interface.ip() {
+                            // Link-local addresses are only unique within their
+                            // interface.
+                            IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80 => {
+                                SocketAddrV6::new(ip, port, 0, interface.index.unwrap_or_default())
+                                    .into()
+                            }
+                            ip => SocketAddr::new(ip, port),
+                        }),
+                );
+
+                if addresses.len() == known {
+                    tracing::warn!(
+                        interface = name,
+                        "Network interface has no addresses to listen on"
+                    );
+                }
+            }
+        }
+    }
+
+    addresses
+}
+
+/// How many UDP sockets to open for each address, 0 gives one per CPU core.
+pub(super) fn udp_workers(configured: Option<usize>) -> usize {
+    let workers = match configured {
+        None => 1,
+        Some(0) => available_parallelism().map(usize::from).unwrap_or(1),
+        Some(workers) => workers,
+    };
+
+    if workers > 1 && !cfg!(unix) {
+        tracing::warn!("Multiple UDP workers are not supported on this platform");
+        return 1;
+    }
+
+    workers
+}
+
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn udp_socket(address: SocketAddr, reuse_port: bool) -> io::Result<UdpSocket> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+
+    // Lets IPv4 and IPv6 addresses be listened to separately.
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+
+    #[cfg(unix)]
+    socket.set_reuse_port(reuse_port)?;
+
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+
+    UdpSocket::from_std(socket.into())
+}
+
+/// Opens `workers` UDP sockets on the address. The kernel spreads queries
+/// across sockets sharing an address with `SO_REUSEPORT` and each is read by
+/// its own task so a busy server isn't limited to what one reader can handle.
+pub(super) fn udp_sockets(address: SocketAddr, workers: usize) -> io::Result<Vec<UdpSocket>> {
+    (0..workers)
+        .map(|_| udp_socket(address, workers > 1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use crate::{
+        dns::listen::{listen_addresses, udp_sockets},
+        util::Bind,
+    };
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn sockets() {
+        assert_eq!(
+            listen_addresses(&[], 53),
+            vec![SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 53)]
+        );
+        assert_eq!(
+            listen_addresses(&[Bind::Interface("missing0".to_owned())], 53),
+            Vec::new()
+        );
+
+        let address = listen_addresses(&[Bind::Address(Ipv4Addr::LOCALHOST.into())], 0)[0];
+        let sockets = udp_sockets(address, 1).unwrap();
+        assert_eq!(sockets.len(), 1);
+
+        #[cfg(unix)]
+        {
+            use std::time::Duration;
+
+            use tokio::{net::UdpSocket, time::sleep};
+
+            let address = sockets[0].local_addr().unwrap();
+            drop(sockets);
+
+            let sockets = udp_sockets(address, 3).unwrap();
+            assert_eq!(sockets.len(), 3);
+            assert!(sockets
+                .iter()
+                .all(|socket| socket.local_addr().unwrap() == address));
+
+            // Datagrams from different client ports are spread across the
+            // sockets.
+            for _ in 0..64 {
+                let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+                client.send_to(b"query", address).await.unwrap();
+            }
+            sleep(Duration::from_millis(100)).await;
+
+            let mut buffer = [0; 16];
+            let received: Vec<usize> = sockets
+                .iter()
+                .map(|socket| {
+                    let mut count = 0;
+                    while socket.try_recv(&mut buffer).is_ok() {
+                        count += 1;
+                    }
+                    count
+                })
+                .collect();
+
+            assert_eq!(received.iter().sum::<usize>(), 64);
+            assert!(received.iter().filter(|count| **count > 0).count() > 1);
+        }
+    }
+}
@@ -1,45 +1,181 @@
-use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use anyhow::Error;
-use futures::FutureExt;
+use futures::{
+    future::{join, join_all, select_ok},
+    FutureExt,
+};
 use hickory_server::{
     proto::{
-        op::Query,
-        rr::{self, Name, RecordType},
+        op::{Query, ResponseCode},
+        rr::{self, rdata::NS, DNSClass, Name, RecordType},
     },
     ServerFuture,
 };
+use rand::{seq::SliceRandom, thread_rng};
+use reqwest::Url;
 use serde::Deserialize;
-use tokio::{
-    net::{TcpListener, UdpSocket},
-    sync::RwLock,
-};
+use tokio::{net::TcpListener, sync::RwLock};
 use tracing::{instrument, Span};
 
+#[cfg(any(test, feature = "record-builder"))]
+mod answer_cache;
+mod builder;
+mod capture;
+mod drain;
+mod forwarding;
 mod handler;
+mod health;
+mod journal;
+mod learned;
+mod listen;
 mod query;
+mod rate_limit;
+mod recent;
 mod record;
+mod serial;
+mod stale;
+mod stats;
+mod targets;
 mod upstream;
 
-pub(crate) use record::{Fqdn, RData, Record, RecordSet};
+#[cfg(any(test, feature = "record-builder"))]
+#[cfg_attr(
+    not(feature = "record-builder"),
+    allow(unreachable_pub, unused_imports)
+)]
+pub use builder::RecordSetBuilder;
+pub(crate) use capture::QueryCapture;
+pub(crate) use health::{UpstreamHealth, UpstreamStatus};
+pub(crate) use learned::LearnedRecords;
+pub(crate) use recent::{RecentQueries, RecentQuery};
+#[cfg_attr(not(feature = "record-builder"), allow(unreachable_pub))]
+pub use record::RecordSet;
+pub(crate) use record::{Fqdn, RData, Record, RecordChanges};
+pub(crate) use stats::{QueryStats, ZoneStats};
 pub(crate) use upstream::Upstream;
 
-use self::handler::Handler;
+use self::{
+    drain::{QueryTracker, DEFAULT_GRACE_MS},
+    handler::Handler,
+    journal::ZoneJournal,
+    rate_limit::RateLimiter,
+    serial::ZoneSerials,
+    stale::StaleCache,
+    targets::TargetCache,
+    upstream::{TsigKey, UpstreamKey},
+};
 use crate::{
-    config::{ZoneConfigProvider, Zones},
+    config::{
+        deserialize_optional_url, AnswerOrder, ConflictPolicy, Delegation, UpstreamStrategy,
+        ZoneConfig, ZoneConfigProvider, Zones,
+    },
     dns::query::QueryState,
+    sources::SourceId,
+    util::{Bind, Subnet},
 };
 
 #[derive(Clone, Debug, PartialEq, Eq, Default, Deserialize)]
 pub(crate) struct ServerConfig {
     #[serde(default)]
     port: Option<u16>,
+
+    #[serde(default)]
+    warmup_ttl: Option<u32>,
+
+    #[serde(default)]
+    max_query_rate: Option<u32>,
+
+    /// The addresses that other servers can reach this server at.
+    #[serde(default)]
+    pub(crate) addresses: Vec<IpAddr>,
+
+    /// A name published with the addresses of this machine.
+    #[serde(default)]
+    pub(crate) hostname: Option<Fqdn>,
+
+    /// How many queries to keep in wire format for download.
+    #[serde(default)]
+    capture_queries: Option<usize>,
+
+    /// Told whenever an upstream server goes down or recovers.
+    #[serde(default, deserialize_with = "deserialize_optional_url")]
+    upstream_webhook: Option<Url>,
+
+    /// How long an unhealthy upstream server is skipped for.
+    #[serde(default)]
+    upstream_cooldown_ms: Option<u64>,
+
+    /// How long shutting down waits for queries that are being handled.
+    #[serde(default)]
+    shutdown_grace_ms: Option<u64>,
+
+    /// The clients that can transfer authoritative zones.
+    #[serde(default)]
+    allow_transfer: Vec<Subnet>,
+
+    /// When set transfers must also be signed with one of these keys.
+    #[serde(default)]
+    transfer_keys: Vec<TsigKey>,
+
+    /// The addresses or network interfaces to listen on.
+    #[serde(default)]
+    listen: Vec<Bind>,
+
+    /// How many UDP sockets share each address.
+    #[serde(default)]
+    udp_workers: Option<usize>,
+}
+
+impl ServerConfig {
+    /// The port that the server listens on.
+    pub(crate) fn port(&self) -> u16 {
+        self.port.unwrap_or(53)
+    }
+
+    /// The addresses that the server listens on.
+    pub(crate) fn listen_addresses(&self) -> Vec<SocketAddr> {
+        listen::listen_addresses(&self.listen, self.port())
+    }
+}
+
+/// The outcome of a query performed outside of a DNS request.
+pub(crate) struct QueryResult {
+    pub(crate) response_code: ResponseCode,
+    pub(crate) authoritative: bool,
+    pub(crate) answers: Vec<rr::Record>,
+    pub(crate) name_servers: Vec<rr::Record>,
+    pub(crate) additionals: Vec<rr::Record>,
+    pub(crate) upstreams: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct ServerState<Z> {
-    pub(crate) records: Arc<RwLock<RecordSet>>,
-    pub(crate) zones: Arc<RwLock<Z>>,
+    pub(crate) records: Arc<RwLock<Arc<RecordSet>>>,
+    pub(crate) zones: Arc<RwLock<Arc<Z>>>,
+    pub(crate) learned: LearnedRecords,
+    pub(crate) recent: RecentQueries,
+    pub(crate) stats: QueryStats,
+    pub(crate) capture: QueryCapture,
+    pub(crate) upstream_health: UpstreamHealth,
+    target_cache: TargetCache,
+    stale_cache: StaleCache,
+    serials: Arc<RwLock<ZoneSerials>>,
+    journal: ZoneJournal,
+    warming_up: Arc<AtomicBool>,
+    /// Counts answers given in rotated order.
+    rotation: Arc<AtomicUsize>,
+    /// Counts lookups sent to round robin upstreams.
+    upstream_rotation: Arc<AtomicUsize>,
 }
 
 async fn resolve_name<Z: ZoneConfigProvider + Clone>(
@@ -62,88 +198,607 @@ impl<Z: ZoneConfigProvider + Clone + Send + Sync + 'static> reqwest::dns::Resolv
     }
 }
 
+/// A snapshot of the server's state. The records and zones are shared with the
+/// server so taking a snapshot costs the same however many records there are.
 pub(crate) struct LockedServerState<Z> {
-    pub(crate) records: RecordSet,
-    pub(crate) zones: Z,
+    pub(crate) records: Arc<RecordSet>,
+    pub(crate) zones: Arc<Z>,
+    learned: LearnedRecords,
+    upstream_health: UpstreamHealth,
+    target_cache: TargetCache,
+    stale_cache: StaleCache,
+    serials: ZoneSerials,
+    rotation: Arc<AtomicUsize>,
+    upstream_rotation: Arc<AtomicUsize>,
 }
 
 impl<Z: Clone> ServerState<Z> {
     pub(crate) fn new(records: RecordSet, zones: Z) -> Self {
         Self {
-            records: Arc::new(RwLock::new(records)),
-            zones: Arc::new(RwLock::new(zones)),
+            records: Arc::new(RwLock::new(Arc::new(records))),
+            zones: Arc::new(RwLock::new(Arc::new(zones))),
+            learned: Default::default(),
+            recent: Default::default(),
+            stats: Default::default(),
+            capture: Default::default(),
+            upstream_health: Default::default(),
+            target_cache: Default::default(),
+            stale_cache: Default::default(),
+            serials: Default::default(),
+            journal: Default::default(),
+            warming_up: Arc::new(AtomicBool::new(true)),
+            rotation: Default::default(),
+            upstream_rotation: Default::default(),
+        }
+    }
+
+    /// Whether the sources are still populating the initial set of records.
+    pub(crate) fn is_warming_up(&self) -> bool {
+        self.warming_up.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn end_warmup(&self) {
+        if self.warming_up.swap(false, Ordering::AcqRel) {
+            tracing::info!("Initial records loaded, warm-up complete");
         }
     }
 
-    pub(crate) async fn replace_records(&self, records: RecordSet) {
-        let mut locked = self.records.write().await;
-        *locked = records;
+    /// Bumps the serials of the zones holding any of the names.
+    async fn bump_serials(&self, names: &HashSet<Fqdn>)
+    where
+        Z: ZoneConfigProvider,
+    {
+        let origins: HashSet<Fqdn> = {
+            let zones = self.zones.read().await;
+            names
+                .iter()
+                .filter_map(|name| zones.zone_config(name).origin)
+                .collect()
+        };
+
+        if !origins.is_empty() {
+            let mut serials = self.serials.write().await;
+            for origin in origins {
+                serials.bump(origin);
+            }
+        }
     }
 
-    pub(crate) async fn replace_zones(&self, zones: Z) {
-        let mut locked = self.zones.write().await;
-        *locked = zones;
+    #[cfg(test)]
+    pub(crate) async fn replace_records(&self, records: RecordSet)
+    where
+        Z: ZoneConfigProvider,
+    {
+        {
+            let mut locked = self.records.write().await;
+            self.bump_serials(&locked.changed_names(&records)).await;
+            *locked = Arc::new(records);
+        }
+
+        self.journal.update(&self.locked().await);
+    }
+
+    /// Applies changes to the records from some sources, given the current
+    /// records of every source in order of preference.
+    pub(crate) async fn update_records(
+        &self,
+        changes: &RecordChanges,
+        sources: &[(&SourceId, &RecordSet)],
+        policy: ConflictPolicy,
+    ) where
+        Z: ZoneConfigProvider,
+    {
+        if changes.is_empty() {
+            return;
+        }
+
+        // Queries in progress keep the set they started with. If one is still
+        // using it the copy shares all but the changed parts with it.
+        {
+            let mut locked = self.records.write().await;
+            let changed = Arc::make_mut(&mut *locked).merge_changes(changes, sources, policy);
+            self.bump_serials(&changed).await;
+        }
+
+        self.journal.update(&self.locked().await);
+    }
+
+    pub(crate) async fn replace_zones(&self, zones: Z)
+    where
+        Z: PartialEq + ZoneConfigProvider,
+    {
+        {
+            let mut locked = self.zones.write().await;
+            if **locked == zones {
+                return;
+            }
+
+            self.serials.write().await.bump_all();
+            *locked = Arc::new(zones);
+        }
+
+        self.target_cache.clear();
+
+        self.journal.update(&self.locked().await);
+    }
+
+    /// The records to send for a transfer of an authoritative zone, just the
+    /// changes since `serial` when they are known.
+    pub(crate) async fn transfer(
+        &self,
+        origin: &Fqdn,
+        serial: Option<u32>,
+    ) -> Option<Vec<rr::Record>>
+    where
+        Z: ZoneConfigProvider,
+    {
+        self.journal.transfer(&self.locked().await, origin, serial)
     }
 
     pub(crate) async fn locked(&self) -> LockedServerState<Z> {
         let zones = self.zones.read().await.clone();
         let records = self.records.read().await.clone();
+        let serials = self.serials.read().await.clone();
 
-        LockedServerState { zones, records }
+        LockedServerState {
+            zones,
+            records,
+            learned: self.learned.clone(),
+            upstream_health: self.upstream_health.clone(),
+            target_cache: self.target_cache.clone(),
+            stale_cache: self.stale_cache.clone(),
+            serials,
+            rotation: self.rotation.clone(),
+            upstream_rotation: self.upstream_rotation.clone(),
+        }
     }
 }
 
 impl<Z: ZoneConfigProvider> LockedServerState<Z> {
+    /// Performs a query in the same way as a request to the DNS server would.
+    pub(crate) async fn query(&self, query: Query, recursion_desired: bool) -> QueryResult {
+        let mut query_state = QueryState::new(query, recursion_desired);
+        self.perform_query(&mut query_state).await;
+        query_state.into()
+    }
+
     #[instrument(skip(self))]
-    async fn resolve_http_address(&self, name: String) -> Result<Vec<SocketAddr>, Error> {
+    pub(crate) async fn resolve_http_address(
+        &self,
+        name: String,
+    ) -> Result<Vec<SocketAddr>, Error> {
         let mut name = Name::from_str(&name)?;
         name.set_fqdn(true);
 
-        let mut results = Vec::<SocketAddr>::new();
+        let lookup = |record_type: RecordType| {
+            let name = &name;
 
-        let query = Query::query(name.clone(), RecordType::A);
-        let mut query_state = QueryState::new(query, true);
-        self.perform_query(&mut query_state).await;
-        results.extend(query_state.resolve_name(&name));
+            async move {
+                let mut query_state =
+                    QueryState::new(Query::query(name.clone(), record_type), true);
+                self.perform_query(&mut query_state).await;
+                query_state.resolve_name(name).collect::<Vec<SocketAddr>>()
+            }
+        };
 
-        let query = Query::query(name.clone(), RecordType::AAAA);
-        let mut query_state = QueryState::new(query, true);
-        self.perform_query(&mut query_state).await;
-        results.extend(query_state.resolve_name(&name));
+        let (mut results, aaaa) = join(lookup(RecordType::A), lookup(RecordType::AAAA)).await;
+        results.extend(aaaa);
 
         Ok(results)
     }
 
+    fn serial(&self, config: &ZoneConfig) -> u32 {
+        match config.origin {
+            Some(ref origin) => self.serials.serial(origin),
+            None => 0,
+        }
+    }
+
+    fn soa(&self, config: &ZoneConfig) -> Option<rr::Record> {
+        config.soa(self.serial(config))
+    }
+
+    /// The serial of an authoritative zone, or `None` if this server isn't
+    /// authoritative for the zone.
+    pub(crate) fn zone_serial(&self, origin: &Fqdn) -> Option<u32> {
+        let config = self.zones.zone_config(origin);
+        if config.origin.as_ref() != Some(origin) || !config.authoritative {
+            return None;
+        }
+
+        Some(self.serial(&config))
+    }
+
+    /// Every record in an authoritative zone starting with its SOA and NS records,
+    /// or `None` if this server isn't authoritative for the zone. Tagged records
+    /// are left out as they are not visible to every client. Reverse zones
+    /// include the PTR records generated from addresses.
+    pub(crate) fn zone_records(&self, origin: &Fqdn) -> Option<Vec<rr::Record>> {
+        let config = self.zones.zone_config(origin);
+        if config.origin.as_ref() != Some(origin) {
+            return None;
+        }
+
+        let soa = self.soa(&config)?;
+        let name_server = config.name_server()?;
+
+        let mut records = vec![
+            soa,
+            rr::Record::from_rdata(
+                origin.name(),
+                config.ttl,
+                rr::RData::NS(NS(name_server.name())),
+            ),
+        ];
+        // A reverse zone's name server is in a forward zone so has no glue here.
+        if origin.zone_of(&name_server) {
+            records.extend(
+                config.name_server_addresses.iter().filter_map(|ip| {
+                    Record::new(name_server.clone(), RData::from(*ip)).raw(&config)
+                }),
+            );
+        }
+
+        let mut zone_records: Vec<rr::Record> = self
+            .records
+            .records()
+            .chain(self.records.reverse_records())
+            .filter(|record| record.tags.is_empty())
+            .filter_map(|record| {
+                let config = self.zones.zone_config(record.name());
+                if config.origin.as_ref() == Some(origin) {
+                    record.raw(&config)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        zone_records.sort();
+        records.extend(zone_records);
+
+        Some(records)
+    }
+
+    /// The records this server publishes about itself in an authoritative zone, an
+    /// NS record at the origin and the addresses of the name server it names.
+    /// Returns the answers and any glue records.
+    fn name_server_records(
+        &self,
+        name: &Name,
+        query_state: &QueryState,
+        config: &ZoneConfig,
+    ) -> (Vec<rr::Record>, Vec<rr::Record>) {
+        let Some(name_server) = config.name_server() else {
+            return Default::default();
+        };
+
+        if query_state.query_class() != DNSClass::IN {
+            return Default::default();
+        }
+
+        let addresses = |record_types: &[RecordType]| -> Vec<rr::Record> {
+            config
+                .name_server_addresses
+                .iter()
+                .map(|ip| Record::new(name_server.clone(), RData::from(*ip)))
+                .filter(|record| record_types.contains(&record.rdata().data_type()))
+                .filter_map(|record| record.raw(config))
+                .collect()
+        };
+
+        let is_origin = config
+            .origin
+            .as_ref()
+            .is_some_and(|origin| origin.name() == *name);
+
+        match query_state.query_type() {
+            RecordType::NS if is_origin => {
+                let record = rr::Record::from_rdata(
+                    name.clone(),
+                    config.ttl,
+                    rr::RData::NS(NS(name_server.name())),
+                );
+
+                (vec![record], addresses(&[RecordType::A, RecordType::AAAA]))
+            }
+            record_type @ (RecordType::A | RecordType::AAAA) if name_server.name() == *name => {
+                (addresses(&[record_type]), Vec::new())
+            }
+            _ => Default::default(),
+        }
+    }
+
+    /// Puts a name's records into the order configured for its zone.
+    fn order_answers(&self, records: &mut [rr::Record], config: &ZoneConfig) {
+        if records.len() < 2 {
+            return;
+        }
+
+        match config.answer_order {
+            AnswerOrder::Fixed => {}
+            AnswerOrder::Rotate => {
+                records.sort();
+                let offset = self.rotation.fetch_add(1, Ordering::Relaxed) % records.len();
+                records.rotate_left(offset);
+            }
+            AnswerOrder::Shuffle => records.shuffle(&mut thread_rng()),
+        }
+    }
+
+    /// Responds with the name servers for a delegated zone along with any addresses
+    /// known for them.
+    fn refer(&self, delegation: &Delegation, config: &ZoneConfig, query_state: &mut QueryState) {
+        tracing::trace!(origin = %delegation.origin, "Referring query to delegated zone");
+
+        query_state.response_code = ResponseCode::NoError;
+
+        for (name_server, addresses) in &delegation.name_servers {
+            query_state.name_servers.push(rr::Record::from_rdata(
+                delegation.origin.name(),
+                config.ttl,
+                rr::RData::NS(NS(name_server.name())),
+            ));
+
+            let glue: Vec<rr::Record> = if addresses.is_empty() {
+                let glue_config = self.zones.zone_config(name_server);
+
+                [RecordType::A, RecordType::AAAA]
+                    .into_iter()
+                    .flat_map(|record_type| {
+                        self.records
+                            .lookup(name_server, DNSClass::IN, record_type)
+                            .filter(|record| matches!(record.rdata(), RData::A(_) | RData::Aaaa(_)))
+                            .filter_map(|record| record.raw(&glue_config))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect()
+            } else {
+                addresses
+                    .iter()
+                    .filter_map(|ip| Record::new(name_server.clone(), RData::from(*ip)).raw(config))
+                    .collect()
+            };
+
+            query_state.add_additionals(glue);
+        }
+    }
+
     async fn lookup_name(&self, name: &Name, query_state: &mut QueryState) {
         let fqdn = Fqdn::from(name.clone());
-        let config = self.zones.zone_config(&fqdn);
+        let config = self.zones.client_zone_config(&fqdn, query_state.client);
         tracing::trace!(name = %name, config = ?config, "Looking up name");
 
-        let records: Vec<rr::Record> = self
+        if config.hide_records {
+            self.forward(name, &config, query_state).await;
+            return;
+        }
+
+        if let Some(ref delegation) = config.delegation {
+            // Names reached through a CNAME are left for the client to follow.
+            if name == query_state.query.name() {
+                self.refer(delegation, &config, query_state);
+            }
+
+            return;
+        }
+
+        let mut records: Vec<rr::Record> = self
             .records
             .lookup(name, query_state.query_class(), query_state.query_type())
+            .filter(|r| r.is_visible(&config.hidden_tags))
             .filter_map(|r| r.raw(&config))
             .collect();
 
+        let mut glue = Vec::new();
+        if records.is_empty() {
+            (records, glue) = self.name_server_records(name, query_state, &config);
+        }
+
         if !config.upstreams.is_empty() && name == query_state.query.name() {
             query_state.recursion_available = true;
         }
 
         if !records.is_empty() {
+            self.order_answers(&mut records, &config);
             query_state.add_answers(records);
+            query_state.add_additionals(glue);
 
             if name == query_state.query.name() {
-                query_state.soa = config.soa();
+                query_state.soa = self.soa(&config);
             }
 
             return;
         };
 
+        if self.records.name_exists(name, &config.hidden_tags) {
+            // The name exists, just without records of the requested type.
+            query_state.response_code = ResponseCode::NoError;
+
+            if config.authoritative || config.nodata {
+                if name == query_state.query.name() {
+                    query_state.soa = self.soa(&config);
+                }
+
+                return;
+            }
+        }
+
+        self.forward(name, &config, query_state).await;
+    }
+
+    /// Asks the zone's upstream servers about a name that isn't known locally.
+    async fn forward(&self, name: &Name, config: &ZoneConfig, query_state: &mut QueryState) {
+        // The target of a local CNAME.
+        let target = name != query_state.query.name();
+
         if query_state.recursion_desired {
-            for upstream in &config.upstreams {
-                upstream.resolve(name, query_state).await;
+            let upstream_key = UpstreamKey::new(&config.upstreams, query_state.client);
+
+            if target {
+                if let Some(answers) = self.target_cache.get(
+                    &upstream_key,
+                    name,
+                    query_state.query_class(),
+                    query_state.query_type(),
+                ) {
+                    query_state.add_answers(answers);
+                    return;
+                }
+            }
+
+            let known = query_state.answers().len();
+            let upstreams = self.available_upstreams(config);
+
+            let answered = match config.upstream_strategy {
+                UpstreamStrategy::Failover => {
+                    self.failover(name, upstreams.iter().copied(), query_state)
+                        .await
+                }
+                UpstreamStrategy::RoundRobin if !upstreams.is_empty() => {
+                    let count = upstreams.len();
+                    let first = self.upstream_rotation.fetch_add(1, Ordering::Relaxed) % count;
+                    let upstreams = upstreams.iter().copied().cycle().skip(first).take(count);
+
+                    self.failover(name, upstreams, query_state).await
+                }
+                UpstreamStrategy::Race if !upstreams.is_empty() => {
+                    let state = &*query_state;
+                    let lookups = upstreams.iter().copied().map(|upstream| {
+                        Box::pin(async move {
+                            upstream
+                                .query(name, state, &self.upstream_health)
+                                .await
+                                .map(|response| (upstream, response))
+                        })
+                    });
+
+                    let winner = select_ok(lookups).await.map(|(winner, _)| winner);
+                    if let Ok((upstream, response)) = winner {
+                        upstream.add_response(name, response, query_state);
+                        true
+                    } else {
+                        false
+                    }
+                }
+                _ => false,
+            };
+
+            if config.max_stale > 0 && !config.upstreams.is_empty() {
+                let max_stale = Duration::from_secs(config.max_stale.into());
+
+                if answered {
+                    self.stale_cache.insert(
+                        &upstream_key,
+                        name,
+                        query_state.query_class(),
+                        query_state.query_type(),
+                        &query_state.answers()[known..],
+                        max_stale,
+                    );
+                } else if let Some(answers) = self.stale_cache.get(
+                    &upstream_key,
+                    name,
+                    query_state.query_class(),
+                    query_state.query_type(),
+                    max_stale,
+                ) {
+                    tracing::debug!(%name, "Upstreams are unreachable, serving stale answers");
+
+                    if name == query_state.query.name() {
+                        query_state.response_code = ResponseCode::NoError;
+                    }
+                    query_state.add_answers(answers);
+                    return;
+                }
+            }
+
+            self.learned.learn(
+                query_state.answers()[known..]
+                    .iter()
+                    .filter(|record| self.zones.zone_config(&record.name().clone().into()).learn),
+            );
+
+            if target {
+                self.target_cache.insert(
+                    &upstream_key,
+                    name,
+                    query_state.query_class(),
+                    query_state.query_type(),
+                    &query_state.answers()[known..],
+                );
+            }
+        }
+
+        // Without upstreams nothing else knows about names in an authoritative zone
+        // so the negative answer is authoritative too.
+        if name == query_state.query.name() && config.upstreams.is_empty() {
+            query_state.soa = self.soa(config);
+        }
+    }
+
+    /// The zone's upstreams that are not being skipped for failing. If they all
+    /// are then they are all tried anyway.
+    fn available_upstreams<'a>(&self, config: &'a ZoneConfig) -> Vec<&'a Upstream> {
+        let available: Vec<&Upstream> = config
+            .upstreams
+            .iter()
+            .filter(|upstream| {
+                let available = self.upstream_health.is_available(&format!("{upstream:?}"));
+                if !available {
+                    tracing::trace!(upstream = ?upstream, "Skipping unhealthy upstream");
+                }
+                available
+            })
+            .collect();
+
+        if available.is_empty() {
+            config.upstreams.iter().collect()
+        } else {
+            available
+        }
+    }
+
+    /// Asks each upstream in turn until one answers. Returns whether any did.
+    async fn failover<'a>(
+        &self,
+        name: &Name,
+        upstreams: impl Iterator<Item = &'a Upstream>,
+        query_state: &mut QueryState,
+    ) -> bool {
+        for upstream in upstreams {
+            if upstream
+                .resolve(name, query_state, &self.upstream_health)
+                .await
+            {
+                return true;
             }
         }
+
+        false
+    }
+
+    /// While warming up, names in authoritative zones that are not yet known get
+    /// an empty answer with a short negative caching time rather than NXDOMAIN so
+    /// that clients retry soon after the sources have populated.
+    fn apply_warmup(&self, query_state: &mut QueryState, ttl: u32) {
+        if query_state.response_code != ResponseCode::NXDomain {
+            return;
+        }
+
+        let config = self
+            .zones
+            .zone_config(&Fqdn::from(query_state.query.name().clone()));
+
+        let serial = self.serial(&config);
+        if let Some(soa) = config.short_soa(ttl, serial) {
+            tracing::debug!(
+                name = %query_state.query.name(),
+                "Serving warm-up response for unknown name"
+            );
+
+            query_state.response_code = ResponseCode::NoError;
+            query_state.soa = Some(soa);
+        }
     }
 
     #[instrument(fields(
@@ -157,9 +812,53 @@ impl<Z: ZoneConfigProvider> LockedServerState<Z> {
         self.lookup_name(&query_state.query.name().clone(), query_state)
             .await;
 
-        // Now lookup any new names that were discovered.
-        while let Some(name) = query_state.next_unknown() {
-            self.lookup_name(&name, query_state).await;
+        let max_depth = self
+            .zones
+            .zone_config(&Fqdn::from(query_state.query.name().clone()))
+            .max_cname_depth;
+
+        // Now lookup any new names that were discovered, those discovered together
+        // are looked up at the same time. Each round follows one more CNAME.
+        let mut depth = 0;
+        loop {
+            let unknowns = query_state.take_unknowns();
+            if unknowns.is_empty() {
+                break;
+            }
+
+            depth += 1;
+            if depth > max_depth {
+                tracing::warn!(
+                    name = %query_state.query.name(),
+                    max_depth,
+                    "Too many CNAME records to follow",
+                );
+                query_state.fail();
+                break;
+            }
+
+            let forks: Vec<(Name, QueryState)> = unknowns
+                .into_iter()
+                .map(|name| (name, query_state.fork()))
+                .collect();
+
+            let lookups = forks.into_iter().map(|(name, mut forked)| async move {
+                self.lookup_name(&name, &mut forked).await;
+                forked
+            });
+
+            for forked in join_all(lookups).await {
+                query_state.merge(forked);
+            }
+        }
+
+        if let Some(looped) = query_state.cname_loop() {
+            tracing::warn!(
+                name = %query_state.query.name(),
+                looped = %looped,
+                "CNAME records form a loop",
+            );
+            query_state.fail();
         }
 
         let span = Span::current();
@@ -170,6 +869,8 @@ impl<Z: ZoneConfigProvider> LockedServerState<Z> {
 pub(crate) struct DnsServer {
     server_state: ServerState<Zones>,
     server: ServerFuture<Handler>,
+    queries: QueryTracker,
+    shutdown_grace: Duration,
 }
 
 impl DnsServer {
@@ -177,15 +878,31 @@ impl DnsServer {
         server_config: &ServerConfig,
         server_state: ServerState<Zones>,
     ) -> Self {
+        let queries = QueryTracker::default();
+
         Self {
             server_state: server_state.clone(),
-            server: Self::build_server(server_config, server_state).await,
+            server: Self::build_server(server_config, server_state, queries.clone()).await,
+            queries,
+            shutdown_grace: Self::shutdown_grace(server_config),
         }
     }
 
+    fn shutdown_grace(server_config: &ServerConfig) -> Duration {
+        Duration::from_millis(server_config.shutdown_grace_ms.unwrap_or(DEFAULT_GRACE_MS))
+    }
+
     pub(crate) async fn shutdown(&mut self) {
         tracing::debug!("Shutting down DNS service");
 
+        let outstanding = self.queries.drain(self.shutdown_grace).await;
+        if outstanding > 0 {
+            tracing::warn!(
+                outstanding,
+                "Queries still in progress after the shutdown grace period"
+            );
+        }
+
         if let Err(e) = self.server.shutdown_gracefully().await {
             tracing::error!(error = %e, "Failure while shutting down DNS server.");
         }
@@ -198,33 +915,66 @@ impl DnsServer {
             tracing::error!(error = %e, "Failure while shutting down DNS server.");
         }
 
-        self.server = Self::build_server(server_config, self.server_state.clone()).await;
+        self.queries = QueryTracker::default();
+        self.shutdown_grace = Self::shutdown_grace(server_config);
+        self.server = Self::build_server(
+            server_config,
+            self.server_state.clone(),
+            self.queries.clone(),
+        )
+        .await;
     }
 
     async fn build_server(
         server_config: &ServerConfig,
         server_state: ServerState<Zones>,
+        queries: QueryTracker,
     ) -> ServerFuture<Handler> {
-        let handler = Handler { server_state };
+        let port = server_config.port();
+
+        server_state
+            .capture
+            .set_limit(server_config.capture_queries);
+        server_state
+            .upstream_health
+            .set_webhook(server_config.upstream_webhook.clone());
+        server_state
+            .upstream_health
+            .set_cooldown(server_config.upstream_cooldown_ms);
 
-        let port = server_config.port.unwrap_or(53);
+        let handler = Handler {
+            server_state,
+            port,
+            warmup_ttl: server_config.warmup_ttl,
+            rate_limiter: server_config
+                .max_query_rate
+                .map(|rate| Arc::new(RateLimiter::new(rate))),
+            queries,
+            transfer_clients: server_config.allow_transfer.clone(),
+            transfer_keys: server_config.transfer_keys.clone(),
+        };
 
         let mut server = ServerFuture::new(handler);
+        let workers = listen::udp_workers(server_config.udp_workers);
 
-        match UdpSocket::bind(("0.0.0.0", port)).await {
-            Ok(socket) => {
-                tracing::info!("Server listening on udp://0.0.0.0:{}", port);
-                server.register_socket(socket);
+        for address in server_config.listen_addresses() {
+            match listen::udp_sockets(address, workers) {
+                Ok(sockets) => {
+                    tracing::info!(workers, "Server listening on udp://{}", address);
+                    for socket in sockets {
+                        server.register_socket(socket);
+                    }
+                }
+                Err(e) => tracing::error!(%address, error = %e, "Unable to open UDP socket"),
             }
-            Err(e) => tracing::error!(error = %e, "Unable to open UDP socket"),
-        }
 
-        match TcpListener::bind(("0.0.0.0", port)).await {
-            Ok(socket) => {
-                tracing::info!("Server listening on tcp://0.0.0.0:{}", port);
-                server.register_listener(socket, Duration::from_millis(500));
+            match TcpListener::bind(address).await {
+                Ok(socket) => {
+                    tracing::info!("Server listening on tcp://{}", address);
+                    server.register_listener(socket, Duration::from_millis(500));
+                }
+                Err(e) => tracing::error!(%address, error = %e, "Unable to open TCP socket"),
             }
-            Err(e) => tracing::error!(error = %e, "Unable to open TCP socket"),
         }
 
         server
@@ -233,15 +983,29 @@ impl DnsServer {
 
 #[cfg(test)]
 mod tests {
+    use std::{str::FromStr, sync::Arc};
+
     use hickory_server::proto::{
         op::{Query, ResponseCode},
-        rr::{DNSClass, RecordType},
+        rr::{
+            self,
+            rdata::{NS, PTR},
+            DNSClass, Name, RData as RRData, RecordType,
+        },
     };
+    use tempfile::TempDir;
+    use tokio::net::UdpSocket;
+    use uuid::Uuid;
 
     use crate::{
-        config::{ZoneConfig, ZoneConfigProvider},
-        dns::{query::QueryState, Fqdn, RData, Record, RecordSet, ServerState},
-        test::{fqdn, name, rdata_a, rdata_cname},
+        config::{Config, ConflictPolicy, ZoneConfig, ZoneConfigProvider},
+        dns::{
+            journal, query::QueryState, DnsServer, Fqdn, RData, Record, RecordChanges, RecordSet,
+            ServerState, Upstream,
+        },
+        sources::{SourceId, SourceType},
+        test::{fqdn, mock_dns, name, rdata_a, rdata_cname, write_file},
+        util::{Address, Host},
     };
 
     #[derive(Clone)]
@@ -253,14 +1017,49 @@ mod tests {
         }
     }
 
+    #[derive(Clone)]
+    struct AuthoritativeZones {}
+
+    impl ZoneConfigProvider for AuthoritativeZones {
+        fn zone_config(&self, name: &Fqdn) -> ZoneConfig {
+            if fqdn("home.local.").zone_of(name) {
+                ZoneConfig {
+                    origin: Some(fqdn("home.local.")),
+                    authoritative: true,
+                    ..Default::default()
+                }
+            } else {
+                Default::default()
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    struct LearningZones {
+        port: u16,
+    }
+
+    impl ZoneConfigProvider for LearningZones {
+        fn zone_config(&self, name: &Fqdn) -> ZoneConfig {
+            let upstream = Upstream::from(Address {
+                host: Host::from_str("127.0.0.1").unwrap(),
+                port: Some(self.port),
+            });
+
+            ZoneConfig {
+                upstreams: [upstream].into(),
+                learn: !fqdn("other.example.org.").zone_of(name),
+                ..Default::default()
+            }
+        }
+    }
+
     #[tracing_test::traced_test]
     #[tokio::test]
     async fn query() {
-        let mut records = RecordSet::new();
-        records.insert(Record::new(
-            fqdn("test.home.local."),
-            RData::Cname(fqdn("other.home.local.")),
-        ));
+        let mut records = RecordSet::builder()
+            .cname("test.home.local.", "other.home.local.")
+            .build();
 
         let query = Query::query(name("test.home.local."), RecordType::A);
 
@@ -288,7 +1087,7 @@ mod tests {
         ));
 
         let mut query_state = QueryState::new(query.clone(), true);
-        server_state.records = records.clone();
+        server_state.records = Arc::new(records.clone());
         server_state.perform_query(&mut query_state).await;
 
         assert_eq!(query_state.response_code, ResponseCode::NoError);
@@ -309,4 +1108,1225 @@ mod tests {
         assert_eq!(record.record_type(), RecordType::A);
         assert_eq!(*record.data().unwrap(), rdata_a("10.10.45.23"));
     }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn query_result() {
+        let records = RecordSet::builder()
+            .cname("www.home.local.", "host.home.local.")
+            .a("host.home.local.", "10.10.45.23")
+            .build();
+
+        let server_state = ServerState::new(records, AuthoritativeZones {});
+        let locked = server_state.locked().await;
+
+        let result = locked
+            .query(Query::query(name("www.home.local."), RecordType::A), true)
+            .await;
+        assert_eq!(result.response_code, ResponseCode::NoError);
+        assert!(result.authoritative);
+        assert_eq!(result.answers.len(), 2);
+        assert_eq!(result.name_servers.len(), 1);
+        assert_eq!(result.name_servers[0].record_type(), RecordType::SOA);
+        assert!(result.upstreams.is_empty());
+
+        let result = locked
+            .query(Query::query(name("www.other.local."), RecordType::A), true)
+            .await;
+        assert_eq!(result.response_code, ResponseCode::NXDomain);
+        assert!(!result.authoritative);
+        assert!(result.answers.is_empty());
+    }
+
+    #[derive(Clone)]
+    struct ShallowZones {}
+
+    impl ZoneConfigProvider for ShallowZones {
+        fn zone_config(&self, _: &Fqdn) -> ZoneConfig {
+            ZoneConfig {
+                max_cname_depth: 2,
+                ..Default::default()
+            }
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn cname_chains() {
+        // Each name points at the next, the last has an address.
+        let mut builder = RecordSet::builder()
+            .cname("loop.home.local.", "back.home.local.")
+            .cname("back.home.local.", "loop.home.local.")
+            .a("chain9.home.local.", "10.10.45.23");
+        for link in 0..9 {
+            builder = builder.cname(
+                &format!("chain{link}.home.local."),
+                &format!("chain{}.home.local.", link + 1),
+            );
+        }
+        let records = builder.build();
+
+        let locked = ServerState::new(records.clone(), EmptyZones {})
+            .locked()
+            .await;
+
+        let result = locked
+            .query(Query::query(name("loop.home.local."), RecordType::A), true)
+            .await;
+        assert_eq!(result.response_code, ResponseCode::ServFail);
+        assert!(result.answers.is_empty());
+        assert!(logs_contain("CNAME records form a loop"));
+
+        let result = locked
+            .query(
+                Query::query(name("chain1.home.local."), RecordType::A),
+                true,
+            )
+            .await;
+        assert_eq!(result.response_code, ResponseCode::NoError);
+        assert_eq!(result.answers.len(), 9);
+
+        let result = locked
+            .query(
+                Query::query(name("chain0.home.local."), RecordType::A),
+                true,
+            )
+            .await;
+        assert_eq!(result.response_code, ResponseCode::ServFail);
+        assert!(result.answers.is_empty());
+        assert!(logs_contain("Too many CNAME records to follow"));
+
+        let locked = ServerState::new(records, ShallowZones {}).locked().await;
+
+        let result = locked
+            .query(
+                Query::query(name("chain7.home.local."), RecordType::A),
+                true,
+            )
+            .await;
+        assert_eq!(result.response_code, ResponseCode::NoError);
+        assert_eq!(result.answers.len(), 3);
+
+        let result = locked
+            .query(
+                Query::query(name("chain6.home.local."), RecordType::A),
+                true,
+            )
+            .await;
+        assert_eq!(result.response_code, ResponseCode::ServFail);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn learning() {
+        let upstream = mock_dns(
+            "example.org",
+            r#"
+$ORIGIN example.org.
+@   3600 IN	SOA sns.dns.icann.org. noc.dns.icann.org. 2024102601 7200 3600 1209600 3600
+
+www     IN A     10.10.10.5
+other   IN A     10.10.10.6
+"#,
+        )
+        .await;
+
+        let server_state = ServerState::new(
+            RecordSet::new(),
+            LearningZones {
+                port: upstream.port(),
+            },
+        );
+        let locked = server_state.locked().await;
+
+        // Nothing is learned unless recursion was requested.
+        let result = locked
+            .query(Query::query(name("www.example.org."), RecordType::A), false)
+            .await;
+        assert!(result.answers.is_empty());
+        assert!(server_state.learned.records().is_empty());
+
+        let result = locked
+            .query(Query::query(name("www.example.org."), RecordType::A), true)
+            .await;
+        assert_eq!(result.answers.len(), 1);
+
+        let result = locked
+            .query(
+                Query::query(name("other.example.org."), RecordType::A),
+                true,
+            )
+            .await;
+        assert_eq!(result.answers.len(), 1);
+
+        let learned = server_state.learned.records();
+        assert_eq!(learned.len(), 1);
+        assert!(learned.contains(
+            &fqdn("www.example.org."),
+            &RData::A("10.10.10.5".parse().unwrap())
+        ));
+        assert!(!learned.has_name(&name("other.example.org.")));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn concurrent_unknowns() {
+        let upstream = mock_dns(
+            "example.org",
+            r#"
+$ORIGIN example.org.
+@   3600 IN	SOA sns.dns.icann.org. noc.dns.icann.org. 2024102601 7200 3600 1209600 3600
+
+www     IN A     10.10.10.5
+other   IN A     10.10.10.6
+alias   IN CNAME other
+"#,
+        )
+        .await;
+
+        let records = RecordSet::builder()
+            .cname("multi.home.local.", "www.example.org.")
+            .cname("multi.home.local.", "alias.example.org.")
+            .build();
+
+        let server_state = ServerState::new(
+            records,
+            LearningZones {
+                port: upstream.port(),
+            },
+        );
+        let locked = server_state.locked().await;
+
+        let result = locked
+            .query(Query::query(name("multi.home.local."), RecordType::A), true)
+            .await;
+        assert_eq!(result.response_code, ResponseCode::NoError);
+
+        let mut addresses: Vec<RRData> = result
+            .answers
+            .iter()
+            .filter(|record| record.record_type() == RecordType::A)
+            .map(|record| record.data().unwrap().clone())
+            .collect();
+        addresses.sort();
+        assert_eq!(
+            addresses,
+            vec![rdata_a("10.10.10.5"), rdata_a("10.10.10.6")]
+        );
+        assert_eq!(
+            result.upstreams,
+            vec![format!("127.0.0.1:{}", upstream.port())]
+        );
+
+        // The CNAME targets are answered from the cache.
+        let result = locked
+            .query(Query::query(name("multi.home.local."), RecordType::A), true)
+            .await;
+        assert_eq!(
+            result
+                .answers
+                .iter()
+                .filter(|record| record.record_type() == RecordType::A)
+                .count(),
+            2
+        );
+        assert!(result.upstreams.is_empty());
+
+        let addresses = locked
+            .resolve_http_address("www.example.org".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(addresses, vec!["10.10.10.5:0".parse().unwrap()]);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn snapshots() {
+        let first = RecordSet::builder()
+            .a("www.home.local.", "10.10.45.23")
+            .build();
+        let server_state = ServerState::new(first.clone(), EmptyZones {});
+
+        let before = server_state.locked().await;
+        assert!(Arc::ptr_eq(
+            &before.records,
+            &server_state.locked().await.records
+        ));
+
+        let second = RecordSet::builder()
+            .a("db.home.local.", "10.10.45.24")
+            .build();
+        let mut changes = RecordChanges::default();
+        changes.add(&second);
+        let first_id = SourceId::new(&Uuid::new_v4(), SourceType::File, "first");
+        let second_id = SourceId::new(&Uuid::new_v4(), SourceType::File, "second");
+        server_state
+            .update_records(
+                &changes,
+                &[(&first_id, &first), (&second_id, &second)],
+                ConflictPolicy::Merge,
+            )
+            .await;
+
+        // Snapshots taken before a change still see the old records.
+        assert_eq!(before.records.len(), 1);
+        assert_eq!(server_state.locked().await.records.len(), 2);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn soa_serial() {
+        async fn serial(server_state: &ServerState<AuthoritativeZones>) -> u32 {
+            let result = server_state
+                .locked()
+                .await
+                .query(Query::query(name("www.home.local."), RecordType::A), false)
+                .await;
+
+            match result.name_servers[0].data() {
+                Some(RRData::SOA(soa)) => soa.serial(),
+                _ => panic!("Expected an SOA record"),
+            }
+        }
+
+        let mut records = RecordSet::builder()
+            .a("www.home.local.", "10.10.45.23")
+            .build();
+
+        let server_state = ServerState::new(records.clone(), AuthoritativeZones {});
+        let initial = serial(&server_state).await;
+        assert_ne!(initial, 0);
+
+        // Unchanged records and changes outside of the zone leave the serial alone.
+        server_state.replace_records(records.clone()).await;
+        assert_eq!(serial(&server_state).await, initial);
+
+        records.insert(Record::new(
+            fqdn("www.other.local."),
+            RData::A("10.10.45.24".parse().unwrap()),
+        ));
+        server_state.replace_records(records.clone()).await;
+        assert_eq!(serial(&server_state).await, initial);
+
+        records.insert(Record::new(
+            fqdn("www.home.local."),
+            RData::A("10.10.45.25".parse().unwrap()),
+        ));
+        server_state.replace_records(records.clone()).await;
+        let updated = serial(&server_state).await;
+        assert!(updated > initial);
+
+        records.insert(Record::new(
+            fqdn("host.home.local."),
+            RData::A("10.10.45.26".parse().unwrap()),
+        ));
+        server_state.replace_records(records).await;
+        assert!(serial(&server_state).await > updated);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn zone_records() {
+        let mut records = RecordSet::builder()
+            .a("www.home.local.", "10.10.45.23")
+            .cname("web.home.local.", "www.home.local.")
+            .a("www.other.local.", "10.10.45.24")
+            .build();
+
+        let mut tagged = Record::new(
+            fqdn("private.home.local."),
+            RData::A("10.10.45.25".parse().unwrap()),
+        );
+        tagged.tags.insert("admin".to_owned());
+        records.insert(tagged);
+
+        let server_state = ServerState::new(records, AuthoritativeZones {});
+        let locked = server_state.locked().await;
+
+        let zone = locked.zone_records(&fqdn("home.local.")).unwrap();
+        let types: Vec<(Name, RecordType)> = zone
+            .iter()
+            .map(|record| (record.name().clone(), record.record_type()))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                (name("home.local."), RecordType::SOA),
+                (name("home.local."), RecordType::NS),
+                (name("web.home.local."), RecordType::CNAME),
+                (name("www.home.local."), RecordType::A),
+            ]
+        );
+
+        assert!(locked.zone_records(&fqdn("other.local.")).is_none());
+        assert!(locked.zone_records(&fqdn("www.home.local.")).is_none());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn reverse_zone_records() {
+        struct ReverseZones {}
+
+        impl ZoneConfigProvider for ReverseZones {
+            fn zone_config(&self, name: &Fqdn) -> ZoneConfig {
+                if fqdn("45.10.10.in-addr.arpa.").zone_of(name) {
+                    ZoneConfig {
+                        origin: Some(fqdn("45.10.10.in-addr.arpa.")),
+                        authoritative: true,
+                        forward_zone: Some(fqdn("home.local.")),
+                        ..Default::default()
+                    }
+                } else {
+                    Default::default()
+                }
+            }
+        }
+
+        let mut records = RecordSet::builder()
+            .a("www.home.local.", "10.10.45.23")
+            .a("db.home.local.", "10.10.45.24")
+            .a("www.other.local.", "10.10.46.23")
+            .build();
+        records.insert(Record::new(
+            fqdn("24.45.10.10.in-addr.arpa."),
+            RData::Ptr(fqdn("database.home.local.")),
+        ));
+
+        let server_state = ServerState::new(records, ReverseZones {});
+        let locked = server_state.locked().await;
+
+        let zone = locked
+            .zone_records(&fqdn("45.10.10.in-addr.arpa."))
+            .unwrap();
+        let ptrs: Vec<(Name, RRData)> = zone
+            .iter()
+            .filter(|record| record.record_type() == RecordType::PTR)
+            .map(|record| (record.name().clone(), record.data().unwrap().clone()))
+            .collect();
+
+        // The explicit PTR record replaces the one generated for its address.
+        assert_eq!(
+            ptrs,
+            vec![
+                (
+                    name("23.45.10.10.in-addr.arpa."),
+                    RRData::PTR(PTR(name("www.home.local.")))
+                ),
+                (
+                    name("24.45.10.10.in-addr.arpa."),
+                    RRData::PTR(PTR(name("database.home.local.")))
+                ),
+            ]
+        );
+
+        assert!(locked
+            .zone_serial(&fqdn("45.10.10.in-addr.arpa."))
+            .is_some());
+        assert_eq!(locked.zone_serial(&fqdn("46.10.10.in-addr.arpa.")), None);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn transfers() {
+        fn types(records: &[rr::Record]) -> Vec<RecordType> {
+            records.iter().map(|record| record.record_type()).collect()
+        }
+
+        let records = RecordSet::builder()
+            .a("www.home.local.", "10.10.45.23")
+            .build();
+        let server_state = ServerState::new(records, AuthoritativeZones {});
+        let origin = fqdn("home.local.");
+
+        let full = server_state.transfer(&origin, None).await.unwrap();
+        assert_eq!(
+            types(&full),
+            vec![
+                RecordType::SOA,
+                RecordType::NS,
+                RecordType::A,
+                RecordType::SOA
+            ]
+        );
+        let serial = journal::soa_serial(&full[0]).unwrap();
+
+        // A secondary that is up to date just gets the SOA record.
+        let current = server_state.transfer(&origin, Some(serial)).await.unwrap();
+        assert_eq!(types(&current), vec![RecordType::SOA]);
+
+        server_state
+            .replace_records(
+                RecordSet::builder()
+                    .a("www.home.local.", "10.10.45.24")
+                    .cname("web.home.local.", "www.home.local.")
+                    .build(),
+            )
+            .await;
+
+        let incremental = server_state.transfer(&origin, Some(serial)).await.unwrap();
+        assert_eq!(incremental.len(), 7);
+        let updated = journal::soa_serial(&incremental[0]).unwrap();
+        assert!(updated > serial);
+        assert_eq!(journal::soa_serial(&incremental[1]), Some(serial));
+        assert_eq!(*incremental[2].data().unwrap(), rdata_a("10.10.45.23"));
+        assert_eq!(journal::soa_serial(&incremental[3]), Some(updated));
+        assert!(incremental[4..6]
+            .iter()
+            .any(|record| *record.data().unwrap() == rdata_a("10.10.45.24")));
+        assert_eq!(journal::soa_serial(&incremental[6]), Some(updated));
+
+        // Serials the journal doesn't know about get the full zone.
+        let full = server_state
+            .transfer(&origin, Some(serial - 1))
+            .await
+            .unwrap();
+        assert_eq!(full.len(), 5);
+
+        assert!(server_state
+            .transfer(&fqdn("other.local."), None)
+            .await
+            .is_none());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn delegation() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+zones:
+  home.local: {}
+  lab.home.local:
+    delegate_to:
+      - 10.10.5.2
+      - gateway.home.local
+  other.home.local:
+    delegate_to:
+      name: ns.other.home.local
+      addresses:
+        - fd00::53
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+
+        let records = RecordSet::builder()
+            .a("gateway.home.local.", "10.10.1.1")
+            .a("www.lab.home.local.", "10.10.5.10")
+            .cname("www.home.local.", "www.lab.home.local.")
+            .build();
+
+        let server_state = ServerState::new(records, config.zones);
+        let locked = server_state.locked().await;
+
+        let result = locked
+            .query(
+                Query::query(name("www.lab.home.local."), RecordType::A),
+                true,
+            )
+            .await;
+        assert_eq!(result.response_code, ResponseCode::NoError);
+        assert!(!result.authoritative);
+        assert!(result.answers.is_empty());
+
+        let mut name_servers: Vec<String> = result
+            .name_servers
+            .iter()
+            .map(|record| {
+                assert_eq!(*record.name(), name("lab.home.local."));
+                record.data().unwrap().to_string()
+            })
+            .collect();
+        name_servers.sort();
+        assert_eq!(
+            name_servers,
+            vec!["gateway.home.local.", "ns1.lab.home.local."]
+        );
+
+        let mut glue: Vec<(String, String)> = result
+            .additionals
+            .iter()
+            .map(|record| {
+                (
+                    record.name().to_string(),
+                    record.data().unwrap().to_string(),
+                )
+            })
+            .collect();
+        glue.sort();
+        assert_eq!(
+            glue,
+            vec![
+                ("gateway.home.local.".to_owned(), "10.10.1.1".to_owned()),
+                ("ns1.lab.home.local.".to_owned(), "10.10.5.2".to_owned()),
+            ]
+        );
+
+        let result = locked
+            .query(
+                Query::query(name("host.other.home.local."), RecordType::AAAA),
+                true,
+            )
+            .await;
+        assert_eq!(result.name_servers.len(), 1);
+        assert_eq!(*result.name_servers[0].name(), name("other.home.local."));
+        assert_eq!(result.additionals.len(), 1);
+        assert_eq!(*result.additionals[0].name(), name("ns.other.home.local."));
+
+        // A CNAME into the delegated zone is returned for the client to follow.
+        let result = locked
+            .query(Query::query(name("www.home.local."), RecordType::A), true)
+            .await;
+        assert_eq!(result.answers.len(), 1);
+        assert_eq!(result.answers[0].record_type(), RecordType::CNAME);
+        assert!(result.additionals.is_empty());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn reverse_zone() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+defaults:
+  upstream: 10.10.14.250
+
+reverse_zones:
+  - 10.10.0.0/16
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+
+        let zone_config = config.zones.zone_config(&fqdn("5.1.10.10.in-addr.arpa."));
+        assert_eq!(zone_config.origin, Some(fqdn("10.10.in-addr.arpa.")));
+        assert!(zone_config.authoritative);
+        assert!(zone_config.upstreams.is_empty());
+
+        let zone_config = config.zones.zone_config(&fqdn("1.1.168.192.in-addr.arpa."));
+        assert_eq!(zone_config.upstreams.len(), 1);
+
+        let records = RecordSet::builder()
+            .a("host.home.local.", "10.10.1.5")
+            .build();
+
+        let server_state = ServerState::new(records, config.zones);
+        let locked = server_state.locked().await;
+
+        let result = locked
+            .query(
+                Query::query(name("5.1.10.10.in-addr.arpa."), RecordType::PTR),
+                true,
+            )
+            .await;
+        assert_eq!(result.response_code, ResponseCode::NoError);
+        assert!(result.authoritative);
+        assert_eq!(result.answers.len(), 1);
+        assert_eq!(result.name_servers[0].record_type(), RecordType::SOA);
+        assert_eq!(*result.name_servers[0].name(), name("10.10.in-addr.arpa."));
+
+        let result = locked
+            .query(
+                Query::query(name("9.9.10.10.in-addr.arpa."), RecordType::PTR),
+                true,
+            )
+            .await;
+        assert_eq!(result.response_code, ResponseCode::NXDomain);
+        assert!(result.authoritative);
+        assert!(result.answers.is_empty());
+        assert!(result.upstreams.is_empty());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn nodata() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+zones:
+  home.local:
+    authoritative: true
+  other.local:
+    authoritative: false
+    nodata: true
+  forward.local:
+    authoritative: false
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+
+        let records = RecordSet::builder()
+            .a("www.home.local.", "10.10.1.5")
+            .a("deep.sub.home.local.", "10.10.1.6")
+            .a("www.other.local.", "10.10.2.5")
+            .a("www.forward.local.", "10.10.3.5")
+            .build();
+
+        let server_state = ServerState::new(records, config.zones);
+        let locked = &server_state.locked().await;
+
+        let query = |name: &str, record_type: RecordType| {
+            let query = Query::query(super::name(name), record_type);
+            async move { locked.query(query, true).await }
+        };
+
+        let result = query("www.home.local.", RecordType::AAAA).await;
+        assert_eq!(result.response_code, ResponseCode::NoError);
+        assert!(result.authoritative);
+        assert!(result.answers.is_empty());
+        assert_eq!(result.name_servers.len(), 1);
+        assert_eq!(result.name_servers[0].record_type(), RecordType::SOA);
+
+        // Names with records beneath them exist too.
+        let result = query("sub.home.local.", RecordType::A).await;
+        assert_eq!(result.response_code, ResponseCode::NoError);
+        assert!(result.authoritative);
+        assert!(result.answers.is_empty());
+
+        let result = query("missing.home.local.", RecordType::A).await;
+        assert_eq!(result.response_code, ResponseCode::NXDomain);
+        assert!(result.authoritative);
+
+        let result = query("www.other.local.", RecordType::AAAA).await;
+        assert_eq!(result.response_code, ResponseCode::NoError);
+        assert!(!result.authoritative);
+        assert!(result.answers.is_empty());
+
+        let result = query("www.forward.local.", RecordType::AAAA).await;
+        assert_eq!(result.response_code, ResponseCode::NoError);
+        assert!(!result.authoritative);
+        assert!(result.answers.is_empty());
+
+        let result = query("missing.forward.local.", RecordType::A).await;
+        assert_eq!(result.response_code, ResponseCode::NXDomain);
+        assert!(!result.authoritative);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn views() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+zones:
+  home.local:
+    views:
+      - clients:
+          - 10.30.0.0/16
+          - fd00:30::/32
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+
+        let records = RecordSet::builder()
+            .a("www.home.local.", "10.10.1.5")
+            .build();
+
+        let server_state = ServerState::new(records, config.zones);
+        let locked = server_state.locked().await;
+
+        let query = |client: Option<&str>| {
+            let mut query_state =
+                QueryState::new(Query::query(name("www.home.local."), RecordType::A), true);
+            query_state.client = client.map(|ip| ip.parse().unwrap());
+            query_state
+        };
+
+        let mut query_state = query(None);
+        locked.perform_query(&mut query_state).await;
+        assert_eq!(query_state.response_code, ResponseCode::NoError);
+        assert_eq!(query_state.answers().len(), 1);
+
+        let mut query_state = query(Some("10.10.1.20"));
+        locked.perform_query(&mut query_state).await;
+        assert_eq!(query_state.response_code, ResponseCode::NoError);
+        assert_eq!(query_state.answers().len(), 1);
+
+        let mut query_state = query(Some("10.30.1.20"));
+        locked.perform_query(&mut query_state).await;
+        assert_eq!(query_state.response_code, ResponseCode::NXDomain);
+        assert!(query_state.answers().is_empty());
+
+        let mut query_state = query(Some("fd00:30::20"));
+        locked.perform_query(&mut query_state).await;
+        assert_eq!(query_state.response_code, ResponseCode::NXDomain);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn answer_order() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+zones:
+  home.local:
+    answer_order: rotate
+  shuffled.local:
+    answer_order: shuffle
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+
+        let records = RecordSet::builder()
+            .a("www.home.local.", "10.10.1.5")
+            .a("www.home.local.", "10.10.1.6")
+            .a("www.home.local.", "10.10.1.7")
+            .a("www.shuffled.local.", "10.10.2.5")
+            .a("www.shuffled.local.", "10.10.2.6")
+            .a("www.shuffled.local.", "10.10.2.7")
+            .build();
+
+        let server_state = ServerState::new(records, config.zones);
+        let locked = &server_state.locked().await;
+
+        let answers = |name: &str| {
+            let query = Query::query(super::name(name), RecordType::A);
+
+            async move {
+                let result = locked.query(query, true).await;
+                result
+                    .answers
+                    .iter()
+                    .map(|record| record.data().unwrap().clone())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let first = answers("www.home.local.").await;
+        assert_eq!(first.len(), 3);
+
+        let second = answers("www.home.local.").await;
+        assert_eq!(second[0], first[1]);
+        assert_eq!(second[1], first[2]);
+        assert_eq!(second[2], first[0]);
+
+        let third = answers("www.home.local.").await;
+        assert_eq!(third[0], first[2]);
+
+        let fourth = answers("www.home.local.").await;
+        assert_eq!(fourth, first);
+
+        let mut shuffled = answers("www.shuffled.local.").await;
+        shuffled.sort();
+        assert_eq!(
+            shuffled,
+            vec![
+                rdata_a("10.10.2.5"),
+                rdata_a("10.10.2.6"),
+                rdata_a("10.10.2.7")
+            ]
+        );
+    }
+
+    /// Looks up a name `count` times using a zone with two upstreams, `first` is
+    /// tried first.
+    async fn strategy_answers(
+        strategy: &str,
+        first: u16,
+        second: u16,
+        count: usize,
+    ) -> Vec<RRData> {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            format!(
+                r#"
+defaults:
+  upstream: 127.0.0.1:{second}
+zones:
+  example.org:
+    upstream_strategy: {strategy}
+    upstream:
+      address: 127.0.0.1:{first}
+      timeout_ms: 200
+"#
+            ),
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+        let server_state = ServerState::new(RecordSet::new(), config.zones);
+        let locked = server_state.locked().await;
+
+        let mut answers = Vec::new();
+        for _ in 0..count {
+            let result = locked
+                .query(Query::query(name("www.example.org."), RecordType::A), true)
+                .await;
+            assert_eq!(result.answers.len(), 1);
+            answers.push(result.answers[0].data().unwrap().clone());
+        }
+
+        answers
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn serve_stale() {
+        let mut upstream = mock_dns(
+            "example.org",
+            r#"
+$ORIGIN example.org.
+@   3600 IN	SOA sns.dns.icann.org. noc.dns.icann.org. 2024102601 7200 3600 1209600 3600
+
+www     IN A     10.10.10.5
+"#,
+        )
+        .await;
+
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            format!(
+                r#"
+zones:
+  example.org:
+    max_stale: 3600
+    upstream:
+      address: 127.0.0.1:{}
+      timeout_ms: 200
+"#,
+                upstream.port()
+            ),
+        )
+        .await;
+
+        let server_state = ServerState::new(
+            RecordSet::new(),
+            Config::from_file(&config_file).unwrap().zones,
+        );
+
+        let result = server_state
+            .locked()
+            .await
+            .query(Query::query(name("www.example.org."), RecordType::A), true)
+            .await;
+        assert_eq!(result.answers.len(), 1);
+        assert_eq!(
+            result.upstreams,
+            vec![format!("127.0.0.1:{}", upstream.port())]
+        );
+
+        upstream.shutdown().await;
+
+        let result = server_state
+            .locked()
+            .await
+            .query(Query::query(name("www.example.org."), RecordType::A), true)
+            .await;
+        assert_eq!(result.response_code, ResponseCode::NoError);
+        assert_eq!(result.answers.len(), 1);
+        assert_eq!(result.answers[0].data(), Some(&rdata_a("10.10.10.5")));
+        assert!(result.upstreams.is_empty());
+
+        // Only names that were answered before are known.
+        let result = server_state
+            .locked()
+            .await
+            .query(
+                Query::query(name("other.example.org."), RecordType::A),
+                true,
+            )
+            .await;
+        assert!(result.answers.is_empty());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn forwarding_loop() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53541
+zones:
+  example.org:
+    upstream:
+      address: 127.0.0.1:53541
+      timeout_ms: 1000
+      localns: true
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+        let server_state = ServerState::new(RecordSet::new(), config.zones);
+        let mut dns_server = DnsServer::new(&config.server, server_state.clone()).await;
+
+        // The query comes straight back to this server which refuses it.
+        let result = server_state
+            .locked()
+            .await
+            .query(Query::query(name("www.example.org."), RecordType::A), true)
+            .await;
+        assert!(result.answers.is_empty());
+
+        let statuses = server_state.upstream_health.statuses();
+        assert_eq!(statuses.len(), 1);
+        assert!(statuses[0]
+            .last_error
+            .as_deref()
+            .unwrap()
+            .starts_with("Forwarding loop"));
+
+        dns_server.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn upstream_strategy() {
+        let zone = |ip: &str| {
+            format!(
+                r#"
+$ORIGIN example.org.
+@   3600 IN	SOA sns.dns.icann.org. noc.dns.icann.org. 2024102601 7200 3600 1209600 3600
+
+www     IN A     {ip}
+"#
+            )
+        };
+
+        let first = mock_dns("example.org", &zone("10.10.10.5")).await;
+        let second = mock_dns("example.org", &zone("10.10.10.6")).await;
+        // Receives queries but never answers them.
+        let dead = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let dead = dead.local_addr().unwrap().port();
+
+        assert_eq!(
+            strategy_answers("failover", first.port(), second.port(), 2).await,
+            vec![rdata_a("10.10.10.5"), rdata_a("10.10.10.5")]
+        );
+        assert_eq!(
+            strategy_answers("failover", dead, second.port(), 1).await,
+            vec![rdata_a("10.10.10.6")]
+        );
+
+        assert_eq!(
+            strategy_answers("round_robin", first.port(), second.port(), 4).await,
+            vec![
+                rdata_a("10.10.10.5"),
+                rdata_a("10.10.10.6"),
+                rdata_a("10.10.10.5"),
+                rdata_a("10.10.10.6")
+            ]
+        );
+        assert_eq!(
+            strategy_answers("round_robin", dead, second.port(), 2).await,
+            vec![rdata_a("10.10.10.6"), rdata_a("10.10.10.6")]
+        );
+
+        assert_eq!(
+            strategy_answers("race", dead, second.port(), 1).await,
+            vec![rdata_a("10.10.10.6")]
+        );
+        assert_eq!(
+            strategy_answers("race", first.port(), second.port(), 1)
+                .await
+                .len(),
+            1
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn tags() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+tags:
+  internal:
+    - 10.10.0.0/16
+  vpn:
+    - 10.50.0.0/16
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+
+        let mut internal = Record::new(
+            fqdn("www.home.local."),
+            RData::A("10.10.1.5".parse().unwrap()),
+        );
+        internal.tags.insert("internal".to_owned());
+        let mut both = Record::new(
+            fqdn("vpn.home.local."),
+            RData::A("10.10.1.6".parse().unwrap()),
+        );
+        both.tags.insert("internal".to_owned());
+        both.tags.insert("vpn".to_owned());
+
+        let mut records = RecordSet::builder()
+            .a("www.home.local.", "203.0.113.5")
+            .build();
+        records.insert(internal);
+        records.insert(both);
+
+        let server_state = ServerState::new(records, config.zones);
+        let locked = &server_state.locked().await;
+
+        let answers = |host: &str, client: Option<&str>| {
+            let mut query_state = QueryState::new(Query::query(name(host), RecordType::A), false);
+            query_state.client = client.map(|ip| ip.parse().unwrap());
+
+            async move {
+                locked.perform_query(&mut query_state).await;
+
+                let mut answers: Vec<String> = query_state
+                    .answers()
+                    .iter()
+                    .map(|record| record.data().unwrap().to_string())
+                    .collect();
+                answers.sort();
+                answers
+            }
+        };
+
+        assert_eq!(
+            answers("www.home.local.", None).await,
+            vec!["10.10.1.5", "203.0.113.5"]
+        );
+        assert_eq!(
+            answers("www.home.local.", Some("10.10.4.20")).await,
+            vec!["10.10.1.5", "203.0.113.5"]
+        );
+        assert_eq!(
+            answers("www.home.local.", Some("192.168.1.20")).await,
+            vec!["203.0.113.5"]
+        );
+        assert_eq!(
+            answers("vpn.home.local.", Some("10.50.0.20")).await,
+            vec!["10.10.1.6"]
+        );
+        assert!(answers("vpn.home.local.", Some("192.168.1.20"))
+            .await
+            .is_empty());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn name_server() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+server:
+  addresses:
+    - 10.10.1.2
+    - fd00::2
+
+zones:
+  home.local: {}
+  other.local:
+    authoritative: false
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+        let server_state = ServerState::new(RecordSet::new(), config.zones);
+        let locked = server_state.locked().await;
+
+        let result = locked
+            .query(Query::query(name("home.local."), RecordType::NS), false)
+            .await;
+        assert_eq!(result.response_code, ResponseCode::NoError);
+        assert!(result.authoritative);
+        assert_eq!(result.answers.len(), 1);
+        assert_eq!(
+            *result.answers[0].data().unwrap(),
+            RRData::NS(NS(name("ns.home.local.")))
+        );
+        assert_eq!(result.additionals.len(), 2);
+        assert!(result
+            .additionals
+            .iter()
+            .any(|record| *record.data().unwrap() == rdata_a("10.10.1.2")));
+
+        let result = locked
+            .query(Query::query(name("ns.home.local."), RecordType::A), false)
+            .await;
+        assert_eq!(result.answers.len(), 1);
+        assert_eq!(*result.answers[0].data().unwrap(), rdata_a("10.10.1.2"));
+
+        let result = locked
+            .query(
+                Query::query(name("ns.home.local."), RecordType::AAAA),
+                false,
+            )
+            .await;
+        assert_eq!(result.answers.len(), 1);
+
+        let result = locked
+            .query(Query::query(name("www.home.local."), RecordType::NS), false)
+            .await;
+        assert!(result.answers.is_empty());
+
+        let result = locked
+            .query(Query::query(name("other.local."), RecordType::NS), false)
+            .await;
+        assert!(result.answers.is_empty());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn warmup() {
+        let server_state = ServerState::new(RecordSet::new(), AuthoritativeZones {});
+        assert!(server_state.is_warming_up());
+
+        let locked = server_state.locked().await;
+
+        let query = Query::query(name("unknown.home.local."), RecordType::A);
+        let mut query_state = QueryState::new(query, false);
+        locked.perform_query(&mut query_state).await;
+        assert_eq!(query_state.response_code, ResponseCode::NXDomain);
+
+        locked.apply_warmup(&mut query_state, 5);
+        assert_eq!(query_state.response_code, ResponseCode::NoError);
+        assert!(query_state.answers().is_empty());
+
+        let soa = query_state.soa.as_ref().unwrap();
+        assert_eq!(soa.ttl(), 5);
+        match soa.data() {
+            Some(RRData::SOA(soa)) => assert_eq!(soa.minimum(), 5),
+            _ => panic!("Expected an SOA record"),
+        }
+
+        // Non-authoritative zones are left alone.
+        let query = Query::query(name("unknown.other.local."), RecordType::A);
+        let mut query_state = QueryState::new(query, false);
+        locked.perform_query(&mut query_state).await;
+        locked.apply_warmup(&mut query_state, 5);
+        assert_eq!(query_state.response_code, ResponseCode::NXDomain);
+        assert!(query_state.soa.is_none());
+
+        server_state.end_warmup();
+        assert!(!server_state.is_warming_up());
+        assert!(logs_contain("warm-up complete"));
+    }
 }
@@ -1,15 +1,26 @@
-use std::{collections::HashSet, iter::once, net::SocketAddr};
+use std::{
+    collections::{HashMap, HashSet},
+    iter::once,
+    net::{IpAddr, SocketAddr},
+};
 
 use hickory_server::proto::{
-    op::{Header, Query, ResponseCode},
+    op::{Edns, Header, Message, Query, ResponseCode},
     rr::{self, DNSClass, Name, RData, RecordType},
+    serialize::binary::BinEncodable,
 };
 
+use super::{forwarding::ForwardPath, QueryResult};
+
 pub(super) struct QueryState {
     /// The original query.
     pub(super) query: Query,
     /// Whether recursion was requested.
     pub(super) recursion_desired: bool,
+    /// Where the query came from, if it came over the network.
+    pub(super) client: Option<IpAddr>,
+    /// The LocalNS servers that forwarded the query here.
+    pub(super) forwarded: Option<ForwardPath>,
 
     /// A list of names that we have already seen
     seen: HashSet<Name>,
@@ -25,6 +36,10 @@ pub(super) struct QueryState {
     additionals: Vec<rr::Record>,
     pub(super) name_servers: Vec<rr::Record>,
     pub(super) soa: Option<rr::Record>,
+    /// The upstream servers that provided a response.
+    pub(super) upstreams: Vec<String>,
+    /// Whether records were left out because the response was too large.
+    truncated: bool,
 }
 
 impl QueryState {
@@ -35,6 +50,8 @@ impl QueryState {
 
             query,
             recursion_desired,
+            client: None,
+            forwarded: None,
 
             recursion_available: true,
             response_code: ResponseCode::NXDomain,
@@ -43,6 +60,8 @@ impl QueryState {
             additionals: Vec::new(),
             name_servers: Vec::new(),
             soa: None,
+            upstreams: Vec::new(),
+            truncated: false,
         }
     }
 
@@ -120,10 +139,90 @@ impl QueryState {
         self.additionals.extend(records);
     }
 
-    pub(super) fn next_unknown(&mut self) -> Option<Name> {
-        let next = self.unknowns.iter().next()?.clone();
-        self.unknowns.remove(&next);
-        Some(next)
+    /// A name in the answers whose CNAME records lead back to itself.
+    pub(super) fn cname_loop(&self) -> Option<Name> {
+        fn find_loop<'a>(
+            targets: &HashMap<&'a Name, Vec<&'a Name>>,
+            name: &'a Name,
+            chain: &mut Vec<&'a Name>,
+        ) -> Option<Name> {
+            if chain.contains(&name) {
+                return Some(name.clone());
+            }
+
+            chain.push(name);
+            for target in targets.get(name).into_iter().flatten() {
+                if let Some(found) = find_loop(targets, target, chain) {
+                    return Some(found);
+                }
+            }
+            chain.pop();
+
+            None
+        }
+
+        let mut targets: HashMap<&Name, Vec<&Name>> = HashMap::new();
+        for record in &self.answers {
+            if let Some(RData::CNAME(cname)) = record.data() {
+                targets.entry(record.name()).or_default().push(&cname.0);
+            }
+        }
+
+        find_loop(&targets, self.query.name(), &mut Vec::new())
+    }
+
+    /// Drops everything found so far and answers with SERVFAIL.
+    pub(super) fn fail(&mut self) {
+        self.response_code = ResponseCode::ServFail;
+        self.unknowns.clear();
+        self.answers.clear();
+        self.additionals.clear();
+        self.name_servers.clear();
+        self.soa = None;
+    }
+
+    /// Takes all of the names that remain to be looked up.
+    pub(super) fn take_unknowns(&mut self) -> Vec<Name> {
+        self.unknowns.drain().collect()
+    }
+
+    /// A separate state for looking up one of the unknown names alongside the
+    /// others. Its results are added back with `merge`.
+    pub(super) fn fork(&self) -> Self {
+        QueryState {
+            query: self.query.clone(),
+            recursion_desired: self.recursion_desired,
+            client: self.client,
+            forwarded: self.forwarded.clone(),
+
+            seen: self.seen.clone(),
+            unknowns: HashSet::new(),
+
+            recursion_available: self.recursion_available,
+            response_code: self.response_code,
+
+            answers: Vec::new(),
+            additionals: Vec::new(),
+            name_servers: Vec::new(),
+            soa: None,
+            upstreams: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    pub(super) fn merge(&mut self, forked: QueryState) {
+        if forked.response_code == ResponseCode::NoError {
+            self.response_code = ResponseCode::NoError;
+        }
+
+        self.add_answers(forked.answers);
+        self.add_additionals(forked.additionals);
+
+        for upstream in forked.upstreams {
+            if !self.upstreams.contains(&upstream) {
+                self.upstreams.push(upstream);
+            }
+        }
     }
 
     pub(super) fn header(&self, request_header: &Header) -> Header {
@@ -131,6 +230,72 @@ impl QueryState {
         response_header.set_authoritative(self.soa.is_some());
         response_header.set_recursion_available(self.recursion_available);
         response_header.set_response_code(self.response_code);
+        response_header.set_truncated(self.truncated);
         response_header
     }
+
+    /// The response as it would be sent to the client.
+    pub(super) fn message(&self, request_header: &Header, edns: Option<&Edns>) -> Message {
+        let mut message = Message::new();
+        message
+            .set_header(self.header(request_header))
+            .add_query(self.query.clone())
+            .add_answers(self.answers.iter().cloned())
+            .add_name_servers(self.name_servers.iter().cloned())
+            .add_name_servers(self.soa.iter().cloned())
+            .add_additionals(self.additionals.iter().cloned());
+
+        if let Some(edns) = edns {
+            message.set_edns(edns.clone());
+        }
+
+        message
+    }
+
+    fn encoded_len(&self, request_header: &Header, edns: Option<&Edns>) -> usize {
+        self.message(request_header, edns)
+            .to_vec()
+            .map_or(usize::MAX, |bytes| bytes.len())
+    }
+
+    /// Shrinks the response to fit in `limit` bytes. Additional records are
+    /// optional so they are dropped first. If that isn't enough the answers are
+    /// dropped too and the response is marked as truncated so the client
+    /// retries over TCP.
+    pub(super) fn fit_payload(
+        &mut self,
+        request_header: &Header,
+        edns: Option<&Edns>,
+        limit: usize,
+    ) {
+        if self.encoded_len(request_header, edns) <= limit {
+            return;
+        }
+
+        self.additionals.clear();
+        if self.encoded_len(request_header, edns) <= limit {
+            return;
+        }
+
+        self.answers.clear();
+        self.name_servers.clear();
+        self.truncated = true;
+    }
+}
+
+impl From<QueryState> for QueryResult {
+    fn from(query_state: QueryState) -> Self {
+        QueryResult {
+            response_code: query_state.response_code,
+            authoritative: query_state.soa.is_some(),
+            answers: query_state.answers,
+            name_servers: query_state
+                .name_servers
+                .into_iter()
+                .chain(query_state.soa)
+                .collect(),
+            additionals: query_state.additionals,
+            upstreams: query_state.upstreams,
+        }
+    }
 }
@@ -0,0 +1,110 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How often to report the number of queries that have been dropped.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    dropped: u64,
+    reported: Option<Instant>,
+}
+
+/// A token bucket limiting the rate of accepted queries. Bursts of up to one
+/// second's worth of queries are allowed.
+pub(crate) struct RateLimiter {
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(rate: u32) -> Self {
+        Self {
+            rate: rate.into(),
+            bucket: Mutex::new(Bucket {
+                tokens: rate.into(),
+                updated: Instant::now(),
+                dropped: 0,
+                reported: None,
+            }),
+        }
+    }
+
+    /// Returns false if the query should be dropped.
+    pub(crate) fn accept(&self) -> bool {
+        self.accept_at(Instant::now())
+    }
+
+    fn accept_at(&self, now: Instant) -> bool {
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return true;
+        }
+
+        bucket.dropped += 1;
+
+        let report = bucket
+            .reported
+            .map(|reported| now.saturating_duration_since(reported) >= REPORT_INTERVAL)
+            .unwrap_or(true);
+
+        if report {
+            tracing::warn!(
+                dropped = bucket.dropped,
+                max_query_rate = self.rate,
+                "Query rate exceeded, dropping UDP queries"
+            );
+
+            bucket.dropped = 0;
+            bucket.reported = Some(now);
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::RateLimiter;
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn rate_limit() {
+        let limiter = RateLimiter::new(10);
+        let start = Instant::now();
+
+        // The initial burst is allowed.
+        for _ in 0..10 {
+            assert!(limiter.accept_at(start));
+        }
+        assert!(!limiter.accept_at(start));
+        assert!(!limiter.accept_at(start));
+        assert!(logs_contain("dropped=1"));
+
+        // Tokens refill at the configured rate.
+        let later = start + Duration::from_millis(500);
+        for _ in 0..5 {
+            assert!(limiter.accept_at(later));
+        }
+        assert!(!limiter.accept_at(later));
+
+        // But never beyond a second's worth.
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..10 {
+            assert!(limiter.accept_at(much_later));
+        }
+        assert!(!limiter.accept_at(much_later));
+        assert!(logs_contain("dropped=3"));
+    }
+}
@@ -0,0 +1,56 @@
+use std::{
+    collections::VecDeque,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::dns::query::QueryState;
+
+/// How many queries are remembered.
+const RECENT_QUERY_LIMIT: usize = 50;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct RecentQuery {
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) client: IpAddr,
+    pub(crate) name: String,
+    #[serde(rename = "type")]
+    pub(crate) record_type: String,
+    pub(crate) response_code: String,
+    pub(crate) answers: usize,
+    /// The upstream servers that provided a response.
+    pub(crate) upstreams: Vec<String>,
+}
+
+/// The most recent queries received by the DNS server, shown by the web
+/// dashboard.
+#[derive(Clone, Default, Debug)]
+pub(crate) struct RecentQueries {
+    queries: Arc<Mutex<VecDeque<RecentQuery>>>,
+}
+
+impl RecentQueries {
+    pub(super) fn record(&self, client: IpAddr, query_state: &QueryState) {
+        let query = RecentQuery {
+            timestamp: Utc::now(),
+            client,
+            name: query_state.query.name().to_string(),
+            record_type: query_state.query_type().to_string(),
+            response_code: query_state.response_code.to_string(),
+            answers: query_state.answers().len(),
+            upstreams: query_state.upstreams.clone(),
+        };
+
+        let mut queries = self.queries.lock().unwrap();
+        queries.push_front(query);
+        queries.truncate(RECENT_QUERY_LIMIT);
+    }
+
+    /// The queries, newest first.
+    pub(crate) fn queries(&self) -> Vec<RecentQuery> {
+        self.queries.lock().unwrap().iter().cloned().collect()
+    }
+}
@@ -1,20 +1,26 @@
 use std::{
-    collections::{hash_map::IntoValues, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::{self},
     hash::Hash,
-    iter::{empty, once, Flatten},
+    iter::{empty, once, FlatMap},
+    mem::size_of,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     ops::Deref,
     str::FromStr,
 };
 
+use anyhow::{bail, Error};
 use hickory_server::proto::{
     error::ProtoError,
-    rr::{self, rdata, DNSClass, IntoName, Name, RecordType},
+    rr::{self, domain::Label, rdata, DNSClass, IntoName, Name, RecordType},
 };
+use im::hashmap::{ConsumingIter, Entry};
 use serde::{Deserialize, Serialize};
 
-use crate::config::ZoneConfig;
+use crate::{
+    config::{ConflictPolicy, ZoneConfig, ZoneConfigProvider},
+    sources::SourceId,
+};
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
 #[serde(tag = "type", content = "value", rename_all = "UPPERCASE")]
@@ -147,8 +153,23 @@ impl From<Name> for Fqdn {
 impl TryFrom<&str> for Fqdn {
     type Error = ProtoError;
 
+    /// Labels are converted to punycode where they are valid international names
+    /// and otherwise must be plain ASCII. This is done per label so names like
+    /// `_http.café.local` work. `Display` gives back the unicode form.
     fn try_from(s: &str) -> Result<Self, Self::Error> {
-        let mut name = Name::from_str(s)?;
+        let s = s.trim();
+        let s = s.strip_suffix('.').unwrap_or(s);
+
+        if s.is_empty() {
+            return Ok(Name::root().into());
+        }
+
+        let labels = s
+            .split('.')
+            .map(|label| Label::from_utf8(label).or_else(|_| Label::from_ascii(label)))
+            .collect::<Result<Vec<Label>, ProtoError>>()?;
+
+        let mut name = Name::from_labels(labels)?;
         name.set_fqdn(true);
         Ok(name.into())
     }
@@ -167,6 +188,13 @@ pub(crate) struct Record {
     name: Fqdn,
     pub(crate) ttl: Option<u32>,
     rdata: RData,
+    /// Freeform information about the record such as its owner. This is only
+    /// exposed through the API, never in DNS answers.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) metadata: BTreeMap<String, String>,
+    /// Limits which clients are given the record, see `ZoneConfig::hidden_tags`.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub(crate) tags: BTreeSet<String>,
 }
 
 impl fmt::Debug for Record {
@@ -183,19 +211,27 @@ impl fmt::Debug for Record {
 
 impl Record {
     pub(crate) fn new(name: Fqdn, rdata: RData) -> Self {
-        if let RData::Cname(ref alias) = rdata {
-            if &name == alias {
-                panic!("Attempted to create a CNAME cycle with {}", name);
-            }
-        }
-
         Self {
             name,
             rdata,
             ttl: None,
+            metadata: BTreeMap::new(),
+            tags: BTreeSet::new(),
         }
     }
 
+    /// Checks for records that can never be served. Sources provide whatever
+    /// they are given so these must be caught before they are used.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if let RData::Cname(ref alias) = self.rdata {
+            if &self.name == alias {
+                bail!("CNAME record for {} points at itself", self.name);
+            }
+        }
+
+        Ok(())
+    }
+
     pub(crate) fn name(&self) -> &Fqdn {
         &self.name
     }
@@ -204,6 +240,39 @@ impl Record {
         &self.rdata
     }
 
+    /// Converts a record received from elsewhere, keeping its TTL. Only the
+    /// record types that can be served are supported.
+    pub(crate) fn from_raw(record: &rr::Record) -> Option<Self> {
+        let name = Fqdn::from(record.name().clone());
+        let rdata = match record.data()? {
+            rr::RData::A(a) => RData::A(a.0),
+            rr::RData::AAAA(aaaa) => RData::Aaaa(aaaa.0),
+            rr::RData::CNAME(cname) => RData::Cname(cname.0.clone().into()),
+            rr::RData::PTR(ptr) => RData::Ptr(ptr.0.clone().into()),
+            _ => return None,
+        };
+
+        if let RData::Cname(ref alias) = rdata {
+            if alias == &name {
+                return None;
+            }
+        }
+
+        Some(Self {
+            name,
+            rdata,
+            ttl: Some(record.ttl()),
+            metadata: BTreeMap::new(),
+            tags: BTreeSet::new(),
+        })
+    }
+
+    /// Whether a client that cannot see `hidden_tags` is given this record.
+    /// Records are visible as long as one of their tags is.
+    pub(crate) fn is_visible(&self, hidden_tags: &HashSet<String>) -> bool {
+        self.tags.is_empty() || self.tags.iter().any(|tag| !hidden_tags.contains(tag))
+    }
+
     pub(crate) fn raw(&self, config: &ZoneConfig) -> Option<rr::Record> {
         let name = self.name().name();
         let data: rr::RData = self.rdata.clone().try_into().ok()?;
@@ -216,13 +285,89 @@ impl Record {
     }
 }
 
-#[derive(Default, PartialEq, Eq, Clone, Deserialize, Serialize)]
+/// Names store up to 32 bytes of label data inline, longer names spill onto the
+/// heap.
+fn name_heap_size(name: &Name) -> usize {
+    match name.len() {
+        len if len > 32 => len,
+        _ => 0,
+    }
+}
+
+impl Record {
+    fn heap_size(&self) -> usize {
+        let metadata: usize = self
+            .metadata
+            .iter()
+            .map(|(key, value)| size_of::<(String, String)>() + key.capacity() + value.capacity())
+            .sum();
+
+        let tags: usize = self
+            .tags
+            .iter()
+            .map(|tag| size_of::<String>() + tag.capacity())
+            .sum();
+
+        name_heap_size(&self.name)
+            + metadata
+            + tags
+            + match self.rdata {
+                RData::Cname(ref name) | RData::Ptr(ref name) => name_heap_size(name),
+                RData::A(_) | RData::Aaaa(_) => 0,
+            }
+    }
+}
+
+/// The names and addresses that may have different records after the sets
+/// from some sources are replaced.
+#[derive(Debug, Default)]
+pub(crate) struct RecordChanges {
+    names: HashSet<Fqdn>,
+    addresses: HashSet<IpAddr>,
+}
+
+impl RecordChanges {
+    /// Notes every name and address that has records in the set.
+    pub(crate) fn add(&mut self, records: &RecordSet) {
+        self.names.extend(records.records.keys().cloned());
+        self.addresses.extend(records.reverse.keys().copied());
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.addresses.is_empty()
+    }
+}
+
+/// The sources that gave a name its records in a merged set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Provenance {
+    /// The sources whose records are used.
+    pub(crate) sources: Vec<SourceId>,
+    /// Every source that gave the name records, only when they disagree.
+    pub(crate) conflicting: Vec<SourceId>,
+}
+
+#[derive(Default, Eq, Clone, Deserialize, Serialize)]
 #[serde(from = "Vec<Record>")]
 #[serde(into = "Vec<Record>")]
-pub(crate) struct RecordSet {
-    records: HashMap<Fqdn, HashSet<Record>>,
-    reverse: HashMap<IpAddr, Record>,
-    names: HashSet<Name>,
+#[cfg_attr(not(feature = "record-builder"), allow(unreachable_pub))]
+pub struct RecordSet {
+    // Persistent maps so that a copy of the set shares everything with the
+    // original and only the parts that are later changed are copied.
+    records: im::HashMap<Fqdn, HashSet<Record>>,
+    reverse: im::HashMap<IpAddr, Record>,
+    names: im::HashSet<Name>,
+    /// How many names with records are below each name.
+    parents: im::HashMap<Name, usize>,
+    /// Only known for sets built by `merge_changes`.
+    provenance: im::HashMap<Fqdn, Provenance>,
+}
+
+// Where the records came from doesn't change what they are.
+impl PartialEq for RecordSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.records == other.records && self.reverse == other.reverse && self.names == other.names
+    }
 }
 
 impl fmt::Debug for RecordSet {
@@ -252,7 +397,6 @@ impl RecordSet {
         Default::default()
     }
 
-    #[cfg(test)]
     pub(crate) fn contains(&self, name: &Fqdn, rdata: &RData) -> bool {
         self.records
             .get(name)
@@ -283,15 +427,74 @@ impl RecordSet {
         self.records.values().flatten()
     }
 
+    /// The reverse lookup records generated from the addresses in the set,
+    /// leaving out those replaced by PTR records given explicitly.
+    pub(crate) fn reverse_records(&self) -> impl Iterator<Item = &Record> {
+        self.reverse.values().filter(|ptr| {
+            !self.records.get(ptr.name()).is_some_and(|records| {
+                records
+                    .iter()
+                    .any(|record| matches!(record.rdata(), RData::Ptr(_) | RData::Cname(_)))
+            })
+        })
+    }
+
+    /// Whether the name has visible records of any type, or is the parent of a
+    /// name that does. Queries for other types of record for the name get an
+    /// empty answer rather than NXDOMAIN.
+    pub(crate) fn name_exists(&self, name: &Name, hidden_tags: &HashSet<String>) -> bool {
+        if name
+            .parse_arpa_name()
+            .ok()
+            .and_then(|net| self.reverse.get(&net.addr()))
+            .is_some_and(|record| record.is_visible(hidden_tags))
+        {
+            return true;
+        }
+
+        if !self.names.contains(name) && !self.parents.contains_key(name) {
+            return false;
+        }
+
+        // Every record is visible when no tags are hidden.
+        if hidden_tags.is_empty() {
+            return true;
+        }
+
+        self.records.iter().any(|(fqdn, records)| {
+            name.zone_of(&fqdn.name()) && records.iter().any(|r| r.is_visible(hidden_tags))
+        })
+    }
+
+    /// Counts a new name with records towards each of its parents.
+    fn add_parents(&mut self, fqdn: &Fqdn) {
+        let mut name = fqdn.name();
+        while name.num_labels() > 1 {
+            name = name.trim_to(name.num_labels() as usize - 1);
+            *self.parents.entry(name.clone()).or_default() += 1;
+        }
+    }
+
+    /// Stops counting a name that no longer has records towards its parents.
+    fn remove_parents(&mut self, fqdn: &Fqdn) {
+        let mut name = fqdn.name();
+        while name.num_labels() > 1 {
+            name = name.trim_to(name.num_labels() as usize - 1);
+            if let Entry::Occupied(mut entry) = self.parents.entry(name.clone()) {
+                *entry.get_mut() -= 1;
+                if *entry.get() == 0 {
+                    entry.remove();
+                }
+            }
+        }
+    }
+
     fn apply_records<T>(&mut self, fqdn: &Fqdn, records: T)
     where
         T: Iterator<Item = Record>,
     {
-        let mut name = fqdn.name();
-        self.names.insert(name.clone());
-        while name.num_labels() > 1 {
-            name = name.trim_to(name.num_labels() as usize - 1);
-            self.names.insert(fqdn.name());
+        if self.names.insert(fqdn.name()).is_none() {
+            self.add_parents(fqdn);
         }
 
         let inner = self.records.entry(fqdn.clone()).or_default();
@@ -304,12 +507,14 @@ impl RecordSet {
                         let mut ptr =
                             Record::new(Name::from(*ip).into(), RData::Ptr(record.name().clone()));
                         ptr.ttl = record.ttl;
+                        ptr.tags = record.tags.clone();
                         self.reverse.insert(IpAddr::from(*ip), ptr);
                     }
                     RData::Aaaa(ip) => {
                         let mut ptr =
                             Record::new(Name::from(*ip).into(), RData::Ptr(record.name().clone()));
                         ptr.ttl = record.ttl;
+                        ptr.tags = record.tags.clone();
                         self.reverse.insert(IpAddr::from(*ip), ptr);
                     }
                     _ => {}
@@ -320,6 +525,26 @@ impl RecordSet {
         }
     }
 
+    /// Drops the records that fail validation, passing each to `invalid`.
+    pub(crate) fn validated<F>(self, mut invalid: F) -> Self
+    where
+        F: FnMut(&Record, Error),
+    {
+        if self.records().all(|record| record.validate().is_ok()) {
+            return self;
+        }
+
+        let mut records = RecordSet::new();
+        for record in self {
+            match record.validate() {
+                Ok(()) => records.insert(record),
+                Err(e) => invalid(&record, e),
+            }
+        }
+
+        records
+    }
+
     pub(crate) fn append(&mut self, records: RecordSet) {
         for (name, records) in records.records {
             self.apply_records(&name, records.into_iter());
@@ -330,7 +555,197 @@ impl RecordSet {
         self.apply_records(&record.name().clone(), once(record));
     }
 
-    pub(crate) fn len(&self) -> usize {
+    /// The names, including those of reverse lookup records, that have different
+    /// records in this set than in `other`.
+    pub(crate) fn changed_names(&self, other: &RecordSet) -> HashSet<Fqdn> {
+        let mut changed = HashSet::new();
+
+        for name in self.records.keys().chain(other.records.keys()) {
+            if self.records.get(name) != other.records.get(name) {
+                changed.insert(name.clone());
+            }
+        }
+
+        for ip in self.reverse.keys().chain(other.reverse.keys()) {
+            let (ours, theirs) = (self.reverse.get(ip), other.reverse.get(ip));
+            if ours != theirs {
+                if let Some(record) = ours.or(theirs) {
+                    changed.insert(record.name().clone());
+                }
+            }
+        }
+
+        changed
+    }
+
+    /// Rebuilds the records for the changed names and addresses from the sets
+    /// that make up this one, returning the names whose records differ. Every
+    /// other name is left alone so the cost depends only on the size of the
+    /// change. The sources are given in order of preference, `policy` decides
+    /// which are used for a name that they give different records.
+    pub(crate) fn merge_changes(
+        &mut self,
+        changes: &RecordChanges,
+        sources: &[(&SourceId, &RecordSet)],
+        policy: ConflictPolicy,
+    ) -> HashSet<Fqdn> {
+        let mut changed = HashSet::new();
+        // Names that are withheld or answered again need their addresses checked
+        // even if the sources giving those addresses haven't changed.
+        let mut addresses = changes.addresses.clone();
+
+        for name in &changes.names {
+            let given: Vec<(&SourceId, &HashSet<Record>)> = sources
+                .iter()
+                .filter_map(|(source_id, source)| {
+                    source
+                        .records
+                        .get(name)
+                        .map(|records| (*source_id, records))
+                })
+                .collect();
+
+            let rdata = |records: &HashSet<Record>| -> HashSet<RData> {
+                records.iter().map(|record| record.rdata.clone()).collect()
+            };
+            let conflicting = given
+                .windows(2)
+                .any(|pair| rdata(pair[0].1) != rdata(pair[1].1));
+
+            let used = match policy {
+                ConflictPolicy::PreferSourceOrder if conflicting => &given[..1],
+                ConflictPolicy::Error if conflicting => &[],
+                _ => &given[..],
+            };
+
+            let provenance = Provenance {
+                sources: used.iter().map(|(id, _)| (*id).clone()).collect(),
+                conflicting: if conflicting {
+                    given.iter().map(|(id, _)| (*id).clone()).collect()
+                } else {
+                    Vec::new()
+                },
+            };
+
+            let previous = self.provenance.remove(name);
+            if conflicting
+                && previous.map_or(true, |previous| {
+                    previous.conflicting != provenance.conflicting
+                })
+            {
+                let ids: Vec<String> = provenance
+                    .conflicting
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+
+                if policy == ConflictPolicy::Error {
+                    tracing::error!(
+                        name = %name,
+                        sources = ids.join(", "),
+                        "Sources gave conflicting records, not answering for the name",
+                    );
+                } else {
+                    tracing::warn!(
+                        name = %name,
+                        sources = ids.join(", "),
+                        policy = ?policy,
+                        "Sources gave conflicting records",
+                    );
+                }
+            }
+
+            if policy == ConflictPolicy::Error {
+                addresses.extend(
+                    given
+                        .iter()
+                        .flat_map(|(_, records)| records.iter())
+                        .filter_map(|record| match record.rdata {
+                            RData::A(ip) => Some(IpAddr::from(ip)),
+                            RData::Aaaa(ip) => Some(IpAddr::from(ip)),
+                            _ => None,
+                        }),
+                );
+            }
+
+            if !given.is_empty() {
+                self.provenance.insert(name.clone(), provenance);
+            }
+
+            let merged: HashSet<Record> = used
+                .iter()
+                .flat_map(|(_, records)| records.iter())
+                .cloned()
+                .collect();
+
+            let current = self.records.get(name);
+            if current.map_or(merged.is_empty(), |current| *current == merged) {
+                continue;
+            }
+
+            changed.insert(name.clone());
+            if merged.is_empty() {
+                self.records.remove(name);
+                if self.names.remove(&name.name()).is_some() {
+                    self.remove_parents(name);
+                }
+            } else {
+                if self.names.insert(name.name()).is_none() {
+                    self.add_parents(name);
+                }
+                self.records.insert(name.clone(), merged);
+            }
+        }
+
+        for ip in &addresses {
+            // Names that aren't answered don't answer reverse lookups either.
+            let merged = sources.iter().find_map(|(_, source)| {
+                source.reverse.get(ip).filter(|ptr| !self.is_withheld(ptr))
+            });
+            if self.reverse.get(ip) == merged {
+                continue;
+            }
+
+            if let Some(record) = merged.or(self.reverse.get(ip)) {
+                changed.insert(record.name().clone());
+            }
+
+            match merged {
+                Some(record) => self.reverse.insert(*ip, record.clone()),
+                None => self.reverse.remove(ip),
+            };
+        }
+
+        changed
+    }
+
+    /// Whether a generated PTR record points at a name whose records are
+    /// withheld because its sources conflict.
+    fn is_withheld(&self, ptr: &Record) -> bool {
+        let RData::Ptr(ref name) = ptr.rdata else {
+            return false;
+        };
+
+        self.provenance
+            .get(name)
+            .is_some_and(|provenance| provenance.sources.is_empty())
+    }
+
+    /// Which sources gave the records for a name, if this set was merged from
+    /// the records of several.
+    pub(crate) fn provenance(&self, name: &Fqdn) -> Option<&Provenance> {
+        self.provenance.get(name)
+    }
+
+    /// The names that sources gave different records.
+    pub(crate) fn conflicts(&self) -> impl Iterator<Item = (&Fqdn, &Provenance)> {
+        self.provenance
+            .iter()
+            .filter(|(_, provenance)| !provenance.conflicting.is_empty())
+    }
+
+    #[cfg_attr(not(feature = "record-builder"), allow(unreachable_pub))]
+    pub fn len(&self) -> usize {
         let mut count: usize = 0;
         for records in self.records.values() {
             count += records.len()
@@ -338,7 +753,70 @@ impl RecordSet {
         count
     }
 
-    pub(crate) fn is_empty(&self) -> bool {
+    /// An approximation of the memory used by this set in bytes. Hash tables are
+    /// assumed to cost one control byte per bucket on top of the entry itself
+    /// and persistent maps a pointer per entry. Anything shared with copies of
+    /// the set is counted in full.
+    pub(crate) fn estimated_size(&self) -> usize {
+        fn table_size<T>(capacity: usize) -> usize {
+            capacity * (size_of::<T>() + 1)
+        }
+
+        fn map_size<T>(len: usize) -> usize {
+            len * (size_of::<T>() + size_of::<usize>())
+        }
+
+        let mut size = size_of::<Self>()
+            + map_size::<(Fqdn, HashSet<Record>)>(self.records.len())
+            + map_size::<(IpAddr, Record)>(self.reverse.len())
+            + map_size::<Name>(self.names.len())
+            + map_size::<(Name, usize)>(self.parents.len());
+
+        for (name, records) in &self.records {
+            size += name_heap_size(name) + table_size::<Record>(records.capacity());
+            size += records.iter().map(Record::heap_size).sum::<usize>();
+        }
+
+        size += self.reverse.values().map(Record::heap_size).sum::<usize>();
+        size += self.names.iter().map(name_heap_size).sum::<usize>();
+        size += self.parents.keys().map(name_heap_size).sum::<usize>();
+
+        size
+    }
+
+    /// Finds names in dual-stack zones that only have one of A or AAAA records,
+    /// returning the missing record type for each.
+    pub(crate) fn missing_address_families<Z: ZoneConfigProvider>(
+        &self,
+        zones: &Z,
+    ) -> Vec<(Fqdn, RecordType)> {
+        let mut missing: Vec<(Fqdn, RecordType)> = self
+            .records
+            .iter()
+            .filter_map(|(name, records)| {
+                let has_type =
+                    |record_type| records.iter().any(|r| r.rdata.data_type() == record_type);
+
+                let missing = match (has_type(RecordType::A), has_type(RecordType::AAAA)) {
+                    (true, false) => RecordType::AAAA,
+                    (false, true) => RecordType::A,
+                    _ => return None,
+                };
+
+                if zones.zone_config(name).dual_stack {
+                    Some((name.clone(), missing))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        missing.sort();
+        missing
+    }
+
+    #[cfg_attr(not(feature = "record-builder"), allow(unreachable_pub))]
+    pub fn is_empty(&self) -> bool {
         for records in self.records.values() {
             if !records.is_empty() {
                 return false;
@@ -359,13 +837,30 @@ impl RecordSet {
         }
 
         match query_type {
-            RecordType::PTR => Box::new(
-                name.parse_arpa_name()
-                    .ok()
-                    .and_then(|net| self.reverse.get(&net.addr()))
+            RecordType::PTR => {
+                // PTR records given explicitly by a source replace the one
+                // generated from the addresses of other records.
+                let explicit: Vec<Record> = self
+                    .records
+                    .get(&name.clone().into())
+                    .into_iter()
+                    .flatten()
+                    .filter(|record| matches!(record.rdata(), RData::Ptr(_) | RData::Cname(_)))
                     .cloned()
-                    .into_iter(),
-            ),
+                    .collect();
+
+                if !explicit.is_empty() {
+                    return Box::new(explicit.into_iter());
+                }
+
+                Box::new(
+                    name.parse_arpa_name()
+                        .ok()
+                        .and_then(|net| self.reverse.get(&net.addr()))
+                        .cloned()
+                        .into_iter(),
+                )
+            }
             _ => match self.records.get(&name.clone().into()) {
                 Some(records) => Box::new(
                     records
@@ -385,10 +880,15 @@ impl RecordSet {
 impl IntoIterator for RecordSet {
     type Item = Record;
 
-    type IntoIter = Flatten<IntoValues<Fqdn, HashSet<Record>>>;
+    type IntoIter = FlatMap<
+        ConsumingIter<(Fqdn, HashSet<Record>)>,
+        HashSet<Record>,
+        fn((Fqdn, HashSet<Record>)) -> HashSet<Record>,
+    >;
 
-    fn into_iter(self) -> Flatten<IntoValues<Fqdn, HashSet<Record>>> {
-        self.records.into_values().flatten()
+    fn into_iter(self) -> Self::IntoIter {
+        let records: fn((Fqdn, HashSet<Record>)) -> HashSet<Record> = |(_, records)| records;
+        self.records.into_iter().flat_map(records)
     }
 }
 
@@ -438,7 +938,21 @@ impl From<RecordSet> for Vec<Record> {
 
 #[cfg(test)]
 mod tests {
-    use crate::dns::Fqdn;
+    use std::{collections::HashSet, mem::size_of, net::IpAddr};
+
+    use hickory_server::proto::rr::{DNSClass, RecordType};
+    use uuid::Uuid;
+
+    use crate::{
+        config::{ConflictPolicy, ZoneConfig, ZoneConfigProvider},
+        dns::{Fqdn, RData, Record, RecordChanges, RecordSet},
+        sources::{SourceId, SourceType},
+        test,
+    };
+
+    fn source_id(name: &str) -> SourceId {
+        SourceId::new(&Uuid::nil(), SourceType::File, name)
+    }
 
     #[tracing_test::traced_test]
     #[test]
@@ -447,5 +961,324 @@ mod tests {
             Fqdn::try_from("test.example.com.").unwrap(),
             Fqdn::try_from("test.example.com").unwrap()
         );
+
+        assert_eq!(
+            Fqdn::try_from(" test.example.com. ").unwrap(),
+            Fqdn::try_from("test.example.com").unwrap()
+        );
+        assert!(Fqdn::try_from("test..example.com").is_err());
+        assert!(Fqdn::try_from("test.example.com..").is_err());
+
+        let unicode = Fqdn::try_from("café.example.com").unwrap();
+        assert_eq!(unicode.to_ascii(), "xn--caf-dma.example.com.");
+        assert_eq!(unicode.to_string(), "café.example.com.");
+        assert_eq!(unicode, Fqdn::try_from("xn--caf-dma.example.com").unwrap());
+        assert_eq!(
+            serde_json::to_string(&unicode).unwrap(),
+            r#""café.example.com.""#
+        );
+
+        // Labels that aren't valid international names can still be used
+        // alongside unicode labels.
+        assert_eq!(
+            Fqdn::try_from("_http._tcp.café.example.com")
+                .unwrap()
+                .to_ascii(),
+            "_http._tcp.xn--caf-dma.example.com."
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn metadata() {
+        let plain = Record::new(
+            test::fqdn("www.home.local"),
+            RData::A("10.10.1.5".parse().unwrap()),
+        );
+
+        // Records without metadata serialize as they always have.
+        let json = serde_json::to_value(&plain).unwrap();
+        assert!(json.get("metadata").is_none());
+
+        let mut annotated = plain.clone();
+        annotated
+            .metadata
+            .insert("owner".to_owned(), "Dave".to_owned());
+
+        let json = serde_json::to_string(&annotated).unwrap();
+        let parsed: Record = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, annotated);
+        assert_ne!(parsed, plain);
+
+        let config = ZoneConfig::default();
+        assert_eq!(annotated.raw(&config), plain.raw(&config));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn explicit_ptr() {
+        let records = RecordSet::builder()
+            .a("www.home.local", "10.10.1.5")
+            .a("printer.home.local", "10.10.1.5")
+            .ptr("5.1.10.10.in-addr.arpa", "printer.home.local")
+            .a("other.home.local", "10.10.1.6")
+            .build();
+
+        let lookup = |name: &str| -> Vec<RData> {
+            records
+                .lookup(&test::name(name), DNSClass::IN, RecordType::PTR)
+                .map(|record| record.rdata().clone())
+                .collect()
+        };
+
+        assert_eq!(
+            lookup("5.1.10.10.in-addr.arpa."),
+            vec![RData::Ptr(test::fqdn("printer.home.local"))]
+        );
+        assert_eq!(
+            lookup("6.1.10.10.in-addr.arpa."),
+            vec![RData::Ptr(test::fqdn("other.home.local"))]
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn validated() {
+        let records = RecordSet::builder()
+            .a("www.home.local", "10.10.1.5")
+            .cname("web.home.local", "www.home.local")
+            .cname("self.home.local", "self.home.local")
+            .build();
+
+        let mut invalid = Vec::new();
+        let records = records.validated(|record, _| invalid.push(record.clone()));
+
+        assert_eq!(
+            invalid,
+            vec![Record::new(
+                test::fqdn("self.home.local"),
+                RData::Cname(test::fqdn("self.home.local"))
+            )]
+        );
+        assert_eq!(records.len(), 2);
+        assert!(records.contains(
+            &test::fqdn("web.home.local"),
+            &RData::Cname(test::fqdn("www.home.local"))
+        ));
+        assert!(records.contains_reverse(
+            "10.10.1.5".parse::<IpAddr>().unwrap(),
+            &test::fqdn("www.home.local")
+        ));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn estimated_size() {
+        let empty = RecordSet::new().estimated_size();
+
+        let mut records = RecordSet::builder()
+            .a("www.home.local", "10.10.1.5")
+            .build();
+        let small = records.estimated_size();
+        assert!(small > empty);
+
+        for i in 0..100 {
+            records.insert(Record::new(
+                test::fqdn(&format!("a-rather-long-hostname-{i}.somewhere.home.local")),
+                RData::A(format!("10.10.2.{i}").parse().unwrap()),
+            ));
+        }
+        assert!(records.estimated_size() > small + 100 * 2 * size_of::<Record>());
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn missing_address_families() {
+        struct DualStack;
+
+        impl ZoneConfigProvider for DualStack {
+            fn zone_config(&self, name: &Fqdn) -> ZoneConfig {
+                ZoneConfig {
+                    dual_stack: test::fqdn("dual.local").zone_of(name),
+                    ..Default::default()
+                }
+            }
+        }
+
+        let records = RecordSet::builder()
+            .a("both.dual.local", "10.10.1.1")
+            .aaaa("both.dual.local", "fd00::1")
+            .a("v4.dual.local", "10.10.1.2")
+            .aaaa("v6.dual.local", "fd00::3")
+            .a("v4.other.local", "10.10.1.4")
+            .cname("alias.dual.local", "v4.dual.local")
+            .build();
+
+        assert_eq!(
+            records.missing_address_families(&DualStack),
+            vec![
+                (test::fqdn("v4.dual.local"), RecordType::AAAA),
+                (test::fqdn("v6.dual.local"), RecordType::A),
+            ]
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn merge_changes() {
+        let first = RecordSet::builder()
+            .a("www.home.local", "10.10.1.5")
+            .a("db.home.local", "10.10.1.6")
+            .build();
+        let second = RecordSet::builder()
+            .a("www.home.local", "10.10.1.5")
+            .cname("web.home.local", "www.home.local")
+            .build();
+
+        let mut merged: RecordSet = [first.clone(), second.clone()].into_iter().collect();
+
+        let updated_id = source_id("first");
+        let second_id = source_id("second");
+
+        // Records also provided by another source remain.
+        let updated = RecordSet::builder().a("db.home.local", "10.10.1.7").build();
+        let mut changes = RecordChanges::default();
+        changes.add(&first);
+        changes.add(&updated);
+
+        let changed = merged.merge_changes(
+            &changes,
+            &[(&updated_id, &updated), (&second_id, &second)],
+            ConflictPolicy::Merge,
+        );
+        assert_eq!(
+            changed,
+            [
+                test::fqdn("db.home.local"),
+                test::fqdn("6.1.10.10.in-addr.arpa"),
+                test::fqdn("7.1.10.10.in-addr.arpa"),
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert_eq!(
+            merged,
+            [updated.clone(), second.clone()].into_iter().collect()
+        );
+        assert!(merged.contains_reverse(
+            "10.10.1.5".parse::<IpAddr>().unwrap(),
+            &test::fqdn("www.home.local")
+        ));
+
+        // Removing a source removes the names only it provided.
+        let mut changes = RecordChanges::default();
+        changes.add(&second);
+
+        let changed =
+            merged.merge_changes(&changes, &[(&updated_id, &updated)], ConflictPolicy::Merge);
+        assert_eq!(
+            changed,
+            [
+                test::fqdn("web.home.local"),
+                test::fqdn("www.home.local"),
+                test::fqdn("5.1.10.10.in-addr.arpa")
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert_eq!(merged, updated);
+        assert!(!merged.has_name(&test::name("www.home.local.")));
+
+        let hidden_tags = HashSet::new();
+        assert!(!merged.name_exists(&test::name("www.home.local."), &hidden_tags));
+        assert!(merged.name_exists(&test::name("db.home.local."), &hidden_tags));
+        assert!(merged.name_exists(&test::name("home.local."), &hidden_tags));
+
+        let mut changes = RecordChanges::default();
+        changes.add(&updated);
+
+        merged.merge_changes(&changes, &[], ConflictPolicy::Merge);
+        assert!(!merged.name_exists(&test::name("home.local."), &hidden_tags));
+        assert!(!merged.name_exists(&test::name("local."), &hidden_tags));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn conflicts() {
+        let docker = RecordSet::builder()
+            .a("www.home.local", "10.10.1.5")
+            .a("db.home.local", "10.10.1.6")
+            .build();
+        let file = RecordSet::builder()
+            .a("www.home.local", "10.10.1.8")
+            .a("db.home.local", "10.10.1.6")
+            .build();
+        let docker_id = source_id("docker");
+        let file_id = source_id("file");
+
+        let mut changes = RecordChanges::default();
+        changes.add(&docker);
+        changes.add(&file);
+        let sources = [(&file_id, &file), (&docker_id, &docker)];
+
+        let merge = |policy: ConflictPolicy| {
+            let mut merged = RecordSet::new();
+            merged.merge_changes(&changes, &sources, policy);
+            merged
+        };
+
+        let merged = merge(ConflictPolicy::Merge);
+        assert_eq!(merged.len(), 3);
+        let provenance = merged.provenance(&test::fqdn("www.home.local")).unwrap();
+        assert_eq!(provenance.sources, vec![file_id.clone(), docker_id.clone()]);
+        assert_eq!(provenance.conflicting, provenance.sources);
+        assert!(logs_contain("Sources gave conflicting records"));
+
+        // Agreeing sources are not a conflict.
+        let provenance = merged.provenance(&test::fqdn("db.home.local")).unwrap();
+        assert_eq!(provenance.sources.len(), 2);
+        assert!(provenance.conflicting.is_empty());
+        assert_eq!(merged.conflicts().count(), 1);
+
+        let merged = merge(ConflictPolicy::PreferSourceOrder);
+        assert_eq!(merged.len(), 2);
+        assert!(merged.contains(
+            &test::fqdn("www.home.local"),
+            &RData::A("10.10.1.8".parse().unwrap())
+        ));
+        let provenance = merged.provenance(&test::fqdn("www.home.local")).unwrap();
+        assert_eq!(provenance.sources, vec![file_id.clone()]);
+        assert_eq!(provenance.conflicting.len(), 2);
+
+        let merged = merge(ConflictPolicy::Error);
+        assert_eq!(merged.len(), 1);
+        assert!(!merged.has_name(&test::name("www.home.local.")));
+        assert!(merged
+            .provenance(&test::fqdn("www.home.local"))
+            .unwrap()
+            .sources
+            .is_empty());
+        assert_eq!(merged.conflicts().count(), 1);
+        let www = test::fqdn("www.home.local");
+        assert!(!merged.contains_reverse("10.10.1.5".parse::<IpAddr>().unwrap(), &www));
+        assert!(!merged.contains_reverse("10.10.1.8".parse::<IpAddr>().unwrap(), &www));
+        assert!(merged.contains_reverse(
+            "10.10.1.6".parse::<IpAddr>().unwrap(),
+            &test::fqdn("db.home.local")
+        ));
+
+        // The addresses of a name are withheld when a conflicting source is
+        // added, even though the first source's addresses didn't change.
+        let mut merged = RecordSet::new();
+        let mut changes = RecordChanges::default();
+        changes.add(&docker);
+        merged.merge_changes(&changes, &[(&docker_id, &docker)], ConflictPolicy::Error);
+        assert!(merged.contains_reverse("10.10.1.5".parse::<IpAddr>().unwrap(), &www));
+
+        let mut changes = RecordChanges::default();
+        changes.add(&file);
+        let changed = merged.merge_changes(&changes, &sources, ConflictPolicy::Error);
+        assert!(changed.contains(&test::fqdn("5.1.10.10.in-addr.arpa")));
+        assert!(!merged.contains_reverse("10.10.1.5".parse::<IpAddr>().unwrap(), &www));
     }
 }
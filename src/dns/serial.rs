@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+
+use crate::dns::Fqdn;
+
+fn now() -> u32 {
+    Utc::now().timestamp().clamp(0, u32::MAX.into()) as u32
+}
+
+/// The SOA serial numbers for each zone. Serials start from the current time so
+/// they keep increasing across restarts and are bumped whenever the records in a
+/// zone change.
+#[derive(Clone, Debug)]
+pub(super) struct ZoneSerials {
+    initial: u32,
+    zones: HashMap<Fqdn, u32>,
+}
+
+impl Default for ZoneSerials {
+    fn default() -> Self {
+        Self {
+            initial: now(),
+            zones: HashMap::new(),
+        }
+    }
+}
+
+impl ZoneSerials {
+    pub(super) fn serial(&self, origin: &Fqdn) -> u32 {
+        self.zones.get(origin).copied().unwrap_or(self.initial)
+    }
+
+    fn next(serial: u32) -> u32 {
+        serial.wrapping_add(1).max(now())
+    }
+
+    pub(super) fn bump(&mut self, origin: Fqdn) {
+        let serial = Self::next(self.serial(&origin));
+        self.zones.insert(origin, serial);
+    }
+
+    /// Bumps the serial of every zone, used when the zone configuration changes.
+    pub(super) fn bump_all(&mut self) {
+        let highest = self.zones.values().copied().fold(self.initial, u32::max);
+
+        self.initial = Self::next(highest);
+        self.zones.clear();
+    }
+}
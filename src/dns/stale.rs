@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use hickory_server::proto::rr::{self, DNSClass, Name, RecordType};
+
+use crate::dns::{answer_cache::AnswerCache, upstream::UpstreamKey};
+
+/// The most lookups that are kept at once.
+const CACHE_LIMIT: usize = 10000;
+/// The TTL given to stale answers so that clients ask again soon, as RFC 8767
+/// recommends.
+const STALE_TTL: u32 = 30;
+
+/// The last answers from upstream servers for names in zones that serve stale
+/// answers (RFC 8767). When none of the upstreams can be reached these are
+/// given instead, for up to the zone's `max_stale` after they expire. Answers
+/// are only given to lookups that would go to the same upstreams with the same
+/// client subnet.
+#[derive(Clone, Debug)]
+pub(crate) struct StaleCache {
+    answers: AnswerCache,
+}
+
+impl Default for StaleCache {
+    fn default() -> Self {
+        Self {
+            answers: AnswerCache::new(CACHE_LIMIT, STALE_TTL),
+        }
+    }
+}
+
+impl StaleCache {
+    /// The cached answers if they expired no more than `max_stale` ago. Their
+    /// TTLs are reduced by the time since they were cached but never below
+    /// `STALE_TTL`.
+    pub(super) fn get(
+        &self,
+        upstreams: &UpstreamKey,
+        name: &Name,
+        query_class: DNSClass,
+        query_type: RecordType,
+        max_stale: Duration,
+    ) -> Option<Vec<rr::Record>> {
+        self.answers.get(
+            &(upstreams.clone(), name.clone(), query_class, query_type),
+            max_stale,
+        )
+    }
+
+    /// Remembers the answers from the upstreams. An empty answer means the
+    /// name or its records have gone so any older answers are forgotten.
+    pub(super) fn insert(
+        &self,
+        upstreams: &UpstreamKey,
+        name: &Name,
+        query_class: DNSClass,
+        query_type: RecordType,
+        answers: &[rr::Record],
+        max_stale: Duration,
+    ) {
+        self.answers.insert(
+            (upstreams.clone(), name.clone(), query_class, query_type),
+            answers,
+            max_stale,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hickory_server::proto::rr::{self, DNSClass, RecordType};
+
+    use crate::{
+        dns::{
+            stale::{StaleCache, STALE_TTL},
+            upstream::{Upstream, UpstreamKey},
+        },
+        test::{name, rdata_a},
+    };
+
+    fn upstreams(address: &str) -> UpstreamKey {
+        let upstream: Upstream = serde_yaml::from_str(address).unwrap();
+        UpstreamKey::new(&[upstream], None)
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn staleness() {
+        let cache = StaleCache::default();
+        let target = name("www.example.org.");
+        let max_stale = Duration::from_secs(3600);
+        let key = upstreams("10.10.1.1");
+
+        let answer = |ttl: u32| rr::Record::from_rdata(target.clone(), ttl, rdata_a("10.10.1.1"));
+
+        assert!(cache
+            .get(&key, &target, DNSClass::IN, RecordType::A, max_stale)
+            .is_none());
+
+        cache.insert(
+            &key,
+            &target,
+            DNSClass::IN,
+            RecordType::A,
+            &[answer(300)],
+            max_stale,
+        );
+        let answers = cache
+            .get(&key, &target, DNSClass::IN, RecordType::A, max_stale)
+            .unwrap();
+        assert_eq!(answers.len(), 1);
+        assert!(answers[0].ttl() <= 300);
+        assert!(cache
+            .get(&key, &target, DNSClass::IN, RecordType::AAAA, max_stale)
+            .is_none());
+
+        // Answers that have already expired get a short TTL.
+        cache.insert(
+            &key,
+            &target,
+            DNSClass::IN,
+            RecordType::A,
+            &[answer(0)],
+            max_stale,
+        );
+        let answers = cache
+            .get(&key, &target, DNSClass::IN, RecordType::A, max_stale)
+            .unwrap();
+        assert_eq!(answers[0].ttl(), STALE_TTL);
+
+        // Until they are too stale to use.
+        assert!(cache
+            .get(&key, &target, DNSClass::IN, RecordType::A, Duration::ZERO)
+            .is_none());
+        assert!(cache
+            .get(&key, &target, DNSClass::IN, RecordType::A, max_stale)
+            .is_none());
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn removed() {
+        let cache = StaleCache::default();
+        let target = name("www.example.org.");
+        let max_stale = Duration::from_secs(3600);
+        let key = upstreams("10.10.1.1");
+        let answer = rr::Record::from_rdata(target.clone(), 300, rdata_a("10.10.1.1"));
+
+        cache.insert(
+            &key,
+            &target,
+            DNSClass::IN,
+            RecordType::A,
+            &[answer],
+            max_stale,
+        );
+
+        // Other upstreams never gave an answer.
+        assert!(cache
+            .get(
+                &upstreams("10.10.1.2"),
+                &target,
+                DNSClass::IN,
+                RecordType::A,
+                max_stale
+            )
+            .is_none());
+        assert!(cache
+            .get(&key, &target, DNSClass::IN, RecordType::A, max_stale)
+            .is_some());
+
+        // The name went away upstream.
+        cache.insert(&key, &target, DNSClass::IN, RecordType::A, &[], max_stale);
+        assert!(cache
+            .get(&key, &target, DNSClass::IN, RecordType::A, max_stale)
+            .is_none());
+    }
+}
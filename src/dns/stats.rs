@@ -0,0 +1,142 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use hickory_server::proto::op::ResponseCode;
+use serde::{Deserialize, Serialize};
+
+use crate::dns::{query::QueryState, Fqdn};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ZoneStats {
+    /// The zone, missing for names that aren't in any configured zone.
+    pub(crate) zone: Option<Fqdn>,
+    pub(crate) queries: u64,
+    pub(crate) nxdomain: u64,
+    /// The fraction of queries answered with NXDOMAIN.
+    pub(crate) nxdomain_rate: f64,
+    /// Queries that were answered at least in part by upstream servers.
+    pub(crate) forwarded: u64,
+    /// The average time taken to answer a query.
+    pub(crate) latency_ms: f64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    queries: u64,
+    nxdomain: u64,
+    forwarded: u64,
+    latency: Duration,
+}
+
+/// Counts the queries received by the DNS server for each zone.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct QueryStats {
+    zones: Arc<Mutex<HashMap<Option<Fqdn>, Counters>>>,
+}
+
+impl QueryStats {
+    pub(super) fn record(&self, zone: Option<Fqdn>, query_state: &QueryState, latency: Duration) {
+        let mut zones = self.zones.lock().unwrap();
+        let counters = zones.entry(zone).or_default();
+
+        counters.queries += 1;
+        counters.latency += latency;
+        if query_state.response_code == ResponseCode::NXDomain {
+            counters.nxdomain += 1;
+        }
+        if !query_state.upstreams.is_empty() {
+            counters.forwarded += 1;
+        }
+    }
+
+    /// The statistics for every zone that has received a query, sorted by zone.
+    pub(crate) fn zones(&self) -> Vec<ZoneStats> {
+        let mut stats: Vec<ZoneStats> = self
+            .zones
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(zone, counters)| {
+                let queries = counters.queries.max(1) as f64;
+
+                ZoneStats {
+                    zone: zone.clone(),
+                    queries: counters.queries,
+                    nxdomain: counters.nxdomain,
+                    nxdomain_rate: counters.nxdomain as f64 / queries,
+                    forwarded: counters.forwarded,
+                    latency_ms: counters.latency.as_secs_f64() * 1000.0 / queries,
+                }
+            })
+            .collect();
+
+        stats.sort_by(|a, b| a.zone.cmp(&b.zone));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hickory_server::proto::{
+        op::{Query, ResponseCode},
+        rr::RecordType,
+    };
+
+    use crate::{
+        dns::{query::QueryState, stats::QueryStats},
+        test::{fqdn, name},
+    };
+
+    fn query_state(response_code: ResponseCode, upstream: Option<&str>) -> QueryState {
+        let mut query_state =
+            QueryState::new(Query::query(name("www.home.local."), RecordType::A), true);
+        query_state.response_code = response_code;
+        query_state.upstreams.extend(upstream.map(str::to_owned));
+        query_state
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn zones() {
+        let stats = QueryStats::default();
+        assert!(stats.zones().is_empty());
+
+        let home = Some(fqdn("home.local"));
+        stats.record(
+            home.clone(),
+            &query_state(ResponseCode::NoError, None),
+            Duration::from_millis(2),
+        );
+        stats.record(
+            home.clone(),
+            &query_state(ResponseCode::NXDomain, None),
+            Duration::from_millis(4),
+        );
+        stats.record(
+            None,
+            &query_state(ResponseCode::NoError, Some("10.10.1.1")),
+            Duration::from_millis(30),
+        );
+
+        let zones = stats.zones();
+        assert_eq!(zones.len(), 2);
+
+        assert_eq!(zones[0].zone, None);
+        assert_eq!(zones[0].queries, 1);
+        assert_eq!(zones[0].forwarded, 1);
+        assert_eq!(zones[0].nxdomain_rate, 0.0);
+        assert!((zones[0].latency_ms - 30.0).abs() < 0.001);
+
+        assert_eq!(zones[1].zone, home);
+        assert_eq!(zones[1].queries, 2);
+        assert_eq!(zones[1].nxdomain, 1);
+        assert_eq!(zones[1].nxdomain_rate, 0.5);
+        assert_eq!(zones[1].forwarded, 0);
+        assert!((zones[1].latency_ms - 3.0).abs() < 0.001);
+    }
+}
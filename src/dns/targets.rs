@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use hickory_server::proto::rr::{self, DNSClass, Name, RecordType};
+
+use crate::dns::{answer_cache::AnswerCache, upstream::UpstreamKey};
+
+/// The most lookups that are cached at once.
+const CACHE_LIMIT: usize = 1000;
+
+/// Upstream answers for the targets of local CNAME records, such as a local name
+/// pointing at a CDN. Without this every query for the local name would need a
+/// lookup upstream. Answers are kept for their shortest TTL and only given to
+/// lookups that would go to the same upstreams with the same client subnet.
+#[derive(Clone, Debug)]
+pub(crate) struct TargetCache {
+    answers: AnswerCache,
+}
+
+impl Default for TargetCache {
+    fn default() -> Self {
+        Self {
+            answers: AnswerCache::new(CACHE_LIMIT, 0),
+        }
+    }
+}
+
+impl TargetCache {
+    /// The cached answers with their TTLs reduced by the time since they were
+    /// cached.
+    pub(super) fn get(
+        &self,
+        upstreams: &UpstreamKey,
+        name: &Name,
+        query_class: DNSClass,
+        query_type: RecordType,
+    ) -> Option<Vec<rr::Record>> {
+        self.answers.get(
+            &(upstreams.clone(), name.clone(), query_class, query_type),
+            Duration::ZERO,
+        )
+    }
+
+    pub(super) fn insert(
+        &self,
+        upstreams: &UpstreamKey,
+        name: &Name,
+        query_class: DNSClass,
+        query_type: RecordType,
+        answers: &[rr::Record],
+    ) {
+        self.answers.insert(
+            (upstreams.clone(), name.clone(), query_class, query_type),
+            answers,
+            Duration::ZERO,
+        );
+    }
+
+    /// Forgets everything, the upstreams may have changed.
+    pub(super) fn clear(&self) {
+        self.answers.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_server::proto::rr::{self, DNSClass, RecordType};
+
+    use crate::{
+        dns::{
+            targets::TargetCache,
+            upstream::{Upstream, UpstreamKey},
+        },
+        test::{name, rdata_a},
+    };
+
+    fn upstreams(config: &str, client: &str) -> UpstreamKey {
+        let upstream: Upstream = serde_yaml::from_str(config).unwrap();
+        UpstreamKey::new(&[upstream], Some(client.parse().unwrap()))
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn expiry() {
+        let cache = TargetCache::default();
+        let target = name("cdn.example.org.");
+        let key = upstreams("10.10.1.1", "10.14.23.102");
+
+        let answer = |ttl: u32| rr::Record::from_rdata(target.clone(), ttl, rdata_a("10.10.1.1"));
+
+        assert!(cache
+            .get(&key, &target, DNSClass::IN, RecordType::A)
+            .is_none());
+
+        cache.insert(&key, &target, DNSClass::IN, RecordType::A, &[answer(300)]);
+        let answers = cache
+            .get(&key, &target, DNSClass::IN, RecordType::A)
+            .unwrap();
+        assert_eq!(answers.len(), 1);
+        assert!(answers[0].ttl() <= 300);
+        assert!(cache
+            .get(&key, &target, DNSClass::IN, RecordType::AAAA)
+            .is_none());
+
+        // Answers that can't be cached.
+        let other = name("other.example.org.");
+        cache.insert(&key, &other, DNSClass::IN, RecordType::A, &[]);
+        cache.insert(&key, &other, DNSClass::IN, RecordType::A, &[answer(0)]);
+        assert!(cache
+            .get(&key, &other, DNSClass::IN, RecordType::A)
+            .is_none());
+
+        cache.clear();
+        assert!(cache
+            .get(&key, &target, DNSClass::IN, RecordType::A)
+            .is_none());
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn upstream_keys() {
+        let cache = TargetCache::default();
+        let target = name("cdn.example.org.");
+        let answer = rr::Record::from_rdata(target.clone(), 300, rdata_a("10.10.1.1"));
+
+        let ecs = "address: 10.10.1.1\nclient_subnet: client";
+        let key = upstreams(ecs, "10.14.23.102");
+        cache.insert(&key, &target, DNSClass::IN, RecordType::A, &[answer]);
+
+        // The same upstream and client subnet.
+        assert!(cache
+            .get(
+                &upstreams(ecs, "10.14.23.50"),
+                &target,
+                DNSClass::IN,
+                RecordType::A
+            )
+            .is_some());
+
+        // A client on a different network.
+        assert!(cache
+            .get(
+                &upstreams(ecs, "10.14.24.50"),
+                &target,
+                DNSClass::IN,
+                RecordType::A
+            )
+            .is_none());
+
+        // A view with different upstreams.
+        assert!(cache
+            .get(
+                &upstreams("10.10.1.2", "10.14.23.102"),
+                &target,
+                DNSClass::IN,
+                RecordType::A
+            )
+            .is_none());
+    }
+}
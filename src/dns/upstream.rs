@@ -1,48 +1,480 @@
-use std::{fmt, net::SocketAddr};
+#[cfg(feature = "quic")]
+use std::{collections::HashMap, future::Future, sync::Mutex};
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use anyhow::{anyhow, bail};
+use base64::{engine::general_purpose::STANDARD, Engine};
+#[cfg(feature = "quic")]
+use hickory_client::proto::quic::QuicClientStream;
 use hickory_client::{
-    client::{AsyncClient, ClientHandle},
-    op::DnsResponse,
-    rr::{self, DNSClass, Name, RecordType},
+    client::AsyncClient,
+    op::{
+        DnsResponse, Edns, Message, MessageFinalizer, MessageType, MessageVerifier, OpCode, Query,
+        ResponseCode,
+    },
+    proto::{
+        error::ProtoResult,
+        xfer::{DnsHandle, DnsRequest, DnsRequestOptions, FirstAnswer},
+    },
+    rr::{
+        self,
+        dnssec::{rdata::tsig::TsigAlgorithm, tsig::TSigner},
+        rdata::opt::{ClientSubnet, EdnsOption},
+        DNSClass, Name, RecordType,
+    },
     udp::UdpClientStream,
 };
 use serde::Deserialize;
-use tokio::net::UdpSocket;
+#[cfg(feature = "quic")]
+use tokio::task::AbortHandle;
+use tokio::{net::UdpSocket, time::timeout};
 use tracing::{instrument, Span};
 
-use crate::{dns::query::QueryState, util::Address, Error};
+use crate::{
+    dns::{forwarding::ForwardPath, health::UpstreamHealth, query::QueryState},
+    util::{Address, Subnet},
+    Error,
+};
+
+/// How long to wait for an upstream server to respond by default.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// The allowed clock skew between us and the upstream server for signed queries.
+const TSIG_FUDGE: u16 = 300;
+/// The EDNS payload size advertised to upstream servers.
+const EDNS_PAYLOAD: u16 = 1232;
+
+/// Hickory only signs zone transfers and updates by default, forwarded queries
+/// must be signed too.
+struct QuerySigner(TSigner);
 
-type UpstreamConfig = Address;
+impl MessageFinalizer for QuerySigner {
+    fn finalize_message(
+        &self,
+        message: &Message,
+        current_time: u32,
+    ) -> ProtoResult<(Vec<rr::Record>, Option<MessageVerifier>)> {
+        self.0.finalize_message(message, current_time)
+    }
 
-async fn connect_client(address: SocketAddr) -> Result<AsyncClient, Error> {
-    let stream = UdpClientStream::<UdpSocket>::new(address);
+    fn should_finalize_message(&self, _: &Message) -> bool {
+        true
+    }
+}
 
-    let client = AsyncClient::connect(stream);
-    let (client, bg) = client.await?;
-    tokio::spawn(bg);
+async fn connect_client(
+    address: SocketAddr,
+    signer: Option<TSigner>,
+    timeout: Duration,
+) -> Result<AsyncClient, Error> {
+    let client = match signer {
+        Some(signer) => {
+            let stream = UdpClientStream::<UdpSocket, QuerySigner>::with_timeout_and_signer(
+                address,
+                timeout,
+                Some(Arc::new(QuerySigner(signer))),
+            );
+            let (client, bg) = AsyncClient::connect(stream).await?;
+            tokio::spawn(bg);
+            client
+        }
+        None => {
+            let stream = UdpClientStream::<UdpSocket>::with_timeout(address, timeout);
+            let (client, bg) = AsyncClient::connect(stream).await?;
+            tokio::spawn(bg);
+            client
+        }
+    };
 
     Ok(client)
 }
 
+/// Connects to a DNS-over-QUIC server, checking that its certificate is for
+/// `tls_name`. The returned task ends when the connection closes.
+#[cfg(feature = "quic")]
+async fn connect_quic_client(
+    address: SocketAddr,
+    tls_name: String,
+    connect_timeout: Duration,
+) -> Result<(AsyncClient, AbortHandle), Error> {
+    let stream = QuicClientStream::builder().build(address, tls_name);
+    let (client, bg) = timeout(connect_timeout, AsyncClient::connect(stream))
+        .await
+        .map_err(|_| anyhow!("Timed out connecting to {address}"))??;
+    let task = tokio::spawn(bg).abort_handle();
+
+    Ok((client, task))
+}
+
+/// Open connections to upstreams that are expensive to set up, shared by every
+/// lookup sent to the same upstream. A connection is replaced once the task
+/// driving it ends.
+#[cfg(feature = "quic")]
+pub(super) struct Connections<C> {
+    open: Arc<Mutex<HashMap<UpstreamKey, (C, AbortHandle)>>>,
+}
+
+#[cfg(feature = "quic")]
+impl<C> Clone for Connections<C> {
+    fn clone(&self) -> Self {
+        Self {
+            open: self.open.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "quic")]
+impl<C> Default for Connections<C> {
+    fn default() -> Self {
+        Self {
+            open: Default::default(),
+        }
+    }
+}
+
+#[cfg(feature = "quic")]
+impl<C> fmt::Debug for Connections<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.open.lock().unwrap().keys())
+            .finish()
+    }
+}
+
+#[cfg(feature = "quic")]
+impl<C: Clone> Connections<C> {
+    /// Returns the open connection to the upstream, or makes a new one if there
+    /// is none or it has closed.
+    async fn get<F>(&self, upstream: &UpstreamKey, connect: F) -> Result<C, Error>
+    where
+        F: Future<Output = Result<(C, AbortHandle), Error>>,
+    {
+        if let Some((connection, task)) = self.open.lock().unwrap().get(upstream) {
+            if !task.is_finished() {
+                return Ok(connection.clone());
+            }
+        }
+
+        let (connection, task) = connect.await?;
+        if let Some((_, previous)) = self
+            .open
+            .lock()
+            .unwrap()
+            .insert(upstream.clone(), (connection.clone(), task))
+        {
+            previous.abort();
+        }
+
+        Ok(connection)
+    }
+
+    /// Closes the connection to an upstream that failed so the next lookup
+    /// reconnects.
+    fn remove(&self, upstream: &UpstreamKey) {
+        if let Some((_, task)) = self.open.lock().unwrap().remove(upstream) {
+            task.abort();
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Deserialize)]
-#[serde(from = "UpstreamConfig")]
+struct TsigConfig {
+    name: String,
+    #[serde(default)]
+    algorithm: Option<String>,
+    secret: String,
+}
+
+/// A TSIG key shared with an upstream server or secondary.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "TsigConfig")]
+pub(crate) struct TsigKey {
+    name: Name,
+    algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+impl fmt::Debug for TsigKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&format!("{} ({})", self.name, self.algorithm))
+    }
+}
+
+impl TsigKey {
+    pub(super) fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub(super) fn signer(&self) -> Result<TSigner, Error> {
+        Ok(TSigner::new(
+            self.secret.clone(),
+            self.algorithm.clone(),
+            self.name.clone(),
+            TSIG_FUDGE,
+        )?)
+    }
+}
+
+impl TryFrom<TsigConfig> for TsigKey {
+    type Error = Error;
+
+    fn try_from(config: TsigConfig) -> Result<Self, Self::Error> {
+        let algorithm = TsigAlgorithm::from_name(Name::from_str(
+            config.algorithm.as_deref().unwrap_or("hmac-sha256"),
+        )?);
+
+        if !algorithm.supported() {
+            bail!("Unsupported TSIG algorithm {algorithm}");
+        }
+
+        let key = TsigKey {
+            name: Name::from_str(&config.name)?,
+            algorithm,
+            secret: STANDARD.decode(config.secret.trim())?,
+        };
+
+        // Catch any other problems with the key at configuration time.
+        key.signer()?;
+
+        Ok(key)
+    }
+}
+
+/// Which EDNS client subnet to send to an upstream server.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) enum ClientSubnetMode {
+    /// The client's address truncated to a /24 for IPv4 or a /56 for IPv6.
+    Client,
+    /// An empty subnet asking the upstream not to tailor its answer.
+    Zeroed,
+    /// A fixed subnet, e.g. the network's public addresses.
+    Fixed(Subnet),
+}
+
+impl ClientSubnetMode {
+    fn subnet(&self, client: Option<IpAddr>) -> Option<Subnet> {
+        let subnet = match self {
+            ClientSubnetMode::Client => {
+                let client = client?;
+                let prefix = if client.is_ipv4() { 24 } else { 56 };
+                Subnet::new(client, prefix)
+            }
+            ClientSubnetMode::Zeroed => match client {
+                Some(IpAddr::V6(_)) => Subnet::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+                _ => Subnet::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            },
+            ClientSubnetMode::Fixed(subnet) => *subnet,
+        };
+
+        Some(subnet)
+    }
+
+    fn option(&self, client: Option<IpAddr>) -> Option<ClientSubnet> {
+        self.subnet(client)
+            .map(|subnet| ClientSubnet::new(subnet.network(), subnet.prefix(), 0))
+    }
+}
+
+impl TryFrom<String> for ClientSubnetMode {
+    type Error = Error;
+
+    fn try_from(mode: String) -> Result<Self, Self::Error> {
+        match mode.as_str() {
+            "client" => Ok(ClientSubnetMode::Client),
+            "zeroed" => Ok(ClientSubnetMode::Zeroed),
+            _ => Ok(ClientSubnetMode::Fixed(Subnet::try_from(mode)?)),
+        }
+    }
+}
+
+/// How queries are sent to an upstream server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    #[default]
+    Udp,
+    /// DNS-over-QUIC (RFC 9250).
+    #[cfg(feature = "quic")]
+    Quic,
+}
+
+impl Protocol {
+    fn default_port(&self) -> u16 {
+        match self {
+            Protocol::Udp => 53,
+            #[cfg(feature = "quic")]
+            Protocol::Quic => 853,
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = Error;
+
+    fn from_str(protocol: &str) -> Result<Self, Self::Err> {
+        match protocol {
+            "udp" => Ok(Protocol::Udp),
+            #[cfg(feature = "quic")]
+            "quic" => Ok(Protocol::Quic),
+            #[cfg(not(feature = "quic"))]
+            "quic" => bail!("This build does not support DNS-over-QUIC upstreams"),
+            _ => bail!("Unknown upstream protocol {protocol}"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+enum UpstreamConfig {
+    Address(Address),
+    Full {
+        address: Address,
+        /// Checked when converting so that an unsupported protocol gives a
+        /// clear error rather than failing to match the untagged enum.
+        #[serde(default)]
+        protocol: Option<String>,
+        /// The name the server's certificate must be for.
+        #[serde(default)]
+        tls_name: Option<String>,
+        #[serde(default)]
+        tsig: Option<TsigKey>,
+        #[serde(default)]
+        client_subnet: Option<ClientSubnetMode>,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+        /// Whether the upstream is another LocalNS server.
+        #[serde(default)]
+        localns: bool,
+    },
+}
+
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "UpstreamConfig")]
 pub(crate) struct Upstream {
-    pub(crate) config: UpstreamConfig,
+    pub(crate) config: Address,
+    protocol: Protocol,
+    tls_name: Option<String>,
+    tsig: Option<TsigKey>,
+    client_subnet: Option<ClientSubnetMode>,
+    timeout_ms: Option<u64>,
+    localns: bool,
 }
 
 impl fmt::Debug for Upstream {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.pad(&format!("{}", self.config))
+        let address = match self.protocol {
+            Protocol::Udp => self.config.to_string(),
+            #[cfg(feature = "quic")]
+            Protocol::Quic => format!("quic://{}", self.config),
+        };
+
+        match self.tsig {
+            Some(ref tsig) => f.pad(&format!("{address} (key {})", tsig.name)),
+            None => f.pad(&address),
+        }
+    }
+}
+
+impl From<Address> for Upstream {
+    fn from(config: Address) -> Upstream {
+        Upstream {
+            config,
+            protocol: Protocol::Udp,
+            tls_name: None,
+            tsig: None,
+            client_subnet: None,
+            timeout_ms: None,
+            localns: false,
+        }
+    }
+}
+
+impl TryFrom<UpstreamConfig> for Upstream {
+    type Error = Error;
+
+    fn try_from(config: UpstreamConfig) -> Result<Self, Self::Error> {
+        match config {
+            UpstreamConfig::Address(config) => Ok(config.into()),
+            UpstreamConfig::Full {
+                address,
+                protocol,
+                tls_name,
+                tsig,
+                client_subnet,
+                timeout_ms,
+                localns,
+            } => {
+                let protocol = protocol
+                    .as_deref()
+                    .map(Protocol::from_str)
+                    .transpose()?
+                    .unwrap_or_default();
+
+                #[cfg(feature = "quic")]
+                if protocol == Protocol::Quic {
+                    if tls_name.is_none() {
+                        bail!("DNS-over-QUIC upstream {address} needs a tls_name");
+                    }
+
+                    // The connection is already authenticated by TLS.
+                    if tsig.is_some() {
+                        bail!("TSIG keys are not supported for DNS-over-QUIC upstreams");
+                    }
+                }
+
+                Ok(Upstream {
+                    config: address,
+                    protocol,
+                    tls_name,
+                    tsig,
+                    client_subnet,
+                    timeout_ms,
+                    localns,
+                })
+            }
+        }
     }
 }
 
-impl From<UpstreamConfig> for Upstream {
-    fn from(config: UpstreamConfig) -> Upstream {
-        Upstream { config }
+/// The upstreams that a lookup is sent to and the client subnet that each is
+/// given. Answers cached for one of these can't be given to lookups that would
+/// have been sent elsewhere or answered for a different network.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(super) struct UpstreamKey(Vec<(String, Option<Subnet>)>);
+
+impl UpstreamKey {
+    pub(super) fn new<'a>(
+        upstreams: impl IntoIterator<Item = &'a Upstream>,
+        client: Option<IpAddr>,
+    ) -> Self {
+        Self(
+            upstreams
+                .into_iter()
+                .map(|upstream| {
+                    let subnet = upstream
+                        .client_subnet
+                        .as_ref()
+                        .and_then(|mode| mode.subnet(client));
+                    (format!("{upstream:?}"), subnet)
+                })
+                .collect(),
+        )
     }
 }
 
 impl Upstream {
+    fn timeout(&self) -> Duration {
+        self.timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(QUERY_TIMEOUT)
+    }
+
+    #[cfg_attr(not(feature = "quic"), allow(unused_variables))]
     #[instrument(fields(
         lookup.upstream = %self.config,
         lookup.name = %name,
@@ -55,57 +487,190 @@ impl Upstream {
         name: &Name,
         query_class: DNSClass,
         query_type: RecordType,
-    ) -> Option<DnsResponse> {
-        let address = self.config.to_socket_address(53);
+        client: Option<IpAddr>,
+        path: &ForwardPath,
+        health: &UpstreamHealth,
+    ) -> Result<DnsResponse, Error> {
+        let address = self.config.to_socket_address(self.protocol.default_port());
+        let subnet = self
+            .client_subnet
+            .as_ref()
+            .and_then(|mode| mode.option(client));
+
+        let signer = match self.tsig.as_ref().map(TsigKey::signer).transpose() {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::error!(error = %e, "Invalid TSIG key");
+                return Err(e);
+            }
+        };
+
+        let connection = match (self.protocol, &self.tls_name) {
+            #[cfg(feature = "quic")]
+            (Protocol::Quic, Some(tls_name)) => {
+                health
+                    .connections()
+                    .get(
+                        &UpstreamKey::new([self], None),
+                        connect_quic_client(address, tls_name.clone(), self.timeout()),
+                    )
+                    .await
+            }
+            _ => connect_client(address, signer, self.timeout()).await,
+        };
 
-        let mut client = match connect_client(address).await {
+        let connection = match connection {
             Ok(c) => c,
             Err(e) => {
                 tracing::error!(error = %e);
-                return None;
+                return Err(e);
             }
         };
 
-        let result = client.query(name.clone(), query_class, query_type).await;
+        let mut query = Query::query(name.clone(), query_type);
+        query.set_query_class(query_class);
+
+        let mut message = Message::new();
+        message
+            .add_query(query)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true);
+        let edns = message.extensions_mut().get_or_insert_with(Edns::new);
+        edns.set_max_payload(EDNS_PAYLOAD).set_version(0);
+        // Lets other LocalNS servers spot queries that loop back to them. The
+        // path identifies this server so it isn't sent to anything else.
+        if self.localns {
+            edns.options_mut().insert(path.option());
+        }
+        if let Some(subnet) = subnet {
+            edns.options_mut().insert(EdnsOption::Subnet(subnet));
+        }
+
+        let response = connection
+            .send(DnsRequest::new(message, DnsRequestOptions::default()))
+            .first_answer();
+        // Only UDP connections time out by themselves.
+        let result = match timeout(self.timeout(), response).await {
+            Ok(result) => result.map_err(Error::from),
+            Err(_) => Err(anyhow!("Timed out waiting for a response")),
+        };
 
         match result {
             Ok(response) => {
                 let span = Span::current();
                 span.record("lookup.response_code", response.response_code().to_string());
-                Some(response)
+                Ok(response)
             }
             Err(e) => {
                 tracing::warn!(error = %e, "Upstream DNS server returned error");
-                None
+                #[cfg(feature = "quic")]
+                if self.protocol == Protocol::Quic {
+                    health.connections().remove(&UpstreamKey::new([self], None));
+                }
+                Err(e)
             }
         }
     }
 
-    pub(super) async fn resolve(&self, name: &Name, query_state: &mut QueryState) {
-        if let Some(response) = self
-            .lookup(name, query_state.query_class(), query_state.query_type())
-            .await
-        {
-            let mut message = response.into_message();
+    /// Looks up a name, recording whether the upstream answered.
+    pub(super) async fn query(
+        &self,
+        name: &Name,
+        query_state: &QueryState,
+        health: &UpstreamHealth,
+    ) -> Result<DnsResponse, Error> {
+        let upstream = format!("{self:?}");
+        let start = Instant::now();
+        let path = health.loops().forward(query_state.forwarded.as_ref());
+
+        let result = self
+            .lookup(
+                name,
+                query_state.query_class(),
+                query_state.query_type(),
+                query_state.client,
+                &path,
+                health,
+            )
+            .await;
 
-            query_state.add_answers(message.take_answers());
-            query_state.add_additionals(message.take_additionals());
+        if health.loops().take_loop(&path) {
+            tracing::error!(
+                upstream,
+                name = %name,
+                "Forwarding loop detected, the upstream sends queries back to this server"
+            );
+            let error = "Forwarding loop, queries sent to the upstream come back to this server";
+            health.failure(&upstream, error.to_owned());
+            bail!(error);
+        }
 
-            if name == query_state.query.name() {
-                let mut name_servers: Vec<rr::Record> = Vec::new();
-                let mut soa: Option<rr::Record> = None;
+        match result {
+            Ok(response) => {
+                health.success(&upstream, start.elapsed());
+                Ok(response)
+            }
+            Err(e) => {
+                health.failure(&upstream, e.to_string());
+                Err(e)
+            }
+        }
+    }
 
-                for record in message.take_name_servers() {
-                    if record.record_type() == rr::RecordType::SOA {
-                        soa.replace(record);
-                    } else {
-                        name_servers.push(record);
-                    }
+    /// Adds the records from this upstream's response to the answer.
+    pub(super) fn add_response(
+        &self,
+        name: &Name,
+        response: DnsResponse,
+        query_state: &mut QueryState,
+    ) {
+        let upstream = format!("{self:?}");
+        let mut message = response.into_message();
+
+        if !query_state.upstreams.contains(&upstream) {
+            query_state.upstreams.push(upstream);
+        }
+
+        query_state.add_answers(message.take_answers());
+        query_state.add_additionals(message.take_additionals());
+
+        if name == query_state.query.name() {
+            // The name exists upstream even if there are no records of the type.
+            if message.response_code() == ResponseCode::NoError {
+                query_state.response_code = ResponseCode::NoError;
+            }
+
+            let mut name_servers: Vec<rr::Record> = Vec::new();
+            let mut soa: Option<rr::Record> = None;
+
+            for record in message.take_name_servers() {
+                if record.record_type() == rr::RecordType::SOA {
+                    soa.replace(record);
+                } else {
+                    name_servers.push(record);
                 }
+            }
+
+            query_state.name_servers.extend(name_servers);
+            query_state.soa = soa;
+        }
+    }
 
-                query_state.name_servers.extend(name_servers);
-                query_state.soa = soa;
+    /// Looks up a name and adds the response to the answer. Returns whether the
+    /// upstream answered.
+    pub(super) async fn resolve(
+        &self,
+        name: &Name,
+        query_state: &mut QueryState,
+        health: &UpstreamHealth,
+    ) -> bool {
+        match self.query(name, query_state, health).await {
+            Ok(response) => {
+                self.add_response(name, response, query_state);
+                true
             }
+            Err(_) => false,
         }
     }
 }
@@ -116,21 +681,21 @@ mod tests {
 
     use hickory_client::{
         op::{Query, ResponseCode},
-        rr::{DNSClass, RecordType},
+        rr::{rdata::opt::ClientSubnet, DNSClass, RecordType},
     };
 
     use crate::{
-        dns::{query::QueryState, Upstream},
-        test::{coredns_container, name, rdata_a, rdata_cname},
+        dns::{
+            health::UpstreamHealth,
+            query::QueryState,
+            upstream::{ClientSubnetMode, Protocol, TsigConfig, TsigKey, UpstreamKey},
+            Upstream,
+        },
+        test::{coredns_container, mock_dns, name, rdata_a, rdata_cname},
         util::{Address, Host},
     };
 
-    #[tracing_test::traced_test]
-    #[tokio::test]
-    async fn test_upstream() {
-        let coredns = coredns_container(
-            "example.org",
-            r#"
+    const ZONE: &str = r#"
 $ORIGIN example.org.
 @   3600 IN	SOA sns.dns.icann.org. noc.dns.icann.org. 2024102601 7200 3600 1209600 3600
     3600 IN NS a.iana-servers.net.
@@ -139,21 +704,263 @@ $ORIGIN example.org.
 www     IN A     10.10.10.5
         IN AAAA  2001::1
 data    IN CNAME www
+"#;
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_upstream() {
+        let upstream = mock_dns("example.org", ZONE).await;
+
+        check_upstream(upstream.port()).await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    #[cfg_attr(not(feature = "container-tests"), ignore = "requires docker")]
+    async fn test_upstream_coredns() {
+        let coredns = coredns_container("example.org", ZONE).await;
+
+        check_upstream(coredns.get_udp_port(53).await).await;
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn tsig_config() {
+        let config = |algorithm: Option<&str>, secret: &str| TsigConfig {
+            name: "localns-key".to_string(),
+            algorithm: algorithm.map(|a| a.to_string()),
+            secret: secret.to_string(),
+        };
+
+        assert!(TsigKey::try_from(config(None, "c2VjcmV0")).is_ok());
+        assert!(TsigKey::try_from(config(Some("hmac-sha512"), "c2VjcmV0")).is_ok());
+        assert!(TsigKey::try_from(config(Some("hmac-md5.sig-alg.reg.int"), "c2VjcmV0")).is_err());
+        assert!(TsigKey::try_from(config(None, "not base64!")).is_err());
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn protocol() {
+        let parse = |config: &str| serde_yaml::from_str::<Upstream>(config);
+
+        let upstream = parse("10.10.1.1").unwrap();
+        assert_eq!(upstream.protocol, Protocol::Udp);
+        assert!(!upstream.localns);
+        assert_eq!(format!("{upstream:?}"), "10.10.1.1");
+
+        let upstream = parse(
+            r#"
+address: 10.10.1.1
+localns: true
+"#,
+        )
+        .unwrap();
+        assert!(upstream.localns);
+
+        assert!(parse(
+            r#"
+address: 10.10.1.1
+protocol: tcp
+"#
+        )
+        .is_err());
+    }
+
+    #[cfg(not(feature = "quic"))]
+    #[tracing_test::traced_test]
+    #[test]
+    fn quic_unsupported() {
+        let error = serde_yaml::from_str::<Upstream>(
+            r#"
+address: 10.10.1.1
+protocol: quic
+tls_name: router.home.local
 "#,
         )
-        .await;
+        .unwrap_err();
+
+        assert!(error.to_string().contains("does not support DNS-over-QUIC"));
+    }
+
+    #[cfg(feature = "quic")]
+    #[tracing_test::traced_test]
+    #[test]
+    fn quic() {
+        let parse = |config: &str| serde_yaml::from_str::<Upstream>(config);
+
+        let upstream = parse(
+            r#"
+address: 10.10.1.1
+protocol: quic
+tls_name: router.home.local
+"#,
+        )
+        .unwrap();
+        assert_eq!(upstream.protocol, Protocol::Quic);
+        assert_eq!(upstream.tls_name.as_deref(), Some("router.home.local"));
+        assert_eq!(format!("{upstream:?}"), "quic://10.10.1.1");
+
+        assert!(parse(
+            r#"
+address: 10.10.1.1
+protocol: quic
+"#
+        )
+        .is_err());
+
+        assert!(parse(
+            r#"
+address: 10.10.1.1
+protocol: quic
+tls_name: router.home.local
+tsig:
+  name: localns-key
+  secret: c2VjcmV0
+"#
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "quic")]
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn connections() {
+        use std::{
+            future::pending,
+            sync::atomic::{AtomicU32, Ordering},
+        };
+
+        use tokio::task::yield_now;
+
+        use crate::{dns::upstream::Connections, Error};
+
+        let connections = Connections::<u32>::default();
+        let upstream = UpstreamKey::new(
+            [&Upstream::from(Address::from_str("10.10.1.1").unwrap())],
+            None,
+        );
+
+        let connects = AtomicU32::new(0);
+        let connect = || async {
+            let connection = connects.fetch_add(1, Ordering::SeqCst) + 1;
+            // Stands in for the task driving the connection until it closes.
+            let task = tokio::spawn(pending::<()>()).abort_handle();
+            Ok::<_, Error>((connection, task))
+        };
+
+        // Lookups share the open connection.
+        assert_eq!(connections.get(&upstream, connect()).await.unwrap(), 1);
+        assert_eq!(connections.get(&upstream, connect()).await.unwrap(), 1);
+
+        // Once the connection closes a new one is made.
+        let task = connections.open.lock().unwrap()[&upstream].1.clone();
+        task.abort();
+        while !task.is_finished() {
+            yield_now().await;
+        }
+        assert_eq!(connections.get(&upstream, connect()).await.unwrap(), 2);
+        assert_eq!(connections.get(&upstream, connect()).await.unwrap(), 2);
+
+        // A failed connection is replaced.
+        connections.remove(&upstream);
+        assert_eq!(connections.get(&upstream, connect()).await.unwrap(), 3);
+
+        // Other upstreams get their own connection.
+        let other = UpstreamKey::new(
+            [&Upstream::from(Address::from_str("10.10.1.2").unwrap())],
+            None,
+        );
+        assert_eq!(connections.get(&other, connect()).await.unwrap(), 4);
+        assert_eq!(connections.get(&upstream, connect()).await.unwrap(), 3);
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn client_subnet() {
+        let mode = |mode: &str| ClientSubnetMode::try_from(mode.to_owned()).unwrap();
+        let option = |mode: &ClientSubnetMode, client: Option<&str>| {
+            mode.option(client.map(|c| c.parse().unwrap()))
+        };
+        let subnet = |address: &str, prefix: u8| {
+            Some(ClientSubnet::new(address.parse().unwrap(), prefix, 0))
+        };
+
+        let client = mode("client");
+        assert_eq!(
+            option(&client, Some("10.14.23.102")),
+            subnet("10.14.23.0", 24)
+        );
+        assert_eq!(
+            option(&client, Some("2001:db8:1234:5678::1")),
+            subnet("2001:db8:1234:5600::", 56)
+        );
+        assert_eq!(option(&client, None), None);
+
+        let zeroed = mode("zeroed");
+        assert_eq!(option(&zeroed, Some("10.14.23.102")), subnet("0.0.0.0", 0));
+        assert_eq!(option(&zeroed, Some("2001:db8::1")), subnet("::", 0));
+
+        let fixed = mode("203.0.113.0/24");
+        assert_eq!(
+            option(&fixed, Some("10.14.23.102")),
+            subnet("203.0.113.0", 24)
+        );
+        assert_eq!(option(&fixed, None), subnet("203.0.113.0", 24));
+
+        assert!(ClientSubnetMode::try_from("nearby".to_owned()).is_err());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_upstream_tsig() {
+        // The mock server doesn't sign its responses so they must be rejected.
+        let mock = mock_dns("example.org", ZONE).await;
+        let health = UpstreamHealth::default();
+
+        let upstream = Upstream {
+            config: Address {
+                host: Host::from_str("127.0.0.1").unwrap(),
+                port: Some(mock.port()),
+            },
+            tsig: Some(
+                TsigKey::try_from(TsigConfig {
+                    name: "localns-key".to_string(),
+                    algorithm: None,
+                    secret: "c2VjcmV0IGtleSBmb3IgdGVzdGluZw==".to_string(),
+                })
+                .unwrap(),
+            ),
+            protocol: Protocol::Udp,
+            tls_name: None,
+            client_subnet: None,
+            timeout_ms: None,
+            localns: false,
+        };
+
+        let mut query_state =
+            QueryState::new(Query::query(name("www.example.org."), RecordType::A), false);
+        upstream
+            .resolve(&name("www.example.org."), &mut query_state, &health)
+            .await;
+
+        assert!(query_state.answers().is_empty());
+        assert!(logs_contain("Upstream DNS server returned error"));
+        assert_eq!(health.statuses()[0].consecutive_failures, 1);
+    }
 
+    async fn check_upstream(port: u16) {
         let upstream = Upstream::from(Address {
             host: Host::from_str("127.0.0.1").unwrap(),
-            port: Some(coredns.get_udp_port(53).await),
+            port: Some(port),
         });
+        let health = UpstreamHealth::default();
 
         let mut query_state = QueryState::new(
             Query::query(name("unknown.example.org."), RecordType::A),
             false,
         );
         upstream
-            .resolve(&name("unknown.example.org."), &mut query_state)
+            .resolve(&name("unknown.example.org."), &mut query_state, &health)
             .await;
 
         assert_eq!(query_state.response_code, ResponseCode::NXDomain);
@@ -163,11 +970,12 @@ data    IN CNAME www
         let mut query_state =
             QueryState::new(Query::query(name("www.example.org."), RecordType::A), false);
         upstream
-            .resolve(&name("www.example.org."), &mut query_state)
+            .resolve(&name("www.example.org."), &mut query_state, &health)
             .await;
 
         assert_eq!(query_state.response_code, ResponseCode::NoError);
         assert!(query_state.additionals().is_empty());
+        assert_eq!(query_state.upstreams, vec![format!("127.0.0.1:{port}")]);
         let mut answers = query_state.answers().clone();
         answers.sort();
         assert_eq!(answers.len(), 1);
@@ -183,7 +991,7 @@ data    IN CNAME www
             false,
         );
         upstream
-            .resolve(&name("data.example.org."), &mut query_state)
+            .resolve(&name("data.example.org."), &mut query_state, &health)
             .await;
 
         assert_eq!(query_state.response_code, ResponseCode::NoError);
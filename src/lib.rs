@@ -1,7 +1,12 @@
 #![deny(unreachable_pub)]
 mod api;
 mod config;
+#[cfg(unix)]
+mod control;
 mod dns;
+mod logging;
+mod outputs;
+mod replication;
 mod run_loop;
 mod sources;
 #[cfg(test)]
@@ -10,35 +15,127 @@ mod util;
 mod watcher;
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{hash_map::Entry, HashMap, HashSet},
     future::Future,
-    mem,
+    iter, mem,
+    net::IpAddr,
     path::{Path, PathBuf},
     sync::{Arc, Mutex as SyncMutex},
+    time::Duration,
 };
 
 pub use anyhow::Error;
 use chrono::{DateTime, Utc};
+pub use config::ConfigProblem;
+#[cfg(unix)]
+pub use control::{send_control_request, ControlRequest};
+#[cfg(feature = "record-builder")]
+pub use dns::{RecordSet, RecordSetBuilder};
+pub use logging::init_logging;
 use reqwest::Client;
-use tokio::sync::Mutex;
+use tokio::{
+    sync::{watch, Mutex},
+    task::AbortHandle,
+    time::sleep,
+};
 use uuid::Uuid;
 
+#[cfg(unix)]
+use crate::control::ControlServer;
 use crate::{
     api::ApiServer,
     config::{Config, Zones},
-    dns::{DnsServer, RecordSet, ServerState},
-    sources::{SourceId, SourceRecords, Sources},
+    dns::{DnsServer, RecordChanges, RecordSet, ServerState},
+    outputs::Outputs,
+    replication::ReplicationLog,
+    sources::{
+        mesh::{MeshPeer, MeshRegistry},
+        SourceId, SourceRecords, SourceStatus, Sources,
+    },
+    util::{Bind, ClientTls, Subnet},
     watcher::{watch, WatchListener, Watcher},
 };
 
 pub(crate) type ServerId = Uuid;
 
+/// The longest time to wait for sources to report their initial records.
+const WARMUP_LIMIT: Duration = Duration::from_secs(30);
+/// How often to check for names missing an address family in dual-stack zones.
+const ADDRESS_CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// How often to look for sources whose records have expired.
+const LEASE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Cargo features that can be left out of the build, and whether this build includes
+/// them.
+pub(crate) const OPTIONAL_FEATURES: &[(&str, bool)] = &[
+    ("docker", cfg!(feature = "docker")),
+    ("mdns", cfg!(feature = "mdns")),
+    ("quic", cfg!(feature = "quic")),
+    ("telemetry", cfg!(feature = "telemetry")),
+    ("record-builder", cfg!(feature = "record-builder")),
+    ("container-tests", cfg!(feature = "container-tests")),
+];
+
 struct ServerInner {
     config: Config,
     records: HashMap<SourceId, SourceRecords>,
+    /// Names and addresses whose records have changed since they were last
+    /// published.
+    changes: RecordChanges,
+    /// The changes to each source's records, sent to replicas that ask for them.
+    replication: ReplicationLog,
+    /// The servers that have registered with this one to form a mesh.
+    mesh_peers: MeshRegistry,
+    /// The most recent failure of each source that hasn't reported records since.
+    source_errors: HashMap<SourceId, String>,
+    /// Sources that failed to start and are being retried or were given up on.
+    source_status: HashMap<SourceId, SourceStatus>,
+    /// The subnets that sources asked to be authoritative for the reverse zones of.
+    source_reverse_zones: HashMap<SourceId, Vec<Subnet>>,
+    /// Why the configuration file couldn't be reloaded.
+    config_error: Option<String>,
+    /// Signalled whenever the records from any source change.
+    records_changed: watch::Sender<()>,
 }
 
 impl ServerInner {
+    /// The configured zones along with the reverse zones that sources asked for.
+    fn zones(&self) -> Zones {
+        if self.source_reverse_zones.is_empty() {
+            return self.config.zones.clone();
+        }
+
+        self.config
+            .zones
+            .with_reverse_zones(self.source_reverse_zones.values().flatten())
+    }
+
+    /// Applies changed record overrides to the records that this server's
+    /// sources already reported rather than waiting for them to report again.
+    fn apply_changed_overrides(&mut self, old_config: &Config, server_id: ServerId) {
+        for source in self.records.values_mut() {
+            if source.source_id.server_id != server_id {
+                continue;
+            }
+
+            let overrides = self.config.sources.overrides(&source.source_id);
+            if overrides == old_config.sources.overrides(&source.source_id) {
+                continue;
+            }
+
+            let previous = source.records.clone();
+            source.apply_overrides(overrides, None);
+            source.validate();
+
+            if source.records != previous {
+                self.replication.changed(Some(&previous), source);
+                self.changes.add(&previous);
+                self.changes.add(&source.records);
+            }
+        }
+    }
+
+    #[cfg(test)]
     fn records(&self) -> RecordSet {
         self.records
             .values()
@@ -83,12 +180,33 @@ where
 {
     type UpdateGuard: Send;
 
-    fn http_client(&self) -> Client;
+    /// A client shared by every source using the same settings. Connections are
+    /// made from the given address or interface using the given TLS settings.
+    fn http_client(&self, bind: Option<&Bind>, tls: Option<&ClientTls>) -> Client;
+
+    /// Replaces the shared client for these settings with one that has a new
+    /// connection pool, for when the host it connects to has moved.
+    fn reconnect_http_client(&self, bind: Option<&Bind>, tls: Option<&ClientTls>) -> Client;
+
+    /// Resolves a hostname using the server's own records and upstreams.
+    fn resolve_host(&self, host: &str) -> impl Future<Output = Vec<IpAddr>> + Send;
 
     fn start_batch_update(&self) -> impl Future<Output = Self::UpdateGuard> + Send;
 
     fn add_source_records(&self, new_records: SourceRecords) -> impl Future<Output = ()> + Send;
 
+    /// Notes why a source failed. This is forgotten once the source next reports
+    /// its records.
+    fn source_failed(&self, source_id: &SourceId, error: String)
+        -> impl Future<Output = ()> + Send;
+
+    /// Notes whether a source is running.
+    fn source_status(
+        &self,
+        source_id: &SourceId,
+        status: SourceStatus,
+    ) -> impl Future<Output = ()> + Send;
+
     fn clear_source_records(
         &self,
         source_id: &SourceId,
@@ -96,6 +214,59 @@ where
     ) -> impl Future<Output = ()> + Send;
 
     async fn prune_sources(&self, keep: &HashSet<SourceId>);
+
+    /// Makes the server authoritative for the reverse zones of a source's
+    /// subnets, replacing those the source gave before.
+    fn set_reverse_zones(
+        &self,
+        source_id: &SourceId,
+        subnets: Vec<Subnet>,
+    ) -> impl Future<Output = ()> + Send;
+
+    /// The servers that have registered with this one to form a mesh.
+    fn mesh_peers(&self) -> impl Future<Output = Vec<MeshPeer>> + Send;
+}
+
+fn build_http_client(
+    server_state: &ServerState<Zones>,
+    bind: Option<&Bind>,
+    tls: Option<&ClientTls>,
+) -> Result<Client, Error> {
+    let mut builder = Client::builder().dns_resolver(Arc::new(server_state.clone()));
+
+    if let Some(bind) = bind {
+        builder = bind.apply(builder)?;
+    }
+
+    if let Some(tls) = tls {
+        builder = tls.apply(builder)?;
+    }
+
+    Ok(builder.build()?)
+}
+
+async fn check_leases(server: Server) {
+    loop {
+        sleep(LEASE_CHECK_INTERVAL).await;
+        server.expire_sources().await;
+    }
+}
+
+async fn check_address_families(server_state: ServerState<Zones>) {
+    loop {
+        sleep(ADDRESS_CHECK_INTERVAL).await;
+
+        let state = server_state.locked().await;
+        let missing = state.records.missing_address_families(&*state.zones);
+
+        for (name, record_type) in missing {
+            tracing::warn!(
+                %name,
+                missing = %record_type,
+                "Name in a dual-stack zone is missing an address family"
+            );
+        }
+    }
 }
 
 pub(crate) struct BatchGuard {
@@ -113,8 +284,8 @@ impl Drop for BatchGuard {
         if count == 0 {
             let server = self.server.clone();
             tokio::spawn(async move {
-                let inner = server.inner.lock().await;
-                server.server_state.replace_records(inner.records()).await;
+                let mut inner = server.inner.lock().await;
+                server.publish_records(&mut inner).await;
             });
         }
     }
@@ -125,51 +296,80 @@ pub struct Server {
     batch_count: Arc<SyncMutex<u8>>,
     server_id: ServerId,
     inner: Arc<Mutex<ServerInner>>,
+    warmup_sources: Arc<SyncMutex<Option<HashSet<SourceId>>>>,
     sources: Arc<Mutex<Sources<Server>>>,
     server_state: ServerState<Zones>,
     dns_server: Arc<Mutex<DnsServer>>,
-    config_watcher: LockedOption<Watcher>,
+    config_watcher: LockedOption<Vec<Watcher>>,
     api_server: LockedOption<ApiServer>,
+    outputs: LockedOption<Outputs>,
+    #[cfg(unix)]
+    control_server: LockedOption<ControlServer>,
+    address_check: AbortHandle,
+    lease_check: LockedOption<AbortHandle>,
     http_client: Client,
+    /// Clients for sources that bind to an address or use TLS settings.
+    http_clients: Arc<SyncMutex<Vec<(Option<Bind>, Option<ClientTls>, Client)>>>,
+    config_path: PathBuf,
 }
 
 struct ConfigWatcher {
-    config_file: PathBuf,
     server: Server,
 }
 
 impl WatchListener for ConfigWatcher {
     async fn event(&mut self, _: watcher::FileEvent) {
-        match Config::from_file(&self.config_file) {
-            Ok(config) => self.server.update_config(config).await,
-            Err(e) => {
-                tracing::error!(error = %e, "Failed to reload config");
-            }
+        if let Err(e) = self.server.reload_config().await {
+            tracing::error!(error = %e, "Failed to reload config");
         }
     }
 }
 
+/// Checks a configuration file for problems without starting the server.
+pub fn check_config(config_path: &Path) -> Vec<ConfigProblem> {
+    Config::check(config_path)
+}
+
+/// The control socket set in a configuration file, if there is one.
+#[cfg(unix)]
+pub fn control_socket(config_path: &Path) -> Result<Option<PathBuf>, Error> {
+    Config::control_socket(config_path)
+}
+
 impl Server {
     pub async fn new(config_path: &Path) -> Result<Self, Error> {
         let config = Config::from_file(config_path)?;
+        logging::apply_config(&config.logging);
+        logging::apply_telemetry(config.telemetry.as_ref());
 
         let server_state = ServerState::new(RecordSet::new(), config.zones.clone());
 
-        let http_client = Client::builder()
-            .dns_resolver(Arc::new(server_state.clone()))
-            .build()?;
+        let http_client = build_http_client(&server_state, None, None)?;
 
         let sources = Sources::new();
         let server_id = sources.server_id();
 
+        let address_check =
+            tokio::spawn(check_address_families(server_state.clone())).abort_handle();
+
         let server = Self {
             http_client,
+            http_clients: Default::default(),
             batch_count: Default::default(),
             server_id,
             inner: Arc::new(Mutex::new(ServerInner {
                 config: config.clone(),
                 records: HashMap::new(),
+                changes: RecordChanges::default(),
+                replication: ReplicationLog::default(),
+                mesh_peers: MeshRegistry::default(),
+                source_errors: HashMap::new(),
+                source_status: HashMap::new(),
+                source_reverse_zones: HashMap::new(),
+                config_error: None,
+                records_changed: watch::Sender::new(()),
             })),
+            warmup_sources: Default::default(),
             sources: Arc::new(Mutex::new(sources)),
             dns_server: Arc::new(Mutex::new(
                 DnsServer::new(&config.server, server_state.clone()).await,
@@ -177,55 +377,235 @@ impl Server {
             server_state,
             config_watcher: Default::default(),
             api_server: Default::default(),
+            outputs: Default::default(),
+            #[cfg(unix)]
+            control_server: Default::default(),
+            address_check,
+            lease_check: Default::default(),
+            config_path: config_path.to_owned(),
         };
 
-        if let Some(api_server) = config
-            .api
+        server
+            .lease_check
+            .replace(tokio::spawn(check_leases(server.clone())).abort_handle())
+            .await;
+
+        if let Some(api_server) = config.api.as_ref().and_then(|api_config| {
+            ApiServer::new(
+                api_config,
+                server_id,
+                server.inner.clone(),
+                server.server_state.clone(),
+            )
+        }) {
+            server.api_server.replace(api_server).await;
+        }
+
+        server.start_outputs(&config).await;
+
+        #[cfg(unix)]
+        if let Some(control_server) = config
+            .control_socket
             .as_ref()
-            .and_then(|api_config| ApiServer::new(api_config, server_id, server.inner.clone()))
+            .and_then(|path| ControlServer::new(path, server.clone()))
         {
-            server.api_server.replace(api_server).await;
+            server.control_server.replace(control_server).await;
         }
 
+        server.watch_config(&config.included_paths).await;
+
         {
             let mut sources = server.sources.lock().await;
+            server.start_warmup(sources.local_source_ids(&config.sources));
             sources.install_sources(&server, config, None).await;
         }
 
-        match watch(
-            config_path,
-            ConfigWatcher {
-                config_file: config_path.to_owned(),
-                server: server.clone(),
-            },
-        )
-        .await
-        {
-            Ok(watcher) => {
-                server.config_watcher.replace(watcher).await;
+        Ok(server)
+    }
+
+    /// Watches the configuration file and everything that it includes, replacing
+    /// any previous watchers.
+    async fn watch_config(&self, included_paths: &[PathBuf]) {
+        let mut watchers = Vec::new();
+
+        for path in iter::once(&self.config_path).chain(included_paths) {
+            match watch(
+                path,
+                ConfigWatcher {
+                    server: self.clone(),
+                },
+            )
+            .await
+            {
+                Ok(watcher) => watchers.push(watcher),
+                Err(e) => {
+                    tracing::error!(error = %e, path = %path.display(), "Failed to set up file watcher, config changes will not be detected.");
+                }
             }
+        }
+
+        self.config_watcher.replace(watchers).await;
+    }
+
+    /// Starts writing the configured outputs, replacing any already running.
+    async fn start_outputs(&self, config: &Config) {
+        let outputs = {
+            let inner = self.inner.lock().await;
+            Outputs::new(&config.outputs, &self.server_state, &inner.records_changed)
+        };
+
+        if let Some(old_outputs) = self.outputs.replace(outputs).await {
+            old_outputs.shutdown();
+        }
+    }
+
+    fn shared_http_client(
+        &self,
+        bind: Option<&Bind>,
+        tls: Option<&ClientTls>,
+        reconnect: bool,
+    ) -> Client {
+        let mut clients = self.http_clients.lock().unwrap();
+        let position = clients
+            .iter()
+            .position(|(b, t, _)| b.as_ref() == bind && t.as_ref() == tls);
+
+        if let (Some(position), false) = (position, reconnect) {
+            return clients[position].2.clone();
+        }
+
+        let client = match build_http_client(&self.server_state, bind, tls) {
+            Ok(client) => client,
             Err(e) => {
-                tracing::error!(error = %e, "Failed to set up file watcher, config changes will not be detected.");
+                tracing::warn!(error = %e, "Failed to create HTTP client");
+                return self.http_client.clone();
             }
+        };
+
+        match position {
+            Some(position) => clients[position].2 = client.clone(),
+            None => clients.push((bind.cloned(), tls.cloned(), client.clone())),
         }
 
-        Ok(server)
+        client
+    }
+
+    fn start_warmup(&self, sources: HashSet<SourceId>) {
+        if sources.is_empty() {
+            self.server_state.end_warmup();
+            return;
+        }
+
+        self.warmup_sources.lock().unwrap().replace(sources);
+
+        let server_state = self.server_state.clone();
+        tokio::spawn(async move {
+            sleep(WARMUP_LIMIT).await;
+            server_state.end_warmup();
+        });
+    }
+
+    /// Notes that a source has provided its initial state.
+    fn source_reported(&self, source_id: &SourceId) {
+        if let Some(sources) = self.warmup_sources.lock().unwrap().as_mut() {
+            sources.remove(source_id);
+        }
+    }
+
+    async fn publish_records(&self, inner: &mut ServerInner) {
+        let changes = mem::take(&mut inner.changes);
+        let conflicts = &inner.config.conflicts;
+
+        let mut sources: Vec<(&SourceId, &RecordSet)> = inner
+            .records
+            .values()
+            .map(|source| (&source.source_id, &source.records))
+            .collect();
+        sources.sort_by_cached_key(|(source_id, _)| {
+            (conflicts.rank(source_id), source_id.to_string())
+        });
+
+        self.server_state
+            .update_records(&changes, &sources, conflicts.policy)
+            .await;
+        inner.records_changed.send_replace(());
+
+        let mut warmup_sources = self.warmup_sources.lock().unwrap();
+        if warmup_sources.as_ref().is_some_and(|s| s.is_empty()) {
+            warmup_sources.take();
+            self.server_state.end_warmup();
+        }
+    }
+
+    /// Removes the records of sources that didn't report them again before
+    /// their lease ran out, most likely because the source has stopped working.
+    async fn expire_sources(&self) {
+        let now = Utc::now();
+        let mut inner = self.inner.lock().await;
+        let inner = &mut *inner;
+
+        let expired: Vec<SourceId> = inner
+            .records
+            .values()
+            .filter(|source| source.expired(now))
+            .map(|source| source.source_id.clone())
+            .collect();
+
+        if expired.is_empty() {
+            return;
+        }
+
+        for source_id in expired {
+            tracing::error!(source = %source_id, "Source stopped reporting records, removing them");
+
+            if let Some(old) = inner.records.remove(&source_id) {
+                inner.changes.add(&old.records);
+                inner.replication.removed(&old);
+            }
+            inner
+                .source_errors
+                .insert(source_id, "Source stopped reporting records".to_owned());
+        }
+
+        let can_update = {
+            let batch_count = self.batch_count.lock().unwrap();
+            *batch_count == 0
+        };
+
+        if can_update {
+            self.publish_records(inner).await;
+        }
     }
 
     #[cfg(test)]
     pub(crate) async fn records(&self) -> RecordSet {
-        self.server_state.records.read().await.clone()
+        self.server_state.locked().await.records.as_ref().clone()
     }
 
     pub async fn shutdown(self) {
         tracing::info!("Server shutting down");
 
+        self.address_check.abort();
+
+        if let Some(lease_check) = self.lease_check.take().await {
+            lease_check.abort();
+        }
+
         self.config_watcher.take().await;
 
         if let Some(old_server) = self.api_server.take().await {
             old_server.shutdown().await;
         }
 
+        if let Some(outputs) = self.outputs.take().await {
+            outputs.shutdown();
+        }
+
+        #[cfg(unix)]
+        if let Some(control_server) = self.control_server.take().await {
+            control_server.shutdown();
+        }
+
         {
             let mut dns_server = self.dns_server.lock().await;
             dns_server.shutdown().await;
@@ -235,18 +615,54 @@ impl Server {
             let mut sources = self.sources.lock().await;
             sources.shutdown().await;
         }
+
+        logging::shutdown_telemetry().await;
+    }
+
+    /// Reads the configuration file again and applies any changes.
+    pub async fn reload_config(&self) -> Result<(), Error> {
+        let config = match Config::from_file(&self.config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                self.inner.lock().await.config_error = Some(format!("{e:#}"));
+                return Err(e);
+            }
+        };
+
+        self.inner.lock().await.config_error = None;
+        // Certificates may have changed.
+        self.http_clients.lock().unwrap().clear();
+        self.update_config(config).await;
+
+        Ok(())
     }
 
     async fn update_config(&self, config: Config) {
         let (restart_server, restart_api_server, old_config) = {
             let mut inner = self.inner.lock().await;
 
+            if inner.config.logging != config.logging {
+                logging::apply_config(&config.logging);
+            }
+            if inner.config.telemetry != config.telemetry {
+                logging::apply_telemetry(config.telemetry.as_ref());
+            }
+
             let restart_server = inner.config.server != config.server;
             let restart_api_server = inner.config.api != config.api;
 
+            // Every name may now be answered differently.
+            if inner.config.conflicts != config.conflicts {
+                let inner = &mut *inner;
+                for source in inner.records.values() {
+                    inner.changes.add(&source.records);
+                }
+            }
+
             let mut old_config = config.clone();
             mem::swap(&mut inner.config, &mut old_config);
-            self.server_state.replace_zones(config.zones.clone()).await;
+            inner.apply_changed_overrides(&old_config, self.server_id);
+            self.server_state.replace_zones(inner.zones()).await;
 
             (restart_server, restart_api_server, old_config)
         };
@@ -269,19 +685,65 @@ impl Server {
             }
 
             if let Some(api_server) = config.api.as_ref().and_then(|api_config| {
-                ApiServer::new(api_config, self.server_id, self.inner.clone())
+                ApiServer::new(
+                    api_config,
+                    self.server_id,
+                    self.inner.clone(),
+                    self.server_state.clone(),
+                )
             }) {
                 self.api_server.replace(api_server).await;
             }
         }
+
+        if old_config.outputs != config.outputs {
+            self.start_outputs(&config).await;
+        }
+
+        #[cfg(unix)]
+        if old_config.control_socket != config.control_socket {
+            if let Some(control_server) = self.control_server.take().await {
+                control_server.shutdown();
+            }
+
+            if let Some(control_server) = config
+                .control_socket
+                .as_ref()
+                .and_then(|path| ControlServer::new(path, self.clone()))
+            {
+                self.control_server.replace(control_server).await;
+            }
+        }
+
+        // Replacing the watchers stops the one that may have called this so it
+        // must come last.
+        if old_config.included_paths != config.included_paths {
+            self.watch_config(&config.included_paths).await;
+        }
     }
 }
 
 impl RecordServer for Server {
     type UpdateGuard = BatchGuard;
 
-    fn http_client(&self) -> Client {
-        self.http_client.clone()
+    fn http_client(&self, bind: Option<&Bind>, tls: Option<&ClientTls>) -> Client {
+        self.shared_http_client(bind, tls, false)
+    }
+
+    fn reconnect_http_client(&self, bind: Option<&Bind>, tls: Option<&ClientTls>) -> Client {
+        self.shared_http_client(bind, tls, true)
+    }
+
+    async fn resolve_host(&self, host: &str) -> Vec<IpAddr> {
+        self.server_state
+            .locked()
+            .await
+            .resolve_http_address(host.to_owned())
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|address| address.ip())
+            .collect()
     }
 
     async fn start_batch_update(&self) -> Self::UpdateGuard {
@@ -295,32 +757,62 @@ impl RecordServer for Server {
         }
     }
 
-    async fn add_source_records(&self, new_records: SourceRecords) {
-        let mut changed = true;
+    async fn add_source_records(&self, mut new_records: SourceRecords) {
+        self.source_reported(&new_records.source_id);
+
         let mut inner = self.inner.lock().await;
+        let inner = &mut *inner;
+        inner.source_errors.remove(&new_records.source_id);
+
+        // Records from remote servers were already adjusted by their own sources.
+        if new_records.source_id.server_id == self.server_id {
+            let previous = inner
+                .records
+                .get(&new_records.source_id)
+                .and_then(|current| current.reported.as_ref());
+            new_records.apply_overrides(
+                inner.config.sources.overrides(&new_records.source_id),
+                previous,
+            );
+        }
 
-        inner
-            .records
-            .entry(new_records.source_id.clone())
-            .and_modify(|current| {
-                if new_records.timestamp < current.timestamp {
-                    changed = false;
+        new_records.validate();
+
+        let source = match inner.records.entry(new_records.source_id.clone()) {
+            Entry::Occupied(entry) => {
+                let current = entry.into_mut();
+
+                // Newer records win. The same records may arrive from several
+                // remote servers, those that took the shortest path win so
+                // that copies going round a loop are ignored.
+                if new_records.timestamp < current.timestamp
+                    || (new_records.timestamp == current.timestamp
+                        && new_records.hops > current.hops)
+                {
                     return;
                 }
 
                 current.timestamp = new_records.timestamp;
+                current.hops = new_records.hops;
+                current.lease = new_records.lease;
+                current.reported = new_records.reported.take();
                 if new_records.records == current.records {
-                    changed = false;
                     return;
                 }
 
-                current.records = new_records.records.clone();
-            })
-            .or_insert(new_records);
-
-        if !changed {
-            return;
-        }
+                inner.changes.add(&current.records);
+                inner
+                    .replication
+                    .changed(Some(&current.records), &new_records);
+                current.records = new_records.records;
+                current
+            }
+            Entry::Vacant(entry) => {
+                inner.replication.changed(None, &new_records);
+                entry.insert(new_records)
+            }
+        };
+        inner.changes.add(&source.records);
 
         let can_update = {
             let batch_count = self.batch_count.lock().unwrap();
@@ -328,11 +820,31 @@ impl RecordServer for Server {
         };
 
         if can_update {
-            self.server_state.replace_records(inner.records()).await;
+            self.publish_records(inner).await;
+        }
+    }
+
+    async fn source_failed(&self, source_id: &SourceId, error: String) {
+        self.inner
+            .lock()
+            .await
+            .source_errors
+            .insert(source_id.clone(), error);
+    }
+
+    async fn source_status(&self, source_id: &SourceId, status: SourceStatus) {
+        let mut inner = self.inner.lock().await;
+
+        if status == SourceStatus::Ok {
+            inner.source_status.remove(source_id);
+        } else {
+            inner.source_status.insert(source_id.clone(), status);
         }
     }
 
     async fn clear_source_records(&self, source_id: &SourceId, timestamp: DateTime<Utc>) {
+        self.source_reported(source_id);
+
         let mut inner = self.inner.lock().await;
 
         if let Some(old) = inner.records.get(source_id) {
@@ -344,14 +856,18 @@ impl RecordServer for Server {
         }
 
         if let Some(old) = inner.records.remove(source_id) {
+            inner.replication.removed(&old);
+
             if !old.records.is_empty() {
+                inner.changes.add(&old.records);
+
                 let can_update = {
                     let batch_count = self.batch_count.lock().unwrap();
                     *batch_count == 0
                 };
 
                 if can_update {
-                    self.server_state.replace_records(inner.records()).await;
+                    self.publish_records(&mut inner).await;
                 }
             }
         }
@@ -362,7 +878,24 @@ impl RecordServer for Server {
 
         let all = inner.records.keys().cloned().collect::<HashSet<SourceId>>();
         for old in all.difference(keep) {
-            inner.records.remove(old);
+            if let Some(old) = inner.records.remove(old) {
+                inner.changes.add(&old.records);
+                inner.replication.removed(&old);
+            }
+        }
+        inner
+            .source_errors
+            .retain(|source_id, _| keep.contains(source_id));
+        inner
+            .source_status
+            .retain(|source_id, _| keep.contains(source_id));
+
+        let reverse_zones = inner.source_reverse_zones.len();
+        inner
+            .source_reverse_zones
+            .retain(|source_id, _| keep.contains(source_id));
+        if inner.source_reverse_zones.len() != reverse_zones {
+            self.server_state.replace_zones(inner.zones()).await;
         }
 
         let can_update = {
@@ -371,9 +904,30 @@ impl RecordServer for Server {
         };
 
         if can_update {
-            self.server_state.replace_records(inner.records()).await;
+            self.publish_records(&mut inner).await;
         }
     }
+
+    async fn set_reverse_zones(&self, source_id: &SourceId, subnets: Vec<Subnet>) {
+        let mut inner = self.inner.lock().await;
+
+        let previous = if subnets.is_empty() {
+            inner.source_reverse_zones.remove(source_id)
+        } else {
+            inner
+                .source_reverse_zones
+                .insert(source_id.clone(), subnets.clone())
+        };
+        if previous.unwrap_or_default() == subnets {
+            return;
+        }
+
+        self.server_state.replace_zones(inner.zones()).await;
+    }
+
+    async fn mesh_peers(&self) -> Vec<MeshPeer> {
+        self.inner.lock().await.mesh_peers.peers()
+    }
 }
 
 #[cfg(test)]
@@ -383,9 +937,10 @@ mod tests {
 
     use super::*;
     use crate::{
+        config::ZoneConfigProvider,
         dns::{RData, Record},
         sources::SourceType,
-        test::{fqdn, write_file},
+        test::{fqdn, name, timeout, write_file},
     };
 
     #[tracing_test::traced_test]
@@ -407,11 +962,9 @@ server:
 
         let source_id_1 = SourceId::new(&Uuid::new_v4(), SourceType::File, "test");
 
-        let mut records_1 = RecordSet::new();
-        records_1.insert(Record::new(
-            fqdn("www.example.org"),
-            RData::Cname(fqdn("other.example.org")),
-        ));
+        let mut records_1 = RecordSet::builder()
+            .cname("www.example.org", "other.example.org")
+            .build();
 
         let now = Utc::now();
         server
@@ -466,11 +1019,9 @@ server:
 
         let source_id_2 = SourceId::new(&Uuid::new_v4(), SourceType::Docker, "test");
 
-        let mut records_2 = RecordSet::new();
-        records_2.insert(Record::new(
-            fqdn("other.data.com"),
-            RData::Cname(fqdn("www.data.com")),
-        ));
+        let records_2 = RecordSet::builder()
+            .cname("other.data.com", "www.data.com")
+            .build();
 
         server
             .add_source_records(SourceRecords::new(&source_id_2, None, records_2.clone()))
@@ -501,4 +1052,240 @@ server:
         let server_records = server.records().await;
         assert!(server_records.is_empty());
     }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lease_expiry() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53540
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let lease = std::time::Duration::from_secs(60);
+        let now = Utc::now();
+
+        let source = |name: &str, target: &str, reported: DateTime<Utc>| {
+            SourceRecords::new(
+                &SourceId::new(&Uuid::new_v4(), SourceType::Http, name),
+                Some(reported),
+                RecordSet::builder()
+                    .cname(target, "www.example.org")
+                    .build(),
+            )
+        };
+
+        let stopped = source("stopped", "stopped.example.org", now - Duration::minutes(2));
+        let stopped_id = stopped.source_id.clone();
+        server.add_source_records(stopped.with_lease(lease)).await;
+        server
+            .add_source_records(source("polling", "polling.example.org", now).with_lease(lease))
+            .await;
+        server
+            .add_source_records(source(
+                "watched",
+                "watched.example.org",
+                now - Duration::days(1),
+            ))
+            .await;
+        assert_eq!(server.records().await.len(), 3);
+
+        server.expire_sources().await;
+
+        let server_records = server.records().await;
+        assert_eq!(server_records.len(), 2);
+        assert!(!server_records.has_name(&name("stopped.example.org.")));
+        assert!(server
+            .inner
+            .lock()
+            .await
+            .source_errors
+            .contains_key(&stopped_id));
+
+        // The error is forgotten once the source reports again.
+        server
+            .add_source_records(
+                SourceRecords::new(
+                    &stopped_id,
+                    None,
+                    RecordSet::builder()
+                        .cname("stopped.example.org", "www.example.org")
+                        .build(),
+                )
+                .with_lease(lease),
+            )
+            .await;
+        assert_eq!(server.records().await.len(), 3);
+        assert!(server.inner.lock().await.source_errors.is_empty());
+
+        server.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn changed_overrides() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+        let runs = temp_dir.path().join("runs");
+
+        let config = |overrides: &str| {
+            format!(
+                r#"
+server:
+  port: 53548
+
+sources:
+  exec:
+    hosts:
+      command:
+        - sh
+        - -c
+        - "echo run >> {}; echo 'www.home.local: [10.10.1.5, fd00::5]'"
+{overrides}
+"#,
+                runs.display()
+            )
+        };
+
+        write_file(&config_file, config("      address_family: ipv4")).await;
+
+        let server = Server::new(&config_file).await.unwrap();
+
+        timeout(async {
+            while !server.records().await.has_name(&name("www.home.local.")) {
+                sleep(std::time::Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+        assert_eq!(server.records().await.len(), 1);
+
+        // The records the command reported are adjusted without running it again.
+        write_file(&config_file, config("      ttl: 60")).await;
+        server.reload_config().await.unwrap();
+
+        let server_records = server.records().await;
+        assert_eq!(server_records.len(), 2);
+        assert!(server_records
+            .records()
+            .all(|record| record.ttl == Some(60)));
+        assert_eq!(std::fs::read_to_string(&runs).unwrap(), "run\n");
+
+        server.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn source_reverse_zones() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53549
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::Docker, "test");
+
+        let zone_config = |server: &Server| {
+            let server_state = server.server_state.clone();
+            async move {
+                server_state
+                    .locked()
+                    .await
+                    .zones
+                    .zone_config(&fqdn("5.1.10.10.in-addr.arpa"))
+            }
+        };
+
+        assert!(!zone_config(&server).await.authoritative);
+
+        server
+            .set_reverse_zones(&source_id, vec!["10.10.1.0/24".parse().unwrap()])
+            .await;
+
+        let config = zone_config(&server).await;
+        assert!(config.authoritative);
+        assert_eq!(config.origin, Some(fqdn("1.10.10.in-addr.arpa")));
+
+        // The zones are forgotten along with the source.
+        server.prune_sources(&HashSet::new()).await;
+        assert!(!zone_config(&server).await.authoritative);
+
+        server.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replication_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53539
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::File, "test");
+
+        let replicated = |timestamp, hops, name: &str| {
+            let mut source_records = SourceRecords::new(
+                &source_id,
+                Some(timestamp),
+                RecordSet::builder().cname(name, "www.example.org").build(),
+            );
+            source_records.hops = hops;
+            source_records
+        };
+
+        let now = Utc::now();
+        server
+            .add_source_records(replicated(now, 2, "first.example.org"))
+            .await;
+        assert!(server.records().await.has_name(&name("first.example.org.")));
+
+        // A copy that came a longer way round is ignored.
+        server
+            .add_source_records(replicated(now, 3, "stale.example.org"))
+            .await;
+        assert!(server.records().await.has_name(&name("first.example.org.")));
+
+        server
+            .add_source_records(replicated(now, 1, "shorter.example.org"))
+            .await;
+        assert!(server
+            .records()
+            .await
+            .has_name(&name("shorter.example.org.")));
+
+        // Newer records always win.
+        server
+            .add_source_records(replicated(
+                now + Duration::seconds(1),
+                4,
+                "newer.example.org",
+            ))
+            .await;
+        let server_records = server.records().await;
+        assert_eq!(server_records.len(), 1);
+        assert!(server_records.has_name(&name("newer.example.org.")));
+
+        server.shutdown().await;
+    }
 }
@@ -0,0 +1,353 @@
+use std::{collections::BTreeMap, env, fmt, io, sync::OnceLock};
+#[cfg(feature = "telemetry")]
+use std::{mem, sync::Mutex};
+
+#[cfg(feature = "telemetry")]
+use opentelemetry::trace::TracerProvider;
+#[cfg(feature = "telemetry")]
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+#[cfg(feature = "telemetry")]
+use opentelemetry_sdk::{
+    trace::{Sampler, SdkTracer, SdkTracerProvider},
+    Resource,
+};
+use reqwest::Url;
+use serde::Deserialize;
+#[cfg(feature = "telemetry")]
+use tokio::task::spawn_blocking;
+#[cfg(feature = "telemetry")]
+use tracing_opentelemetry::OpenTelemetryLayer;
+#[cfg(feature = "telemetry")]
+use tracing_subscriber::layer::Layered;
+use tracing_subscriber::{
+    filter::{Builder, EnvFilter, LevelFilter},
+    layer::SubscriberExt,
+    reload,
+    util::SubscriberInitExt,
+    Registry,
+};
+
+#[cfg(feature = "telemetry")]
+use crate::{config::deserialize_url as deserialize_endpoint, Error};
+
+/// The level of LocalNS's own logging when none is configured.
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::TRACE;
+
+/// The name that traces are reported under.
+#[cfg(feature = "telemetry")]
+const SERVICE_NAME: &str = "localns";
+
+#[cfg(feature = "telemetry")]
+type Filtered = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+#[cfg(feature = "telemetry")]
+type TelemetryLayer = Option<OpenTelemetryLayer<Filtered, SdkTracer>>;
+
+/// Changes the filter once logging has been set up.
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Replaces the layer exporting traces once logging has been set up.
+#[cfg(feature = "telemetry")]
+static TELEMETRY: OnceLock<reload::Handle<TelemetryLayer, Filtered>> = OnceLock::new();
+
+/// The exporter that the telemetry layer sends traces to.
+#[cfg(feature = "telemetry")]
+static PROVIDER: Mutex<Option<SdkTracerProvider>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct LogLevel(LevelFilter);
+
+impl TryFrom<String> for LogLevel {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        value
+            .parse()
+            .map(Self)
+            .map_err(|_| format!("Unknown log level '{value}'"))
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct LoggingConfig {
+    /// The level of LocalNS's own logging.
+    #[serde(default)]
+    level: Option<LogLevel>,
+    /// Levels for specific modules or libraries, such as `localns::sources`
+    /// or `hickory_server`.
+    #[serde(default)]
+    targets: BTreeMap<String, LogLevel>,
+}
+
+impl LoggingConfig {
+    fn directives(&self) -> String {
+        let level = self.level.map_or(DEFAULT_LEVEL, |level| level.0);
+
+        let mut directives = vec![format!("localns={level}")];
+        directives.extend(
+            self.targets
+                .iter()
+                .map(|(target, level)| format!("{target}={level}")),
+        );
+
+        directives.join(",")
+    }
+}
+
+/// The fraction of traces to export, between 0 and 1.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "f64")]
+#[cfg_attr(not(feature = "telemetry"), allow(dead_code))]
+pub(crate) struct SamplingRatio(f64);
+
+impl Default for SamplingRatio {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+impl TryFrom<f64> for SamplingRatio {
+    type Error = String;
+
+    fn try_from(value: f64) -> Result<Self, String> {
+        if (0.0..=1.0).contains(&value) {
+            Ok(Self(value))
+        } else {
+            Err(format!("Sampling ratio {value} is not between 0 and 1"))
+        }
+    }
+}
+
+/// Builds without the `telemetry` feature reject any configured endpoint.
+#[cfg(not(feature = "telemetry"))]
+fn deserialize_endpoint<'de, D>(_: D) -> Result<Url, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Err(serde::de::Error::custom(
+        "This build does not support exporting telemetry",
+    ))
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[cfg_attr(not(feature = "telemetry"), allow(dead_code))]
+pub(crate) struct TelemetryConfig {
+    /// The OTLP/HTTP endpoint that traces are sent to.
+    #[serde(deserialize_with = "deserialize_endpoint")]
+    endpoint: Url,
+    /// The fraction of traces that are exported.
+    #[serde(default)]
+    sampling_ratio: SamplingRatio,
+}
+
+#[cfg(feature = "telemetry")]
+impl TelemetryConfig {
+    fn provider(&self) -> Result<SdkTracerProvider, Error> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(self.endpoint.as_str())
+            .build()?;
+
+        // The spans within a trace are either all exported or none are.
+        let sampler =
+            Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(self.sampling_ratio.0)));
+
+        Ok(SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_sampler(sampler)
+            .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+            .build())
+    }
+}
+
+/// The `RUST_LOG` environment variable takes precedence over the configuration
+/// file so debugging doesn't need the file to be changed.
+fn filter(config: &LoggingConfig) -> EnvFilter {
+    if env::var_os(EnvFilter::DEFAULT_ENV).is_some() {
+        return Builder::default()
+            .with_default_directive(
+                format!("localns={DEFAULT_LEVEL}")
+                    .parse()
+                    .expect("Default directive should be valid"),
+            )
+            .from_env_lossy();
+    }
+
+    Builder::default().parse_lossy(config.directives())
+}
+
+/// Sets up logging to stderr. Until the configuration is loaded the default
+/// levels are used.
+pub fn init_logging() {
+    let (filter, handle) = reload::Layer::new(filter(&LoggingConfig::default()));
+
+    let formatter = tracing_subscriber::fmt::layer()
+        .with_ansi(true)
+        .pretty()
+        .with_writer(io::stderr);
+
+    #[cfg(feature = "telemetry")]
+    {
+        let (telemetry, telemetry_handle) = reload::Layer::<TelemetryLayer, Filtered>::new(None);
+
+        Registry::default()
+            .with(filter)
+            .with(telemetry)
+            .with(formatter)
+            .init();
+
+        let _ = TELEMETRY.set(telemetry_handle);
+    }
+
+    #[cfg(not(feature = "telemetry"))]
+    Registry::default().with(filter).with(formatter).init();
+
+    let _ = FILTER.set(handle);
+}
+
+/// Applies the levels from the configuration, if logging has been set up.
+pub(crate) fn apply_config(config: &LoggingConfig) {
+    let Some(handle) = FILTER.get() else {
+        return;
+    };
+
+    if let Err(e) = handle.reload(filter(config)) {
+        tracing::warn!(error = %e, "Failed to change log levels");
+    }
+}
+
+/// Switches the exporter that traces are sent to, returning the one it
+/// replaces.
+#[cfg(feature = "telemetry")]
+fn replace_provider(provider: Option<SdkTracerProvider>) -> Option<SdkTracerProvider> {
+    let Some(handle) = TELEMETRY.get() else {
+        return None;
+    };
+
+    let layer = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+    if let Err(e) = handle.reload(layer) {
+        tracing::warn!(error = %e, "Failed to change telemetry");
+        return provider;
+    }
+
+    mem::replace(&mut *PROVIDER.lock().unwrap(), provider)
+}
+
+/// Sends any traces that are still waiting. This blocks until they are sent so
+/// is kept off the async runtime.
+#[cfg(feature = "telemetry")]
+async fn shutdown_provider(provider: SdkTracerProvider) {
+    let result = spawn_blocking(move || provider.shutdown()).await;
+    if let Ok(Err(e)) = result {
+        tracing::warn!(error = %e, "Failed to send the remaining traces");
+    }
+}
+
+/// Starts, stops or changes the export of traces to match the configuration,
+/// if logging has been set up.
+#[cfg(feature = "telemetry")]
+pub(crate) fn apply_telemetry(config: Option<&TelemetryConfig>) {
+    if TELEMETRY.get().is_none() {
+        return;
+    }
+
+    let provider = match config.map(TelemetryConfig::provider).transpose() {
+        Ok(provider) => provider,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to set up telemetry");
+            None
+        }
+    };
+
+    if let Some(previous) = replace_provider(provider) {
+        tokio::spawn(shutdown_provider(previous));
+    }
+}
+
+/// Without the `telemetry` feature no configuration can be loaded so there is
+/// nothing to export.
+#[cfg(not(feature = "telemetry"))]
+pub(crate) fn apply_telemetry(_config: Option<&TelemetryConfig>) {}
+
+/// Stops exporting traces once the server has shut down.
+pub(crate) async fn shutdown_telemetry() {
+    #[cfg(feature = "telemetry")]
+    if let Some(previous) = replace_provider(None) {
+        shutdown_provider(previous).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logging::{LoggingConfig, TelemetryConfig};
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn directives() {
+        assert_eq!(LoggingConfig::default().directives(), "localns=trace");
+
+        let config: LoggingConfig = serde_yaml::from_str(
+            r#"
+level: INFO
+targets:
+  localns::sources: debug
+  hickory_server: warn
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.directives(),
+            "localns=info,hickory_server=warn,localns::sources=debug"
+        );
+
+        assert!(serde_yaml::from_str::<LoggingConfig>("level: loud").is_err());
+    }
+
+    #[cfg(feature = "telemetry")]
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn telemetry() {
+        let config: TelemetryConfig = serde_yaml::from_str(
+            r#"
+endpoint: http://collector.home.local:4318/v1/traces
+sampling_ratio: 0.25
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.sampling_ratio.0, 0.25);
+
+        // Nothing is sent until a span ends.
+        config.provider().unwrap().shutdown().unwrap();
+
+        let config: TelemetryConfig =
+            serde_yaml::from_str("endpoint: http://collector.home.local:4318/v1/traces").unwrap();
+        assert_eq!(config.sampling_ratio.0, 1.0);
+
+        assert!(serde_yaml::from_str::<TelemetryConfig>(
+            "endpoint: http://collector.home.local:4318/v1/traces\nsampling_ratio: 2"
+        )
+        .is_err());
+    }
+
+    #[cfg(not(feature = "telemetry"))]
+    #[tracing_test::traced_test]
+    #[test]
+    fn telemetry_unsupported() {
+        let error = serde_yaml::from_str::<TelemetryConfig>(
+            "endpoint: http://collector.home.local:4318/v1/traces",
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("This build does not support exporting telemetry"));
+    }
+}
@@ -1,37 +1,212 @@
-use std::{env, io, path::PathBuf};
+use std::{env, path::PathBuf, process};
 
-use clap::Parser;
-use localns::{Error, Server};
+#[cfg(unix)]
+use anyhow::bail;
+use clap::{Args, Parser, Subcommand};
+use localns::{check_config, init_logging, Error, Server};
+#[cfg(unix)]
+use localns::{control_socket, send_control_request, ControlRequest};
 use tokio::signal;
-use tracing_subscriber::{
-    filter::Builder, layer::SubscriberExt, util::SubscriberInitExt, Layer, Registry,
-};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 
 #[derive(Parser)]
-#[clap(author, version)]
+#[clap(author, version, args_conflicts_with_subcommands = true)]
 struct CliArgs {
+    #[command(flatten)]
+    serve: ServeArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Args)]
+struct ServeArgs {
     config: Option<String>,
+
+    /// Checks the configuration file for problems and exits without starting
+    /// the server.
+    #[arg(long)]
+    check: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the server, the same as when no command is given.
+    Serve(ServeArgs),
+    #[cfg(unix)]
+    #[command(flatten)]
+    Control(ControlCommand),
+}
+
+#[cfg(unix)]
+#[derive(Args)]
+struct ControlArgs {
+    /// The control socket of the server, by default the one set in the
+    /// configuration file.
+    #[arg(long)]
+    socket: Option<PathBuf>,
+
+    /// The configuration file to read the control socket from.
+    #[arg(long)]
+    config: Option<String>,
+}
+
+/// Commands that talk to a running server over its control socket.
+#[cfg(unix)]
+#[derive(Subcommand)]
+enum ControlCommand {
+    /// Shows the state of the server and its sources.
+    Status(ControlArgs),
+    /// Lists the records from every source.
+    #[command(alias = "dump-records")]
+    Dump(ControlArgs),
+    /// Reloads the configuration file.
+    Reload(ControlArgs),
+    /// Resolves a name in the same way as a DNS request would.
+    Query {
+        /// The name to look up.
+        name: String,
+
+        /// The record type to look up, A by default.
+        record_type: Option<String>,
+
+        /// The record type to look up.
+        #[arg(long = "type", conflicts_with = "record_type")]
+        type_option: Option<String>,
+
+        /// Don't forward the query to upstream servers.
+        #[arg(long)]
+        no_recurse: bool,
+
+        #[command(flatten)]
+        control: ControlArgs,
+    },
 }
 
+/// Missing files are left for loading the configuration to report.
 fn config_file(arg: Option<&str>) -> PathBuf {
-    if let Some(str) = arg {
-        PathBuf::from(str).canonicalize().unwrap()
+    let path = if let Some(str) = arg {
+        PathBuf::from(str)
     } else if let Ok(value) = env::var("LOCALNS_CONFIG") {
-        PathBuf::from(value).canonicalize().unwrap()
+        PathBuf::from(value)
     } else {
-        PathBuf::from("config.yaml").canonicalize().unwrap()
-    }
+        PathBuf::from("config.yaml")
+    };
+
+    path.canonicalize().unwrap_or(path)
 }
 
+/// Waits for ctrl-c or SIGTERM, which is how container runtimes stop the server.
 async fn wait_for_termination() {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal(SignalKind::terminate()).unwrap();
+
+        tokio::select! {
+            _ = signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
+    }
+
+    #[cfg(not(unix))]
     signal::ctrl_c().await.unwrap();
 }
 
-async fn run() -> Result<(), Error> {
-    let args = CliArgs::parse();
+/// Reloads the configuration whenever SIGHUP is received.
+#[cfg(unix)]
+async fn reload_on_hangup(server: &Server) -> Result<(), Error> {
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    while hangup.recv().await.is_some() {
+        tracing::info!("Received SIGHUP, reloading config");
+
+        if let Err(e) = server.reload_config().await {
+            tracing::error!(error = %e, "Failed to reload config");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn run_command(command: ControlCommand) -> Result<(), Error> {
+    let (request, control) = match command {
+        ControlCommand::Status(control) => (ControlRequest::Status, control),
+        ControlCommand::Dump(control) => (ControlRequest::Dump, control),
+        ControlCommand::Reload(control) => (ControlRequest::Reload, control),
+        ControlCommand::Query {
+            name,
+            record_type,
+            type_option,
+            no_recurse,
+            control,
+        } => (
+            ControlRequest::Query {
+                name,
+                record_type: Some(
+                    record_type
+                        .or(type_option)
+                        .unwrap_or_else(|| "A".to_owned()),
+                ),
+                recurse: Some(!no_recurse),
+            },
+            control,
+        ),
+    };
+
+    let socket = match control.socket {
+        Some(socket) => socket,
+        None => match control_socket(&config_file(control.config.as_deref()))? {
+            Some(socket) => socket,
+            None => bail!("No control socket is configured"),
+        },
+    };
+
+    let response = send_control_request(&socket, &request).await?;
+    if !response.is_null() {
+        println!("{}", serde_json::to_string_pretty(&response)?);
+    }
+
+    Ok(())
+}
+
+async fn serve(args: ServeArgs) -> Result<(), Error> {
     let config_path = config_file(args.config.as_deref());
+
+    if args.check {
+        let problems = check_config(&config_path);
+        for problem in &problems {
+            if problem.warning {
+                println!("Warning: {problem}");
+            } else {
+                println!("{problem}");
+            }
+        }
+
+        // Likely mistakes are reported but don't fail the check.
+        if problems.iter().any(|problem| !problem.warning) {
+            process::exit(1);
+        }
+
+        println!("{} is valid", config_path.display());
+        return Ok(());
+    }
+
     let server = Server::new(&config_path).await?;
 
+    #[cfg(unix)]
+    tokio::select! {
+        _ = wait_for_termination() => {},
+        result = reload_on_hangup(&server) => {
+            if let Err(e) = result {
+                tracing::error!(error = %e, "Failed to listen for SIGHUP");
+                wait_for_termination().await;
+            }
+        },
+    }
+
+    #[cfg(not(unix))]
     wait_for_termination().await;
 
     server.shutdown().await;
@@ -39,21 +214,23 @@ async fn run() -> Result<(), Error> {
     Ok(())
 }
 
-#[tokio::main]
-async fn main() {
-    let env_filter = Builder::default()
-        .with_default_directive("localns=trace".parse().unwrap())
-        .from_env_lossy();
+async fn run() -> Result<(), Error> {
+    let args = CliArgs::parse();
 
-    let formatter = tracing_subscriber::fmt::layer()
-        .with_ansi(true)
-        .pretty()
-        .with_writer(io::stderr)
-        .with_filter(env_filter);
+    match args.command {
+        Some(Command::Serve(serve_args)) => serve(serve_args).await,
+        #[cfg(unix)]
+        Some(Command::Control(command)) => run_command(command).await,
+        None => serve(args.serve).await,
+    }
+}
 
-    Registry::default().with(formatter).init();
+#[tokio::main]
+async fn main() {
+    init_logging();
 
     if let Err(e) = run().await {
         tracing::error!(error = %e, "Unexpected error");
+        process::exit(1);
     }
 }
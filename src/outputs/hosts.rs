@@ -0,0 +1,150 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+    net::IpAddr,
+    path::PathBuf,
+};
+
+use figment::value::magic::RelativePathBuf;
+use serde::Deserialize;
+
+use crate::{
+    config::Zones,
+    dns::{Fqdn, LockedServerState, RData, RecordSet},
+    outputs::OutputConfig,
+    Error,
+};
+
+/// How many aliases are followed to find the addresses for a name.
+const MAX_ALIAS_DEPTH: usize = 8;
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct HostsFileConfig {
+    path: RelativePathBuf,
+}
+
+impl HostsFileConfig {
+    #[cfg(test)]
+    pub(crate) fn new(path: RelativePathBuf) -> Self {
+        Self { path }
+    }
+}
+
+pub(super) fn host_name(name: &Fqdn) -> String {
+    name.to_ascii().trim_end_matches('.').to_owned()
+}
+
+/// The addresses that a name resolves to, following aliases.
+fn resolve(name: &Fqdn, targets: &HashMap<&Fqdn, Vec<&RData>>, depth: usize) -> BTreeSet<IpAddr> {
+    let mut addresses = BTreeSet::new();
+    if depth > MAX_ALIAS_DEPTH {
+        return addresses;
+    }
+
+    for rdata in targets.get(name).into_iter().flatten() {
+        match rdata {
+            RData::A(ip) => {
+                addresses.insert(IpAddr::V4(*ip));
+            }
+            RData::Aaaa(ip) => {
+                addresses.insert(IpAddr::V6(*ip));
+            }
+            RData::Cname(alias) => addresses.extend(resolve(alias, targets, depth + 1)),
+            RData::Ptr(_) => {}
+        }
+    }
+
+    addresses
+}
+
+impl OutputConfig for HostsFileConfig {
+    fn output_type() -> &'static str {
+        "hosts_file"
+    }
+
+    fn path(&self) -> PathBuf {
+        self.path.relative()
+    }
+
+    fn render(&self, state: &LockedServerState<Zones>) -> Result<String, Error> {
+        Ok(hosts(&state.records))
+    }
+}
+
+/// Every name with the addresses it resolves to. Tagged records are left out as
+/// they are not visible to every client.
+pub(super) fn name_addresses(records: &RecordSet) -> BTreeMap<Fqdn, BTreeSet<IpAddr>> {
+    let mut targets: HashMap<&Fqdn, Vec<&RData>> = HashMap::new();
+    for record in records.records().filter(|record| record.tags.is_empty()) {
+        targets
+            .entry(record.name())
+            .or_default()
+            .push(record.rdata());
+    }
+
+    targets
+        .keys()
+        .map(|name| ((*name).clone(), resolve(name, &targets, 0)))
+        .filter(|(_, addresses)| !addresses.is_empty())
+        .collect()
+}
+
+fn hosts(records: &RecordSet) -> String {
+    let mut hosts: BTreeMap<IpAddr, BTreeSet<String>> = BTreeMap::new();
+    for (name, addresses) in name_addresses(records) {
+        for ip in addresses {
+            hosts.entry(ip).or_default().insert(host_name(&name));
+        }
+    }
+
+    let mut contents = String::from("# Generated by LocalNS, changes will be overwritten.\n");
+    for (ip, names) in hosts {
+        let names: Vec<String> = names.into_iter().collect();
+        let _ = writeln!(contents, "{ip}\t{}", names.join(" "));
+    }
+
+    contents
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use crate::{
+        dns::{RData, Record, RecordSet},
+        outputs::hosts::hosts,
+        test::fqdn,
+    };
+
+    fn address(ip: &str) -> RData {
+        RData::from(ip.parse::<IpAddr>().unwrap())
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn render() {
+        let mut tagged = Record::new(fqdn("private.home.local"), address("10.10.1.9"));
+        tagged.tags.insert("admin".to_owned());
+
+        let records = RecordSet::from(vec![
+            Record::new(fqdn("www.home.local"), address("10.10.1.5")),
+            Record::new(fqdn("www.home.local"), address("fd00::5")),
+            Record::new(fqdn("app.home.local"), address("10.10.1.5")),
+            Record::new(fqdn("db.home.local"), address("10.10.1.6")),
+            Record::new(fqdn("web.home.local"), RData::Cname(fqdn("www.home.local"))),
+            Record::new(
+                fqdn("external.home.local"),
+                RData::Cname(fqdn("www.example.com")),
+            ),
+            tagged,
+        ]);
+
+        assert_eq!(
+            hosts(&records),
+            "# Generated by LocalNS, changes will be overwritten.\n\
+             10.10.1.5\tapp.home.local web.home.local www.home.local\n\
+             10.10.1.6\tdb.home.local\n\
+             fd00::5\tweb.home.local www.home.local\n"
+        );
+    }
+}
@@ -0,0 +1,309 @@
+#[cfg(unix)]
+use std::str::FromStr;
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+#[cfg(not(unix))]
+use anyhow::bail;
+use figment::value::magic::RelativePathBuf;
+#[cfg(unix)]
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use serde::Deserialize;
+use tokio::{fs, sync::watch, task::JoinHandle};
+
+use crate::{
+    config::Zones,
+    dns::{LockedServerState, ServerState},
+    Error,
+};
+
+pub(crate) mod hosts;
+pub(crate) mod resolver;
+pub(crate) mod zone_file;
+
+/// Something that writes the server's records to a file for other tools to use.
+trait OutputConfig: Clone + Send + Sync + 'static {
+    fn output_type() -> &'static str;
+
+    fn path(&self) -> PathBuf;
+
+    fn render(&self, state: &LockedServerState<Zones>) -> Result<String, Error>;
+
+    /// The daemon to signal once the file has been written.
+    fn reload(&self) -> Option<&Reload> {
+        None
+    }
+}
+
+/// Signals a running daemon, found through its PID file, to reload its
+/// configuration.
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct Reload {
+    pid_file: RelativePathBuf,
+    /// Defaults to SIGHUP.
+    #[serde(default)]
+    signal: Option<String>,
+}
+
+impl Reload {
+    #[cfg(unix)]
+    async fn send(&self) -> Result<(), Error> {
+        let signal = match self.signal.as_deref() {
+            Some(name) if name.starts_with("SIG") => Signal::from_str(name)?,
+            Some(name) => Signal::from_str(&format!("SIG{name}"))?,
+            None => Signal::SIGHUP,
+        };
+
+        let pid: i32 = fs::read_to_string(self.pid_file.relative())
+            .await?
+            .trim()
+            .parse()?;
+
+        kill(Pid::from_raw(pid), signal)?;
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    async fn send(&self) -> Result<(), Error> {
+        bail!("Signalling other processes is not supported on this platform")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Default, Deserialize)]
+pub(crate) struct OutputsConfig {
+    #[serde(default)]
+    pub(crate) hosts_file: HashMap<String, hosts::HostsFileConfig>,
+
+    #[serde(default)]
+    pub(crate) zone_file: HashMap<String, zone_file::ZoneFileConfig>,
+
+    #[serde(default)]
+    pub(crate) unbound: HashMap<String, resolver::UnboundConfig>,
+
+    #[serde(default)]
+    pub(crate) dnsmasq: HashMap<String, resolver::DnsmasqConfig>,
+}
+
+impl OutputsConfig {
+    /// The files that outputs write to, which must be in existing directories.
+    pub(crate) fn created_files(&self) -> Vec<(String, PathBuf)> {
+        fn files<C: OutputConfig>(
+            outputs: &HashMap<String, C>,
+            created_files: &mut Vec<(String, PathBuf)>,
+        ) {
+            for (name, config) in outputs {
+                created_files.push((
+                    format!("outputs.{}.{name}", C::output_type()),
+                    config.path(),
+                ));
+            }
+        }
+
+        let mut created_files = Vec::new();
+        files(&self.hosts_file, &mut created_files);
+        files(&self.zone_file, &mut created_files);
+        files(&self.unbound, &mut created_files);
+        files(&self.dnsmasq, &mut created_files);
+
+        created_files
+    }
+}
+
+/// Replaces the file in a single step so readers never see it half written.
+async fn write_file(path: &Path, contents: &str) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+
+    fs::write(&temp, contents).await?;
+    fs::rename(&temp, path).await
+}
+
+async fn output_loop<C: OutputConfig>(
+    name: String,
+    config: C,
+    server_state: ServerState<Zones>,
+    mut changes: watch::Receiver<()>,
+) {
+    let path = config.path();
+    let mut written: Option<String> = None;
+
+    loop {
+        changes.borrow_and_update();
+
+        let state = server_state.locked().await;
+        // Don't replace a file from an earlier run before any records are known.
+        let contents = if written.is_none() && state.records.is_empty() {
+            None
+        } else {
+            match config.render(&state) {
+                Ok(contents) => Some(contents),
+                Err(e) => {
+                    tracing::warn!(
+                        error = %e,
+                        output_type = C::output_type(),
+                        output = %name,
+                        "Failed to generate output"
+                    );
+                    None
+                }
+            }
+        };
+
+        if let Some(contents) = contents.filter(|c| written.as_ref() != Some(c)) {
+            match write_file(&path, &contents).await {
+                Ok(()) => {
+                    tracing::debug!(
+                        output_type = C::output_type(),
+                        output = %name,
+                        path = %path.display(),
+                        "Wrote records"
+                    );
+                    written = Some(contents);
+
+                    if let Some(reload) = config.reload() {
+                        if let Err(e) = reload.send().await {
+                            tracing::warn!(
+                                error = %e,
+                                output_type = C::output_type(),
+                                output = %name,
+                                "Failed to signal the daemon to reload"
+                            );
+                        }
+                    }
+                }
+                Err(e) => tracing::warn!(
+                    error = %e,
+                    output_type = C::output_type(),
+                    output = %name,
+                    path = %path.display(),
+                    "Failed to write records"
+                ),
+            }
+        }
+
+        if changes.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// The tasks keeping each configured output up to date.
+pub(crate) struct Outputs {
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Outputs {
+    pub(crate) fn new(
+        config: &OutputsConfig,
+        server_state: &ServerState<Zones>,
+        changes: &watch::Sender<()>,
+    ) -> Self {
+        fn spawn<C: OutputConfig>(
+            outputs: &HashMap<String, C>,
+            server_state: &ServerState<Zones>,
+            changes: &watch::Sender<()>,
+            handles: &mut Vec<JoinHandle<()>>,
+        ) {
+            for (name, config) in outputs {
+                handles.push(tokio::spawn(output_loop(
+                    name.clone(),
+                    config.clone(),
+                    server_state.clone(),
+                    changes.subscribe(),
+                )));
+            }
+        }
+
+        let mut handles = Vec::new();
+        spawn(&config.hosts_file, server_state, changes, &mut handles);
+        spawn(&config.zone_file, server_state, changes, &mut handles);
+        spawn(&config.unbound, server_state, changes, &mut handles);
+        spawn(&config.dnsmasq, server_state, changes, &mut handles);
+
+        Self { handles }
+    }
+
+    pub(crate) fn shutdown(self) {
+        for handle in self.handles {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, time::Duration};
+
+    use tempfile::TempDir;
+    use tokio::{fs, sync::watch, time::sleep};
+
+    use crate::{
+        config::Zones,
+        dns::{RData, Record, RecordSet, ServerState},
+        outputs::{hosts::HostsFileConfig, Outputs, OutputsConfig},
+        test::{fqdn, timeout},
+    };
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn write_on_change() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("hosts");
+        fs::write(&path, "previous\n").await.unwrap();
+
+        let mut config = OutputsConfig::default();
+        config
+            .hosts_file
+            .insert("test".to_owned(), HostsFileConfig::new(path.clone().into()));
+
+        let server_state = ServerState::new(RecordSet::new(), Zones::default());
+        let changes = watch::Sender::new(());
+        let outputs = Outputs::new(&config, &server_state, &changes);
+
+        // Nothing is written until there are records.
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(fs::read_to_string(&path).await.unwrap(), "previous\n");
+
+        server_state
+            .replace_records(RecordSet::from(vec![Record::new(
+                fqdn("www.home.local"),
+                RData::from("10.10.1.5".parse::<IpAddr>().unwrap()),
+            )]))
+            .await;
+        changes.send_replace(());
+
+        timeout(async {
+            loop {
+                let contents = fs::read_to_string(&path).await.unwrap();
+                if contents.contains("www.home.local") {
+                    break;
+                }
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await;
+
+        server_state.replace_records(RecordSet::new()).await;
+        changes.send_replace(());
+
+        timeout(async {
+            loop {
+                let contents = fs::read_to_string(&path).await.unwrap();
+                if !contents.contains("www.home.local") {
+                    break;
+                }
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await;
+
+        outputs.shutdown();
+    }
+}
@@ -0,0 +1,196 @@
+use std::{fmt::Write, path::PathBuf};
+
+use figment::value::magic::RelativePathBuf;
+use hickory_server::proto::rr;
+use serde::Deserialize;
+
+use crate::{
+    config::{ZoneConfigProvider, Zones},
+    dns::{LockedServerState, RData, RecordSet},
+    outputs::{
+        hosts::{host_name, name_addresses},
+        zone_file::rdata,
+        OutputConfig, Reload,
+    },
+    Error,
+};
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct UnboundConfig {
+    path: RelativePathBuf,
+    #[serde(default)]
+    reload: Option<Reload>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct DnsmasqConfig {
+    path: RelativePathBuf,
+    #[serde(default)]
+    reload: Option<Reload>,
+}
+
+/// Every record visible to all clients with the TTL of its zone.
+fn visible_records(state: &LockedServerState<Zones>) -> Vec<rr::Record> {
+    let mut records: Vec<rr::Record> = state
+        .records
+        .records()
+        .filter(|record| record.tags.is_empty())
+        .filter_map(|record| record.raw(&state.zones.zone_config(record.name())))
+        .collect();
+    records.sort();
+
+    records
+}
+
+fn unbound(records: &[rr::Record]) -> String {
+    let mut contents =
+        String::from("# Generated by LocalNS, changes will be overwritten.\nserver:\n");
+
+    for record in records {
+        let Some(data) = record.data() else {
+            continue;
+        };
+
+        let _ = writeln!(
+            contents,
+            "    local-data: \"{} {} {} {} {}\"",
+            record.name().to_ascii(),
+            record.ttl(),
+            record.dns_class(),
+            record.record_type(),
+            rdata(data)
+        );
+    }
+
+    contents
+}
+
+/// Aliases are given the addresses of their targets as dnsmasq only follows
+/// aliases to names it knows from hosts files or DHCP.
+fn dnsmasq(records: &RecordSet) -> String {
+    let mut contents = String::from("# Generated by LocalNS, changes will be overwritten.\n");
+
+    for (name, addresses) in name_addresses(records) {
+        for ip in addresses {
+            let _ = writeln!(contents, "host-record={},{ip}", host_name(&name));
+        }
+    }
+
+    let mut pointers: Vec<String> = records
+        .records()
+        .filter(|record| record.tags.is_empty())
+        .filter_map(|record| match record.rdata() {
+            RData::Ptr(target) => Some(format!(
+                "ptr-record={},{}",
+                host_name(record.name()),
+                host_name(target)
+            )),
+            _ => None,
+        })
+        .collect();
+    pointers.sort();
+
+    for pointer in pointers {
+        let _ = writeln!(contents, "{pointer}");
+    }
+
+    contents
+}
+
+impl OutputConfig for UnboundConfig {
+    fn output_type() -> &'static str {
+        "unbound"
+    }
+
+    fn path(&self) -> PathBuf {
+        self.path.relative()
+    }
+
+    fn render(&self, state: &LockedServerState<Zones>) -> Result<String, Error> {
+        Ok(unbound(&visible_records(state)))
+    }
+
+    fn reload(&self) -> Option<&Reload> {
+        self.reload.as_ref()
+    }
+}
+
+impl OutputConfig for DnsmasqConfig {
+    fn output_type() -> &'static str {
+        "dnsmasq"
+    }
+
+    fn path(&self) -> PathBuf {
+        self.path.relative()
+    }
+
+    fn render(&self, state: &LockedServerState<Zones>) -> Result<String, Error> {
+        Ok(dnsmasq(&state.records))
+    }
+
+    fn reload(&self) -> Option<&Reload> {
+        self.reload.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use hickory_server::proto::rr;
+
+    use crate::{
+        dns::{RData, Record, RecordSet},
+        outputs::resolver::{dnsmasq, unbound},
+        test::{fqdn, name, rdata_a, rdata_cname},
+    };
+
+    fn address(ip: &str) -> RData {
+        RData::from(ip.parse::<IpAddr>().unwrap())
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn render_unbound() {
+        let records = vec![
+            rr::Record::from_rdata(name("www.home.local."), 300, rdata_a("10.10.1.5")),
+            rr::Record::from_rdata(name("web.home.local."), 60, rdata_cname("www.home.local.")),
+        ];
+
+        assert_eq!(
+            unbound(&records),
+            "# Generated by LocalNS, changes will be overwritten.\n\
+             server:\n    \
+             local-data: \"www.home.local. 300 IN A 10.10.1.5\"\n    \
+             local-data: \"web.home.local. 60 IN CNAME www.home.local.\"\n"
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn render_dnsmasq() {
+        let mut tagged = Record::new(fqdn("private.home.local"), address("10.10.1.9"));
+        tagged.tags.insert("admin".to_owned());
+
+        let records = RecordSet::from(vec![
+            Record::new(fqdn("www.home.local"), address("10.10.1.5")),
+            Record::new(fqdn("www.home.local"), address("fd00::5")),
+            Record::new(fqdn("web.home.local"), RData::Cname(fqdn("www.home.local"))),
+            Record::new(
+                fqdn("5.1.10.10.in-addr.arpa"),
+                RData::Ptr(fqdn("www.home.local")),
+            ),
+            tagged,
+        ]);
+
+        assert_eq!(
+            dnsmasq(&records),
+            "# Generated by LocalNS, changes will be overwritten.\n\
+             host-record=web.home.local,10.10.1.5\n\
+             host-record=web.home.local,fd00::5\n\
+             host-record=www.home.local,10.10.1.5\n\
+             host-record=www.home.local,fd00::5\n\
+             ptr-record=5.1.10.10.in-addr.arpa,www.home.local\n"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::{fmt::Write, path::PathBuf};
+
+use anyhow::anyhow;
+use figment::value::magic::RelativePathBuf;
+use hickory_server::proto::rr::{self, Name};
+use serde::Deserialize;
+
+use crate::{
+    config::Zones,
+    dns::{Fqdn, LockedServerState},
+    outputs::OutputConfig,
+    Error,
+};
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct ZoneFileConfig {
+    zone: Fqdn,
+    path: RelativePathBuf,
+}
+
+/// Record data in master file form. Names are given in their ASCII form.
+pub(super) fn rdata(rdata: &rr::RData) -> String {
+    match rdata {
+        rr::RData::CNAME(cname) => cname.0.to_ascii(),
+        rr::RData::PTR(ptr) => ptr.0.to_ascii(),
+        rr::RData::NS(ns) => ns.0.to_ascii(),
+        rr::RData::SOA(soa) => format!(
+            "{} {} {} {} {} {} {}",
+            soa.mname().to_ascii(),
+            soa.rname().to_ascii(),
+            soa.serial(),
+            soa.refresh(),
+            soa.retry(),
+            soa.expire(),
+            soa.minimum()
+        ),
+        _ => rdata.to_string(),
+    }
+}
+
+/// Renders records as an RFC 1035 master file.
+fn zone_file(origin: &Name, records: &[rr::Record]) -> String {
+    let mut contents = format!(
+        "; Generated by LocalNS, changes will be overwritten.\n$ORIGIN {}\n",
+        origin.to_ascii()
+    );
+
+    for record in records {
+        let Some(data) = record.data() else {
+            continue;
+        };
+
+        let _ = writeln!(
+            contents,
+            "{}\t{}\t{}\t{}\t{}",
+            record.name().to_ascii(),
+            record.ttl(),
+            record.dns_class(),
+            record.record_type(),
+            rdata(data)
+        );
+    }
+
+    contents
+}
+
+impl OutputConfig for ZoneFileConfig {
+    fn output_type() -> &'static str {
+        "zone_file"
+    }
+
+    fn path(&self) -> PathBuf {
+        self.path.relative()
+    }
+
+    fn render(&self, state: &LockedServerState<Zones>) -> Result<String, Error> {
+        let records = state
+            .zone_records(&self.zone)
+            .ok_or_else(|| anyhow!("{} is not an authoritative zone", self.zone))?;
+
+        Ok(zone_file(&self.zone.name(), &records))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_server::proto::rr::{self, rdata::SOA};
+
+    use crate::{
+        outputs::zone_file::zone_file,
+        test::{name, rdata_a, rdata_cname},
+    };
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn render() {
+        let records = vec![
+            rr::Record::from_rdata(
+                name("home.local."),
+                300,
+                rr::RData::SOA(SOA::new(
+                    name("ns.home.local."),
+                    name("hostmaster.home.local."),
+                    12,
+                    300,
+                    300,
+                    3000,
+                    60,
+                )),
+            ),
+            rr::Record::from_rdata(name("www.home.local."), 300, rdata_a("10.10.1.5")),
+            rr::Record::from_rdata(name("café.home.local."), 60, rdata_cname("www.home.local.")),
+        ];
+
+        assert_eq!(
+            zone_file(&name("home.local."), &records),
+            "; Generated by LocalNS, changes will be overwritten.\n\
+             $ORIGIN home.local.\n\
+             home.local.\t300\tIN\tSOA\tns.home.local. hostmaster.home.local. 12 300 300 3000 60\n\
+             www.home.local.\t300\tIN\tA\t10.10.1.5\n\
+             xn--caf-dma.home.local.\t60\tIN\tCNAME\twww.home.local.\n"
+        );
+    }
+}
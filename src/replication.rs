@@ -0,0 +1,260 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    mem,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    dns::{Record, RecordSet},
+    sources::{SourceId, SourceRecords},
+};
+
+/// The most records kept in the log of changes. Replicas that fall further
+/// behind than this are sent every record again.
+const LOG_LIMIT: usize = 10000;
+
+/// A change to the records of a single source.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SourceChange {
+    pub(crate) source_id: SourceId,
+    pub(crate) timestamp: DateTime<Utc>,
+    #[serde(default)]
+    pub(crate) hops: u32,
+    /// The source was removed along with all of its records.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub(crate) cleared: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) added: Vec<Record>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) removed: Vec<Record>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl SourceChange {
+    /// Applies the change to a replica's copy of the sources.
+    pub(crate) fn apply(self, sources: &mut HashMap<SourceId, SourceRecords>) {
+        if self.cleared {
+            sources.remove(&self.source_id);
+            return;
+        }
+
+        let source = sources
+            .entry(self.source_id.clone())
+            .or_insert_with(|| SourceRecords::new(&self.source_id, None, RecordSet::new()));
+        source.timestamp = self.timestamp;
+        source.hops = self.hops;
+
+        if !self.removed.is_empty() {
+            let removed: HashSet<Record> = self.removed.into_iter().collect();
+            source.records = mem::take(&mut source.records)
+                .into_iter()
+                .filter(|record| !removed.contains(record))
+                .collect();
+        }
+
+        for record in self.added {
+            source.records.insert(record);
+        }
+    }
+
+    /// How much of the log the change uses up.
+    fn size(&self) -> usize {
+        (self.added.len() + self.removed.len()).max(1)
+    }
+}
+
+/// The changes to every source's records, numbered by generation so replicas
+/// can ask for only what changed since they last looked.
+#[derive(Debug)]
+pub(crate) struct ReplicationLog {
+    /// Differs every time the server starts as generations start again from 0.
+    epoch: Uuid,
+    generation: u64,
+    /// The log holds every change after this generation.
+    oldest: u64,
+    changes: VecDeque<(u64, SourceChange)>,
+    size: usize,
+}
+
+impl Default for ReplicationLog {
+    fn default() -> Self {
+        Self {
+            epoch: Uuid::new_v4(),
+            generation: 0,
+            oldest: 0,
+            changes: VecDeque::new(),
+            size: 0,
+        }
+    }
+}
+
+impl ReplicationLog {
+    pub(crate) fn epoch(&self) -> Uuid {
+        self.epoch
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn push(&mut self, change: SourceChange) {
+        self.generation += 1;
+        self.size += change.size();
+        self.changes.push_back((self.generation, change));
+
+        while self.size > LOG_LIMIT {
+            let Some((generation, change)) = self.changes.pop_front() else {
+                break;
+            };
+
+            self.size -= change.size();
+            self.oldest = generation;
+        }
+    }
+
+    /// Notes a source's new records, `old` are the records they replace.
+    pub(crate) fn changed(&mut self, old: Option<&RecordSet>, new: &SourceRecords) {
+        let (added, removed) = match old {
+            Some(old) => {
+                let old_records: HashSet<&Record> = old.records().collect();
+                let new_records: HashSet<&Record> = new.records.records().collect();
+
+                (
+                    new_records
+                        .difference(&old_records)
+                        .copied()
+                        .cloned()
+                        .collect(),
+                    old_records
+                        .difference(&new_records)
+                        .copied()
+                        .cloned()
+                        .collect(),
+                )
+            }
+            None => (new.records.records().cloned().collect(), Vec::new()),
+        };
+
+        self.push(SourceChange {
+            source_id: new.source_id.clone(),
+            timestamp: new.timestamp,
+            hops: new.hops,
+            cleared: false,
+            added,
+            removed,
+        });
+    }
+
+    /// Notes that a source and all of its records were removed.
+    pub(crate) fn removed(&mut self, old: &SourceRecords) {
+        self.push(SourceChange {
+            source_id: old.source_id.clone(),
+            timestamp: old.timestamp,
+            hops: old.hops,
+            cleared: true,
+            added: Vec::new(),
+            removed: Vec::new(),
+        });
+    }
+
+    /// The changes after a generation from the given epoch. Returns `None` when
+    /// the log no longer reaches back that far.
+    pub(crate) fn since(&self, epoch: Uuid, generation: u64) -> Option<Vec<SourceChange>> {
+        if epoch != self.epoch || generation < self.oldest || generation > self.generation {
+            return None;
+        }
+
+        Some(
+            self.changes
+                .iter()
+                .skip_while(|(change_generation, _)| *change_generation <= generation)
+                .map(|(_, change)| change.clone())
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use uuid::Uuid;
+
+    use crate::{
+        dns::{RData, Record, RecordSet},
+        replication::{ReplicationLog, SourceChange, LOG_LIMIT},
+        sources::{SourceId, SourceRecords, SourceType},
+        test::fqdn,
+    };
+
+    fn source_records(source_id: &SourceId, addresses: &[&str]) -> SourceRecords {
+        let records: RecordSet = addresses
+            .iter()
+            .map(|address| Record::new(fqdn("www.home.local"), RData::A(address.parse().unwrap())))
+            .collect();
+
+        SourceRecords::new(source_id, None, records)
+    }
+
+    fn replay(replica: &mut HashMap<SourceId, SourceRecords>, changes: Option<Vec<SourceChange>>) {
+        for change in changes.unwrap() {
+            change.apply(replica);
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn changes() {
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::File, "test");
+        let mut log = ReplicationLog::default();
+        let mut replica = HashMap::new();
+
+        assert_eq!(log.since(log.epoch(), 0), Some(Vec::new()));
+        assert_eq!(log.since(Uuid::new_v4(), 0), None);
+
+        let first = source_records(&source_id, &["10.1.1.1", "10.1.1.2"]);
+        log.changed(None, &first);
+        replay(&mut replica, log.since(log.epoch(), 0));
+        assert_eq!(replica.get(&source_id).unwrap().records, first.records);
+
+        let generation = log.generation();
+        let second = source_records(&source_id, &["10.1.1.2", "10.1.1.3"]);
+        log.changed(Some(&first.records), &second);
+
+        let changes = log.since(log.epoch(), generation).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].added.len(), 1);
+        assert_eq!(changes[0].removed.len(), 1);
+
+        replay(&mut replica, Some(changes));
+        assert_eq!(replica.get(&source_id).unwrap().records, second.records);
+
+        let generation = log.generation();
+        log.removed(&second);
+        replay(&mut replica, log.since(log.epoch(), generation));
+        assert!(replica.is_empty());
+
+        assert_eq!(log.since(log.epoch(), log.generation() + 1), None);
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn truncation() {
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::File, "test");
+        let mut log = ReplicationLog::default();
+
+        let records = source_records(&source_id, &["10.1.1.1"]);
+        for _ in 0..=LOG_LIMIT {
+            log.changed(None, &records);
+        }
+
+        assert_eq!(log.since(log.epoch(), 0), None);
+        assert_eq!(log.since(log.epoch(), 1).unwrap().len(), LOG_LIMIT);
+    }
+}
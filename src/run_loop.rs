@@ -1,14 +1,28 @@
-use std::{cmp::min, future::Future, time::Duration};
+use std::{
+    cmp::min,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use chrono::Utc;
 use tokio::time::sleep;
 
-use crate::{sources::SourceId, RecordServer};
+use crate::{dns::RecordSet, sources::SourceId, RecordServer, SourceRecords};
+
+/// How many polls a source can miss before its records expire.
+const LEASE_POLLS: u32 = 3;
 
 pub(crate) enum LoopResult {
+    #[cfg_attr(not(feature = "docker"), allow(dead_code))]
     Sleep,
-    Backoff,
-    Quit,
+    /// The source failed with an error and should be retried.
+    Backoff(String),
+    /// The source failed with an error it cannot recover from.
+    Quit(String),
 }
 
 pub(crate) struct Backoff {
@@ -44,6 +58,39 @@ impl Backoff {
     }
 }
 
+/// How long a polling source waits between polls. Shared with the running source
+/// so it can be changed without restarting the source.
+#[derive(Clone, Debug)]
+pub(crate) struct PollInterval(Arc<AtomicU64>);
+
+impl PollInterval {
+    pub(crate) fn new(interval: u64) -> Self {
+        Self(Arc::new(AtomicU64::new(interval)))
+    }
+
+    pub(crate) fn millis(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set(&self, interval: u64) {
+        self.0.store(interval, Ordering::Relaxed);
+    }
+
+    pub(crate) fn duration(&self) -> Duration {
+        Duration::from_millis(self.millis())
+    }
+
+    /// How long the records from a poll remain valid.
+    pub(crate) fn lease(&self) -> Duration {
+        self.duration() * LEASE_POLLS
+    }
+
+    /// The records from a poll, which expire if the source stops polling.
+    pub(crate) fn records(&self, source_id: &SourceId, records: RecordSet) -> SourceRecords {
+        SourceRecords::new(source_id, None, records).with_lease(self.lease())
+    }
+}
+
 pub(crate) struct RunLoop {
     backoff: Backoff,
 }
@@ -66,12 +113,19 @@ impl RunLoop {
 
             match result {
                 LoopResult::Sleep => self.backoff.reset(),
-                LoopResult::Backoff => {
+                LoopResult::Backoff(error) => {
                     server.clear_source_records(&source_id, Utc::now()).await;
-                    self.backoff.backoff()
+                    self.backoff.backoff();
+
+                    let error = format!(
+                        "{error}, retrying in {}s",
+                        self.backoff.duration().as_secs()
+                    );
+                    server.source_failed(&source_id, error).await;
                 }
-                LoopResult::Quit => {
+                LoopResult::Quit(error) => {
                     server.clear_source_records(&source_id, Utc::now()).await;
+                    server.source_failed(&source_id, error).await;
                     return;
                 }
             };
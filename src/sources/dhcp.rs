@@ -1,26 +1,83 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
+use chrono::{DateTime, Utc};
 use figment::value::magic::RelativePathBuf;
 use serde::Deserialize;
-use tokio::fs::read_to_string;
+use tokio::{fs::read_to_string, task::JoinHandle, time::sleep};
 use tracing::instrument;
 
 use crate::{
     dns::{Fqdn, RData, Record, RecordSet},
-    sources::{SourceConfig, SourceHandle, SourceId, SourceType},
+    sources::{RecordOverrides, SourceConfig, SourceHandle, SourceId, SourceType},
     watcher::{watch, FileEvent, WatchListener},
     Error, RecordServer, SourceRecords,
 };
 
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum LeaseFormat {
+    #[default]
+    Dnsmasq,
+    Dhcpd,
+    Kea,
+    Networkd,
+}
+
 #[derive(Debug, PartialEq, Deserialize, Clone)]
 pub(crate) struct DhcpConfig {
     lease_file: RelativePathBuf,
 
     zone: Fqdn,
+
+    #[serde(default)]
+    format: LeaseFormat,
+    #[serde(flatten)]
+    overrides: RecordOverrides,
 }
 
-fn parse_dnsmasq(zone: &Fqdn, data: &str) -> RecordSet {
-    let mut records = RecordSet::new();
+fn add_lease(records: &mut RecordSet, zone: &Fqdn, hostname: &str, ip: IpAddr) {
+    let name = match zone.child(hostname) {
+        Ok(n) => n,
+        Err(e) => {
+            tracing::warn!(error=%e, "Error parsing lease file");
+            return;
+        }
+    };
+
+    records.insert(Record::new(name, RData::from(ip)));
+}
+
+/// The records from a lease file along with the time that the next lease will
+/// expire.
+#[derive(Default)]
+struct Leases {
+    records: RecordSet,
+    next_expiry: Option<DateTime<Utc>>,
+}
+
+impl From<RecordSet> for Leases {
+    fn from(records: RecordSet) -> Self {
+        Self {
+            records,
+            next_expiry: None,
+        }
+    }
+}
+
+/// Dnsmasq lease lines start with the lease expiry time, zero for infinite
+/// leases. Expired leases are skipped and the rest are given a TTL that runs
+/// out when the lease does. A client that has moved to a new address can still
+/// have a lease for its old one, only the lease that expires last is used.
+/// Clients are identified by their hardware address, or IAID for IPv6 leases,
+/// along with their client ID so different hosts using the same name are all
+/// published.
+fn parse_dnsmasq(zone: &Fqdn, data: &str, now: DateTime<Utc>) -> Leases {
+    let mut clients: HashMap<(&str, &str), (Option<DateTime<Utc>>, Fqdn, RData)> = HashMap::new();
 
     for line in data.lines() {
         let parts: Vec<&str> = line.split_ascii_whitespace().collect();
@@ -33,6 +90,15 @@ fn parse_dnsmasq(zone: &Fqdn, data: &str) -> RecordSet {
                 continue;
             }
 
+            let expiry = match parts[0].parse::<i64>() {
+                Ok(0) => None,
+                Ok(timestamp) => match DateTime::from_timestamp(timestamp, 0) {
+                    Some(expiry) if expiry > now => Some(expiry),
+                    _ => continue,
+                },
+                Err(_) => continue,
+            };
+
             let name = match zone.child(*name) {
                 Ok(n) => n,
                 Err(e) => {
@@ -49,42 +115,328 @@ fn parse_dnsmasq(zone: &Fqdn, data: &str) -> RecordSet {
                 }
             };
 
-            records.insert(Record::new(name, rdata));
+            match clients.entry((parts[1], parts[4])) {
+                Entry::Occupied(mut entry) => {
+                    let (current, _, _) = entry.get();
+                    // Leases that never expire are the newest.
+                    if current.is_some() && expiry.is_none_or(|expiry| Some(expiry) > *current) {
+                        entry.insert((expiry, name, rdata));
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert((expiry, name, rdata));
+                }
+            }
+        }
+    }
+
+    let mut leases = Leases::default();
+
+    for (expiry, name, rdata) in clients.into_values() {
+        let mut record = Record::new(name, rdata);
+
+        if let Some(expiry) = expiry {
+            let remaining = (expiry - now).num_seconds();
+            record.ttl = Some(remaining.clamp(1, u32::MAX.into()) as u32);
+
+            if leases.next_expiry.is_none_or(|next| expiry < next) {
+                leases.next_expiry = Some(expiry);
+            }
+        }
+
+        leases.records.insert(record);
+    }
+
+    leases
+}
+
+/// ISC dhcpd appends to its lease file as leases change so later entries for an
+/// address replace earlier ones.
+fn parse_dhcpd(zone: &Fqdn, data: &str) -> RecordSet {
+    let mut leases: HashMap<IpAddr, (usize, bool, Option<String>)> = HashMap::new();
+    let mut current: Option<(IpAddr, bool, Option<String>)> = None;
+
+    for (position, line) in data.lines().enumerate() {
+        let line = line.trim();
+
+        if let Some(lease) = line.strip_prefix("lease ") {
+            let Some(ip) = lease.strip_suffix('{') else {
+                continue;
+            };
+
+            current = match ip.trim().parse() {
+                Ok(ip) => Some((ip, false, None)),
+                Err(e) => {
+                    tracing::warn!(error=%e, "Error parsing lease file");
+                    None
+                }
+            };
+        } else if line == "}" {
+            if let Some((ip, active, hostname)) = current.take() {
+                leases.insert(ip, (position, active, hostname));
+            }
+        } else if let Some((_, active, hostname)) = current.as_mut() {
+            if let Some(state) = line.strip_prefix("binding state ") {
+                *active = state.trim_end_matches(';').trim() == "active";
+            } else if let Some(name) = line.strip_prefix("client-hostname ") {
+                *hostname = Some(
+                    name.trim_end_matches(';')
+                        .trim()
+                        .trim_matches('"')
+                        .to_owned(),
+                );
+            }
+        }
+    }
+
+    // A host that moved to a new address is only given the one that it was
+    // leased most recently.
+    let mut hosts: HashMap<(String, bool), (usize, IpAddr)> = HashMap::new();
+    for (ip, (position, active, hostname)) in leases {
+        if let (true, Some(hostname)) = (active, hostname) {
+            let entry = hosts
+                .entry((hostname, ip.is_ipv4()))
+                .or_insert((position, ip));
+            if position > entry.0 {
+                *entry = (position, ip);
+            }
+        }
+    }
+
+    let mut records = RecordSet::new();
+
+    for ((hostname, _), (_, ip)) in hosts {
+        add_lease(&mut records, zone, &hostname, ip);
+    }
+
+    records
+}
+
+#[derive(Deserialize)]
+struct KeaLease {
+    #[serde(rename = "ip-address")]
+    ip_address: IpAddr,
+    #[serde(default)]
+    hostname: String,
+    #[serde(default)]
+    state: u32,
+}
+
+#[derive(Deserialize)]
+struct KeaLeaseList {
+    leases: Vec<KeaLease>,
+}
+
+#[derive(Deserialize)]
+struct KeaResponse {
+    arguments: KeaLeaseList,
+}
+
+/// Kea leases as exported by the `lease4-get-all` and `lease6-get-all` commands,
+/// either the full command response or just the lease list.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeaLeases {
+    Leases(KeaLeaseList),
+    Responses(Vec<KeaResponse>),
+}
+
+fn parse_kea(zone: &Fqdn, data: &str) -> RecordSet {
+    let mut records = RecordSet::new();
+
+    let leases = match serde_json::from_str::<KeaLeases>(data) {
+        Ok(KeaLeases::Leases(list)) => list.leases,
+        Ok(KeaLeases::Responses(responses)) => responses
+            .into_iter()
+            .flat_map(|response| response.arguments.leases)
+            .collect(),
+        Err(e) => {
+            tracing::warn!(error=%e, "Error parsing lease file");
+            return records;
+        }
+    };
+
+    for lease in leases {
+        // Only state 0 is an assigned lease, others are declined or expired.
+        if lease.state != 0 || lease.hostname.is_empty() {
+            continue;
+        }
+
+        let hostname = lease.hostname.trim_end_matches('.');
+        add_lease(&mut records, zone, hostname, lease.ip_address);
+    }
+
+    records
+}
+
+/// systemd-networkd gives addresses as either a string or an array of bytes.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NetworkdAddress {
+    Text(IpAddr),
+    Bytes([u8; 4]),
+}
+
+impl From<NetworkdAddress> for IpAddr {
+    fn from(address: NetworkdAddress) -> Self {
+        match address {
+            NetworkdAddress::Text(ip) => ip,
+            NetworkdAddress::Bytes(bytes) => IpAddr::from(bytes),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NetworkdLease {
+    address: NetworkdAddress,
+    #[serde(default)]
+    hostname: Option<String>,
+    #[serde(default)]
+    expiration_realtime_u_sec: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NetworkdLeases {
+    #[serde(default)]
+    leases: Vec<NetworkdLease>,
+    #[serde(default)]
+    static_leases: Vec<NetworkdLease>,
+}
+
+/// The leases that systemd-networkd's DHCP server has handed out, saved as JSON
+/// for each interface the server runs on.
+fn parse_networkd(zone: &Fqdn, data: &str, now: DateTime<Utc>) -> RecordSet {
+    let mut records = RecordSet::new();
+
+    let leases = match serde_json::from_str::<NetworkdLeases>(data) {
+        Ok(leases) => leases,
+        Err(e) => {
+            tracing::warn!(error=%e, "Error parsing lease file");
+            return records;
+        }
+    };
+
+    for lease in leases.leases.into_iter().chain(leases.static_leases) {
+        let Some(hostname) = lease.hostname.filter(|hostname| !hostname.is_empty()) else {
+            continue;
+        };
+
+        if lease
+            .expiration_realtime_u_sec
+            .is_some_and(|expiry| expiry <= now.timestamp_micros())
+        {
+            continue;
         }
+
+        add_lease(&mut records, zone, &hostname, lease.address.into());
     }
 
     records
 }
 
 #[instrument(fields(%source_id), )]
-async fn parse_file(source_id: &SourceId, zone: &Fqdn, lease_file: &Path) -> RecordSet {
+async fn parse_file(
+    source_id: &SourceId,
+    zone: &Fqdn,
+    format: LeaseFormat,
+    lease_file: &Path,
+) -> Leases {
     tracing::trace!("Parsing dhcp lease file");
 
     let data = match read_to_string(lease_file).await {
         Ok(s) => s,
         Err(e) => {
             tracing::error!(error = %e, "Failed to read lease file");
-            return RecordSet::new();
+            return Leases::default();
         }
     };
 
-    parse_dnsmasq(zone, &data)
+    match format {
+        LeaseFormat::Dnsmasq => parse_dnsmasq(zone, &data, Utc::now()),
+        LeaseFormat::Dhcpd => parse_dhcpd(zone, &data).into(),
+        LeaseFormat::Kea => parse_kea(zone, &data).into(),
+        LeaseFormat::Networkd => parse_networkd(zone, &data, Utc::now()).into(),
+    }
 }
 
-struct SourceWatcher<S> {
+#[derive(Clone)]
+struct LeaseSource<S> {
     source_id: SourceId,
     dhcp_config: DhcpConfig,
     lease_file: PathBuf,
     server: S,
 }
 
-impl<S: RecordServer> WatchListener for SourceWatcher<S> {
-    async fn event(&mut self, _: FileEvent) {
-        let records = parse_file(&self.source_id, &self.dhcp_config.zone, &self.lease_file).await;
+impl<S: RecordServer> LeaseSource<S> {
+    /// Publishes the current leases, returning when the next one will expire.
+    async fn publish(&self) -> Option<DateTime<Utc>> {
+        let leases = parse_file(
+            &self.source_id,
+            &self.dhcp_config.zone,
+            self.dhcp_config.format,
+            &self.lease_file,
+        )
+        .await;
 
         self.server
-            .add_source_records(SourceRecords::new(&self.source_id, None, records))
-            .await
+            .add_source_records(SourceRecords::new(&self.source_id, None, leases.records))
+            .await;
+
+        leases.next_expiry
+    }
+}
+
+/// Republishes the leases as they expire, the DHCP server may not rewrite the
+/// lease file when that happens.
+#[derive(Default)]
+struct ExpiryTimer {
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ExpiryTimer {
+    fn schedule<S: RecordServer>(
+        &self,
+        source: LeaseSource<S>,
+        next_expiry: Option<DateTime<Utc>>,
+    ) {
+        let task = next_expiry.map(|mut expiry| {
+            tokio::spawn(async move {
+                loop {
+                    sleep((expiry - Utc::now()).to_std().unwrap_or_default()).await;
+
+                    match source.publish().await {
+                        Some(next) => expiry = next,
+                        None => break,
+                    }
+                }
+            })
+        });
+
+        if let Some(old_task) = std::mem::replace(&mut *self.task.lock().unwrap(), task) {
+            old_task.abort();
+        }
+    }
+}
+
+impl Drop for ExpiryTimer {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+}
+
+struct SourceWatcher<S> {
+    source: LeaseSource<S>,
+    expiry: Arc<ExpiryTimer>,
+}
+
+impl<S: RecordServer> WatchListener for SourceWatcher<S> {
+    async fn event(&mut self, _: FileEvent) {
+        let next_expiry = self.source.publish().await;
+        self.expiry.schedule(self.source.clone(), next_expiry);
     }
 }
 
@@ -93,6 +445,21 @@ impl SourceConfig for DhcpConfig {
         SourceType::Dhcp
     }
 
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        Some(&self.overrides)
+    }
+
+    fn without_live_settings(&self) -> Self {
+        Self {
+            overrides: Default::default(),
+            ..self.clone()
+        }
+    }
+
+    fn required_files(&self) -> Vec<PathBuf> {
+        vec![self.lease_file.relative()]
+    }
+
     #[instrument(fields(%source_id), skip(self, server))]
     async fn spawn<S: RecordServer>(
         self,
@@ -101,26 +468,26 @@ impl SourceConfig for DhcpConfig {
     ) -> Result<SourceHandle<S>, Error> {
         tracing::trace!("Adding source");
         let lease_file = self.lease_file.relative();
-        let zone = self.zone.clone();
+
+        let source = LeaseSource {
+            source_id,
+            server: server.clone(),
+            dhcp_config: self,
+            lease_file: lease_file.clone(),
+        };
+        let expiry = Arc::new(ExpiryTimer::default());
 
         let watcher = watch(
-            &lease_file.clone(),
+            &lease_file,
             SourceWatcher {
-                source_id: source_id.clone(),
-                server: server.clone(),
-                dhcp_config: self,
-                lease_file: lease_file.clone(),
+                source: source.clone(),
+                expiry: expiry.clone(),
             },
         )
         .await?;
 
-        server
-            .add_source_records(SourceRecords::new(
-                &source_id,
-                None,
-                parse_file(&source_id, &zone, &lease_file).await,
-            ))
-            .await;
+        let next_expiry = source.publish().await;
+        expiry.schedule(source, next_expiry);
 
         Ok(watcher.into())
     }
@@ -133,11 +500,13 @@ mod tests {
         str::FromStr,
     };
 
+    use chrono::{DateTime, Utc};
+    use hickory_server::proto::rr::{DNSClass, RecordType};
     use tempfile::TempDir;
     use uuid::Uuid;
 
     use crate::{
-        dns::RData,
+        dns::{RData, RecordSet},
         sources::{dhcp::DhcpConfig, SourceConfig, SourceId},
         test::{fqdn, name, write_file, SingleSourceServer},
     };
@@ -147,7 +516,7 @@ mod tests {
     fn parse_hosts() {
         let zone = fqdn("home.local");
 
-        let records = super::parse_dnsmasq(
+        let leases = super::parse_dnsmasq(
             &zone,
             r#"
 1646820667 64:4b:c2:7a:cd:83 10.10.1.24 caldigit 01:64:4b:c2:7a:cd:83
@@ -163,10 +532,21 @@ duid 00:01:00:01:2f:0e:bf:99:00:e2:69:3e:6c:0a
 1736266909 0 2b02:c7a:7e12:5b00:1::7a36 shashlik 00:01:00:01:2f:0e:b5:f6:84:2f:57:64:43:9f
 1736266908 0 2b02:c7a:7e12:5b00:1::36a3 * 00:03:00:01:92:c1:8f:99:66:8c
 1736266906 74879383 2a02:c7c:8e12:5b00:1::c8da tikka 00:02:00:00:ab:11:57:4e:b6:bf:29:c2:65:a7
+0 f4:d4:ac:db:a5:4d 10.10.1.99 forever 01:f4:d4:ac:db:a5:4d
         "#,
+            DateTime::from_timestamp(1646820000, 0).unwrap(),
         );
 
-        assert_eq!(records.len(), 10);
+        assert_eq!(leases.next_expiry, DateTime::from_timestamp(1646820343, 0));
+
+        let records = leases.records;
+        assert_eq!(records.len(), 11);
+
+        assert_eq!(
+            ttl(&records, "laptop.home.local.", RecordType::A),
+            Some(649)
+        );
+        assert_eq!(ttl(&records, "forever.home.local.", RecordType::A), None);
 
         assert!(records.contains(
             &fqdn("mandelbrot.home.local"),
@@ -214,19 +594,258 @@ duid 00:01:00:01:2f:0e:bf:99:00:e2:69:3e:6c:0a
         ));
     }
 
+    fn ttl(records: &RecordSet, host: &str, record_type: RecordType) -> Option<u32> {
+        records
+            .lookup(&name(host), DNSClass::IN, record_type)
+            .next()
+            .unwrap()
+            .ttl
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn expired_leases() {
+        let leases = super::parse_dnsmasq(
+            &fqdn("home.local"),
+            r#"
+1646820667 64:4b:c2:7a:cd:83 10.10.1.24 caldigit 01:64:4b:c2:7a:cd:83
+1646820649 8c:85:c2:7a:cf:8d 10.10.1.70 laptop 01:8c:85:c2:7a:cf:8d
+1646820540 08:aa:0b:47:a3:f8 10.10.1.163 moto-power 01:08:aa:0b:47:a3:f8
+"#,
+            DateTime::from_timestamp(1646820649, 0).unwrap(),
+        );
+
+        assert_eq!(leases.next_expiry, DateTime::from_timestamp(1646820667, 0));
+        assert_eq!(leases.records.len(), 1);
+        assert_eq!(
+            ttl(&leases.records, "caldigit.home.local.", RecordType::A),
+            Some(18)
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn moved_hosts() {
+        let leases = super::parse_dnsmasq(
+            &fqdn("home.local"),
+            r#"
+1646820667 64:4b:c2:7a:cd:83 10.10.1.24 caldigit 01:64:4b:c2:7a:cd:83
+1646824267 64:4b:c2:7a:cd:83 10.10.1.58 caldigit 01:64:4b:c2:7a:cd:83
+1646820667 64:4b:c2:7a:cd:83 10.10.1.59 caldigit 01:64:4b:c2:7a:cd:83
+1736266946 1 2b02:c7a:7e12:5b00:1::26b7 caldigit 00:01:00:01:2f:0e:b5:f6:84:2f:57:64:43:9f
+1646820649 8c:85:c2:7a:cf:8d 10.10.1.70 laptop 01:8c:85:c2:7a:cf:8d
+0 8c:85:c2:7a:cf:8d 10.10.1.71 laptop 01:8c:85:c2:7a:cf:8d
+1646824267 8c:85:c2:7a:cf:8d 10.10.1.72 laptop 01:8c:85:c2:7a:cf:8d
+1646820667 3c:22:fb:10:4e:01 10.10.1.80 phone 01:3c:22:fb:10:4e:01
+1646824267 3c:22:fb:10:4e:02 10.10.1.81 phone 01:3c:22:fb:10:4e:02
+"#,
+            DateTime::from_timestamp(1646820000, 0).unwrap(),
+        );
+
+        let records = leases.records;
+        assert_eq!(records.len(), 5);
+        assert!(records.contains(
+            &fqdn("caldigit.home.local"),
+            &RData::A(Ipv4Addr::from_str("10.10.1.58").unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("caldigit.home.local"),
+            &RData::Aaaa(Ipv6Addr::from_str("2b02:c7a:7e12:5b00:1::26b7").unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("laptop.home.local"),
+            &RData::A(Ipv4Addr::from_str("10.10.1.71").unwrap())
+        ));
+        // Different hosts using the same name are all published.
+        assert!(records.contains(
+            &fqdn("phone.home.local"),
+            &RData::A(Ipv4Addr::from_str("10.10.1.80").unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("phone.home.local"),
+            &RData::A(Ipv4Addr::from_str("10.10.1.81").unwrap())
+        ));
+        assert_eq!(leases.next_expiry, DateTime::from_timestamp(1646820667, 0));
+
+        let records = super::parse_dhcpd(
+            &fqdn("home.local"),
+            r#"
+lease 10.10.1.24 {
+  binding state active;
+  client-hostname "caldigit";
+}
+lease 10.10.1.58 {
+  binding state active;
+  client-hostname "caldigit";
+}
+lease 10.10.1.24 {
+  binding state active;
+  client-hostname "caldigit";
+}
+lease 10.10.1.70 {
+  binding state active;
+  client-hostname "laptop";
+}
+lease 10.10.1.71 {
+  binding state free;
+  client-hostname "laptop";
+}
+"#,
+        );
+
+        assert_eq!(records.len(), 2);
+        assert!(records.contains(
+            &fqdn("caldigit.home.local"),
+            &RData::A(Ipv4Addr::from_str("10.10.1.24").unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("laptop.home.local"),
+            &RData::A(Ipv4Addr::from_str("10.10.1.70").unwrap())
+        ));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn parse_dhcpd() {
+        let zone = fqdn("home.local");
+
+        let records = super::parse_dhcpd(
+            &zone,
+            r#"
+# The format of this file is documented in the dhcpd.leases(5) manual page.
+authoring-byte-order little-endian;
+
+lease 10.10.1.24 {
+  starts 4 2024/01/04 10:00:00;
+  ends 4 2024/01/04 22:00:00;
+  binding state active;
+  next binding state free;
+  hardware ethernet 64:4b:c2:7a:cd:83;
+  client-hostname "caldigit";
+}
+lease 10.10.1.70 {
+  starts 4 2024/01/04 10:00:00;
+  ends 4 2024/01/04 22:00:00;
+  binding state active;
+  next binding state free;
+  client-hostname "laptop";
+}
+lease 10.10.1.163 {
+  binding state active;
+  hardware ethernet 08:aa:0b:47:a3:f8;
+}
+lease 10.10.1.70 {
+  starts 4 2024/01/04 12:00:00;
+  ends 4 2024/01/04 12:00:00;
+  binding state free;
+  client-hostname "laptop";
+}
+"#,
+        );
+
+        assert_eq!(records.len(), 1);
+
+        assert!(records.contains(
+            &fqdn("caldigit.home.local"),
+            &RData::A(Ipv4Addr::from_str("10.10.1.24").unwrap())
+        ));
+        assert!(!records.has_name(&name("laptop.home.local.")));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn parse_kea() {
+        let zone = fqdn("home.local");
+
+        let records = super::parse_kea(
+            &zone,
+            r#"[{
+  "result": 0,
+  "text": "3 IPv4 lease(s) found.",
+  "arguments": {
+    "leases": [
+      { "ip-address": "10.10.1.24", "hw-address": "64:4b:c2:7a:cd:83", "hostname": "caldigit.", "state": 0 },
+      { "ip-address": "10.10.1.70", "hw-address": "8c:85:c2:7a:cf:8d", "hostname": "laptop", "state": 2 },
+      { "ip-address": "10.10.1.163", "hw-address": "08:aa:0b:47:a3:f8", "hostname": "", "state": 0 }
+    ]
+  }
+}]"#,
+        );
+
+        assert_eq!(records.len(), 1);
+        assert!(records.contains(
+            &fqdn("caldigit.home.local"),
+            &RData::A(Ipv4Addr::from_str("10.10.1.24").unwrap())
+        ));
+
+        let records = super::parse_kea(
+            &zone,
+            r#"{ "leases": [ { "ip-address": "2b02:c7a:7e12:5b00:1::7a36", "hostname": "shashlik" } ] }"#,
+        );
+
+        assert_eq!(records.len(), 1);
+        assert!(records.contains(
+            &fqdn("shashlik.home.local"),
+            &RData::Aaaa(Ipv6Addr::from_str("2b02:c7a:7e12:5b00:1::7a36").unwrap())
+        ));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn parse_networkd() {
+        let zone = fqdn("home.local");
+        let now = Utc::now();
+        let expiry = now.timestamp_micros() + 3_600_000_000;
+        let expired = now.timestamp_micros() - 1;
+
+        let records = super::parse_networkd(
+            &zone,
+            &format!(
+                r#"{{
+  "BootID": [136, 80, 236, 110, 61, 137, 69, 83, 145, 68, 150, 152, 245, 58, 155, 174],
+  "Address": [10, 10, 1, 1],
+  "PrefixLength": 24,
+  "Leases": [
+    {{ "ClientId": [1, 100, 75, 194, 122, 205, 131], "Address": [10, 10, 1, 24], "Hostname": "caldigit", "ExpirationRealtimeUSec": {expiry} }},
+    {{ "ClientId": [1, 140, 133, 194, 122, 207, 141], "Address": [10, 10, 1, 70], "Hostname": "laptop", "ExpirationRealtimeUSec": {expired} }},
+    {{ "ClientId": [1, 8, 170, 11, 71, 163, 248], "Address": [10, 10, 1, 163] }}
+  ],
+  "StaticLeases": [
+    {{ "ClientId": [1, 82, 84, 0, 18, 52, 86], "Address": "10.10.1.5", "Hostname": "nas" }}
+  ]
+}}"#
+            ),
+            now,
+        );
+
+        assert_eq!(records.len(), 2);
+        assert!(records.contains(
+            &fqdn("caldigit.home.local"),
+            &RData::A(Ipv4Addr::from_str("10.10.1.24").unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("nas.home.local"),
+            &RData::A(Ipv4Addr::from_str("10.10.1.5").unwrap())
+        ));
+        assert!(!records.has_name(&name("laptop.home.local.")));
+    }
+
     #[tracing_test::traced_test]
     #[tokio::test(flavor = "multi_thread")]
     async fn integration() {
         let temp = TempDir::new().unwrap();
 
         let lease_file = temp.path().join("leases");
+        let expiry = Utc::now().timestamp() + 3600;
 
         write_file(
             &lease_file,
-            r#"
-1646820667 64:4b:c2:7a:cd:83 10.10.1.24 caldigit 01:64:4b:c2:7a:cd:83
-1646820649 8c:85:c2:7a:cf:8d 10.10.1.70 laptop 01:8c:85:c2:7a:cf:8d
-"#,
+            format!(
+                r#"
+{expiry} 64:4b:c2:7a:cd:83 10.10.1.24 caldigit 01:64:4b:c2:7a:cd:83
+{expiry} 8c:85:c2:7a:cf:8d 10.10.1.70 laptop 01:8c:85:c2:7a:cf:8d
+"#
+            ),
         )
         .await;
 
@@ -239,6 +858,8 @@ duid 00:01:00:01:2f:0e:bf:99:00:e2:69:3e:6c:0a
         let config = DhcpConfig {
             lease_file: lease_file.as_path().into(),
             zone: fqdn("home.local."),
+            format: Default::default(),
+            overrides: Default::default(),
         };
 
         let mut test_server = SingleSourceServer::new(&source_id);
@@ -263,16 +884,24 @@ duid 00:01:00:01:2f:0e:bf:99:00:e2:69:3e:6c:0a
 
         assert!(!records.has_name(&name("other.home.local")));
 
+        // The short lease should disappear without the file changing again.
         write_file(
             &lease_file,
-            r#"
-1646820667 64:4b:c2:7a:cd:83 10.10.1.58 other 01:64:4b:c2:7a:cd:83
+            format!(
+                r#"
+{expiry} 64:4b:c2:7a:cd:83 10.10.1.58 other 01:64:4b:c2:7a:cd:83
+{} 8c:85:c2:7a:cf:8d 10.10.1.70 laptop 01:8c:85:c2:7a:cf:8d
         "#,
+                Utc::now().timestamp() + 2
+            ),
         )
         .await;
 
         let records = test_server
-            .wait_for_records(|records| records.has_name(&name("other.home.local.")))
+            .wait_for_records(|records| {
+                records.has_name(&name("other.home.local."))
+                    && !records.has_name(&name("laptop.home.local."))
+            })
             .await;
 
         assert_eq!(records.len(), 1);
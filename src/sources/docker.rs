@@ -1,23 +1,27 @@
 use std::{
+    cmp::min,
     collections::{HashMap, HashSet},
     fs,
-    net::{Ipv4Addr, Ipv6Addr},
-    path::Path,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 
 use anyhow::{bail, Context};
 use bollard::{models, Docker, API_DEFAULT_VERSION};
 use figment::value::magic::RelativePathBuf;
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
+use hickory_server::proto::rr::Name;
 use serde::Deserialize;
+use tokio::time::{timeout, timeout_at, Instant};
 use tracing::instrument;
 
 use crate::{
     dns::{Fqdn, RData, Record, RecordSet},
     run_loop::{LoopResult, RunLoop},
-    sources::{SourceConfig, SourceHandle, SourceId, SourceType},
-    util::Address,
+    sources::{RecordOverrides, SourceConfig, SourceHandle, SourceId, SourceType},
+    util::{Address, Subnet},
     Error, RecordServer, SourceRecords,
 };
 
@@ -27,6 +31,109 @@ pub(crate) struct DockerTls {
     pub private_key: RelativePathBuf,
     pub certificate: RelativePathBuf,
     pub ca: RelativePathBuf,
+    #[serde(default)]
+    pub auto_hostnames: Option<AutoHostnames>,
+    #[serde(default)]
+    pub duplicates: DuplicatePolicy,
+    #[serde(default)]
+    pub ipv6_prefixes: Option<Vec<Subnet>>,
+    #[serde(default)]
+    pub reverse_records: bool,
+    #[serde(flatten)]
+    pub overrides: RecordOverrides,
+}
+
+/// What the source does when more than one container produces records for the
+/// same name.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum DuplicatePolicy {
+    /// None of the conflicting records are published.
+    Error,
+    /// Only the records from the first entry are published.
+    FirstWins,
+    /// The records from every entry are published.
+    #[default]
+    MergeAll,
+}
+
+/// Collects the records while tracking which container produced each name so
+/// that conflicts can be resolved according to a `DuplicatePolicy`.
+struct RecordCollector {
+    policy: DuplicatePolicy,
+    names: HashMap<Fqdn, Vec<(String, Vec<Record>)>>,
+}
+
+impl RecordCollector {
+    fn new(policy: DuplicatePolicy) -> Self {
+        Self {
+            policy,
+            names: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, entry: &str, record: Record) {
+        let entries = self.names.entry(record.name().clone()).or_default();
+
+        match entries.iter_mut().find(|(e, _)| e == entry) {
+            Some((_, records)) => records.push(record),
+            None => entries.push((entry.to_owned(), vec![record])),
+        }
+    }
+
+    fn finish(self, source_id: &SourceId) -> RecordSet {
+        let mut records = RecordSet::new();
+
+        for (name, mut entries) in self.names {
+            if entries.len() > 1 {
+                let names: Vec<&str> = entries.iter().map(|(e, _)| e.as_str()).collect();
+                let entry_list = names.join(", ");
+
+                match self.policy {
+                    DuplicatePolicy::Error => {
+                        tracing::error!(
+                            %source_id,
+                            %name,
+                            entries = entry_list,
+                            "Multiple containers produced records for the same name, ignoring all of them",
+                        );
+                        continue;
+                    }
+                    DuplicatePolicy::FirstWins => {
+                        tracing::warn!(
+                            %source_id,
+                            %name,
+                            entries = entry_list,
+                            "Multiple containers produced records for the same name, using the first",
+                        );
+                        entries.truncate(1);
+                    }
+                    DuplicatePolicy::MergeAll => {
+                        tracing::warn!(
+                            %source_id,
+                            %name,
+                            entries = entry_list,
+                            "Multiple containers produced records for the same name, merging them",
+                        );
+                    }
+                }
+            }
+
+            for (_, entry_records) in entries {
+                for record in entry_records {
+                    records.insert(record);
+                }
+            }
+        }
+
+        records
+    }
+}
+
+/// Generates names for containers that have no `localns.hostname` label.
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct AutoHostnames {
+    pub zone: Fqdn,
 }
 
 #[derive(Debug, PartialEq, Deserialize, Clone)]
@@ -34,7 +141,74 @@ pub(crate) struct DockerTls {
 pub(crate) enum DockerConfig {
     Address(String),
     Tls(Box<DockerTls>),
-    Local {},
+    Connection {
+        address: String,
+        #[serde(default)]
+        auto_hostnames: Option<AutoHostnames>,
+        #[serde(default)]
+        duplicates: DuplicatePolicy,
+        #[serde(default)]
+        ipv6_prefixes: Option<Vec<Subnet>>,
+        #[serde(default)]
+        reverse_records: bool,
+        #[serde(flatten)]
+        overrides: RecordOverrides,
+    },
+    Local {
+        #[serde(default)]
+        auto_hostnames: Option<AutoHostnames>,
+        #[serde(default)]
+        duplicates: DuplicatePolicy,
+        #[serde(default)]
+        ipv6_prefixes: Option<Vec<Subnet>>,
+        #[serde(default)]
+        reverse_records: bool,
+        #[serde(flatten)]
+        overrides: RecordOverrides,
+    },
+}
+
+impl DockerConfig {
+    fn auto_hostnames(&self) -> Option<&AutoHostnames> {
+        match self {
+            DockerConfig::Address(_) => None,
+            DockerConfig::Tls(tls_config) => tls_config.auto_hostnames.as_ref(),
+            DockerConfig::Connection { auto_hostnames, .. } => auto_hostnames.as_ref(),
+            DockerConfig::Local { auto_hostnames, .. } => auto_hostnames.as_ref(),
+        }
+    }
+
+    fn duplicates(&self) -> DuplicatePolicy {
+        match self {
+            DockerConfig::Address(_) => DuplicatePolicy::default(),
+            DockerConfig::Tls(tls_config) => tls_config.duplicates,
+            DockerConfig::Connection { duplicates, .. } => *duplicates,
+            DockerConfig::Local { duplicates, .. } => *duplicates,
+        }
+    }
+
+    /// When set only IPv6 addresses within these prefixes are published.
+    fn ipv6_prefixes(&self) -> Option<&[Subnet]> {
+        match self {
+            DockerConfig::Address(_) => None,
+            DockerConfig::Tls(tls_config) => tls_config.ipv6_prefixes.as_deref(),
+            DockerConfig::Connection { ipv6_prefixes, .. } => ipv6_prefixes.as_deref(),
+            DockerConfig::Local { ipv6_prefixes, .. } => ipv6_prefixes.as_deref(),
+        }
+    }
+
+    fn reverse_records(&self) -> bool {
+        match self {
+            DockerConfig::Address(_) => false,
+            DockerConfig::Tls(tls_config) => tls_config.reverse_records,
+            DockerConfig::Connection {
+                reverse_records, ..
+            } => *reverse_records,
+            DockerConfig::Local {
+                reverse_records, ..
+            } => *reverse_records,
+        }
+    }
 }
 
 type Labels = HashMap<String, String>;
@@ -45,6 +219,8 @@ struct Network {
     name: String,
     driver: Option<String>,
     labels: Labels,
+    /// The subnets that docker allocates addresses from.
+    subnets: Vec<Subnet>,
 }
 
 impl TryFrom<models::Network> for Network {
@@ -56,6 +232,13 @@ impl TryFrom<models::Network> for Network {
             name: state.name.ok_or_else(|| String::from("Missing name"))?,
             driver: state.driver,
             labels: state.labels.unwrap_or_default(),
+            subnets: state
+                .ipam
+                .and_then(|ipam| ipam.config)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|config| Subnet::from_str(config.subnet.as_deref()?).ok())
+                .collect(),
         })
     }
 }
@@ -88,6 +271,7 @@ impl ContainerEndpoint {
 #[derive(Debug, PartialEq, Eq, Clone)]
 struct Container {
     id: String,
+    created: Option<i64>,
     names: Vec<String>,
     image: Option<String>,
     networks: HashMap<String, ContainerEndpoint>,
@@ -113,12 +297,38 @@ impl Container {
 
         Ok(Container {
             id: state.id.context("Missing id")?,
+            created: state.created,
             image: state.image,
             names: state.names.unwrap_or_default(),
             networks: container_networks,
             labels: state.labels.unwrap_or_default(),
         })
     }
+
+    /// A record for the container, tagged with the comma separated tags in its
+    /// `localns.tags` label.
+    fn record(&self, name: Fqdn, rdata: RData) -> Record {
+        let mut record = Record::new(name, rdata);
+
+        if let Some(tags) = self.labels.get("localns.tags") {
+            record.tags = tags
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_owned)
+                .collect();
+        }
+
+        record
+    }
+
+    /// The name used to identify the container in log messages.
+    fn display_name(&self) -> &str {
+        match self.names.first() {
+            Some(name) => name.trim_start_matches('/'),
+            None => &self.id,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -129,6 +339,16 @@ struct DockerState {
 
 const DOCKER_TIMEOUT: u64 = 4;
 
+/// Events this close together are handled as one change.
+const EVENT_BURST_GAP: Duration = Duration::from_millis(300);
+/// The longest that a continuous burst of events delays an update.
+const EVENT_BURST_MAX: Duration = Duration::from_secs(2);
+/// Without any events the state is still fetched this often to renew the lease
+/// on the records.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// How long the records remain valid without being refreshed.
+const RECORDS_LEASE: Duration = Duration::from_secs(3 * 60);
+
 fn check_file(file: &Path) -> Result<(), Error> {
     let metadata = fs::metadata(file)?;
 
@@ -146,7 +366,7 @@ fn useful_event(ev: &models::EventMessage) -> bool {
 #[instrument(fields(%source_id), skip(docker_config))]
 fn connect(source_id: &SourceId, docker_config: &DockerConfig) -> Result<Docker, Error> {
     let docker = match docker_config {
-        DockerConfig::Address(address) => {
+        DockerConfig::Address(address) | DockerConfig::Connection { address, .. } => {
             if address.starts_with("http://") {
                 tracing::trace!(address, "Attempting to connect to docker daemon over HTTP");
                 Docker::connect_with_http(address, DOCKER_TIMEOUT, API_DEFAULT_VERSION)?
@@ -155,7 +375,7 @@ fn connect(source_id: &SourceId, docker_config: &DockerConfig) -> Result<Docker,
                 Docker::connect_with_local(address, DOCKER_TIMEOUT, API_DEFAULT_VERSION)?
             }
         }
-        DockerConfig::Local {} => {
+        DockerConfig::Local { .. } => {
             tracing::trace!("Attempting to connect to local docker daemon");
 
             Docker::connect_with_local_defaults()?
@@ -235,13 +455,67 @@ fn visible_networks(state: &DockerState) -> HashSet<String> {
         .collect()
 }
 
-#[instrument(fields(%source_id), skip(state))]
-fn generate_records(source_id: &SourceId, state: DockerState) -> RecordSet {
-    let mut records = RecordSet::new();
+fn add_visible_endpoints(
+    records: &mut RecordCollector,
+    fqdn: &Fqdn,
+    container: &Container,
+    networks: &HashSet<String>,
+) -> bool {
+    let mut seen_ip = false;
+    let entry = container.display_name();
+
+    for endpoint in container.networks.values() {
+        if networks.contains(&endpoint.network.id) {
+            if let Some(ipv4) = endpoint.ipv4 {
+                seen_ip = true;
+                records.insert(entry, container.record(fqdn.clone(), RData::A(ipv4)));
+            }
+
+            if let Some(ipv6) = endpoint.ipv6 {
+                seen_ip = true;
+                records.insert(entry, container.record(fqdn.clone(), RData::Aaaa(ipv6)));
+            }
+        }
+    }
+
+    seen_ip
+}
+
+/// Builds `<container name>.<compose project>.<zone>` or `<container name>.<zone>`
+/// for containers not started by compose.
+fn auto_hostname(container: &Container, auto_hostnames: &AutoHostnames) -> Option<Fqdn> {
+    let name = container.names.first()?.trim_start_matches('/');
+
+    let zone = match container.labels.get("com.docker.compose.project") {
+        Some(project) => auto_hostnames.zone.child(project.as_str()),
+        None => Ok(auto_hostnames.zone.clone()),
+    };
+
+    match zone.and_then(|zone| zone.child(name)) {
+        Ok(fqdn) => Some(fqdn),
+        Err(e) => {
+            tracing::warn!(error=%e, name, "Unable to generate hostname for container");
+            None
+        }
+    }
+}
+
+#[instrument(fields(%source_id), skip(state, auto_hostnames))]
+fn generate_records(
+    source_id: &SourceId,
+    state: DockerState,
+    auto_hostnames: Option<&AutoHostnames>,
+    duplicates: DuplicatePolicy,
+) -> RecordSet {
+    let mut records = RecordCollector::new(duplicates);
 
     let networks = visible_networks(&state);
 
-    for container in state.containers.values() {
+    // Oldest first so that the container that claimed a name first keeps it.
+    let mut containers: Vec<&Container> = state.containers.values().collect();
+    containers.sort_by(|a, b| (a.created, &a.id).cmp(&(b.created, &b.id)));
+
+    for container in containers {
         if let Some(hostname) = container.labels.get("localns.hostname") {
             let fqdn = match Fqdn::try_from(hostname.as_str()) {
                 Ok(f) => f,
@@ -257,12 +531,18 @@ fn generate_records(source_id: &SourceId, state: DockerState) -> RecordSet {
                 for endpoint in container.networks.values() {
                     if &endpoint.network.name == network {
                         if let Some(ip) = endpoint.ipv4 {
-                            records.insert(Record::new(fqdn.clone(), RData::A(ip)));
+                            records.insert(
+                                container.display_name(),
+                                container.record(fqdn.clone(), RData::A(ip)),
+                            );
                             seen = true;
                         }
 
                         if let Some(ip) = endpoint.ipv6 {
-                            records.insert(Record::new(fqdn.clone(), RData::Aaaa(ip)));
+                            records.insert(
+                                container.display_name(),
+                                container.record(fqdn.clone(), RData::Aaaa(ip)),
+                            );
                             seen = true;
                         }
                     }
@@ -274,36 +554,152 @@ fn generate_records(source_id: &SourceId, state: DockerState) -> RecordSet {
                         "Cannot add record as its 'localns.network' label references an invalid network.",
                     )
                 }
-            } else {
-                let mut seen_ip = false;
-
-                for endpoint in container.networks.values() {
-                    if networks.contains(&endpoint.network.id) {
-                        if let Some(ipv4) = endpoint.ipv4 {
-                            seen_ip = true;
-                            records.insert(Record::new(fqdn.clone(), RData::A(ipv4)));
-                        }
+            } else if !add_visible_endpoints(&mut records, &fqdn, container, &networks) {
+                tracing::warn!(
+                    hostname,
+                    "Cannot add record as none of its networks appeared usable.",
+                );
+            }
+        } else if let Some(fqdn) = auto_hostnames.and_then(|auto| auto_hostname(container, auto)) {
+            add_visible_endpoints(&mut records, &fqdn, container, &networks);
+        }
+    }
 
-                        if let Some(ipv6) = endpoint.ipv6 {
-                            seen_ip = true;
-                            records.insert(Record::new(fqdn.clone(), RData::Aaaa(ipv6)));
-                        }
-                    }
-                }
+    records.finish(source_id)
+}
 
-                if !seen_ip {
-                    tracing::warn!(
-                        hostname,
-                        "Cannot add record as none of its networks appeared usable.",
-                    );
-                }
+/// Forgets the IPv6 addresses of containers that are outside of the routable
+/// prefixes.
+fn filter_ipv6(state: &mut DockerState, prefixes: &[Subnet]) {
+    for container in state.containers.values_mut() {
+        for endpoint in container.networks.values_mut() {
+            if endpoint.ipv6.is_some_and(|ip| {
+                !prefixes
+                    .iter()
+                    .any(|prefix| prefix.contains(&IpAddr::V6(ip)))
+            }) {
+                endpoint.ipv6 = None;
             }
         }
     }
+}
+
+/// Adds reverse lookup records for the addresses that docker allocated to
+/// containers from its networks' subnets. These replace the reverse records
+/// the server would otherwise generate from other sources using the same
+/// addresses.
+fn add_reverse_records(records: &mut RecordSet, networks: &HashMap<String, Network>) {
+    let subnets: Vec<&Subnet> = networks
+        .values()
+        .flat_map(|network| network.subnets.iter())
+        .collect();
+
+    let pointers: Vec<Record> = records
+        .records()
+        .filter_map(|record| {
+            let ip = match record.rdata() {
+                RData::A(ip) => IpAddr::V4(*ip),
+                RData::Aaaa(ip) => IpAddr::V6(*ip),
+                _ => return None,
+            };
+
+            if !subnets.iter().any(|subnet| subnet.contains(&ip)) {
+                return None;
+            }
+
+            let mut ptr = Record::new(Name::from(ip).into(), RData::Ptr(record.name().clone()));
+            ptr.tags = record.tags.clone();
+            Some(ptr)
+        })
+        .collect();
+
+    for ptr in pointers {
+        records.insert(ptr);
+    }
+}
+
+fn config_records(
+    source_id: &SourceId,
+    mut state: DockerState,
+    docker_config: &DockerConfig,
+) -> RecordSet {
+    if let Some(prefixes) = docker_config.ipv6_prefixes() {
+        filter_ipv6(&mut state, prefixes);
+    }
+
+    let networks = docker_config
+        .reverse_records()
+        .then(|| state.networks.clone());
+
+    let mut records = generate_records(
+        source_id,
+        state,
+        docker_config.auto_hostnames(),
+        docker_config.duplicates(),
+    );
+
+    if let Some(networks) = networks {
+        add_reverse_records(&mut records, &networks);
+    }
 
     records
 }
 
+/// The subnets of the networks to answer reverse lookups for, sorted so that
+/// they only differ when the networks change.
+fn reverse_subnets(state: &DockerState, docker_config: &DockerConfig) -> Vec<Subnet> {
+    if !docker_config.reverse_records() {
+        return Vec::new();
+    }
+
+    let mut subnets: Vec<Subnet> = state
+        .networks
+        .values()
+        .flat_map(|network| network.subnets.iter().copied())
+        .collect();
+    subnets.sort_by_key(|subnet| (subnet.network(), subnet.prefix()));
+    subnets.dedup();
+
+    subnets
+}
+
+/// Publishes the records for the current state and makes the server
+/// authoritative for the reverse zones of the networks when asked to.
+async fn publish_state<S: RecordServer>(
+    server: &S,
+    source_id: &SourceId,
+    state: DockerState,
+    docker_config: &DockerConfig,
+) {
+    let subnets = reverse_subnets(&state, docker_config);
+    let records = config_records(source_id, state, docker_config);
+
+    server.set_reverse_zones(source_id, subnets).await;
+    server
+        .add_source_records(SourceRecords::new(source_id, None, records).with_lease(RECORDS_LEASE))
+        .await;
+}
+
+/// Waits for a burst of events, such as from starting a compose project, to end
+/// so that the state is only fetched once. Returns false if the stream of events
+/// ended.
+async fn coalesce_events<E, St>(events: &mut St) -> bool
+where
+    St: Stream<Item = E> + Unpin,
+{
+    let deadline = Instant::now() + EVENT_BURST_MAX;
+
+    loop {
+        let wait_until = min(Instant::now() + EVENT_BURST_GAP, deadline);
+
+        match timeout_at(wait_until, events.next()).await {
+            Ok(Some(_)) => continue,
+            Ok(None) => return false,
+            Err(_) => return true,
+        }
+    }
+}
+
 async fn docker_loop<S: RecordServer>(
     server: S,
     source_id: SourceId,
@@ -313,7 +709,7 @@ async fn docker_loop<S: RecordServer>(
         Ok(docker) => docker,
         Err(e) => {
             tracing::error!(%source_id, error=%e, "Error connecting to docker");
-            return LoopResult::Backoff;
+            return LoopResult::Backoff(format!("Error connecting to docker: {e}"));
         }
     };
 
@@ -321,7 +717,7 @@ async fn docker_loop<S: RecordServer>(
         Ok(version) => version,
         Err(e) => {
             tracing::error!(%source_id, error=%e, "Failed to get docker version");
-            return LoopResult::Backoff;
+            return LoopResult::Backoff(format!("Failed to get docker version: {e}"));
         }
     };
 
@@ -339,38 +735,40 @@ async fn docker_loop<S: RecordServer>(
         Ok(state) => state,
         Err(e) => {
             tracing::error!(%source_id, error = %e);
-            return LoopResult::Backoff;
+            return LoopResult::Backoff(format!("Failed to fetch docker state: {e}"));
         }
     };
 
-    let records = generate_records(&source_id, state);
-    server
-        .add_source_records(SourceRecords::new(&source_id, None, records))
-        .await;
+    publish_state(&server, &source_id, state, &docker_config).await;
 
     let mut events = docker.events::<&str>(None);
     loop {
-        match events.next().await {
-            Some(Ok(ev)) => {
-                if useful_event(&ev) {
-                    let state = match fetch_state(&docker).await {
-                        Ok(state) => state,
-                        Err(e) => {
-                            tracing::error!(%source_id, error = %e);
-                            return LoopResult::Backoff;
-                        }
-                    };
+        match timeout(REFRESH_INTERVAL, events.next()).await {
+            Ok(Some(Ok(ev))) => {
+                if !useful_event(&ev) {
+                    continue;
+                }
 
-                    let records = generate_records(&source_id, state);
-                    server
-                        .add_source_records(SourceRecords::new(&source_id, None, records))
-                        .await;
+                if !coalesce_events(&mut events).await {
+                    return LoopResult::Sleep;
                 }
             }
-            _ => {
+            Ok(_) => {
                 return LoopResult::Sleep;
             }
+            // Nothing has happened for a while, refresh anyway.
+            Err(_) => {}
         }
+
+        let state = match fetch_state(&docker).await {
+            Ok(state) => state,
+            Err(e) => {
+                tracing::error!(%source_id, error = %e);
+                return LoopResult::Backoff(format!("Failed to fetch docker state: {e}"));
+            }
+        };
+
+        publish_state(&server, &source_id, state, &docker_config).await;
     }
 }
 
@@ -379,6 +777,37 @@ impl SourceConfig for DockerConfig {
         SourceType::Docker
     }
 
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        match self {
+            DockerConfig::Address(_) => None,
+            DockerConfig::Tls(tls_config) => Some(&tls_config.overrides),
+            DockerConfig::Connection { overrides, .. } => Some(overrides),
+            DockerConfig::Local { overrides, .. } => Some(overrides),
+        }
+    }
+
+    fn without_live_settings(&self) -> Self {
+        let mut config = self.clone();
+        match &mut config {
+            DockerConfig::Address(_) => {}
+            DockerConfig::Tls(tls_config) => tls_config.overrides = Default::default(),
+            DockerConfig::Connection { overrides, .. } | DockerConfig::Local { overrides, .. } => {
+                *overrides = Default::default()
+            }
+        }
+        config
+    }
+
+    fn required_files(&self) -> Vec<PathBuf> {
+        match self {
+            DockerConfig::Tls(tls) => [&tls.private_key, &tls.certificate, &tls.ca]
+                .into_iter()
+                .map(RelativePathBuf::relative)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
     #[instrument(fields(%source_id), skip(self, server))]
     async fn spawn<S: RecordServer>(
         self,
@@ -404,19 +833,411 @@ impl SourceConfig for DockerConfig {
 
 #[cfg(test)]
 mod tests {
-    use std::net::IpAddr;
+    use std::{collections::HashMap, net::IpAddr, time::Duration};
 
+    use futures::{channel::mpsc, StreamExt};
     use testcontainers::{runners::AsyncRunner, GenericImage};
+    use tokio::time::sleep;
     use uuid::Uuid;
 
     use crate::{
         dns::RData,
-        sources::{docker::DockerConfig, SourceConfig, SourceId},
+        sources::{
+            docker::{
+                AutoHostnames, Container, ContainerEndpoint, DockerConfig, DockerState,
+                DuplicatePolicy, Network,
+            },
+            SourceConfig, SourceId,
+        },
         test::{fqdn, name, SingleSourceServer},
+        util::Subnet,
     };
 
+    fn container(name: &str, network: &Network, ip: &str, labels: &[(&str, &str)]) -> Container {
+        Container {
+            id: name.to_owned(),
+            created: None,
+            names: vec![format!("/{name}")],
+            image: None,
+            networks: HashMap::from([(
+                network.id.clone(),
+                ContainerEndpoint {
+                    network: network.clone(),
+                    ipv4: Some(ip.parse().unwrap()),
+                    ipv6: None,
+                },
+            )]),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn auto_hostnames() {
+        let visible = Network {
+            id: "visible".to_owned(),
+            name: "lan".to_owned(),
+            driver: Some("macvlan".to_owned()),
+            labels: HashMap::new(),
+            subnets: Vec::new(),
+        };
+        let hidden = Network {
+            id: "hidden".to_owned(),
+            name: "bridge".to_owned(),
+            driver: Some("bridge".to_owned()),
+            labels: HashMap::new(),
+            subnets: Vec::new(),
+        };
+
+        let containers = [
+            container(
+                "media-plex-1",
+                &visible,
+                "10.10.1.5",
+                &[("com.docker.compose.project", "media")],
+            ),
+            container("standalone", &visible, "10.10.1.6", &[]),
+            container(
+                "labelled",
+                &visible,
+                "10.10.1.7",
+                &[("localns.hostname", "www.home.local")],
+            ),
+            container("internal", &hidden, "172.17.0.2", &[]),
+        ];
+
+        let state = DockerState {
+            networks: HashMap::from([
+                (visible.id.clone(), visible.clone()),
+                (hidden.id.clone(), hidden.clone()),
+            ]),
+            containers: containers.into_iter().map(|c| (c.id.clone(), c)).collect(),
+        };
+
+        let source_id = SourceId::new(&Uuid::new_v4(), DockerConfig::source_type(), "test");
+
+        let records =
+            super::generate_records(&source_id, state.clone(), None, DuplicatePolicy::default());
+        assert_eq!(records.len(), 1);
+        assert!(records.contains(
+            &fqdn("www.home.local"),
+            &RData::A("10.10.1.7".parse().unwrap())
+        ));
+
+        let auto_hostnames = AutoHostnames {
+            zone: fqdn("docker.local"),
+        };
+        let records = super::generate_records(
+            &source_id,
+            state,
+            Some(&auto_hostnames),
+            DuplicatePolicy::default(),
+        );
+        assert_eq!(records.len(), 3);
+        assert!(records.contains(
+            &fqdn("media-plex-1.media.docker.local"),
+            &RData::A("10.10.1.5".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("standalone.docker.local"),
+            &RData::A("10.10.1.6".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("www.home.local"),
+            &RData::A("10.10.1.7".parse().unwrap())
+        ));
+        assert!(!records.has_name(&name("internal.docker.local.")));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn tags() {
+        let network = Network {
+            id: "lan".to_owned(),
+            name: "lan".to_owned(),
+            driver: Some("macvlan".to_owned()),
+            labels: HashMap::new(),
+            subnets: Vec::new(),
+        };
+
+        let tagged = container(
+            "tagged",
+            &network,
+            "10.10.1.5",
+            &[
+                ("localns.hostname", "www.home.local"),
+                ("localns.tags", "internal, vpn,"),
+            ],
+        );
+        let untagged = container(
+            "untagged",
+            &network,
+            "10.10.1.6",
+            &[("localns.hostname", "other.home.local")],
+        );
+
+        let state = DockerState {
+            networks: HashMap::from([(network.id.clone(), network.clone())]),
+            containers: [tagged, untagged]
+                .into_iter()
+                .map(|c| (c.id.clone(), c))
+                .collect(),
+        };
+
+        let source_id = SourceId::new(&Uuid::new_v4(), DockerConfig::source_type(), "test");
+        let records = super::generate_records(&source_id, state, None, DuplicatePolicy::default());
+
+        let tags = |hostname: &str| -> Vec<String> {
+            records
+                .records()
+                .find(|record| *record.name() == fqdn(hostname))
+                .unwrap()
+                .tags
+                .iter()
+                .cloned()
+                .collect()
+        };
+
+        assert_eq!(tags("www.home.local"), vec!["internal", "vpn"]);
+        assert!(tags("other.home.local").is_empty());
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn duplicates() {
+        let network = Network {
+            id: "lan".to_owned(),
+            name: "lan".to_owned(),
+            driver: Some("macvlan".to_owned()),
+            labels: HashMap::new(),
+            subnets: Vec::new(),
+        };
+
+        let mut old = container(
+            "web-old",
+            &network,
+            "10.10.1.5",
+            &[("localns.hostname", "www.home.local")],
+        );
+        old.created = Some(1000);
+        let mut new = container(
+            "web-new",
+            &network,
+            "10.10.1.6",
+            &[("localns.hostname", "www.home.local")],
+        );
+        new.created = Some(2000);
+        let other = container(
+            "other",
+            &network,
+            "10.10.1.7",
+            &[("localns.hostname", "other.home.local")],
+        );
+
+        let state = DockerState {
+            networks: HashMap::from([(network.id.clone(), network.clone())]),
+            containers: [new, old, other]
+                .into_iter()
+                .map(|c| (c.id.clone(), c))
+                .collect(),
+        };
+
+        let source_id = SourceId::new(&Uuid::new_v4(), DockerConfig::source_type(), "test");
+
+        let records =
+            super::generate_records(&source_id, state.clone(), None, DuplicatePolicy::MergeAll);
+        assert_eq!(records.len(), 3);
+        assert!(records.contains(
+            &fqdn("www.home.local"),
+            &RData::A("10.10.1.5".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("www.home.local"),
+            &RData::A("10.10.1.6".parse().unwrap())
+        ));
+        assert!(logs_contain("web-old, web-new"));
+
+        let records =
+            super::generate_records(&source_id, state.clone(), None, DuplicatePolicy::FirstWins);
+        assert_eq!(records.len(), 2);
+        assert!(records.contains(
+            &fqdn("www.home.local"),
+            &RData::A("10.10.1.5".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("other.home.local"),
+            &RData::A("10.10.1.7".parse().unwrap())
+        ));
+
+        let records = super::generate_records(&source_id, state, None, DuplicatePolicy::Error);
+        assert_eq!(records.len(), 1);
+        assert!(!records.has_name(&name("www.home.local.")));
+        assert!(records.has_name(&name("other.home.local.")));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn ipv6_prefixes() {
+        let network = Network {
+            id: "lan".to_owned(),
+            name: "lan".to_owned(),
+            driver: Some("macvlan".to_owned()),
+            labels: HashMap::new(),
+            subnets: Vec::new(),
+        };
+
+        let mut routable = container(
+            "routable",
+            &network,
+            "10.10.1.5",
+            &[("localns.hostname", "routable.home.local")],
+        );
+        routable.networks.get_mut("lan").unwrap().ipv6 = Some("2001:db8:5::5".parse().unwrap());
+        let mut internal = container(
+            "internal",
+            &network,
+            "10.10.1.6",
+            &[("localns.hostname", "internal.home.local")],
+        );
+        internal.networks.get_mut("lan").unwrap().ipv6 = Some("fd00:dead::6".parse().unwrap());
+
+        let state = DockerState {
+            networks: HashMap::from([(network.id.clone(), network.clone())]),
+            containers: [routable, internal]
+                .into_iter()
+                .map(|c| (c.id.clone(), c))
+                .collect(),
+        };
+
+        let source_id = SourceId::new(&Uuid::new_v4(), DockerConfig::source_type(), "test");
+        let config = |ipv6_prefixes: Option<Vec<Subnet>>| DockerConfig::Local {
+            auto_hostnames: None,
+            duplicates: DuplicatePolicy::default(),
+            ipv6_prefixes,
+            reverse_records: false,
+            overrides: Default::default(),
+        };
+
+        let records = super::config_records(&source_id, state.clone(), &config(None));
+        assert_eq!(records.len(), 4);
+
+        let records = super::config_records(
+            &source_id,
+            state.clone(),
+            &config(Some(vec!["2001:db8::/32".parse().unwrap()])),
+        );
+        assert_eq!(records.len(), 3);
+        assert!(records.contains(
+            &fqdn("routable.home.local"),
+            &RData::Aaaa("2001:db8:5::5".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("internal.home.local"),
+            &RData::A("10.10.1.6".parse().unwrap())
+        ));
+
+        let records = super::config_records(&source_id, state, &config(Some(Vec::new())));
+        assert_eq!(records.len(), 2);
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn reverse_records() {
+        let network = Network {
+            id: "lan".to_owned(),
+            name: "lan".to_owned(),
+            driver: Some("macvlan".to_owned()),
+            labels: HashMap::new(),
+            subnets: vec!["10.10.1.0/24".parse().unwrap()],
+        };
+        let other = Network {
+            id: "other".to_owned(),
+            name: "other".to_owned(),
+            driver: Some("macvlan".to_owned()),
+            labels: HashMap::new(),
+            subnets: Vec::new(),
+        };
+
+        let state = DockerState {
+            networks: HashMap::from([
+                (network.id.clone(), network.clone()),
+                (other.id.clone(), other.clone()),
+            ]),
+            containers: [
+                container(
+                    "www",
+                    &network,
+                    "10.10.1.5",
+                    &[("localns.hostname", "www.home.local")],
+                ),
+                container(
+                    "db",
+                    &other,
+                    "10.10.2.6",
+                    &[("localns.hostname", "db.home.local")],
+                ),
+            ]
+            .into_iter()
+            .map(|c| (c.id.clone(), c))
+            .collect(),
+        };
+
+        let source_id = SourceId::new(&Uuid::new_v4(), DockerConfig::source_type(), "test");
+        let config = |reverse_records: bool| DockerConfig::Local {
+            auto_hostnames: None,
+            duplicates: DuplicatePolicy::default(),
+            ipv6_prefixes: None,
+            reverse_records,
+            overrides: Default::default(),
+        };
+
+        assert!(super::reverse_subnets(&state, &config(false)).is_empty());
+        assert_eq!(
+            super::reverse_subnets(&state, &config(true)),
+            vec!["10.10.1.0/24".parse::<Subnet>().unwrap()]
+        );
+
+        let records = super::config_records(&source_id, state.clone(), &config(false));
+        assert_eq!(records.len(), 2);
+
+        let records = super::config_records(&source_id, state, &config(true));
+        assert_eq!(records.len(), 3);
+        assert!(records.contains(
+            &fqdn("5.1.10.10.in-addr.arpa"),
+            &RData::Ptr(fqdn("www.home.local"))
+        ));
+        assert!(!records.has_name(&name("6.2.10.10.in-addr.arpa.")));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn coalesce_events() {
+        let (sender, mut events) = mpsc::unbounded::<u32>();
+
+        for event in 0..5 {
+            sender.unbounded_send(event).unwrap();
+        }
+
+        let burst = tokio::spawn(async move {
+            sleep(Duration::from_millis(100)).await;
+            sender.unbounded_send(5).unwrap();
+            sleep(Duration::from_millis(500)).await;
+            sender.unbounded_send(6).unwrap();
+        });
+
+        assert!(super::coalesce_events(&mut events).await);
+        assert_eq!(events.next().await, Some(6));
+
+        burst.await.unwrap();
+        assert!(!super::coalesce_events(&mut events).await);
+    }
+
     #[tracing_test::traced_test]
     #[tokio::test]
+    #[cfg_attr(not(feature = "container-tests"), ignore = "requires docker")]
     async fn integration() {
         let test_container = GenericImage::new("localns_test_empty", "latest")
             .start()
@@ -430,7 +1251,13 @@ mod tests {
             source_name: "test".to_string(),
         };
 
-        let config = DockerConfig::Local {};
+        let config = DockerConfig::Local {
+            auto_hostnames: None,
+            duplicates: DuplicatePolicy::default(),
+            ipv6_prefixes: None,
+            reverse_records: false,
+            overrides: Default::default(),
+        };
 
         let mut test_server = SingleSourceServer::new(&source_id);
 
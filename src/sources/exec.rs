@@ -0,0 +1,389 @@
+use std::{mem, process::Stdio, str, time::Duration};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    time::{sleep, timeout},
+};
+use tracing::instrument;
+
+use crate::{
+    dns::RecordSet,
+    run_loop::{LoopResult, PollInterval, RunLoop},
+    sources::{
+        file::parse_records, spawn_polling, RecordOverrides, SourceConfig, SourceHandle, SourceId,
+        SourceType,
+    },
+    Error, RecordServer, SourceRecords,
+};
+
+const POLL_INTERVAL_MS: u64 = 60000;
+/// How long a command may run for by default.
+const DEFAULT_EXEC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The line separating the documents that a streaming command outputs.
+const DOCUMENT_SEPARATOR: &str = "---";
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct ExecConfig {
+    /// The program to run followed by its arguments.
+    command: Vec<String>,
+    #[serde(default)]
+    stream: bool,
+    #[serde(default)]
+    interval_ms: Option<u64>,
+    /// How long a polled command may run before it is killed.
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    #[serde(flatten)]
+    overrides: RecordOverrides,
+}
+
+impl ExecConfig {
+    fn command(&self) -> Result<Command, Error> {
+        let Some((program, args)) = self.command.split_first() else {
+            bail!("No command to run");
+        };
+
+        let mut command = Command::new(program);
+        command.args(args).stdin(Stdio::null()).kill_on_drop(true);
+
+        Ok(command)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_EXEC_TIMEOUT)
+    }
+}
+
+/// Runs the command to completion and parses the records it outputs.
+async fn run_command(config: &ExecConfig) -> Result<RecordSet, Error> {
+    let output = config.command()?.output().await?;
+    if !output.status.success() {
+        bail!(
+            "Command failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    parse_records(str::from_utf8(&output.stdout)?)
+}
+
+async fn exec_loop<S: RecordServer>(
+    server: S,
+    source_id: SourceId,
+    exec_config: ExecConfig,
+    interval: PollInterval,
+) -> LoopResult {
+    let limit = exec_config.timeout();
+
+    loop {
+        // The command is killed if it is still running when this gives up.
+        let records = match timeout(limit, run_command(&exec_config)).await {
+            Ok(Ok(records)) => records,
+            Ok(Err(e)) => {
+                tracing::error!(%source_id, error = %e, "Failed to run command");
+                return LoopResult::Backoff(format!("Failed to run command: {e}"));
+            }
+            Err(_) => {
+                tracing::error!(%source_id, timeout_ms = limit.as_millis() as u64, "Command timed out");
+                return LoopResult::Backoff(format!(
+                    "Command timed out after {}ms",
+                    limit.as_millis()
+                ));
+            }
+        };
+
+        server
+            .add_source_records(interval.records(&source_id, records))
+            .await;
+
+        sleep(interval.duration()).await;
+    }
+}
+
+/// Collects the lines of a command's output into documents.
+#[derive(Default)]
+struct Documents {
+    buffer: String,
+}
+
+impl Documents {
+    /// Adds a line of output, returning the document that it completes.
+    fn push_line(&mut self, line: &str) -> Option<String> {
+        if line.trim_end() == DOCUMENT_SEPARATOR {
+            self.finish()
+        } else {
+            self.buffer.push_str(line);
+            self.buffer.push('\n');
+            None
+        }
+    }
+
+    /// Takes any incomplete document. Documents with no content are ignored.
+    fn finish(&mut self) -> Option<String> {
+        let document = mem::take(&mut self.buffer);
+        (!document.trim().is_empty()).then_some(document)
+    }
+}
+
+async fn publish<S: RecordServer>(server: &S, source_id: &SourceId, document: &str) {
+    match parse_records(document) {
+        Ok(records) => {
+            server
+                .add_source_records(SourceRecords::new(source_id, None, records))
+                .await
+        }
+        Err(e) => {
+            tracing::warn!(%source_id, error = %e, "Failed to parse command output");
+            server
+                .source_failed(source_id, format!("Failed to parse command output: {e}"))
+                .await;
+        }
+    }
+}
+
+/// Runs a command that keeps running, publishing every document it outputs.
+async fn stream_loop<S: RecordServer>(
+    server: S,
+    source_id: SourceId,
+    exec_config: ExecConfig,
+) -> LoopResult {
+    let child = exec_config
+        .command()
+        .and_then(|mut command| Ok(command.stdout(Stdio::piped()).spawn()?));
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            tracing::error!(%source_id, error = %e, "Failed to run command");
+            return LoopResult::Backoff(format!("Failed to run command: {e}"));
+        }
+    };
+
+    let Some(stdout) = child.stdout.take() else {
+        return LoopResult::Backoff("Command has no output".to_owned());
+    };
+
+    let mut lines = BufReader::new(stdout).lines();
+    let mut documents = Documents::default();
+
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => {
+                if let Some(document) = documents.push_line(&line) {
+                    publish(&server, &source_id, &document).await;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                tracing::error!(%source_id, error = %e, "Failed to read command output");
+                return LoopResult::Backoff(format!("Failed to read command output: {e}"));
+            }
+        }
+    }
+
+    if let Some(document) = documents.finish() {
+        publish(&server, &source_id, &document).await;
+    }
+
+    match child.wait().await.context("Failed to wait for command") {
+        Ok(status) if status.success() => {
+            tracing::debug!(%source_id, "Command exited, restarting");
+            LoopResult::Sleep
+        }
+        Ok(status) => {
+            tracing::error!(%source_id, %status, "Command failed");
+            LoopResult::Backoff(format!("Command failed: {status}"))
+        }
+        Err(e) => {
+            tracing::error!(%source_id, error = %e);
+            LoopResult::Backoff(format!("{e:#}"))
+        }
+    }
+}
+
+impl SourceConfig for ExecConfig {
+    fn source_type() -> SourceType {
+        SourceType::Exec
+    }
+
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        Some(&self.overrides)
+    }
+
+    fn interval_ms(&self) -> Option<u64> {
+        Some(self.interval_ms.unwrap_or(POLL_INTERVAL_MS))
+    }
+
+    fn without_live_settings(&self) -> Self {
+        Self {
+            interval_ms: None,
+            overrides: Default::default(),
+            ..self.clone()
+        }
+    }
+
+    #[instrument(fields(%source_id), skip(self, server))]
+    async fn spawn<S: RecordServer>(
+        self,
+        source_id: SourceId,
+        server: &S,
+    ) -> Result<SourceHandle<S>, Error> {
+        tracing::trace!("Adding source");
+
+        if !self.stream {
+            return spawn_polling(self, source_id, server, exec_loop);
+        }
+
+        // A streaming command only uses the interval when it is restarted.
+        let backoff = RunLoop::new(self.interval_ms.unwrap_or(POLL_INTERVAL_MS));
+        let handle = tokio::spawn(backoff.run(
+            server.clone(),
+            source_id,
+            move |server, source_id| stream_loop(server, source_id, self.clone()),
+        ));
+
+        Ok(handle.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+    use uuid::Uuid;
+
+    use crate::{
+        dns::RData,
+        run_loop::{LoopResult, PollInterval},
+        sources::{
+            exec::{exec_loop, Documents, ExecConfig},
+            SourceConfig, SourceId,
+        },
+        test::{fqdn, name, SingleSourceServer},
+    };
+
+    fn config(script: &str, stream: bool) -> ExecConfig {
+        ExecConfig {
+            command: vec!["sh".to_owned(), "-c".to_owned(), script.to_owned()],
+            stream,
+            interval_ms: None,
+            timeout_ms: None,
+            overrides: Default::default(),
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn documents() {
+        let mut documents = Documents::default();
+
+        assert_eq!(documents.push_line("---"), None);
+        assert_eq!(documents.push_line("www.home.local: 10.10.1.5"), None);
+        assert_eq!(
+            documents.push_line("---"),
+            Some("www.home.local: 10.10.1.5\n".to_owned())
+        );
+        assert_eq!(
+            documents.push_line("{\"db.home.local\": \"10.10.1.6\"}"),
+            None
+        );
+        assert_eq!(
+            documents.finish(),
+            Some("{\"db.home.local\": \"10.10.1.6\"}\n".to_owned())
+        );
+        assert_eq!(documents.finish(), None);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn run_command() {
+        let records = super::run_command(&config(
+            r#"echo '{"www.home.local": ["10.10.1.5", "fd00::5"]}'"#,
+            false,
+        ))
+        .await
+        .unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert!(records.contains(
+            &fqdn("www.home.local"),
+            &RData::A("10.10.1.5".parse().unwrap())
+        ));
+
+        let records = super::run_command(&config("true", false)).await.unwrap();
+        assert!(records.is_empty());
+
+        assert!(super::run_command(&config("echo oops >&2; exit 1", false))
+            .await
+            .is_err());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn command_timeout() {
+        let source_id = SourceId::new(&Uuid::new_v4(), ExecConfig::source_type(), "test");
+        let test_server = SingleSourceServer::new(&source_id);
+
+        let config = ExecConfig {
+            timeout_ms: Some(100),
+            ..config("sleep 60", false)
+        };
+
+        let result = timeout(
+            Duration::from_secs(5),
+            exec_loop(test_server, source_id, config, PollInterval::new(60000)),
+        )
+        .await
+        .unwrap();
+
+        match result {
+            LoopResult::Backoff(message) => {
+                assert_eq!(message, "Command timed out after 100ms")
+            }
+            _ => panic!("Expected the command to time out"),
+        }
+        assert!(logs_contain("Command timed out"));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stream() {
+        let source_id = SourceId::new(&Uuid::new_v4(), ExecConfig::source_type(), "test");
+
+        let config = config(
+            "echo 'www.home.local: 10.10.1.5'; echo ---; sleep 0.2; \
+             echo 'db.home.local: 10.10.1.6'; echo ---; sleep 60",
+            true,
+        );
+
+        let mut test_server = SingleSourceServer::new(&source_id);
+
+        let handle = config.spawn(source_id.clone(), &test_server).await.unwrap();
+
+        let records = test_server
+            .wait_for_records(|records| records.has_name(&name("www.home.local.")))
+            .await;
+
+        assert_eq!(records.len(), 1);
+
+        // Each document replaces the records from the one before.
+        let records = test_server
+            .wait_for_records(|records| records.has_name(&name("db.home.local.")))
+            .await;
+
+        assert_eq!(records.len(), 1);
+        assert!(records.contains(
+            &fqdn("db.home.local"),
+            &RData::A("10.10.1.6".parse().unwrap())
+        ));
+
+        handle.drop().await;
+    }
+}
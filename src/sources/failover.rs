@@ -0,0 +1,315 @@
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use futures::future::join_all;
+use serde::Deserialize;
+use tokio::{
+    net::TcpStream,
+    time::{sleep, timeout, Instant},
+};
+use tracing::instrument;
+
+use crate::{
+    dns::{Fqdn, RData, Record, RecordSet},
+    run_loop::{LoopResult, PollInterval},
+    sources::{spawn_polling, RecordOverrides, SourceConfig, SourceHandle, SourceId, SourceType},
+    Error, RecordServer,
+};
+
+const POLL_INTERVAL_MS: u64 = 10000;
+const FAILBACK_DELAY_MS: u64 = 60000;
+/// How long a health check waits for a connection.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct FailoverConfig {
+    name: Fqdn,
+    /// The port that health checks connect to.
+    port: u16,
+    primary: Vec<IpAddr>,
+    backup: Vec<IpAddr>,
+    #[serde(default)]
+    interval_ms: Option<u64>,
+    /// How long the primary addresses must stay healthy before they are used
+    /// again.
+    #[serde(default)]
+    failback_delay_ms: Option<u64>,
+    #[serde(flatten)]
+    overrides: RecordOverrides,
+}
+
+impl FailoverConfig {
+    fn failback_delay(&self) -> Duration {
+        Duration::from_millis(self.failback_delay_ms.unwrap_or(FAILBACK_DELAY_MS))
+    }
+}
+
+/// Tracks whether the backup addresses are being served.
+#[derive(Debug, Default)]
+struct FailoverState {
+    on_backup: bool,
+    /// When the primaries were first seen healthy again while on the backups.
+    recovered: Option<Instant>,
+}
+
+impl FailoverState {
+    /// Switches to the backups when none of the primaries are healthy and back
+    /// once a primary has been healthy for the failback delay.
+    fn update(&mut self, config: &FailoverConfig, healthy: &HashSet<IpAddr>, now: Instant) {
+        let primary_healthy = config.primary.iter().any(|ip| healthy.contains(ip));
+        let backup_healthy = config.backup.iter().any(|ip| healthy.contains(ip));
+
+        if !self.on_backup {
+            self.on_backup = !primary_healthy && backup_healthy;
+            return;
+        }
+
+        if !primary_healthy {
+            self.recovered = None;
+            return;
+        }
+
+        let recovered = *self.recovered.get_or_insert(now);
+        // There is no point waiting when the backups have failed too.
+        if now - recovered >= config.failback_delay() || !backup_healthy {
+            self.on_backup = false;
+            self.recovered = None;
+        }
+    }
+
+    /// The healthy addresses of the group in use, or all of them if none are
+    /// healthy.
+    fn addresses(&self, config: &FailoverConfig, healthy: &HashSet<IpAddr>) -> Vec<IpAddr> {
+        let group = if self.on_backup {
+            &config.backup
+        } else {
+            &config.primary
+        };
+
+        let addresses: Vec<IpAddr> = group
+            .iter()
+            .filter(|ip| healthy.contains(ip))
+            .copied()
+            .collect();
+
+        if addresses.is_empty() {
+            group.clone()
+        } else {
+            addresses
+        }
+    }
+}
+
+async fn check(address: IpAddr, port: u16) -> bool {
+    matches!(
+        timeout(
+            CHECK_TIMEOUT,
+            TcpStream::connect(SocketAddr::new(address, port))
+        )
+        .await,
+        Ok(Ok(_))
+    )
+}
+
+#[instrument(fields(%source_id), skip(config, addresses))]
+fn generate_records(
+    source_id: &SourceId,
+    config: &FailoverConfig,
+    addresses: Vec<IpAddr>,
+) -> RecordSet {
+    let mut records = RecordSet::new();
+
+    for ip in addresses {
+        records.insert(Record::new(config.name.clone(), RData::from(ip)));
+    }
+
+    records
+}
+
+async fn failover_loop<S: RecordServer>(
+    server: S,
+    source_id: SourceId,
+    failover_config: FailoverConfig,
+    interval: PollInterval,
+) -> LoopResult {
+    let mut state = FailoverState::default();
+
+    loop {
+        let port = failover_config.port;
+        let checks = failover_config
+            .primary
+            .iter()
+            .chain(failover_config.backup.iter())
+            .map(|ip| async move { check(*ip, port).await.then_some(*ip) });
+        let healthy: HashSet<IpAddr> = join_all(checks).await.into_iter().flatten().collect();
+
+        let was_on_backup = state.on_backup;
+        state.update(&failover_config, &healthy, Instant::now());
+
+        match (was_on_backup, state.on_backup) {
+            (false, true) => tracing::warn!(
+                %source_id,
+                name = %failover_config.name,
+                "Primary addresses are down, switching to backups"
+            ),
+            (true, false) => tracing::info!(
+                %source_id,
+                name = %failover_config.name,
+                "Primary addresses have recovered, switching back"
+            ),
+            _ => {}
+        }
+
+        let records = generate_records(
+            &source_id,
+            &failover_config,
+            state.addresses(&failover_config, &healthy),
+        );
+        server
+            .add_source_records(interval.records(&source_id, records))
+            .await;
+
+        sleep(interval.duration()).await;
+    }
+}
+
+impl SourceConfig for FailoverConfig {
+    fn source_type() -> SourceType {
+        SourceType::Failover
+    }
+
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        Some(&self.overrides)
+    }
+
+    fn interval_ms(&self) -> Option<u64> {
+        Some(self.interval_ms.unwrap_or(POLL_INTERVAL_MS))
+    }
+
+    fn without_live_settings(&self) -> Self {
+        Self {
+            interval_ms: None,
+            overrides: Default::default(),
+            ..self.clone()
+        }
+    }
+
+    #[instrument(fields(%source_id), skip(self, server))]
+    async fn spawn<S: RecordServer>(
+        self,
+        source_id: SourceId,
+        server: &S,
+    ) -> Result<SourceHandle<S>, Error> {
+        tracing::trace!("Adding source");
+
+        spawn_polling(self, source_id, server, failover_loop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, net::IpAddr, time::Duration};
+
+    use tokio::{net::TcpListener, time::Instant};
+
+    use crate::{
+        sources::failover::{check, FailoverConfig, FailoverState},
+        test::fqdn,
+    };
+
+    fn ips(ips: &[&str]) -> HashSet<IpAddr> {
+        ips.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn failover() {
+        let config = FailoverConfig {
+            name: fqdn("app.home.local"),
+            port: 443,
+            primary: vec!["10.10.1.5".parse().unwrap(), "10.10.1.6".parse().unwrap()],
+            backup: vec!["10.10.2.5".parse().unwrap()],
+            interval_ms: None,
+            failback_delay_ms: Some(30000),
+            overrides: Default::default(),
+        };
+
+        let mut state = FailoverState::default();
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let addresses = |state: &FailoverState, healthy: &HashSet<IpAddr>| -> HashSet<IpAddr> {
+            state.addresses(&config, healthy).into_iter().collect()
+        };
+
+        let healthy = ips(&["10.10.1.5", "10.10.1.6", "10.10.2.5"]);
+        state.update(&config, &healthy, at(0));
+        assert!(!state.on_backup);
+        assert_eq!(
+            addresses(&state, &healthy),
+            ips(&["10.10.1.5", "10.10.1.6"])
+        );
+
+        // One primary failing just removes it.
+        let healthy = ips(&["10.10.1.6", "10.10.2.5"]);
+        state.update(&config, &healthy, at(10));
+        assert!(!state.on_backup);
+        assert_eq!(addresses(&state, &healthy), ips(&["10.10.1.6"]));
+
+        let healthy = ips(&["10.10.2.5"]);
+        state.update(&config, &healthy, at(20));
+        assert!(state.on_backup);
+        assert_eq!(addresses(&state, &healthy), ips(&["10.10.2.5"]));
+
+        // The primary must stay up for the failback delay.
+        let healthy = ips(&["10.10.1.5", "10.10.2.5"]);
+        state.update(&config, &healthy, at(30));
+        assert!(state.on_backup);
+
+        let healthy = ips(&["10.10.2.5"]);
+        state.update(&config, &healthy, at(40));
+        assert!(state.on_backup);
+
+        let healthy = ips(&["10.10.1.5", "10.10.2.5"]);
+        state.update(&config, &healthy, at(50));
+        state.update(&config, &healthy, at(70));
+        assert!(state.on_backup);
+
+        state.update(&config, &healthy, at(80));
+        assert!(!state.on_backup);
+        assert_eq!(addresses(&state, &healthy), ips(&["10.10.1.5"]));
+
+        // Nothing healthy stays on the primaries.
+        let healthy = ips(&[]);
+        state.update(&config, &healthy, at(90));
+        assert!(!state.on_backup);
+        assert_eq!(
+            addresses(&state, &healthy),
+            ips(&["10.10.1.5", "10.10.1.6"])
+        );
+
+        let healthy = ips(&["10.10.2.5"]);
+        state.update(&config, &healthy, at(100));
+        assert!(state.on_backup);
+
+        // Switches back straight away if the backups fail too.
+        let healthy = ips(&["10.10.1.6"]);
+        state.update(&config, &healthy, at(110));
+        assert!(!state.on_backup);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn health_check() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+
+        assert!(check(ip, port).await);
+
+        drop(listener);
+        assert!(!check(ip, port).await);
+    }
+}
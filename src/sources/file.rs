@@ -1,13 +1,19 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet},
     fs::File,
+    io::Write,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
+use anyhow::bail;
 use chrono::Utc;
 use figment::value::magic::RelativePathBuf;
-use hickory_server::proto::error::ProtoError;
-use serde::Deserialize;
+use hickory_server::proto::rr::{Name, RecordType};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use tempfile::NamedTempFile;
 use tracing::instrument;
 
 use crate::{
@@ -19,70 +25,442 @@ use crate::{
 
 pub(crate) type FileConfig = RelativePathBuf;
 
-#[derive(Deserialize, Eq, PartialEq, Debug)]
+/// The file holding the records managed through the API, written in the same
+/// format as a zone file.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ApiRecordsConfig {
+    pub(crate) records_file: PathBuf,
+}
+
+/// A record given in full, optionally with its own TTL, metadata and tags.
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+struct RecordEntry {
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    record_type: Option<String>,
+    value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    tags: BTreeSet<String>,
+}
+
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
 #[serde(untagged)]
 enum RDataItem {
-    RData(RData),
+    Entry(RecordEntry),
     Str(String),
 }
 
-impl TryFrom<RDataItem> for RData {
-    type Error = ProtoError;
+impl RDataItem {
+    fn into_record(self, name: Fqdn) -> Result<Record, Error> {
+        let entry = match self {
+            RDataItem::Entry(entry) => entry,
+            RDataItem::Str(value) => RecordEntry {
+                record_type: None,
+                value,
+                ttl: None,
+                metadata: BTreeMap::new(),
+                tags: BTreeSet::new(),
+            },
+        };
+        let value = entry.value;
 
-    fn try_from(item: RDataItem) -> Result<Self, Self::Error> {
-        match item {
-            RDataItem::RData(rdata) => Ok(rdata),
-            RDataItem::Str(str) => RData::try_from(str.as_str()),
-        }
+        let rdata = match entry.record_type.map(|t| t.to_uppercase()).as_deref() {
+            None => match RData::try_from(value.as_str())? {
+                // A name for a reverse lookup can only sensibly point to a host.
+                RData::Cname(target) if name.parse_arpa_name().is_ok() => RData::Ptr(target),
+                rdata => rdata,
+            },
+            Some("A") => RData::A(Ipv4Addr::from_str(&value)?),
+            Some("AAAA") => RData::Aaaa(Ipv6Addr::from_str(&value)?),
+            Some("CNAME") => RData::Cname(Fqdn::try_from(value.as_str())?),
+            Some("PTR") => RData::Ptr(Fqdn::try_from(value.as_str())?),
+            Some(other) => bail!("Unsupported record type {other}"),
+        };
+
+        let mut record = Record::new(name, rdata);
+        record.ttl = entry.ttl;
+        record.metadata = entry.metadata;
+        record.tags = entry.tags;
+        Ok(record)
     }
 }
 
-#[derive(Deserialize, Eq, PartialEq, Debug)]
+#[derive(Deserialize, Serialize, Eq, PartialEq, Debug)]
 #[serde(untagged)]
 enum RDataOneOrMany {
     List(Vec<RDataItem>),
     RData(RDataItem),
 }
 
-type ZoneFile = HashMap<Fqdn, RDataOneOrMany>;
+impl RDataOneOrMany {
+    fn into_items(self) -> Vec<RDataItem> {
+        match self {
+            RDataOneOrMany::RData(item) => vec![item],
+            RDataOneOrMany::List(list) => list,
+        }
+    }
+}
 
-#[instrument(fields(%source_id), err)]
-fn parse_file(source_id: &SourceId, zone_file: &Path) -> Result<RecordSet, Error> {
-    tracing::trace!("Parsing zone file");
+/// The text in a generated record's name and value that is replaced with the
+/// number.
+const GENERATE_VARIABLE: &str = "${i}";
+
+/// The most records that a single `$generate` entry can create, enough for
+/// every address in a /16.
+const MAX_GENERATED: u32 = 65536;
+
+/// A range of numbers written as `start-stop` with an optional `/step`.
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(try_from = "String", into = "String")]
+struct GenerateRange {
+    start: u32,
+    stop: u32,
+    step: u32,
+}
+
+impl GenerateRange {
+    fn iter(&self) -> impl Iterator<Item = u32> {
+        (self.start..=self.stop).step_by(self.step as usize)
+    }
+}
+
+impl TryFrom<String> for GenerateRange {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let (range, step) = match s.split_once('/') {
+            Some((range, step)) => (range, step.trim().parse()?),
+            None => (s.as_str(), 1),
+        };
+
+        let Some((start, stop)) = range.split_once('-') else {
+            bail!("Expected a range such as 1-254 but found {s}");
+        };
+        let start: u32 = start.trim().parse()?;
+        let stop: u32 = stop.trim().parse()?;
+
+        if start > stop || step == 0 {
+            bail!("Invalid range {s}");
+        }
+
+        if (stop - start) / step >= MAX_GENERATED {
+            bail!("Range {s} generates more than {MAX_GENERATED} records");
+        }
+
+        Ok(Self { start, stop, step })
+    }
+}
+
+impl From<GenerateRange> for String {
+    fn from(range: GenerateRange) -> String {
+        if range.step == 1 {
+            format!("{}-{}", range.start, range.stop)
+        } else {
+            format!("{}-{}/{}", range.start, range.stop, range.step)
+        }
+    }
+}
+
+/// Generates a record for every number in a range.
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+struct Generate {
+    range: GenerateRange,
+    name: String,
+    #[serde(flatten)]
+    entry: RecordEntry,
+}
+
+impl Generate {
+    fn records(&self) -> impl Iterator<Item = Result<Record, Error>> + '_ {
+        self.range.iter().map(|i| {
+            let i = i.to_string();
+            let name = Fqdn::try_from(self.name.replace(GENERATE_VARIABLE, &i))?;
+
+            let mut entry = self.entry.clone();
+            entry.value = entry.value.replace(GENERATE_VARIABLE, &i);
+            RDataItem::Entry(entry).into_record(name)
+        })
+    }
+}
+
+/// A `$generate` entry. Invalid entries are kept as they were written so that
+/// the rest of the file can still be used and rewriting the file preserves them.
+#[derive(Debug)]
+enum GenerateEntry {
+    Valid(Generate),
+    Invalid(Value, String),
+}
+
+impl<'de> Deserialize<'de> for GenerateEntry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+
+        Ok(match Generate::deserialize(&value) {
+            Ok(generate) => GenerateEntry::Valid(generate),
+            Err(e) => GenerateEntry::Invalid(value, e.to_string()),
+        })
+    }
+}
+
+impl Serialize for GenerateEntry {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            GenerateEntry::Valid(generate) => generate.serialize(serializer),
+            GenerateEntry::Invalid(value, _) => value.serialize(serializer),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+struct ZoneFile {
+    #[serde(rename = "$generate", default, skip_serializing_if = "Vec::is_empty")]
+    generate: Vec<GenerateEntry>,
+    #[serde(flatten, deserialize_with = "deserialize_names")]
+    records: BTreeMap<Fqdn, RDataOneOrMany>,
+}
 
+/// Names may also be given as IP addresses which are converted to the name used
+/// for reverse lookups of the address.
+fn deserialize_names<'de, D>(deserializer: D) -> Result<BTreeMap<Fqdn, RDataOneOrMany>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    BTreeMap::<String, RDataOneOrMany>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, rdata)| {
+            let name = match IpAddr::from_str(&name) {
+                Ok(ip) => Fqdn::from(Name::from(ip)),
+                Err(_) => Fqdn::try_from(name.as_str()).map_err(<D::Error as de::Error>::custom)?,
+            };
+
+            Ok((name, rdata))
+        })
+        .collect()
+}
+
+fn read_zone_file(zone_file: &Path) -> Result<ZoneFile, Error> {
     let f = File::open(zone_file)?;
-    let zone_data: ZoneFile = serde_yaml::from_reader(f)?;
+    Ok(serde_yaml::from_reader(f)?)
+}
 
-    let mut records = RecordSet::new();
+/// Writes through a temporary file so the file watcher never sees a partially
+/// written file. The temporary file is created alongside so it can be renamed
+/// into place.
+fn write_zone_file(zone_file: &Path, zone_data: &ZoneFile) -> Result<(), Error> {
+    let directory = zone_file
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let mut temp_file = NamedTempFile::new_in(directory)?;
+    temp_file.write_all(serde_yaml::to_string(zone_data)?.as_bytes())?;
+    temp_file.persist(zone_file)?;
+
+    Ok(())
+}
+
+/// A record to add to a zone file.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub(crate) struct NewRecord {
+    name: Fqdn,
+    #[serde(flatten)]
+    entry: RecordEntry,
+}
+
+impl NewRecord {
+    /// The record that this will add, fails if the value is invalid.
+    pub(crate) fn record(&self) -> Result<Record, Error> {
+        let record = RDataItem::Entry(self.entry.clone()).into_record(self.name.clone())?;
+        record.validate()?;
+        Ok(record)
+    }
+}
+
+/// Adds a record to a zone file. An existing entry for the same name and data is
+/// replaced so that its TTL or metadata can be updated.
+pub(crate) fn add_record(zone_file: &Path, new_record: &NewRecord) -> Result<Record, Error> {
+    let record = new_record.record()?;
+
+    let mut zone_data = read_zone_file(zone_file)?;
+
+    let mut items: Vec<RDataItem> = zone_data
+        .records
+        .remove(&new_record.name)
+        .map(RDataOneOrMany::into_items)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|item| {
+            item.clone()
+                .into_record(new_record.name.clone())
+                .map_or(true, |existing| existing.rdata() != record.rdata())
+        })
+        .collect();
+    items.push(RDataItem::Entry(new_record.entry.clone()));
+
+    zone_data
+        .records
+        .insert(new_record.name.clone(), RDataOneOrMany::List(items));
+    write_zone_file(zone_file, &zone_data)?;
+
+    Ok(record)
+}
+
+/// Selects records of a name, optionally only those of a type or with a value.
+#[derive(Debug)]
+pub(crate) struct RecordFilter {
+    name: Fqdn,
+    record_type: Option<RecordType>,
+    rdata: Option<RData>,
+}
 
-    for (name, rdata) in zone_data {
-        match rdata {
-            RDataOneOrMany::RData(item) => {
-                let rdata = match item.try_into() {
-                    Ok(r) => r,
-                    Err(e) => {
-                        tracing::warn!(error=%e, "Error parsing zone file");
-                        continue;
-                    }
+impl RecordFilter {
+    pub(crate) fn new(
+        name: &str,
+        record_type: Option<&str>,
+        value: Option<&str>,
+    ) -> Result<Self, Error> {
+        let name = Fqdn::try_from(name)?;
+
+        let rdata = match value {
+            Some(value) => {
+                let entry = RecordEntry {
+                    record_type: record_type.map(str::to_owned),
+                    value: value.to_owned(),
+                    ttl: None,
+                    metadata: BTreeMap::new(),
+                    tags: BTreeSet::new(),
                 };
-                records.insert(Record::new(name, rdata));
+
+                Some(
+                    RDataItem::Entry(entry)
+                        .into_record(name.clone())?
+                        .rdata()
+                        .clone(),
+                )
             }
-            RDataOneOrMany::List(list) => {
-                for item in list {
-                    let rdata = match item.try_into() {
-                        Ok(r) => r,
-                        Err(e) => {
-                            tracing::warn!(error=%e, "Error parsing zone file");
-                            continue;
-                        }
-                    };
-                    records.insert(Record::new(name.clone(), rdata));
-                }
+            None => None,
+        };
+
+        let record_type = match record_type {
+            Some(record_type) => Some(RecordType::from_str(&record_type.to_uppercase())?),
+            None => None,
+        };
+
+        Ok(Self {
+            name,
+            record_type,
+            rdata,
+        })
+    }
+
+    pub(crate) fn name(&self) -> &Fqdn {
+        &self.name
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        self.record_type
+            .is_none_or(|record_type| record.rdata().data_type() == record_type)
+            && self
+                .rdata
+                .as_ref()
+                .is_none_or(|rdata| record.rdata() == rdata)
+    }
+}
+
+/// Removes the records selected by the filter from a zone file, returning how
+/// many were removed.
+pub(crate) fn remove_records(zone_file: &Path, filter: &RecordFilter) -> Result<usize, Error> {
+    let mut zone_data = read_zone_file(zone_file)?;
+
+    let Some(entries) = zone_data.records.remove(&filter.name) else {
+        return Ok(0);
+    };
+
+    let items = entries.into_items();
+    let count = items.len();
+
+    let kept: Vec<RDataItem> = items
+        .into_iter()
+        .filter(|item| {
+            // Entries that can't be parsed are never served so are left alone.
+            item.clone()
+                .into_record(filter.name.clone())
+                .map_or(true, |record| !filter.matches(&record))
+        })
+        .collect();
+
+    let removed = count - kept.len();
+    if removed == 0 {
+        return Ok(0);
+    }
+
+    if !kept.is_empty() {
+        zone_data
+            .records
+            .insert(filter.name.clone(), RDataOneOrMany::List(kept));
+    }
+    write_zone_file(zone_file, &zone_data)?;
+
+    Ok(removed)
+}
+
+/// The records in a zone file, skipping any that are invalid.
+fn zone_records(zone_data: ZoneFile) -> RecordSet {
+    let mut records = RecordSet::new();
+    let mut insert = |result: Result<Record, Error>| match result {
+        Ok(record) => records.insert(record),
+        Err(e) => {
+            tracing::warn!(error=%e, "Error parsing zone file");
+        }
+    };
+
+    for (name, rdata) in zone_data.records {
+        for item in rdata.into_items() {
+            insert(item.into_record(name.clone()));
+        }
+    }
+
+    for generate in &zone_data.generate {
+        match generate {
+            GenerateEntry::Valid(generate) => generate.records().for_each(&mut insert),
+            GenerateEntry::Invalid(_, e) => {
+                tracing::warn!(error = %e, "Error parsing $generate entry in zone file");
             }
         }
     }
 
-    Ok(records)
+    records
+}
+
+/// Parses records written in the zone file format. As JSON is also valid YAML
+/// this accepts either.
+pub(crate) fn parse_records(contents: &str) -> Result<RecordSet, Error> {
+    if contents.trim().is_empty() {
+        return Ok(RecordSet::new());
+    }
+
+    Ok(zone_records(serde_yaml::from_str(contents)?))
+}
+
+/// Parses records in the zone file format from an already decoded JSON value.
+pub(crate) fn parse_json(value: Value) -> Result<RecordSet, Error> {
+    Ok(zone_records(serde_json::from_value(value)?))
+}
+
+#[instrument(fields(%source_id), err)]
+fn parse_file(source_id: &SourceId, zone_file: &Path) -> Result<RecordSet, Error> {
+    tracing::trace!("Parsing zone file");
+
+    Ok(zone_records(read_zone_file(zone_file)?))
 }
 
 struct SourceWatcher<S> {
@@ -104,16 +482,56 @@ impl<S: RecordServer> WatchListener for SourceWatcher<S> {
                 self.server
                     .clear_source_records(&self.source_id, Utc::now())
                     .await;
+                self.server
+                    .source_failed(&self.source_id, format!("Failed to read zone file: {e}"))
+                    .await;
             }
         }
     }
 }
 
+async fn watch_zone_file<S: RecordServer>(
+    source_id: SourceId,
+    zone_file: PathBuf,
+    server: &S,
+) -> Result<SourceHandle<S>, Error> {
+    let watcher = watch(
+        &zone_file.clone(),
+        SourceWatcher {
+            source_id: source_id.clone(),
+            server: server.clone(),
+            zone_file: zone_file.clone(),
+        },
+    )
+    .await?;
+
+    match parse_file(&source_id, &zone_file) {
+        Ok(records) => {
+            server
+                .add_source_records(SourceRecords::new(&source_id, None, records))
+                .await
+        }
+        Err(e) => {
+            tracing::warn!(error=%e, "Failed to read zone file");
+            server.clear_source_records(&source_id, Utc::now()).await;
+            server
+                .source_failed(&source_id, format!("Failed to read zone file: {e}"))
+                .await;
+        }
+    }
+
+    Ok(watcher.into())
+}
+
 impl SourceConfig for FileConfig {
     fn source_type() -> SourceType {
         SourceType::File
     }
 
+    fn required_files(&self) -> Vec<PathBuf> {
+        vec![self.relative()]
+    }
+
     #[instrument(fields(%source_id), skip(self, server))]
     async fn spawn<S: RecordServer>(
         self,
@@ -121,31 +539,29 @@ impl SourceConfig for FileConfig {
         server: &S,
     ) -> Result<SourceHandle<S>, Error> {
         tracing::trace!("Adding source");
-        let zone_file = self.relative();
-
-        let watcher = watch(
-            &zone_file.clone(),
-            SourceWatcher {
-                source_id: source_id.clone(),
-                server: server.clone(),
-                zone_file: zone_file.clone(),
-            },
-        )
-        .await?;
+        watch_zone_file(source_id, self.relative(), server).await
+    }
+}
 
-        match parse_file(&source_id, &zone_file) {
-            Ok(records) => {
-                server
-                    .add_source_records(SourceRecords::new(&source_id, None, records))
-                    .await
-            }
-            Err(e) => {
-                tracing::warn!(error=%e, "Failed to read zone file");
-                server.clear_source_records(&source_id, Utc::now()).await;
-            }
+impl SourceConfig for ApiRecordsConfig {
+    fn source_type() -> SourceType {
+        SourceType::Api
+    }
+
+    #[instrument(fields(%source_id), skip(self, server))]
+    async fn spawn<S: RecordServer>(
+        self,
+        source_id: SourceId,
+        server: &S,
+    ) -> Result<SourceHandle<S>, Error> {
+        tracing::trace!("Adding source");
+
+        // Nothing has been added through the API yet.
+        if !self.records_file.exists() {
+            write_zone_file(&self.records_file, &ZoneFile::default())?;
         }
 
-        Ok(watcher.into())
+        watch_zone_file(source_id, self.records_file, server).await
     }
 }
 
@@ -156,16 +572,136 @@ mod tests {
         str::FromStr,
     };
 
+    use hickory_server::proto::rr::{DNSClass, Name, RecordType};
     use tempfile::TempDir;
     use tokio::fs;
     use uuid::Uuid;
 
     use crate::{
         dns::RData,
-        sources::{file::FileConfig, SourceConfig, SourceId},
+        sources::{
+            file::{parse_file, FileConfig},
+            SourceConfig, SourceId,
+        },
         test::{fqdn, name, write_file, SingleSourceServer},
     };
 
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn generate() {
+        let temp = TempDir::new().unwrap();
+        let zone_file = temp.path().join("zone.yml");
+
+        write_file(
+            &zone_file,
+            r#"
+$generate:
+  - range: 1-254
+    name: dhcp-${i}.home.local
+    value: 10.0.0.${i}
+  - range: 10-20/5
+    name: alias-${i}.home.local
+    type: CNAME
+    value: dhcp-${i}.home.local
+    ttl: 60
+www.home.local: 10.0.0.1
+"#,
+        )
+        .await;
+
+        let source_id = SourceId {
+            server_id: Uuid::new_v4(),
+            source_type: FileConfig::source_type(),
+            source_name: "test".to_string(),
+        };
+
+        let records = parse_file(&source_id, &zone_file).unwrap();
+        assert_eq!(records.len(), 258);
+
+        assert!(records.contains(
+            &fqdn("dhcp-1.home.local"),
+            &RData::A(Ipv4Addr::from_str("10.0.0.1").unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("dhcp-254.home.local"),
+            &RData::A(Ipv4Addr::from_str("10.0.0.254").unwrap())
+        ));
+        assert!(!records.has_name(&name("dhcp-255.home.local.")));
+
+        for i in [10, 15, 20] {
+            assert!(records.contains(
+                &fqdn(&format!("alias-{i}.home.local")),
+                &RData::Cname(fqdn(&format!("dhcp-{i}.home.local")))
+            ));
+        }
+        assert!(!records.has_name(&name("alias-11.home.local.")));
+
+        // Bad entries are skipped rather than failing the whole file.
+        write_file(
+            &zone_file,
+            r#"
+$generate:
+  - range: 20-10
+    name: dhcp-${i}.home.local
+    value: 10.0.0.${i}
+  - range: 0-4294967295
+    name: dhcp-${i}.home.local
+    value: 10.0.0.1
+  - range: 1-2
+    name: dhcp-${i}.home.local
+    value: 10.0.0.${i}
+www.home.local: 10.0.0.1
+"#,
+        )
+        .await;
+
+        let records = parse_file(&source_id, &zone_file).unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(logs_contain("Invalid range 20-10"));
+        assert!(logs_contain("generates more than 65536 records"));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn reverse_names() {
+        let temp = TempDir::new().unwrap();
+        let zone_file = temp.path().join("zone.yml");
+
+        write_file(
+            &zone_file,
+            r#"
+10.0.0.5:
+  type: PTR
+  value: printer.home.local
+fd00::5: printer.home.local
+6.0.0.10.in-addr.arpa: scanner.home.local
+"#,
+        )
+        .await;
+
+        let source_id = SourceId {
+            server_id: Uuid::new_v4(),
+            source_type: FileConfig::source_type(),
+            source_name: "test".to_string(),
+        };
+
+        let records = parse_file(&source_id, &zone_file).unwrap();
+        assert_eq!(records.len(), 3);
+
+        assert!(records.contains(
+            &fqdn("5.0.0.10.in-addr.arpa"),
+            &RData::Ptr(fqdn("printer.home.local"))
+        ));
+        assert!(records.contains(
+            &Name::from(Ipv6Addr::from_str("fd00::5").unwrap()).into(),
+            &RData::Ptr(fqdn("printer.home.local"))
+        ));
+        assert!(records.contains(
+            &fqdn("6.0.0.10.in-addr.arpa"),
+            &RData::Ptr(fqdn("scanner.home.local"))
+        ));
+    }
+
     #[tracing_test::traced_test]
     #[tokio::test(flavor = "multi_thread")]
     async fn integration() {
@@ -180,6 +716,18 @@ www.home.local:
   - 10.14.23.123
   - 1af2:cac:8e12:5b00::2
 other.home.local: www.home.local
+short.home.local:
+  value: 10.14.23.124
+  ttl: 30
+  metadata:
+    owner: Dave
+    description: Temporary test machine
+typed.home.local:
+  - type: cname
+    value: www.home.local
+    ttl: 600
+  - type: A
+    value: not-an-address
 "#,
         )
         .await;
@@ -200,7 +748,33 @@ other.home.local: www.home.local
             .wait_for_records(|records| records.has_name(&name("www.home.local.")))
             .await;
 
-        assert_eq!(records.len(), 3);
+        assert_eq!(records.len(), 5);
+
+        let ttl = |host: &str| {
+            records
+                .lookup(&name(host), DNSClass::IN, RecordType::A)
+                .next()
+                .unwrap()
+                .ttl
+        };
+        assert_eq!(ttl("www.home.local."), None);
+        assert_eq!(ttl("short.home.local."), Some(30));
+        assert_eq!(ttl("typed.home.local."), Some(600));
+
+        let short = records
+            .lookup(&name("short.home.local."), DNSClass::IN, RecordType::A)
+            .next()
+            .unwrap();
+        assert_eq!(
+            short.metadata.get("owner").map(String::as_str),
+            Some("Dave")
+        );
+        assert_eq!(short.metadata.len(), 2);
+        assert!(records.contains(
+            &fqdn("typed.home.local"),
+            &RData::Cname(fqdn("www.home.local"))
+        ));
+        assert!(logs_contain("Error parsing zone file"));
 
         assert!(records.contains(
             &fqdn("www.home.local"),
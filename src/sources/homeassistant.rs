@@ -0,0 +1,398 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::time::sleep;
+use tracing::instrument;
+
+use crate::{
+    config::deserialize_url,
+    dns::{Fqdn, RData, Record, RecordSet},
+    run_loop::{LoopResult, PollInterval},
+    sources::{spawn_polling, RecordOverrides, SourceConfig, SourceHandle, SourceId, SourceType},
+    util::{Bind, HostAddresses},
+    Error, RecordServer,
+};
+
+const POLL_INTERVAL_MS: u64 = 60000;
+
+/// Attributes that integrations use for the address of a device.
+const ADDRESS_ATTRIBUTES: &[&str] = &["ip", "ip_address", "ip_addr"];
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Clone)]
+pub(crate) struct HomeAssistantConfig {
+    #[serde(deserialize_with = "deserialize_url")]
+    url: Url,
+    /// A long-lived access token created from a Home Assistant user's profile.
+    token: String,
+    zone: Fqdn,
+    #[serde(default)]
+    interval_ms: Option<u64>,
+    #[serde(default)]
+    bind: Option<Bind>,
+    #[serde(flatten)]
+    overrides: RecordOverrides,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct EntityState {
+    entity_id: String,
+    #[serde(default)]
+    state: String,
+    #[serde(default)]
+    attributes: HashMap<String, Value>,
+}
+
+impl EntityState {
+    /// Device trackers and some other integrations give the address as an
+    /// attribute. Others, like ESPHome's IP address sensor, report it as the
+    /// entity's state.
+    fn addresses(&self) -> Vec<IpAddr> {
+        let mut addresses: Vec<IpAddr> = ADDRESS_ATTRIBUTES
+            .iter()
+            .filter_map(|attribute| self.attributes.get(*attribute))
+            .flat_map(|value| match value {
+                Value::String(s) => vec![s.as_str()],
+                Value::Array(values) => values.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            })
+            .filter_map(|s| s.parse().ok())
+            .collect();
+
+        if addresses.is_empty() {
+            addresses.extend(self.state.parse::<IpAddr>());
+        }
+
+        addresses.retain(|ip| match ip {
+            IpAddr::V4(ip) => !ip.is_loopback() && !ip.is_link_local() && !ip.is_unspecified(),
+            IpAddr::V6(ip) => {
+                !ip.is_loopback() && !ip.is_unicast_link_local() && !ip.is_unspecified()
+            }
+        });
+
+        addresses
+    }
+
+    /// The friendly name of the entity or failing that its object id. Sensors
+    /// that report an address are usually named after their device with an "IP
+    /// address" suffix which isn't wanted in the DNS name.
+    fn label(&self) -> Option<String> {
+        let name = self
+            .attributes
+            .get("friendly_name")
+            .and_then(Value::as_str)
+            .or_else(|| self.entity_id.split_once('.').map(|(_, id)| id))?;
+
+        let mut words: Vec<String> = name
+            .to_lowercase()
+            .split(|ch: char| !ch.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_owned)
+            .collect();
+
+        if words.ends_with(&["ip".to_owned(), "address".to_owned()]) {
+            words.truncate(words.len() - 2);
+        } else if words.ends_with(&["ip".to_owned()]) {
+            words.truncate(words.len() - 1);
+        }
+
+        if words.is_empty() {
+            None
+        } else {
+            Some(words.join("-"))
+        }
+    }
+}
+
+#[instrument(fields(%source_id, %base_url), skip(client, token))]
+async fn fetch_states(
+    source_id: &SourceId,
+    client: &Client,
+    base_url: &Url,
+    token: &str,
+) -> Result<Vec<EntityState>, LoopResult> {
+    let target = base_url.join("api/states").map_err(|e| {
+        tracing::error!(error = %e, "Unable to generate API URL");
+        LoopResult::Quit(format!("Unable to generate API URL: {e}"))
+    })?;
+
+    let response = client
+        .get(target)
+        .bearer_auth(token)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to connect to Home Assistant");
+            LoopResult::Backoff(format!("Failed to connect to Home Assistant: {e}"))
+        })?;
+
+    response.json().await.map_err(|e| {
+        tracing::error!(error = %e, "Failed to parse response from Home Assistant");
+        LoopResult::Backoff(format!("Failed to parse response from Home Assistant: {e}"))
+    })
+}
+
+#[instrument(fields(%source_id), skip(states))]
+fn generate_records(source_id: &SourceId, zone: &Fqdn, states: Vec<EntityState>) -> RecordSet {
+    let mut records = RecordSet::new();
+
+    for entity in states {
+        let addresses = entity.addresses();
+        if addresses.is_empty() {
+            continue;
+        }
+
+        let Some(label) = entity.label() else {
+            continue;
+        };
+
+        let name = match zone.child(label.as_str()) {
+            Ok(n) => n,
+            Err(e) => {
+                tracing::warn!(error = %e, entity = entity.entity_id, "Invalid entity name");
+                continue;
+            }
+        };
+
+        for ip in addresses {
+            records.insert(Record::new(name.clone(), RData::from(ip)));
+        }
+    }
+
+    records
+}
+
+async fn homeassistant_loop<S: RecordServer>(
+    server: S,
+    source_id: SourceId,
+    config: HomeAssistantConfig,
+    interval: PollInterval,
+) -> LoopResult {
+    let mut client = server.http_client(config.bind.as_ref(), None);
+    let mut host = HostAddresses::new(&config.url);
+    host.changed(&server).await;
+
+    loop {
+        if host.changed(&server).await {
+            tracing::info!(%source_id, "Home Assistant host address changed, reconnecting");
+            client = server.reconnect_http_client(config.bind.as_ref(), None);
+        }
+
+        let states = match fetch_states(&source_id, &client, &config.url, &config.token).await {
+            Ok(states) => states,
+            Err(result) => return result,
+        };
+
+        let records = generate_records(&source_id, &config.zone, states);
+        server
+            .add_source_records(interval.records(&source_id, records))
+            .await;
+
+        sleep(interval.duration()).await;
+    }
+}
+
+impl SourceConfig for HomeAssistantConfig {
+    fn source_type() -> SourceType {
+        SourceType::HomeAssistant
+    }
+
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        Some(&self.overrides)
+    }
+
+    fn interval_ms(&self) -> Option<u64> {
+        Some(self.interval_ms.unwrap_or(POLL_INTERVAL_MS))
+    }
+
+    fn without_live_settings(&self) -> Self {
+        Self {
+            interval_ms: None,
+            overrides: Default::default(),
+            ..self.clone()
+        }
+    }
+
+    #[instrument(fields(%source_id), skip(self, server))]
+    async fn spawn<S: RecordServer>(
+        self,
+        source_id: SourceId,
+        server: &S,
+    ) -> Result<SourceHandle<S>, Error> {
+        tracing::trace!("Adding source");
+
+        spawn_polling(self, source_id, server, homeassistant_loop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use serde_json::json;
+    use tokio::time::sleep;
+    use uuid::Uuid;
+
+    use crate::{
+        dns::RData,
+        sources::{
+            homeassistant::{EntityState, HomeAssistantConfig},
+            SourceConfig, SourceId, SourceType,
+        },
+        test::{fqdn, name, SingleSourceServer},
+    };
+
+    const STATES: &str = r#"[
+  {
+    "entity_id": "sensor.kitchen_display_ip_address",
+    "state": "10.10.4.21",
+    "attributes": { "friendly_name": "Kitchen Display IP Address" }
+  },
+  {
+    "entity_id": "device_tracker.daves_phone",
+    "state": "home",
+    "attributes": {
+      "friendly_name": "Dave's Phone",
+      "ip": "10.10.4.30",
+      "mac": "aa:bb:cc:dd:ee:ff"
+    }
+  },
+  {
+    "entity_id": "sensor.garage_door_ip",
+    "state": "fd00::4:22",
+    "attributes": {}
+  },
+  {
+    "entity_id": "sensor.porch_light_ip_address",
+    "state": "unavailable",
+    "attributes": { "friendly_name": "Porch Light IP Address" }
+  },
+  {
+    "entity_id": "sensor.loopback",
+    "state": "127.0.0.1",
+    "attributes": {}
+  },
+  {
+    "entity_id": "light.lounge",
+    "state": "on",
+    "attributes": { "friendly_name": "Lounge", "brightness": 255 }
+  }
+]"#;
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn parse_states() {
+        let states: Vec<EntityState> = serde_json::from_str(STATES).unwrap();
+
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::HomeAssistant, "test");
+        let records = super::generate_records(&source_id, &fqdn("ha.home.local"), states);
+
+        assert_eq!(records.len(), 3);
+
+        assert!(records.contains(
+            &fqdn("kitchen-display.ha.home.local"),
+            &RData::A("10.10.4.21".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("dave-s-phone.ha.home.local"),
+            &RData::A("10.10.4.30".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("garage-door.ha.home.local"),
+            &RData::Aaaa("fd00::4:22".parse().unwrap())
+        ));
+        assert!(!records.has_name(&name("porch-light.ha.home.local.")));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mock_api() {
+        let rejected = web::Data::new(AtomicUsize::new(0));
+        let server_rejected = rejected.clone();
+
+        let server = HttpServer::new(move || {
+            App::new().app_data(server_rejected.clone()).route(
+                "/api/states",
+                web::get().to(
+                    |request: HttpRequest, rejected: web::Data<AtomicUsize>| async move {
+                        let authorization = request
+                            .headers()
+                            .get("Authorization")
+                            .and_then(|value| value.to_str().ok());
+
+                        if authorization == Some("Bearer secret") {
+                            HttpResponse::Ok()
+                                .json(serde_json::from_str::<serde_json::Value>(STATES).unwrap())
+                        } else {
+                            rejected.fetch_add(1, Ordering::SeqCst);
+                            HttpResponse::Unauthorized().json(json!({ "message": "Invalid token" }))
+                        }
+                    },
+                ),
+            )
+        })
+        .disable_signals()
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+
+        let port = server.addrs().first().unwrap().port();
+        let server = server.run();
+        let server_handle = server.handle();
+        tokio::spawn(server);
+
+        let source_id = SourceId {
+            server_id: Uuid::new_v4(),
+            source_type: HomeAssistantConfig::source_type(),
+            source_name: "test".to_string(),
+        };
+
+        let config = HomeAssistantConfig {
+            url: format!("http://127.0.0.1:{port}/").parse().unwrap(),
+            token: "secret".to_string(),
+            zone: fqdn("ha.home.local"),
+            interval_ms: Some(100),
+            bind: None,
+            overrides: Default::default(),
+        };
+
+        let mut test_server = SingleSourceServer::new(&source_id);
+        let handle = config.spawn(source_id.clone(), &test_server).await.unwrap();
+
+        let records = test_server
+            .wait_for_records(|records| records.has_name(&name("kitchen-display.ha.home.local.")))
+            .await;
+        assert_eq!(records.len(), 3);
+
+        handle.drop().await;
+
+        let config = HomeAssistantConfig {
+            url: format!("http://127.0.0.1:{port}/").parse().unwrap(),
+            token: "wrong".to_string(),
+            zone: fqdn("ha.home.local"),
+            interval_ms: Some(100),
+            bind: None,
+            overrides: Default::default(),
+        };
+
+        let mut test_server = SingleSourceServer::new(&source_id);
+        let handle = config.spawn(source_id.clone(), &test_server).await.unwrap();
+
+        while rejected.load(Ordering::SeqCst) == 0 {
+            sleep(Duration::from_millis(50)).await;
+        }
+        assert!(test_server
+            .wait_for_maybe_records(|_| true)
+            .await
+            .is_none_or(|records| records.is_empty()));
+
+        handle.drop().await;
+        server_handle.stop(true).await;
+    }
+}
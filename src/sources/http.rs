@@ -0,0 +1,356 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{anyhow, bail};
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::time::sleep;
+use tracing::instrument;
+
+use crate::{
+    config::deserialize_url,
+    dns::RecordSet,
+    run_loop::{LoopResult, PollInterval},
+    sources::{
+        file::parse_json, spawn_polling, RecordOverrides, SourceConfig, SourceHandle, SourceId,
+        SourceType,
+    },
+    util::{Bind, ClientTls, HostAddresses},
+    Error, RecordServer,
+};
+
+const POLL_INTERVAL_MS: u64 = 60000;
+
+/// The fields of the objects in a list that hold each name and its values.
+#[derive(Debug, PartialEq, Eq, Deserialize, Clone)]
+pub(crate) struct Fields {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct HttpConfig {
+    #[serde(deserialize_with = "deserialize_url")]
+    url: Url,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// Where the records are in the response, as `.` separated keys and indexes.
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    fields: Option<Fields>,
+    #[serde(default)]
+    interval_ms: Option<u64>,
+    #[serde(default)]
+    bind: Option<Bind>,
+    #[serde(default)]
+    tls: Option<ClientTls>,
+    #[serde(flatten)]
+    overrides: RecordOverrides,
+}
+
+/// Finds the value at a `.` separated path of object keys and array indexes.
+fn select<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .filter(|key| !key.is_empty())
+        .try_fold(value, |value, key| match value {
+            Value::Object(map) => map.get(key),
+            Value::Array(values) => values.get(key.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
+/// Converts a list of objects into the name to values form of a zone file.
+/// Entries missing either field are skipped.
+fn collect_fields(list: &Value, fields: &Fields) -> Result<Value, Error> {
+    let Value::Array(entries) = list else {
+        bail!("Expected a list of entries");
+    };
+
+    let mut names: Map<String, Value> = Map::new();
+    for entry in entries {
+        let Some(name) = select(entry, &fields.name).and_then(Value::as_str) else {
+            continue;
+        };
+
+        let values = match select(entry, &fields.value) {
+            Some(Value::Array(values)) => values.clone(),
+            Some(value @ (Value::String(_) | Value::Object(_))) => vec![value.clone()],
+            _ => continue,
+        };
+
+        if let Value::Array(existing) = names
+            .entry(name.to_owned())
+            .or_insert_with(|| Value::Array(Vec::new()))
+        {
+            existing.extend(values);
+        }
+    }
+
+    Ok(Value::Object(names))
+}
+
+fn extract_records(response: &Value, config: &HttpConfig) -> Result<RecordSet, Error> {
+    let selected = match config.path.as_deref() {
+        Some(path) => select(response, path).ok_or_else(|| anyhow!("Nothing found at {path}"))?,
+        None => response,
+    };
+
+    match &config.fields {
+        Some(fields) => parse_json(collect_fields(selected, fields)?),
+        None => parse_json(selected.clone()),
+    }
+}
+
+#[instrument(fields(%source_id, url = %config.url), skip(client, config))]
+async fn fetch_records(
+    source_id: &SourceId,
+    client: &Client,
+    config: &HttpConfig,
+) -> Result<RecordSet, LoopResult> {
+    let mut request = client.get(config.url.clone());
+    for (header, value) in &config.headers {
+        request = request.header(header, value);
+    }
+
+    let response = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to fetch records");
+            LoopResult::Backoff(format!("Failed to fetch records: {e}"))
+        })?;
+
+    let response: Value = response.json().await.map_err(|e| {
+        tracing::error!(error = %e, "Failed to parse response");
+        LoopResult::Backoff(format!("Failed to parse response: {e}"))
+    })?;
+
+    extract_records(&response, config).map_err(|e| {
+        tracing::error!(error = %e, "Failed to find records in response");
+        LoopResult::Backoff(format!("Failed to find records in response: {e}"))
+    })
+}
+
+async fn http_loop<S: RecordServer>(
+    server: S,
+    source_id: SourceId,
+    config: HttpConfig,
+    interval: PollInterval,
+) -> LoopResult {
+    let mut client = server.http_client(config.bind.as_ref(), config.tls.as_ref());
+    let mut host = HostAddresses::new(&config.url);
+    host.changed(&server).await;
+
+    loop {
+        if host.changed(&server).await {
+            tracing::info!(%source_id, "Host address changed, reconnecting");
+            client = server.reconnect_http_client(config.bind.as_ref(), config.tls.as_ref());
+        }
+
+        let records = match fetch_records(&source_id, &client, &config).await {
+            Ok(records) => records,
+            Err(result) => return result,
+        };
+
+        server
+            .add_source_records(interval.records(&source_id, records))
+            .await;
+
+        sleep(interval.duration()).await;
+    }
+}
+
+impl SourceConfig for HttpConfig {
+    fn source_type() -> SourceType {
+        SourceType::Http
+    }
+
+    fn required_files(&self) -> Vec<PathBuf> {
+        self.tls.as_ref().map(ClientTls::files).unwrap_or_default()
+    }
+
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        Some(&self.overrides)
+    }
+
+    fn interval_ms(&self) -> Option<u64> {
+        Some(self.interval_ms.unwrap_or(POLL_INTERVAL_MS))
+    }
+
+    fn without_live_settings(&self) -> Self {
+        Self {
+            interval_ms: None,
+            overrides: Default::default(),
+            ..self.clone()
+        }
+    }
+
+    #[instrument(fields(%source_id), skip(self, server))]
+    async fn spawn<S: RecordServer>(
+        self,
+        source_id: SourceId,
+        server: &S,
+    ) -> Result<SourceHandle<S>, Error> {
+        tracing::trace!("Adding source");
+
+        spawn_polling(self, source_id, server, http_loop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use crate::{
+        dns::RData,
+        sources::{
+            http::{Fields, HttpConfig},
+            SourceConfig, SourceId,
+        },
+        test::{fqdn, name, SingleSourceServer},
+    };
+
+    fn config(url: &str, path: Option<&str>, fields: Option<Fields>) -> HttpConfig {
+        HttpConfig {
+            url: url.parse().unwrap(),
+            headers: HashMap::new(),
+            path: path.map(str::to_owned),
+            fields,
+            interval_ms: Some(100),
+            bind: None,
+            tls: None,
+            overrides: Default::default(),
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn extract_records() {
+        let response = json!({
+            "result": {
+                "hosts": [
+                    { "host": { "name": "nas.home.local" }, "ip": "10.10.4.6" },
+                    { "host": { "name": "nas.home.local" }, "ip": ["fd00::4:6"] },
+                    { "host": { "name": "tv.home.local" }, "ip": { "value": "10.10.4.7", "ttl": 60 } },
+                    { "host": { "name": "broken.home.local" } },
+                    { "ip": "10.10.4.8" }
+                ]
+            }
+        });
+
+        let records = super::extract_records(
+            &response,
+            &config(
+                "http://localhost/",
+                Some("result.hosts"),
+                Some(Fields {
+                    name: "host.name".to_owned(),
+                    value: "ip".to_owned(),
+                }),
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert!(records.contains(
+            &fqdn("nas.home.local"),
+            &RData::A("10.10.4.6".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("nas.home.local"),
+            &RData::Aaaa("fd00::4:6".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("tv.home.local"),
+            &RData::A("10.10.4.7".parse().unwrap())
+        ));
+
+        let records = super::extract_records(
+            &response,
+            &config(
+                "http://localhost/",
+                Some("result.hosts.2.ip"),
+                Some(Fields {
+                    name: "host.name".to_owned(),
+                    value: "ip".to_owned(),
+                }),
+            ),
+        );
+        assert!(records.is_err());
+
+        let records = super::extract_records(
+            &json!({ "data": [{ "www.home.local": "10.10.4.5" }] }),
+            &config("http://localhost/", Some("data.0"), None),
+        )
+        .unwrap();
+        assert_eq!(records.len(), 1);
+
+        assert!(super::extract_records(
+            &response,
+            &config("http://localhost/", Some("result.missing"), None),
+        )
+        .is_err());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mock_api() {
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/hosts",
+                web::get().to(|request: HttpRequest| async move {
+                    let key = request
+                        .headers()
+                        .get("X-Api-Key")
+                        .and_then(|value| value.to_str().ok());
+
+                    if key == Some("secret") {
+                        HttpResponse::Ok().json(json!({
+                            "www.home.local": ["10.10.4.5", "fd00::4:5"],
+                            "web.home.local": "www.home.local",
+                        }))
+                    } else {
+                        HttpResponse::Unauthorized().finish()
+                    }
+                }),
+            )
+        })
+        .disable_signals()
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+
+        let port = server.addrs().first().unwrap().port();
+        let server = server.run();
+        let server_handle = server.handle();
+        tokio::spawn(server);
+
+        let source_id = SourceId::new(&Uuid::new_v4(), HttpConfig::source_type(), "test");
+
+        let mut config = config(&format!("http://127.0.0.1:{port}/hosts"), None, None);
+        config
+            .headers
+            .insert("X-Api-Key".to_owned(), "secret".to_owned());
+
+        let mut test_server = SingleSourceServer::new(&source_id);
+        let handle = config.spawn(source_id.clone(), &test_server).await.unwrap();
+
+        let records = test_server
+            .wait_for_records(|records| records.has_name(&name("www.home.local.")))
+            .await;
+        assert_eq!(records.len(), 3);
+        assert!(records.contains(
+            &fqdn("web.home.local"),
+            &RData::Cname(fqdn("www.home.local"))
+        ));
+
+        handle.drop().await;
+        server_handle.stop(true).await;
+    }
+}
@@ -0,0 +1,285 @@
+use std::net::IpAddr;
+
+use if_addrs::{get_if_addrs, Interface};
+use serde::Deserialize;
+use tokio::time::sleep;
+use tracing::instrument;
+
+use crate::{
+    dns::{Fqdn, RData, Record, RecordSet},
+    run_loop::{LoopResult, PollInterval},
+    sources::{spawn_polling, RecordOverrides, SourceConfig, SourceHandle, SourceId, SourceType},
+    Error, RecordServer,
+};
+
+const POLL_INTERVAL_MS: u64 = 30000;
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct InterfacesConfig {
+    hostnames: Vec<Fqdn>,
+    #[serde(default)]
+    interfaces: Option<Vec<String>>,
+    #[serde(default)]
+    interval_ms: Option<u64>,
+    #[serde(flatten)]
+    overrides: RecordOverrides,
+}
+
+impl InterfacesConfig {
+    pub(crate) fn new(hostnames: Vec<Fqdn>) -> Self {
+        Self {
+            hostnames,
+            interfaces: None,
+            interval_ms: None,
+            overrides: Default::default(),
+        }
+    }
+
+    /// Whether addresses on this interface should be published. Loopback and
+    /// link-local addresses are never useful to other machines.
+    fn includes(&self, interface: &Interface) -> bool {
+        if interface.is_loopback() || interface.is_link_local() {
+            return false;
+        }
+
+        match self.interfaces {
+            Some(ref names) => names.iter().any(|name| name == &interface.name),
+            None => true,
+        }
+    }
+}
+
+#[instrument(fields(%source_id), skip(config, addresses))]
+fn generate_records<I>(source_id: &SourceId, config: &InterfacesConfig, addresses: I) -> RecordSet
+where
+    I: IntoIterator<Item = IpAddr>,
+{
+    let mut records = RecordSet::new();
+
+    for ip in addresses {
+        for hostname in &config.hostnames {
+            records.insert(Record::new(hostname.clone(), RData::from(ip)));
+        }
+    }
+
+    records
+}
+
+async fn interfaces_loop<S: RecordServer>(
+    server: S,
+    source_id: SourceId,
+    interfaces_config: InterfacesConfig,
+    interval: PollInterval,
+) -> LoopResult {
+    loop {
+        let interfaces = match get_if_addrs() {
+            Ok(interfaces) => interfaces,
+            Err(e) => {
+                tracing::error!(%source_id, error = %e, "Failed to list network interfaces");
+                return LoopResult::Backoff(format!("Failed to list network interfaces: {e}"));
+            }
+        };
+
+        let addresses = interfaces
+            .iter()
+            .filter(|interface| interfaces_config.includes(interface))
+            .map(Interface::ip);
+
+        let records = generate_records(&source_id, &interfaces_config, addresses);
+        server
+            .add_source_records(interval.records(&source_id, records))
+            .await;
+
+        sleep(interval.duration()).await;
+    }
+}
+
+impl SourceConfig for InterfacesConfig {
+    fn source_type() -> SourceType {
+        SourceType::Interfaces
+    }
+
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        Some(&self.overrides)
+    }
+
+    fn interval_ms(&self) -> Option<u64> {
+        Some(self.interval_ms.unwrap_or(POLL_INTERVAL_MS))
+    }
+
+    fn without_live_settings(&self) -> Self {
+        Self {
+            interval_ms: None,
+            overrides: Default::default(),
+            ..self.clone()
+        }
+    }
+
+    #[instrument(fields(%source_id), skip(self, server))]
+    async fn spawn<S: RecordServer>(
+        self,
+        source_id: SourceId,
+        server: &S,
+    ) -> Result<SourceHandle<S>, Error> {
+        tracing::trace!("Adding source");
+
+        spawn_polling(self, source_id, server, interfaces_loop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use if_addrs::{IfAddr, Ifv4Addr, Ifv6Addr, Interface};
+    use uuid::Uuid;
+
+    use crate::{
+        dns::RData,
+        sources::{interfaces::InterfacesConfig, SourceConfig, SourceId, SourceType},
+        test::fqdn,
+    };
+
+    fn interface(name: &str, ip: &str) -> Interface {
+        let addr = match ip.parse().unwrap() {
+            IpAddr::V4(ip) => IfAddr::V4(Ifv4Addr {
+                ip,
+                netmask: "255.255.255.0".parse().unwrap(),
+                prefixlen: 24,
+                broadcast: None,
+            }),
+            IpAddr::V6(ip) => IfAddr::V6(Ifv6Addr {
+                ip,
+                netmask: "ffff:ffff:ffff:ffff::".parse().unwrap(),
+                prefixlen: 64,
+                broadcast: None,
+            }),
+        };
+
+        Interface {
+            name: name.to_string(),
+            addr,
+            index: None,
+            #[cfg(windows)]
+            adapter_name: String::new(),
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn interface_records() {
+        let interfaces = [
+            interface("lo", "127.0.0.1"),
+            interface("lo", "::1"),
+            interface("eth0", "10.10.1.5"),
+            interface("eth0", "fe80::1"),
+            interface("eth0", "fd00::5"),
+            interface("docker0", "172.17.0.1"),
+        ];
+
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::Interfaces, "test");
+
+        let config: InterfacesConfig = serde_yaml::from_str(
+            r#"
+hostnames:
+  - router.home.local
+  - gateway.home.local
+"#,
+        )
+        .unwrap();
+
+        let addresses = interfaces
+            .iter()
+            .filter(|i| config.includes(i))
+            .map(Interface::ip);
+        let records = super::generate_records(&source_id, &config, addresses);
+
+        assert_eq!(records.len(), 6);
+        assert!(records.contains(
+            &fqdn("router.home.local"),
+            &RData::A("10.10.1.5".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("gateway.home.local"),
+            &RData::Aaaa("fd00::5".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("router.home.local"),
+            &RData::A("172.17.0.1".parse().unwrap())
+        ));
+
+        let config: InterfacesConfig = serde_yaml::from_str(
+            r#"
+hostnames:
+  - router.home.local
+interfaces:
+  - eth0
+"#,
+        )
+        .unwrap();
+
+        let addresses = interfaces
+            .iter()
+            .filter(|i| config.includes(i))
+            .map(Interface::ip);
+        let records = super::generate_records(&source_id, &config, addresses);
+
+        assert_eq!(records.len(), 2);
+        assert!(records.contains(
+            &fqdn("router.home.local"),
+            &RData::A("10.10.1.5".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("router.home.local"),
+            &RData::Aaaa("fd00::5".parse().unwrap())
+        ));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn updates_in_place() {
+        let config: InterfacesConfig = serde_yaml::from_str(
+            r#"
+hostnames:
+  - router.home.local
+"#,
+        )
+        .unwrap();
+
+        let faster: InterfacesConfig = serde_yaml::from_str(
+            r#"
+hostnames:
+  - router.home.local
+interval_ms: 5000
+"#,
+        )
+        .unwrap();
+
+        let with_ttl: InterfacesConfig = serde_yaml::from_str(
+            r#"
+hostnames:
+  - router.home.local
+ttl: 60
+"#,
+        )
+        .unwrap();
+
+        let renamed: InterfacesConfig = serde_yaml::from_str(
+            r#"
+hostnames:
+  - gateway.home.local
+interval_ms: 5000
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(faster.interval_ms(), Some(5000));
+        assert_eq!(config.interval_ms(), Some(30000));
+
+        assert!(faster.updates_in_place(&config));
+        assert!(config.updates_in_place(&faster));
+        assert!(with_ttl.updates_in_place(&faster));
+        assert!(!renamed.updates_in_place(&config));
+        assert!(!renamed.updates_in_place(&faster));
+    }
+}
@@ -0,0 +1,250 @@
+use std::net::IpAddr;
+
+use anyhow::bail;
+use serde::Deserialize;
+use tokio::{process::Command, time::sleep};
+use tracing::instrument;
+
+use crate::{
+    dns::{Fqdn, RData, Record, RecordSet},
+    run_loop::{LoopResult, PollInterval},
+    sources::{spawn_polling, RecordOverrides, SourceConfig, SourceHandle, SourceId, SourceType},
+    Error, RecordServer,
+};
+
+const POLL_INTERVAL_MS: u64 = 15000;
+
+/// Where libvirt finds the addresses of a guest.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum AddressSource {
+    /// The DHCP leases of libvirt's own networks.
+    #[default]
+    Lease,
+    /// The QEMU guest agent running inside the guest.
+    Agent,
+    /// The host's ARP table, useful for guests on bridged networks.
+    Arp,
+}
+
+impl AddressSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AddressSource::Lease => "lease",
+            AddressSource::Agent => "agent",
+            AddressSource::Arp => "arp",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct LibvirtConfig {
+    zone: Fqdn,
+    #[serde(default)]
+    uri: Option<String>,
+    #[serde(default)]
+    address_source: AddressSource,
+    #[serde(default)]
+    interval_ms: Option<u64>,
+    #[serde(flatten)]
+    overrides: RecordOverrides,
+}
+
+/// Runs a virsh command and returns its output.
+async fn virsh(config: &LibvirtConfig, args: &[&str]) -> Result<String, Error> {
+    let mut command = Command::new("virsh");
+    if let Some(ref uri) = config.uri {
+        command.arg("--connect").arg(uri);
+    }
+
+    let output = command.args(args).kill_on_drop(true).output().await?;
+    if !output.status.success() {
+        bail!(
+            "virsh {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Turns a domain name into a DNS label, libvirt allows almost anything in
+/// domain names.
+fn label(domain: &str) -> Option<String> {
+    let label = domain
+        .to_lowercase()
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '-' })
+        .collect::<String>()
+        .trim_matches('-')
+        .to_owned();
+
+    if label.is_empty() {
+        None
+    } else {
+        Some(label)
+    }
+}
+
+/// Parses the table printed by `virsh domifaddr`. Loopback and link-local
+/// addresses, which the guest agent reports, are never useful to other machines.
+fn parse_addresses(output: &str) -> Vec<IpAddr> {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("---"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().last())
+        .filter_map(|address| address.split('/').next()?.parse::<IpAddr>().ok())
+        .filter(|ip| match ip {
+            IpAddr::V4(ip) => !ip.is_loopback() && !ip.is_link_local(),
+            IpAddr::V6(ip) => !ip.is_loopback() && !ip.is_unicast_link_local(),
+        })
+        .collect()
+}
+
+#[instrument(fields(%source_id), skip(config))]
+async fn fetch_records(source_id: &SourceId, config: &LibvirtConfig) -> Result<RecordSet, Error> {
+    let domains = virsh(config, &["list", "--name"]).await?;
+
+    let mut records = RecordSet::new();
+
+    for domain in domains.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let Some(label) = label(domain) else {
+            continue;
+        };
+
+        let name = match config.zone.child(label.as_str()) {
+            Ok(n) => n,
+            Err(e) => {
+                tracing::warn!(error = %e, domain, "Invalid libvirt domain name");
+                continue;
+            }
+        };
+
+        // Domains may stop after being listed and the guest agent may not be
+        // installed so failures here only skip the domain.
+        let output = match virsh(
+            config,
+            &[
+                "domifaddr",
+                domain,
+                "--source",
+                config.address_source.as_str(),
+            ],
+        )
+        .await
+        {
+            Ok(output) => output,
+            Err(e) => {
+                tracing::debug!(error = %e, domain, "Failed to list domain addresses");
+                continue;
+            }
+        };
+
+        for ip in parse_addresses(&output) {
+            records.insert(Record::new(name.clone(), RData::from(ip)));
+        }
+    }
+
+    Ok(records)
+}
+
+async fn libvirt_loop<S: RecordServer>(
+    server: S,
+    source_id: SourceId,
+    libvirt_config: LibvirtConfig,
+    interval: PollInterval,
+) -> LoopResult {
+    loop {
+        let records = match fetch_records(&source_id, &libvirt_config).await {
+            Ok(records) => records,
+            Err(e) => {
+                tracing::error!(%source_id, error = %e, "Failed to list libvirt domains");
+                return LoopResult::Backoff(format!("Failed to list libvirt domains: {e}"));
+            }
+        };
+
+        server
+            .add_source_records(interval.records(&source_id, records))
+            .await;
+
+        sleep(interval.duration()).await;
+    }
+}
+
+impl SourceConfig for LibvirtConfig {
+    fn source_type() -> SourceType {
+        SourceType::Libvirt
+    }
+
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        Some(&self.overrides)
+    }
+
+    fn interval_ms(&self) -> Option<u64> {
+        Some(self.interval_ms.unwrap_or(POLL_INTERVAL_MS))
+    }
+
+    fn without_live_settings(&self) -> Self {
+        Self {
+            interval_ms: None,
+            overrides: Default::default(),
+            ..self.clone()
+        }
+    }
+
+    #[instrument(fields(%source_id), skip(self, server))]
+    async fn spawn<S: RecordServer>(
+        self,
+        source_id: SourceId,
+        server: &S,
+    ) -> Result<SourceHandle<S>, Error> {
+        tracing::trace!("Adding source");
+
+        spawn_polling(self, source_id, server, libvirt_loop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use crate::sources::libvirt::{label, parse_addresses};
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn parse_domifaddr() {
+        let addresses = parse_addresses(
+            r#" Name       MAC address          Protocol     Address
+-------------------------------------------------------------------------------
+ lo         00:00:00:00:00:00    ipv4         127.0.0.1/8
+ -          -                    ipv6         ::1/128
+ enp1s0     52:54:00:b2:9c:2a    ipv4         192.168.122.45/24
+ -          -                    ipv6         fd00:122::45/64
+ -          -                    ipv6         fe80::5054:ff:feb2:9c2a/64
+"#,
+        );
+
+        assert_eq!(
+            addresses,
+            vec![
+                "192.168.122.45".parse::<IpAddr>().unwrap(),
+                "fd00:122::45".parse::<IpAddr>().unwrap(),
+            ]
+        );
+
+        assert!(parse_addresses("").is_empty());
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn domain_labels() {
+        assert_eq!(label("webserver").as_deref(), Some("webserver"));
+        assert_eq!(
+            label("Ubuntu 24.04 (test)").as_deref(),
+            Some("ubuntu-24-04--test")
+        );
+        assert_eq!(label("___"), None);
+    }
+}
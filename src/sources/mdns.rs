@@ -0,0 +1,370 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+};
+
+use futures::{stream::select_all, StreamExt};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    dns::{Fqdn, RData, Record, RecordSet},
+    run_loop::{LoopResult, RunLoop},
+    sources::{RecordOverrides, SourceConfig, SourceHandle, SourceId, SourceType},
+    Error, RecordServer, SourceRecords,
+};
+
+const DEFAULT_SERVICE_TYPES: &[&str] = &["_workstation._tcp", "_http._tcp"];
+const MDNS_DOMAIN: &str = "local.";
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct MdnsConfig {
+    zone: Fqdn,
+    #[serde(default)]
+    service_types: Option<Vec<String>>,
+    #[serde(flatten)]
+    overrides: RecordOverrides,
+}
+
+impl MdnsConfig {
+    /// The service types to browse for, fully qualified in the `local.` domain.
+    fn service_types(&self) -> Vec<String> {
+        let service_types = match self.service_types {
+            Some(ref types) => types.clone(),
+            None => DEFAULT_SERVICE_TYPES
+                .iter()
+                .map(|t| t.to_string())
+                .collect(),
+        };
+
+        service_types
+            .into_iter()
+            .map(|service_type| {
+                let service_type = service_type.trim_end_matches('.');
+                if service_type.ends_with(".local") {
+                    format!("{service_type}.")
+                } else {
+                    format!("{service_type}.{MDNS_DOMAIN}")
+                }
+            })
+            .collect()
+    }
+}
+
+/// A hostname and its addresses as announced by a single service instance.
+#[derive(Debug, PartialEq, Eq)]
+struct Announcement {
+    hostname: String,
+    addresses: HashSet<IpAddr>,
+}
+
+impl Announcement {
+    fn from_service(info: &ServiceInfo) -> Option<Self> {
+        let hostname = info
+            .get_hostname()
+            .trim_end_matches('.')
+            .trim_end_matches(".local")
+            .to_lowercase();
+
+        if hostname.is_empty() || hostname.contains('.') {
+            return None;
+        }
+
+        Some(Self {
+            hostname,
+            addresses: info.get_addresses().clone(),
+        })
+    }
+}
+
+/// Tracks the service instances currently announced on the network, keyed by
+/// their full service name.
+#[derive(Default)]
+struct Announcements {
+    services: HashMap<String, Announcement>,
+}
+
+impl Announcements {
+    /// Applies an event from the daemon, returning true if the published records
+    /// may have changed.
+    fn apply(&mut self, event: ServiceEvent) -> bool {
+        match event {
+            ServiceEvent::ServiceResolved(info) => match Announcement::from_service(&info) {
+                Some(announcement) => {
+                    let fullname = info.get_fullname().to_owned();
+                    if self.services.get(&fullname) == Some(&announcement) {
+                        false
+                    } else {
+                        tracing::trace!(
+                            fullname,
+                            hostname = announcement.hostname,
+                            "Resolved mDNS service"
+                        );
+                        self.services.insert(fullname, announcement);
+                        true
+                    }
+                }
+                None => {
+                    tracing::debug!(
+                        hostname = info.get_hostname(),
+                        "Ignoring service with an unusable hostname"
+                    );
+                    false
+                }
+            },
+            ServiceEvent::ServiceRemoved(_, fullname) => {
+                tracing::trace!(fullname, "Removed mDNS service");
+                self.services.remove(&fullname).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    fn generate_records(&self, source_id: &SourceId, zone: &Fqdn) -> RecordSet {
+        let mut records = RecordSet::new();
+
+        for announcement in self.services.values() {
+            let name = match zone.child(announcement.hostname.as_str()) {
+                Ok(n) => n,
+                Err(e) => {
+                    tracing::warn!(
+                        %source_id,
+                        error = %e,
+                        hostname = announcement.hostname,
+                        "Invalid mDNS hostname"
+                    );
+                    continue;
+                }
+            };
+
+            for ip in &announcement.addresses {
+                records.insert(Record::new(name.clone(), RData::from(*ip)));
+            }
+        }
+
+        records
+    }
+}
+
+/// Shuts down the mDNS daemon when the source is dropped.
+struct Daemon(ServiceDaemon);
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.shutdown() {
+            tracing::warn!(error = %e, "Failed to shut down mDNS daemon");
+        }
+    }
+}
+
+#[instrument(fields(%source_id), skip(server, mdns_config))]
+async fn mdns_loop<S: RecordServer>(
+    server: S,
+    source_id: SourceId,
+    mdns_config: MdnsConfig,
+) -> LoopResult {
+    let daemon = match ServiceDaemon::new() {
+        Ok(daemon) => Daemon(daemon),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to start mDNS daemon");
+            return LoopResult::Backoff(format!("Failed to start mDNS daemon: {e}"));
+        }
+    };
+
+    let mut receivers = Vec::new();
+    for service_type in mdns_config.service_types() {
+        match daemon.0.browse(&service_type) {
+            Ok(receiver) => receivers.push(receiver.into_stream()),
+            Err(e) => {
+                tracing::error!(
+                    error = %e,
+                    service_type,
+                    "Failed to browse for mDNS services"
+                );
+                return LoopResult::Backoff(format!("Failed to browse for mDNS services: {e}"));
+            }
+        }
+    }
+
+    let mut events = select_all(receivers);
+    let mut announcements = Announcements::default();
+
+    server
+        .add_source_records(SourceRecords::new(&source_id, None, RecordSet::new()))
+        .await;
+
+    while let Some(event) = events.next().await {
+        if announcements.apply(event) {
+            let records = announcements.generate_records(&source_id, &mdns_config.zone);
+            server
+                .add_source_records(SourceRecords::new(&source_id, None, records))
+                .await;
+        }
+    }
+
+    tracing::warn!("mDNS daemon stopped unexpectedly");
+    LoopResult::Backoff("mDNS daemon stopped unexpectedly".to_owned())
+}
+
+impl SourceConfig for MdnsConfig {
+    fn source_type() -> SourceType {
+        SourceType::Mdns
+    }
+
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        Some(&self.overrides)
+    }
+
+    fn without_live_settings(&self) -> Self {
+        Self {
+            overrides: Default::default(),
+            ..self.clone()
+        }
+    }
+
+    #[instrument(fields(%source_id), skip(self, server))]
+    async fn spawn<S: RecordServer>(
+        self,
+        source_id: SourceId,
+        server: &S,
+    ) -> Result<SourceHandle<S>, Error> {
+        tracing::trace!("Adding source");
+
+        let handle = {
+            let backoff = RunLoop::new(5000);
+            let config = self.clone();
+
+            tokio::spawn(
+                backoff.run(server.clone(), source_id, move |server, source_id| {
+                    mdns_loop(server, source_id, config.clone())
+                }),
+            )
+        };
+
+        Ok(handle.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use mdns_sd::{ServiceEvent, ServiceInfo};
+    use uuid::Uuid;
+
+    use crate::{
+        dns::RData,
+        sources::{
+            mdns::{Announcements, MdnsConfig},
+            SourceId, SourceType,
+        },
+        test::{fqdn, name},
+    };
+
+    fn service(service_type: &str, instance: &str, host: &str, ips: &[&str]) -> ServiceEvent {
+        let ips: Vec<IpAddr> = ips.iter().map(|ip| ip.parse().unwrap()).collect();
+        ServiceEvent::ServiceResolved(
+            ServiceInfo::new(service_type, instance, host, &ips[..], 80, None).unwrap(),
+        )
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn service_types() {
+        let config: MdnsConfig = serde_yaml::from_str("zone: mdns.home.local").unwrap();
+        assert_eq!(
+            config.service_types(),
+            vec!["_workstation._tcp.local.", "_http._tcp.local."]
+        );
+
+        let config: MdnsConfig = serde_yaml::from_str(
+            r#"
+zone: mdns.home.local
+service_types:
+  - _ipp._tcp
+  - _airplay._tcp.local
+  - _ssh._tcp.local.
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.service_types(),
+            vec![
+                "_ipp._tcp.local.",
+                "_airplay._tcp.local.",
+                "_ssh._tcp.local."
+            ]
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn announcements() {
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::Mdns, "test");
+        let zone = fqdn("mdns.home.local");
+        let mut announcements = Announcements::default();
+
+        assert!(announcements.apply(service(
+            "_workstation._tcp.local.",
+            "printer",
+            "Printer.local.",
+            &["10.10.1.50", "fd00::50"],
+        )));
+        assert!(announcements.apply(service(
+            "_http._tcp.local.",
+            "nas web",
+            "nas.local.",
+            &["10.10.1.60"],
+        )));
+        assert!(announcements.apply(service(
+            "_workstation._tcp.local.",
+            "nas",
+            "nas.local.",
+            &["10.10.1.60"],
+        )));
+        assert!(!announcements.apply(service(
+            "_http._tcp.local.",
+            "nas web",
+            "nas.local.",
+            &["10.10.1.60"],
+        )));
+        assert!(!announcements.apply(service(
+            "_http._tcp.local.",
+            "other",
+            "host.other.domain.",
+            &["10.10.1.70"],
+        )));
+
+        let records = announcements.generate_records(&source_id, &zone);
+        assert_eq!(records.len(), 3);
+        assert!(records.contains(
+            &fqdn("printer.mdns.home.local"),
+            &RData::A("10.10.1.50".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("printer.mdns.home.local"),
+            &RData::Aaaa("fd00::50".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("nas.mdns.home.local"),
+            &RData::A("10.10.1.60".parse().unwrap())
+        ));
+
+        // The name remains while any service still announces it.
+        assert!(announcements.apply(ServiceEvent::ServiceRemoved(
+            "_workstation._tcp.local.".to_string(),
+            "nas._workstation._tcp.local.".to_string(),
+        )));
+        let records = announcements.generate_records(&source_id, &zone);
+        assert!(records.has_name(&name("nas.mdns.home.local.")));
+
+        assert!(announcements.apply(ServiceEvent::ServiceRemoved(
+            "_http._tcp.local.".to_string(),
+            "nas web._http._tcp.local.".to_string(),
+        )));
+        let records = announcements.generate_records(&source_id, &zone);
+        assert_eq!(records.len(), 2);
+        assert!(!records.has_name(&name("nas.mdns.home.local.")));
+    }
+}
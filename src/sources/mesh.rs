@@ -0,0 +1,490 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, task::JoinHandle, time::sleep};
+use tracing::instrument;
+
+use crate::{
+    api::ApiMeshPeers,
+    config::{deserialize_url, deserialize_urls},
+    sources::{
+        remote::{RemoteConfig, RemoteRecords},
+        SourceConfig, SourceHandle, SourceId, SourceType,
+    },
+    util::{Bind, ClientTls},
+    Error, RecordServer, ServerId,
+};
+
+/// The name of the source that replicates from the rest of the mesh.
+pub(crate) const MESH_SOURCE: &str = "mesh";
+
+const POLL_INTERVAL_MS: u64 = 60000;
+/// How long a peer is remembered after it last registered or was listed by a
+/// seed.
+const PEER_EXPIRY: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct MeshConfig {
+    /// The URL of this server's API that other peers replicate from.
+    #[serde(deserialize_with = "deserialize_url")]
+    url: Url,
+    /// The seed peers that this server registers with to discover the others.
+    #[serde(default, deserialize_with = "deserialize_urls")]
+    peers: Vec<Url>,
+    /// Sent to seeds that require the API token to register.
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    interval_ms: Option<u64>,
+    #[serde(default)]
+    bind: Option<Bind>,
+    #[serde(default)]
+    tls: Option<ClientTls>,
+}
+
+impl MeshConfig {
+    pub(crate) fn tls_files(&self) -> Vec<PathBuf> {
+        self.tls.as_ref().map(ClientTls::files).unwrap_or_default()
+    }
+}
+
+/// A server in the mesh and the URL of its API.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct MeshPeer {
+    pub(crate) server_id: ServerId,
+    pub(crate) url: String,
+}
+
+/// The peers that have registered with this server.
+#[derive(Debug, Default)]
+pub(crate) struct MeshRegistry {
+    peers: HashMap<ServerId, (String, Instant)>,
+}
+
+impl MeshRegistry {
+    pub(crate) fn register(&mut self, peer: MeshPeer) {
+        // A peer that restarted registers again with a new id.
+        self.peers.retain(|_, (url, _)| *url != peer.url);
+        self.peers
+            .insert(peer.server_id, (peer.url, Instant::now()));
+    }
+
+    /// The peers that have registered recently.
+    pub(crate) fn peers(&mut self) -> Vec<MeshPeer> {
+        self.peers
+            .retain(|_, (_, registered)| registered.elapsed() < PEER_EXPIRY);
+
+        self.peers
+            .iter()
+            .map(|(server_id, (url, _))| MeshPeer {
+                server_id: *server_id,
+                url: url.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Registers this server with a seed and returns the peers that the seed knows
+/// of.
+#[instrument(fields(%seed), skip(client, local, token))]
+async fn register(
+    client: &Client,
+    seed: &Url,
+    local: &MeshPeer,
+    token: Option<&str>,
+) -> Result<ApiMeshPeers, Error> {
+    let mut request = client.post(seed.join("v2/mesh/peers")?).json(local);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+
+    Ok(request.send().await?.error_for_status()?.json().await?)
+}
+
+struct MeshRemote<S: RecordServer> {
+    url: Url,
+    records: RemoteRecords<S>,
+}
+
+/// Starts replicating from newly discovered peers and stops for those that
+/// haven't been seen for a while.
+async fn update_remotes<S: RecordServer>(
+    server: &S,
+    source_id: &SourceId,
+    config: &MeshConfig,
+    peers: &HashMap<ServerId, (Url, Instant)>,
+    remotes: &Mutex<HashMap<ServerId, MeshRemote<S>>>,
+) {
+    let mut remotes = remotes.lock().await;
+
+    let gone: Vec<ServerId> = remotes
+        .iter()
+        .filter(|(server_id, remote)| peers.get(server_id).map(|(url, _)| url) != Some(&remote.url))
+        .map(|(server_id, _)| *server_id)
+        .collect();
+
+    for server_id in gone {
+        if let Some(remote) = remotes.remove(&server_id) {
+            tracing::info!(%source_id, peer = %server_id, url = %remote.url, "Mesh peer is gone");
+            remote.records.drop().await;
+        }
+    }
+
+    for (server_id, (url, _)) in peers {
+        if remotes.contains_key(server_id) {
+            continue;
+        }
+
+        tracing::info!(%source_id, peer = %server_id, %url, "Replicating from mesh peer");
+
+        let remote_config =
+            RemoteConfig::peer(url.clone(), config.bind.clone(), config.tls.clone());
+        let remote_id = SourceId::new(
+            &source_id.server_id,
+            SourceType::Mesh,
+            &server_id.to_string(),
+        );
+
+        remotes.insert(
+            *server_id,
+            MeshRemote {
+                url: url.clone(),
+                records: remote_config.start(remote_id, server),
+            },
+        );
+    }
+}
+
+async fn mesh_loop<S: RecordServer>(
+    server: S,
+    source_id: SourceId,
+    config: MeshConfig,
+    remotes: Arc<Mutex<HashMap<ServerId, MeshRemote<S>>>>,
+) {
+    let interval = Duration::from_millis(config.interval_ms.unwrap_or(POLL_INTERVAL_MS));
+    let client = server.http_client(config.bind.as_ref(), config.tls.as_ref());
+    let local = MeshPeer {
+        server_id: source_id.server_id,
+        url: config.url.to_string(),
+    };
+
+    let mut peers: HashMap<ServerId, (Url, Instant)> = HashMap::new();
+
+    loop {
+        let mut discovered = server.mesh_peers().await;
+
+        for seed in &config.peers {
+            match register(&client, seed, &local, config.token.as_deref()).await {
+                Ok(response) => {
+                    discovered.push(MeshPeer {
+                        server_id: response.server_id,
+                        url: seed.to_string(),
+                    });
+                    discovered.extend(response.peers);
+                }
+                Err(e) => {
+                    tracing::warn!(%source_id, %seed, error = %e, "Failed to register with mesh seed");
+                }
+            }
+        }
+
+        let now = Instant::now();
+        for peer in discovered {
+            // Seeds pass this server back, possibly from before it restarted.
+            if peer.server_id == local.server_id || peer.url == local.url {
+                continue;
+            }
+
+            match Url::parse(&peer.url) {
+                Ok(url) => {
+                    peers.insert(peer.server_id, (url, now));
+                }
+                Err(e) => {
+                    tracing::warn!(%source_id, peer = %peer.server_id, url = peer.url, error = %e, "Invalid mesh peer URL");
+                }
+            }
+        }
+
+        peers.retain(|_, (_, seen)| seen.elapsed() < PEER_EXPIRY);
+
+        update_remotes(&server, &source_id, &config, &peers, &remotes).await;
+
+        sleep(interval).await;
+    }
+}
+
+pub(super) struct MeshRemotes<S: RecordServer> {
+    handle: JoinHandle<()>,
+    remotes: Arc<Mutex<HashMap<ServerId, MeshRemote<S>>>>,
+}
+
+impl<S: RecordServer> MeshRemotes<S> {
+    pub(super) async fn drop(&self) {
+        self.handle.abort();
+
+        let remotes: Vec<MeshRemote<S>> = self
+            .remotes
+            .lock()
+            .await
+            .drain()
+            .map(|(_, remote)| remote)
+            .collect();
+
+        for remote in remotes {
+            remote.records.drop().await;
+        }
+    }
+}
+
+impl SourceConfig for MeshConfig {
+    fn source_type() -> SourceType {
+        SourceType::Mesh
+    }
+
+    fn required_files(&self) -> Vec<PathBuf> {
+        self.tls_files()
+    }
+
+    #[instrument(fields(%source_id), skip(self, server))]
+    async fn spawn<S: RecordServer>(
+        self,
+        source_id: SourceId,
+        server: &S,
+    ) -> Result<SourceHandle<S>, Error> {
+        tracing::trace!("Adding source");
+
+        let remotes = Arc::new(Mutex::new(HashMap::new()));
+
+        let handle = tokio::spawn(mesh_loop(server.clone(), source_id, self, remotes.clone()));
+
+        Ok(MeshRemotes { handle, remotes }.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::{Ipv4Addr, SocketAddr},
+        sync::Arc,
+    };
+
+    use reqwest::StatusCode;
+    use tokio::sync::{watch, Mutex};
+    use uuid::Uuid;
+
+    use crate::{
+        api::{ApiConfig, ApiMeshPeers, ApiServer},
+        config::Config,
+        dns::{RData, Record, RecordSet, ServerState},
+        sources::{
+            mesh::{MeshConfig, MeshPeer, MeshRegistry, MESH_SOURCE},
+            SourceConfig, SourceId, SourceRecords, SourceType,
+        },
+        test::{fqdn, name, MultiSourceServer},
+        ServerId, ServerInner,
+    };
+
+    const TOKEN: &str = "mesh-token";
+
+    /// Starts an API server for a peer with a single name, optionally as part of
+    /// a mesh that others can register with.
+    fn peer(server_id: ServerId, host: &str, ip: &str, mesh: bool) -> ApiServer {
+        let source_id = SourceId::new(&server_id, SourceType::File, "hosts");
+
+        let mut records = RecordSet::new();
+        records.insert(Record::new(fqdn(host), RData::A(ip.parse().unwrap())));
+
+        let mut config = Config::default();
+        if mesh {
+            config.sources.mesh.insert(
+                MESH_SOURCE.to_owned(),
+                MeshConfig {
+                    url: format!("http://{ip}/").parse().unwrap(),
+                    peers: Vec::new(),
+                    token: None,
+                    interval_ms: None,
+                    bind: None,
+                    tls: None,
+                },
+            );
+        }
+
+        let inner = ServerInner {
+            config,
+            records: HashMap::from([(
+                source_id.clone(),
+                SourceRecords::new(&source_id, None, records),
+            )]),
+            changes: Default::default(),
+            replication: Default::default(),
+            mesh_peers: Default::default(),
+            source_errors: HashMap::new(),
+            source_status: HashMap::new(),
+            source_reverse_zones: HashMap::new(),
+            config_error: None,
+            records_changed: watch::Sender::new(()),
+        };
+
+        let server_state = ServerState::new(inner.records(), inner.config.zones.clone());
+        let api_config = ApiConfig {
+            address: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0),
+            token: Some(TOKEN.to_owned()),
+            tokens: HashMap::new(),
+            records_file: None,
+            tls: None,
+        };
+
+        ApiServer::new(
+            &api_config,
+            server_id,
+            Arc::new(Mutex::new(inner)),
+            server_state,
+        )
+        .unwrap()
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn registry() {
+        let mut registry = MeshRegistry::default();
+        let first = ServerId::new_v4();
+        let second = ServerId::new_v4();
+
+        registry.register(MeshPeer {
+            server_id: first,
+            url: "http://10.10.3.4/".to_owned(),
+        });
+        registry.register(MeshPeer {
+            server_id: ServerId::new_v4(),
+            url: "http://10.10.3.5/".to_owned(),
+        });
+        assert_eq!(registry.peers().len(), 2);
+
+        // The same server after a restart.
+        registry.register(MeshPeer {
+            server_id: second,
+            url: "http://10.10.3.5/".to_owned(),
+        });
+
+        let mut peers = registry.peers();
+        peers.sort_by(|a, b| a.url.cmp(&b.url));
+        assert_eq!(
+            peers,
+            vec![
+                MeshPeer {
+                    server_id: first,
+                    url: "http://10.10.3.4/".to_owned(),
+                },
+                MeshPeer {
+                    server_id: second,
+                    url: "http://10.10.3.5/".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn discovery() {
+        let seed_id = ServerId::new_v4();
+        let seed = peer(seed_id, "seed.mesh.local", "10.10.1.1", true);
+        let other_id = ServerId::new_v4();
+        let other = peer(other_id, "other.mesh.local", "10.10.1.2", false);
+
+        let client = reqwest::Client::new();
+        let seed_url = format!("http://localhost:{}/", seed.port);
+        let other_peer = MeshPeer {
+            server_id: other_id,
+            url: format!("http://localhost:{}/", other.port),
+        };
+
+        // Registering needs the token and a server that is part of a mesh.
+        let response = client
+            .post(format!("{seed_url}v2/mesh/peers"))
+            .json(&other_peer)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client
+            .post(format!("http://localhost:{}/v2/mesh/peers", other.port))
+            .bearer_auth(TOKEN)
+            .json(&other_peer)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Another peer has already registered with the seed.
+        let response: ApiMeshPeers = client
+            .post(format!("{seed_url}v2/mesh/peers"))
+            .bearer_auth(TOKEN)
+            .json(&other_peer)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response.server_id, seed_id);
+
+        let mut test_server = MultiSourceServer::new();
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::Mesh, "mesh");
+
+        let config = MeshConfig {
+            url: "http://localhost:1/".parse().unwrap(),
+            peers: vec![seed_url.parse().unwrap()],
+            token: Some(TOKEN.to_owned()),
+            interval_ms: Some(100),
+            bind: None,
+            tls: None,
+        };
+
+        let handle = config.spawn(source_id.clone(), &test_server).await.unwrap();
+
+        let records = test_server
+            .wait_for_state(|records| {
+                records
+                    .values()
+                    .any(|records| records.has_name(&name("seed.mesh.local.")))
+                    && records
+                        .values()
+                        .any(|records| records.has_name(&name("other.mesh.local.")))
+            })
+            .await;
+        assert_eq!(records.len(), 2);
+
+        // This server registered itself with the seed.
+        let response: ApiMeshPeers = client
+            .get(format!("{seed_url}v2/mesh/peers"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response.peers.len(), 2);
+        assert!(response
+            .peers
+            .iter()
+            .any(|peer| peer.server_id == source_id.server_id));
+
+        handle.drop().await;
+
+        let records = test_server
+            .wait_for_state(|source_records| source_records.is_empty())
+            .await;
+        assert!(records.is_empty());
+
+        seed.shutdown().await;
+        other.shutdown().await;
+    }
+}
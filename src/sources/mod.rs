@@ -1,39 +1,125 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt,
-    mem::forget,
+    future::Future,
+    mem::{self, forget},
+    path::PathBuf,
+    time::Duration,
 };
 
-use chrono::{DateTime, Utc};
+use anyhow::bail;
+use chrono::{DateTime, TimeDelta, Utc};
 use serde::{Deserialize, Serialize};
 use serde_plain::derive_display_from_serialize;
 use tokio::task::JoinHandle;
 use tracing::warn;
 use uuid::Uuid;
 
-use crate::{config::Config, dns::RecordSet, watcher::Watcher, Error, RecordServer, ServerId};
+use crate::{
+    config::Config,
+    dns::{Fqdn, RData, Record, RecordSet},
+    run_loop::{LoopResult, PollInterval, RunLoop},
+    watcher::Watcher,
+    Error, RecordServer, ServerId,
+};
 
 pub(crate) mod dhcp;
+#[cfg(feature = "docker")]
 pub(crate) mod docker;
+pub(crate) mod exec;
+pub(crate) mod failover;
 pub(crate) mod file;
+pub(crate) mod homeassistant;
+pub(crate) mod http;
+pub(crate) mod interfaces;
+pub(crate) mod libvirt;
+#[cfg(feature = "mdns")]
+pub(crate) mod mdns;
+pub(crate) mod mesh;
+pub(crate) mod pihole;
 pub(crate) mod remote;
+mod supervise;
+pub(crate) mod tailscale;
 pub(crate) mod traefik;
 
-trait SourceConfig: PartialEq {
+trait SourceConfig: PartialEq + Clone + Send + 'static {
     fn source_type() -> SourceType;
 
-    async fn spawn<S: RecordServer>(
+    /// Local files that must already exist for the source to work.
+    fn required_files(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// How often the source polls for records, for sources that poll.
+    fn interval_ms(&self) -> Option<u64> {
+        None
+    }
+
+    /// This config with the settings that a running source picks up without
+    /// being restarted, its poll interval and record overrides, cleared.
+    fn without_live_settings(&self) -> Self {
+        self.clone()
+    }
+
+    /// Whether the source running with the `old` config can carry on with this
+    /// one rather than being restarted.
+    fn updates_in_place(&self, old: &Self) -> bool {
+        self.without_live_settings() == old.without_live_settings()
+    }
+
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        None
+    }
+
+    fn spawn<S: RecordServer>(
         self,
         source_id: SourceId,
         server: &S,
-    ) -> Result<SourceHandle<S>, Error>;
+    ) -> impl Future<Output = Result<SourceHandle<S>, Error>> + Send;
 }
 
 enum SourceHandle<S: RecordServer> {
     Spawned(JoinHandle<()>),
+    Polling(JoinHandle<()>, PollInterval),
     #[allow(dead_code)]
     Watcher(Watcher),
     Remote(remote::RemoteRecords<S>),
+    Mesh(mesh::MeshRemotes<S>),
+    Supervised(supervise::Supervisor<S>),
+}
+
+/// Spawns the loop of a source that polls for its records. Failed polls are
+/// retried with a backoff and the poll interval can be changed while it runs.
+fn spawn_polling<S, C, L, F>(
+    config: C,
+    source_id: SourceId,
+    server: &S,
+    poll_loop: L,
+) -> Result<SourceHandle<S>, Error>
+where
+    S: RecordServer,
+    C: SourceConfig,
+    L: Fn(S, SourceId, C, PollInterval) -> F + Send + 'static,
+    F: Future<Output = LoopResult> + Send + 'static,
+{
+    let Some(interval_ms) = config.interval_ms() else {
+        bail!("{} sources don't poll for records", C::source_type());
+    };
+
+    let interval = PollInterval::new(interval_ms);
+
+    let handle = {
+        let backoff = RunLoop::new(interval.millis());
+        let interval = interval.clone();
+
+        tokio::spawn(
+            backoff.run(server.clone(), source_id, move |server, source_id| {
+                poll_loop(server, source_id, config.clone(), interval.clone())
+            }),
+        )
+    };
+
+    Ok(SourceHandle::Polling(handle, interval))
 }
 
 impl<S: RecordServer> From<remote::RemoteRecords<S>> for SourceHandle<S> {
@@ -42,6 +128,18 @@ impl<S: RecordServer> From<remote::RemoteRecords<S>> for SourceHandle<S> {
     }
 }
 
+impl<S: RecordServer> From<mesh::MeshRemotes<S>> for SourceHandle<S> {
+    fn from(handle: mesh::MeshRemotes<S>) -> Self {
+        SourceHandle::Mesh(handle)
+    }
+}
+
+impl<S: RecordServer> From<supervise::Supervisor<S>> for SourceHandle<S> {
+    fn from(supervisor: supervise::Supervisor<S>) -> Self {
+        SourceHandle::Supervised(supervisor)
+    }
+}
+
 impl<S: RecordServer> From<JoinHandle<()>> for SourceHandle<S> {
     fn from(handle: JoinHandle<()>) -> Self {
         SourceHandle::Spawned(handle)
@@ -57,8 +155,10 @@ impl<S: RecordServer> From<Watcher> for SourceHandle<S> {
 impl<S: RecordServer> SourceHandle<S> {
     async fn drop(mut self) {
         match &mut self {
-            Self::Spawned(handle) => handle.abort(),
+            Self::Spawned(handle) | Self::Polling(handle, _) => handle.abort(),
             Self::Remote(records) => records.drop().await,
+            Self::Mesh(remotes) => remotes.drop().await,
+            Self::Supervised(supervisor) => supervisor.drop().await,
             _ => {}
         }
 
@@ -74,13 +174,29 @@ impl<S: RecordServer> Drop for SourceHandle<S> {
     }
 }
 
+/// Source types that can be left out of the build. Each is provided by the cargo
+/// feature of the same name.
+pub(crate) const OPTIONAL_SOURCE_TYPES: &[&str] = &["docker", "mdns"];
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub(crate) enum SourceType {
+    Api,
     File,
     Dhcp,
     Docker,
+    Exec,
+    Failover,
+    HomeAssistant,
+    Http,
+    #[serde(rename = "self")]
+    Interfaces,
+    Libvirt,
+    Mdns,
+    Mesh,
+    Pihole,
     Remote,
+    Tailscale,
     Traefik,
 }
 
@@ -118,7 +234,19 @@ impl fmt::Display for SourceId {
 pub(crate) struct SourceRecords {
     pub(crate) source_id: SourceId,
     pub(crate) timestamp: DateTime<Utc>,
+    /// How many servers the records have been replicated through since leaving
+    /// the server that owns the source.
+    #[serde(default)]
+    pub(crate) hops: u32,
+    /// How long the records remain valid if the source doesn't report them
+    /// again. Remote servers expire the records of their own sources.
+    #[serde(skip)]
+    pub(crate) lease: Option<Duration>,
     pub(crate) records: RecordSet,
+    /// The records as the source reported them when record overrides changed
+    /// them, so that changed overrides can be applied straight away.
+    #[serde(skip)]
+    pub(crate) reported: Option<RecordSet>,
 }
 
 impl SourceRecords {
@@ -130,13 +258,174 @@ impl SourceRecords {
         Self {
             source_id: source_id.clone(),
             timestamp: timestamp.unwrap_or_else(Utc::now),
+            hops: 0,
+            lease: None,
             records,
+            reported: None,
         }
     }
+
+    pub(crate) fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = Some(lease);
+        self
+    }
+
+    /// Whether the lease on the records ran out before `now`.
+    pub(crate) fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.lease
+            .and_then(|lease| TimeDelta::from_std(lease).ok())
+            .is_some_and(|lease| self.timestamp + lease < now)
+    }
+
+    /// Applies the source's record overrides to the records it reported.
+    /// `previous` is what the source last reported if the overrides haven't
+    /// changed since.
+    pub(crate) fn apply_overrides(
+        &mut self,
+        overrides: Option<&RecordOverrides>,
+        previous: Option<&RecordSet>,
+    ) {
+        let reported = self
+            .reported
+            .take()
+            .unwrap_or_else(|| mem::take(&mut self.records));
+
+        match overrides.filter(|overrides| !overrides.is_empty()) {
+            Some(overrides) => {
+                self.records = overrides.apply(&self.source_id, reported.clone(), previous);
+                self.reported = Some(reported);
+            }
+            None => self.records = reported,
+        }
+    }
+
+    /// Drops any invalid records.
+    pub(crate) fn validate(&mut self) {
+        let source_id = &self.source_id;
+        self.records = mem::take(&mut self.records).validated(|record, error| {
+            tracing::warn!(
+                source = %source_id,
+                record = ?record,
+                error = %error,
+                "Skipping invalid record",
+            );
+        });
+    }
+}
+
+/// Whether a source is running. Sources that fail to start are retried a few
+/// times before giving up until the configuration changes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub(crate) enum SourceStatus {
+    #[default]
+    Ok,
+    Retrying {
+        /// How many attempts to start the source have failed.
+        attempts: u32,
+        retry_at: DateTime<Utc>,
+    },
+    Failed,
+}
+
+/// An address family that a source can be limited to publishing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
+
+/// Settings available to most sources that adjust the records they publish.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct RecordOverrides {
+    /// The TTL of records that the source doesn't give one.
+    #[serde(default)]
+    pub(crate) ttl: Option<u32>,
+    /// Only address records of this family are published.
+    #[serde(default)]
+    pub(crate) address_family: Option<AddressFamily>,
+    /// Only names within these zones are published.
+    #[serde(default)]
+    pub(crate) allowed_zones: Vec<Fqdn>,
+}
+
+impl RecordOverrides {
+    /// Whether the overrides leave records unchanged.
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether the record is for a name in one of the allowed zones. PTR records
+    /// are checked by the name that they point at.
+    fn is_allowed(&self, record: &Record) -> bool {
+        if self.allowed_zones.is_empty() {
+            return true;
+        }
+
+        let name = match record.rdata() {
+            RData::Ptr(target) => target,
+            _ => record.name(),
+        };
+
+        self.allowed_zones.iter().any(|zone| zone.zone_of(name))
+    }
+
+    /// Adjusts the records that a source reported. Records outside of the
+    /// allowed zones are only warned about if they weren't in the `previous`
+    /// records that the source reported with the same overrides.
+    pub(crate) fn apply(
+        &self,
+        source_id: &SourceId,
+        records: RecordSet,
+        previous: Option<&RecordSet>,
+    ) -> RecordSet {
+        if self.is_empty() {
+            return records;
+        }
+
+        records
+            .into_iter()
+            .filter(|record| match (self.address_family, record.rdata()) {
+                (Some(AddressFamily::Ipv4), RData::Aaaa(_)) => false,
+                (Some(AddressFamily::Ipv6), RData::A(_)) => false,
+                _ => true,
+            })
+            .filter(|record| {
+                let allowed = self.is_allowed(record);
+                if allowed {
+                    return true;
+                }
+
+                if previous.is_some_and(|previous| previous.contains(record.name(), record.rdata()))
+                {
+                    tracing::debug!(
+                        %source_id,
+                        name = %record.name(),
+                        "Ignoring record outside of the source's allowed zones",
+                    );
+                } else {
+                    tracing::warn!(
+                        %source_id,
+                        name = %record.name(),
+                        "Record is outside of the source's allowed zones, ignoring it",
+                    );
+                }
+                false
+            })
+            .map(|mut record| {
+                if record.ttl.is_none() {
+                    record.ttl = self.ttl;
+                }
+                record
+            })
+            .collect()
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Default, Deserialize)]
 pub(crate) struct SourcesConfig {
+    #[cfg(feature = "docker")]
     #[serde(default)]
     pub(crate) docker: HashMap<String, docker::DockerConfig>,
 
@@ -149,8 +438,138 @@ pub(crate) struct SourcesConfig {
     #[serde(default)]
     pub(crate) file: HashMap<String, file::FileConfig>,
 
+    /// Filled in from the API configuration rather than this section.
+    #[serde(skip)]
+    pub(crate) api: HashMap<String, file::ApiRecordsConfig>,
+
     #[serde(default)]
     pub remote: HashMap<String, remote::RemoteConfig>,
+
+    /// Filled in from the mesh configuration rather than this section.
+    #[serde(skip)]
+    pub(crate) mesh: HashMap<String, mesh::MeshConfig>,
+
+    #[serde(default)]
+    pub(crate) tailscale: HashMap<String, tailscale::TailscaleConfig>,
+
+    #[serde(default)]
+    pub(crate) libvirt: HashMap<String, libvirt::LibvirtConfig>,
+
+    #[serde(default)]
+    pub(crate) homeassistant: HashMap<String, homeassistant::HomeAssistantConfig>,
+
+    #[serde(default)]
+    pub(crate) failover: HashMap<String, failover::FailoverConfig>,
+
+    #[serde(default)]
+    pub(crate) exec: HashMap<String, exec::ExecConfig>,
+
+    #[serde(default)]
+    pub(crate) http: HashMap<String, http::HttpConfig>,
+
+    #[serde(default)]
+    pub(crate) pihole: HashMap<String, pihole::PiholeConfig>,
+
+    #[cfg(feature = "mdns")]
+    #[serde(default)]
+    pub(crate) mdns: HashMap<String, mdns::MdnsConfig>,
+
+    #[serde(default, rename = "self")]
+    pub(crate) interfaces: HashMap<String, interfaces::InterfacesConfig>,
+}
+
+impl SourcesConfig {
+    /// The ids of the configured sources that publish records under their own id.
+    /// Remote sources instead publish the records of the remote server's sources.
+    pub(crate) fn local_source_ids(&self, server_id: &ServerId) -> HashSet<SourceId> {
+        fn ids<'a, C: SourceConfig>(
+            server_id: &'a ServerId,
+            sources: &'a HashMap<String, C>,
+        ) -> impl Iterator<Item = SourceId> + 'a {
+            sources
+                .keys()
+                .map(move |name| SourceId::new(server_id, C::source_type(), name))
+        }
+
+        let mut source_ids = HashSet::new();
+        source_ids.extend(ids(server_id, &self.dhcp));
+        source_ids.extend(ids(server_id, &self.file));
+        source_ids.extend(ids(server_id, &self.api));
+        source_ids.extend(ids(server_id, &self.tailscale));
+        source_ids.extend(ids(server_id, &self.libvirt));
+        source_ids.extend(ids(server_id, &self.homeassistant));
+        source_ids.extend(ids(server_id, &self.interfaces));
+        source_ids.extend(ids(server_id, &self.traefik));
+        source_ids.extend(ids(server_id, &self.failover));
+        source_ids.extend(ids(server_id, &self.exec));
+        source_ids.extend(ids(server_id, &self.http));
+        source_ids.extend(ids(server_id, &self.pihole));
+
+        #[cfg(feature = "mdns")]
+        source_ids.extend(ids(server_id, &self.mdns));
+        #[cfg(feature = "docker")]
+        source_ids.extend(ids(server_id, &self.docker));
+
+        source_ids
+    }
+
+    /// The record overrides configured for one of this server's sources.
+    pub(crate) fn overrides(&self, source_id: &SourceId) -> Option<&RecordOverrides> {
+        fn find<'a, C: SourceConfig>(
+            sources: &'a HashMap<String, C>,
+            source_id: &SourceId,
+        ) -> Option<&'a RecordOverrides> {
+            sources.get(&source_id.source_name)?.overrides()
+        }
+
+        match source_id.source_type {
+            SourceType::Dhcp => find(&self.dhcp, source_id),
+            #[cfg(feature = "docker")]
+            SourceType::Docker => find(&self.docker, source_id),
+            SourceType::Exec => find(&self.exec, source_id),
+            SourceType::Failover => find(&self.failover, source_id),
+            SourceType::HomeAssistant => find(&self.homeassistant, source_id),
+            SourceType::Http => find(&self.http, source_id),
+            SourceType::Interfaces => find(&self.interfaces, source_id),
+            SourceType::Libvirt => find(&self.libvirt, source_id),
+            #[cfg(feature = "mdns")]
+            SourceType::Mdns => find(&self.mdns, source_id),
+            SourceType::Pihole => find(&self.pihole, source_id),
+            SourceType::Tailscale => find(&self.tailscale, source_id),
+            SourceType::Traefik => find(&self.traefik, source_id),
+            _ => None,
+        }
+    }
+
+    /// The local files that the configured sources need, along with the location
+    /// of the source in the configuration file.
+    pub(crate) fn required_files(&self) -> Vec<(String, PathBuf)> {
+        fn files<C: SourceConfig>(
+            sources: &HashMap<String, C>,
+            required_files: &mut Vec<(String, PathBuf)>,
+        ) {
+            for (name, config) in sources {
+                let location = format!("sources.{}.{name}", C::source_type());
+                required_files.extend(
+                    config
+                        .required_files()
+                        .into_iter()
+                        .map(|path| (location.clone(), path)),
+                );
+            }
+        }
+
+        let mut required_files = Vec::new();
+        #[cfg(feature = "docker")]
+        files(&self.docker, &mut required_files);
+        files(&self.dhcp, &mut required_files);
+        files(&self.file, &mut required_files);
+        files(&self.remote, &mut required_files);
+        files(&self.http, &mut required_files);
+
+        required_files.sort();
+        required_files
+    }
 }
 
 pub(crate) struct Sources<S: RecordServer> {
@@ -170,6 +589,10 @@ impl<S: RecordServer> Sources<S> {
         self.server_id
     }
 
+    pub(crate) fn local_source_ids(&self, config: &SourcesConfig) -> HashSet<SourceId> {
+        config.local_source_ids(&self.server_id)
+    }
+
     async fn list_sources<C>(
         &mut self,
         sources: &HashMap<String, C>,
@@ -197,18 +620,47 @@ impl<S: RecordServer> Sources<S> {
             let previous = old_sources.and_then(|c| c.get(&name));
 
             if Some(&source_config) != previous {
-                let _guard = server.start_batch_update().await;
+                // Sources with a different poll interval or record overrides keep
+                // running with the records they have.
+                if let Some(old) = previous.filter(|old| source_config.updates_in_place(old)) {
+                    match (self.sources.get(&source_id), source_config.interval_ms()) {
+                        (Some(SourceHandle::Polling(_, poll_interval)), Some(interval)) => {
+                            tracing::debug!(
+                                source = %source_id,
+                                interval_ms = interval,
+                                "Updating source in place",
+                            );
+                            poll_interval.set(interval);
+                            continue;
+                        }
+                        (Some(_), interval) if interval == old.interval_ms() => {
+                            tracing::debug!(source = %source_id, "Updating source in place");
+                            continue;
+                        }
+                        _ => {}
+                    }
+                }
 
                 if let Some(handle) = self.sources.remove(&source_id) {
                     handle.drop().await;
                 }
 
-                match source_config.spawn(source_id.clone(), server).await {
+                // Boxed as otherwise every source type's future ends up inlined in
+                // install_sources making it large enough to overflow the stack.
+                match Box::pin(source_config.clone().spawn(source_id.clone(), server)).await {
                     Ok(handle) => {
+                        server.source_status(&source_id, SourceStatus::Ok).await;
                         self.sources.insert(source_id, handle);
                     }
                     Err(e) => {
-                        tracing::error!(source = %source_id, error = %e, "Failed adding source")
+                        tracing::error!(source = %source_id, error = %e, "Failed adding source");
+                        server
+                            .source_failed(&source_id, format!("Failed adding source: {e}"))
+                            .await;
+
+                        let supervisor =
+                            supervise::Supervisor::start(source_config, source_id.clone(), server);
+                        self.sources.insert(source_id, supervisor.into());
                     }
                 }
             }
@@ -221,22 +673,48 @@ impl<S: RecordServer> Sources<S> {
         config: Config,
         old_config: Option<&Config>,
     ) {
+        // All changes are made as a single batch so the DNS server never sees the
+        // partial state where some sources have been removed or not yet added.
+        let _guard = server.start_batch_update().await;
+
         {
             // First enumerate the configured sources and drop those that are no longer present.
-            let _guard = server.start_batch_update().await;
-
             let mut seen_sources: HashSet<SourceId> = HashSet::new();
 
             self.list_sources(&config.sources.dhcp, &mut seen_sources)
                 .await;
             self.list_sources(&config.sources.file, &mut seen_sources)
                 .await;
+            self.list_sources(&config.sources.api, &mut seen_sources)
+                .await;
+            self.list_sources(&config.sources.tailscale, &mut seen_sources)
+                .await;
+            self.list_sources(&config.sources.libvirt, &mut seen_sources)
+                .await;
+            #[cfg(feature = "mdns")]
+            self.list_sources(&config.sources.mdns, &mut seen_sources)
+                .await;
+            self.list_sources(&config.sources.interfaces, &mut seen_sources)
+                .await;
+            #[cfg(feature = "docker")]
             self.list_sources(&config.sources.docker, &mut seen_sources)
                 .await;
             self.list_sources(&config.sources.traefik, &mut seen_sources)
                 .await;
+            self.list_sources(&config.sources.homeassistant, &mut seen_sources)
+                .await;
+            self.list_sources(&config.sources.failover, &mut seen_sources)
+                .await;
+            self.list_sources(&config.sources.exec, &mut seen_sources)
+                .await;
+            self.list_sources(&config.sources.http, &mut seen_sources)
+                .await;
+            self.list_sources(&config.sources.pihole, &mut seen_sources)
+                .await;
             self.list_sources(&config.sources.remote, &mut seen_sources)
                 .await;
+            self.list_sources(&config.sources.mesh, &mut seen_sources)
+                .await;
 
             let all = self.sources.keys().cloned().collect::<HashSet<SourceId>>();
             for old in all.difference(&seen_sources) {
@@ -266,7 +744,49 @@ impl<S: RecordServer> Sources<S> {
         )
         .await;
 
+        // As are records added through the API.
+        self.spawn_sources(
+            config.sources.api,
+            old_config.map(|c| &c.sources.api),
+            server,
+        )
+        .await;
+
+        // Tailscale talks to a local socket so needs no resolution.
+        self.spawn_sources(
+            config.sources.tailscale,
+            old_config.map(|c| &c.sources.tailscale),
+            server,
+        )
+        .await;
+
+        // libvirt is queried through virsh which resolves any remote hosts itself.
+        self.spawn_sources(
+            config.sources.libvirt,
+            old_config.map(|c| &c.sources.libvirt),
+            server,
+        )
+        .await;
+
+        // mDNS browses the local network directly so needs no resolution.
+        #[cfg(feature = "mdns")]
+        self.spawn_sources(
+            config.sources.mdns,
+            old_config.map(|c| &c.sources.mdns),
+            server,
+        )
+        .await;
+
+        // Interface addresses are read from the local machine.
+        self.spawn_sources(
+            config.sources.interfaces,
+            old_config.map(|c| &c.sources.interfaces),
+            server,
+        )
+        .await;
+
         // Docker hostname may depend on DHCP records above.
+        #[cfg(feature = "docker")]
         self.spawn_sources(
             config.sources.docker,
             old_config.map(|c| &c.sources.docker),
@@ -282,6 +802,46 @@ impl<S: RecordServer> Sources<S> {
         )
         .await;
 
+        // Home Assistant often runs in docker or has a DHCP lease.
+        self.spawn_sources(
+            config.sources.homeassistant,
+            old_config.map(|c| &c.sources.homeassistant),
+            server,
+        )
+        .await;
+
+        // Pi-hole often runs in docker or has a DHCP lease.
+        self.spawn_sources(
+            config.sources.pihole,
+            old_config.map(|c| &c.sources.pihole),
+            server,
+        )
+        .await;
+
+        // Health checks connect to addresses directly.
+        self.spawn_sources(
+            config.sources.failover,
+            old_config.map(|c| &c.sources.failover),
+            server,
+        )
+        .await;
+
+        // Commands may look up any name themselves.
+        self.spawn_sources(
+            config.sources.exec,
+            old_config.map(|c| &c.sources.exec),
+            server,
+        )
+        .await;
+
+        // Like remote servers, HTTP hostnames may depend on anything.
+        self.spawn_sources(
+            config.sources.http,
+            old_config.map(|c| &c.sources.http),
+            server,
+        )
+        .await;
+
         // Remote hostname may depend on anything.
         self.spawn_sources(
             config.sources.remote,
@@ -289,6 +849,14 @@ impl<S: RecordServer> Sources<S> {
             server,
         )
         .await;
+
+        // As may the hostnames of mesh peers.
+        self.spawn_sources(
+            config.sources.mesh,
+            old_config.map(|c| &c.sources.mesh),
+            server,
+        )
+        .await;
     }
 
     pub(crate) async fn shutdown(&mut self) {
@@ -303,11 +871,12 @@ mod tests {
     use std::{net::Ipv4Addr, str::FromStr};
 
     use tempfile::TempDir;
+    use uuid::Uuid;
 
     use crate::{
         config::Config,
-        dns::RData,
-        sources::{SourceId, SourceType, Sources},
+        dns::{RData, Record, RecordSet},
+        sources::{AddressFamily, RecordOverrides, SourceId, SourceType, Sources},
         test::{fqdn, name, write_file, MultiSourceServer},
     };
 
@@ -493,4 +1062,214 @@ sources:
         assert!(state.is_empty());
         assert!(sources.sources.is_empty());
     }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn single_snapshot() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+
+        write_file(
+            &temp.path().join("zone1.yml"),
+            "www.test.local: 10.45.23.56",
+        )
+        .await;
+        write_file(
+            &temp.path().join("zone2.yml"),
+            "www.other.local: 10.45.23.57",
+        )
+        .await;
+        write_file(
+            &temp.path().join("zone3.yml"),
+            "www.third.local: 10.45.23.58",
+        )
+        .await;
+
+        write_file(
+            &config_file,
+            r#"
+sources:
+  file:
+    file1: zone1.yml
+    file2: zone2.yml
+"#,
+        )
+        .await;
+
+        let mut sources = Sources::new();
+        let mut test_server = MultiSourceServer::new();
+
+        let config_1 = Config::from_file(&config_file).unwrap();
+        sources
+            .install_sources(&test_server, config_1.clone(), None)
+            .await;
+
+        // The very first update seen must already include every source.
+        let state = test_server.wait_for_change().await;
+        assert_eq!(state.len(), 2);
+
+        write_file(
+            &config_file,
+            r#"
+sources:
+  file:
+    file1: zone1.yml
+    file3: zone3.yml
+"#,
+        )
+        .await;
+
+        let config_2 = Config::from_file(&config_file).unwrap();
+        sources
+            .install_sources(&test_server, config_2.clone(), Some(&config_1))
+            .await;
+
+        let state = test_server.wait_for_change().await;
+        assert_eq!(state.len(), 2);
+        assert!(state.contains_key(&SourceId::new(
+            &sources.server_id,
+            SourceType::File,
+            "file3"
+        )));
+        assert!(!state.contains_key(&SourceId::new(
+            &sources.server_id,
+            SourceType::File,
+            "file2"
+        )));
+
+        sources.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn record_overrides() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+sources:
+  dhcp:
+    leases:
+      lease_file: dnsmasq.leases
+      zone: home.local
+      ttl: 60
+      address_family: ipv4
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::Dhcp, "leases");
+        let overrides = config.sources.overrides(&source_id).unwrap();
+        assert_eq!(
+            *overrides,
+            RecordOverrides {
+                ttl: Some(60),
+                address_family: Some(AddressFamily::Ipv4),
+                allowed_zones: Vec::new(),
+            }
+        );
+
+        let mut timed = Record::new(
+            fqdn("timed.home.local."),
+            RData::A(Ipv4Addr::from_str("10.10.1.6").unwrap()),
+        );
+        timed.ttl = Some(10);
+
+        let records = overrides.apply(
+            &source_id,
+            RecordSet::from(vec![
+                Record::new(
+                    fqdn("www.home.local."),
+                    RData::A(Ipv4Addr::from_str("10.10.1.5").unwrap()),
+                ),
+                Record::new(
+                    fqdn("www.home.local."),
+                    RData::Aaaa("fd00::5".parse().unwrap()),
+                ),
+                timed,
+            ]),
+            None,
+        );
+
+        let mut ttls: Vec<(String, Option<u32>)> = records
+            .records()
+            .map(|record| (record.name().to_string(), record.ttl))
+            .collect();
+        ttls.sort();
+        assert_eq!(
+            ttls,
+            vec![
+                ("timed.home.local.".to_owned(), Some(10)),
+                ("www.home.local.".to_owned(), Some(60)),
+            ]
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn allowed_zones() {
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.yml");
+        write_file(
+            &config_file,
+            r#"
+sources:
+  traefik:
+    proxy:
+      url: http://traefik.home.local:8080/
+      address: 10.10.1.5
+      allowed_zones:
+        - apps.home.local
+"#,
+        )
+        .await;
+
+        let config = Config::from_file(&config_file).unwrap();
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::Traefik, "proxy");
+        let overrides = config.sources.overrides(&source_id).unwrap();
+        assert_eq!(overrides.allowed_zones, vec![fqdn("apps.home.local.")]);
+
+        let reported = RecordSet::builder()
+            .a("wiki.apps.home.local", "10.10.1.5")
+            .a("router.home.local", "10.10.1.5")
+            .ptr("6.1.10.10.in-addr.arpa", "router.home.local")
+            .ptr("7.1.10.10.in-addr.arpa", "git.apps.home.local")
+            .build();
+        let records = overrides.apply(&source_id, reported.clone(), None);
+
+        let mut names: Vec<String> = records
+            .records()
+            .map(|record| record.name().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "7.1.10.10.in-addr.arpa.".to_owned(),
+                "wiki.apps.home.local.".to_owned(),
+            ]
+        );
+        assert!(logs_contain(
+            "Record is outside of the source's allowed zones"
+        ));
+
+        // Records that were already ignored aren't warned about again.
+        let records = overrides.apply(&source_id, reported.clone(), Some(&reported));
+        assert_eq!(records.len(), 2);
+        logs_assert(|lines: &[&str]| {
+            let warnings = lines
+                .iter()
+                .filter(|line| line.contains("Record is outside of the source's allowed zones"))
+                .count();
+            match warnings {
+                2 => Ok(()),
+                n => Err(format!("Expected 2 warnings, saw {n}")),
+            }
+        });
+        assert!(logs_contain(
+            "Ignoring record outside of the source's allowed zones"
+        ));
+    }
 }
@@ -0,0 +1,457 @@
+use std::net::IpAddr;
+
+use anyhow::{anyhow, Context};
+use reqwest::{Client, RequestBuilder, Url};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::time::sleep;
+use tracing::instrument;
+
+use crate::{
+    config::deserialize_url,
+    dns::{Fqdn, RData, Record, RecordSet},
+    run_loop::{LoopResult, PollInterval},
+    sources::{spawn_polling, RecordOverrides, SourceConfig, SourceHandle, SourceId, SourceType},
+    util::{Bind, HostAddresses},
+    Error, RecordServer,
+};
+
+const POLL_INTERVAL_MS: u64 = 60000;
+
+/// The header that authenticates requests to Pi-hole's API.
+const SESSION_HEADER: &str = "X-FTL-SID";
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Clone)]
+pub(crate) struct PiholeConfig {
+    #[serde(deserialize_with = "deserialize_url")]
+    url: Url,
+    /// The web interface or app password, not needed if Pi-hole has none set.
+    #[serde(default)]
+    password: Option<String>,
+    /// The zone for names that Pi-hole was given without a domain.
+    #[serde(default)]
+    zone: Option<Fqdn>,
+    #[serde(default)]
+    interval_ms: Option<u64>,
+    #[serde(default)]
+    bind: Option<Bind>,
+    #[serde(flatten)]
+    overrides: RecordOverrides,
+}
+
+impl PiholeConfig {
+    fn name(&self, name: &str) -> Result<Fqdn, Error> {
+        match &self.zone {
+            Some(zone) if !name.trim_end_matches('.').contains('.') => Ok(zone.child(name)?),
+            _ => Ok(Fqdn::try_from(name)?),
+        }
+    }
+
+    /// The URL of an API endpoint. The API lives beside the web interface so
+    /// the web interface's `/admin/` address is accepted too.
+    fn api_url(&self, endpoint: &str) -> Result<Url, Error> {
+        let path = self.url.path().trim_end_matches('/');
+        let path = path.strip_suffix("/admin").unwrap_or(path);
+
+        let mut base = self.url.clone();
+        base.set_path(&format!("{path}/"));
+
+        Ok(base.join(endpoint)?)
+    }
+}
+
+#[derive(Deserialize)]
+struct Session {
+    sid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AuthResponse {
+    session: Session,
+}
+
+/// A logged in session with Pi-hole's API, if it requires one.
+struct PiholeApi<'a> {
+    client: &'a Client,
+    config: &'a PiholeConfig,
+    sid: Option<String>,
+}
+
+impl<'a> PiholeApi<'a> {
+    async fn login(client: &'a Client, config: &'a PiholeConfig) -> Result<Self, Error> {
+        let sid = match &config.password {
+            Some(password) => {
+                let response: AuthResponse = client
+                    .post(config.api_url("api/auth")?)
+                    .json(&json!({ "password": password }))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+
+                response.session.sid
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            client,
+            config,
+            sid,
+        })
+    }
+
+    fn authenticate(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.sid {
+            Some(sid) => request.header(SESSION_HEADER, sid),
+            None => request,
+        }
+    }
+
+    /// Fetches one of the lists from Pi-hole's DNS configuration.
+    async fn dns_config(&self, setting: &str) -> Result<Vec<String>, Error> {
+        let response: Value = self
+            .authenticate(
+                self.client
+                    .get(self.config.api_url(&format!("api/config/dns/{setting}"))?),
+            )
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let entries = response
+            .pointer(&format!("/config/dns/{setting}"))
+            .cloned()
+            .ok_or_else(|| anyhow!("Missing {setting} in response"))?;
+
+        Ok(serde_json::from_value(entries)?)
+    }
+
+    /// Ends the session, Pi-hole only allows a few at once.
+    async fn logout(self) {
+        if self.sid.is_none() {
+            return;
+        }
+
+        let Ok(target) = self.config.api_url("api/auth") else {
+            return;
+        };
+
+        if let Err(e) = self.authenticate(self.client.delete(target)).send().await {
+            tracing::debug!(error = %e, "Failed to log out of Pi-hole");
+        }
+    }
+}
+
+/// Parses Pi-hole's local DNS records, which are in hosts file form, and local
+/// CNAME records, written as `name,target[,ttl]`.
+fn generate_records(config: &PiholeConfig, hosts: &[String], cnames: &[String]) -> RecordSet {
+    let mut records = RecordSet::new();
+
+    for line in hosts {
+        let mut parts = line.split_whitespace();
+        let Some(ip) = parts.next().and_then(|ip| ip.parse::<IpAddr>().ok()) else {
+            tracing::warn!(line, "Invalid local DNS record");
+            continue;
+        };
+
+        for name in parts {
+            match config.name(name) {
+                Ok(name) => records.insert(Record::new(name, RData::from(ip))),
+                Err(e) => tracing::warn!(error = %e, name, "Invalid local DNS name"),
+            }
+        }
+    }
+
+    for line in cnames {
+        let mut parts = line.split(',').map(str::trim);
+        let (Some(name), Some(target)) = (parts.next(), parts.next()) else {
+            tracing::warn!(line, "Invalid local CNAME record");
+            continue;
+        };
+
+        let record = config.name(name).and_then(|name| {
+            let mut record = Record::new(name, RData::Cname(config.name(target)?));
+            record.ttl = parts.next().and_then(|ttl| ttl.parse().ok());
+            Ok(record)
+        });
+
+        match record {
+            Ok(record) => records.insert(record),
+            Err(e) => tracing::warn!(error = %e, line, "Invalid local CNAME record"),
+        }
+    }
+
+    records
+}
+
+#[instrument(fields(%source_id, url = %config.url), skip(client, config))]
+async fn fetch_records(
+    source_id: &SourceId,
+    client: &Client,
+    config: &PiholeConfig,
+) -> Result<RecordSet, Error> {
+    let api = PiholeApi::login(client, config)
+        .await
+        .context("Failed to log in to Pi-hole")?;
+
+    let lists = async {
+        let hosts = api.dns_config("hosts").await?;
+        let cnames = api.dns_config("cnameRecords").await?;
+        Ok::<_, Error>((hosts, cnames))
+    }
+    .await;
+
+    api.logout().await;
+
+    let (hosts, cnames) = lists?;
+    Ok(generate_records(config, &hosts, &cnames))
+}
+
+async fn pihole_loop<S: RecordServer>(
+    server: S,
+    source_id: SourceId,
+    config: PiholeConfig,
+    interval: PollInterval,
+) -> LoopResult {
+    let mut client = server.http_client(config.bind.as_ref(), None);
+    let mut host = HostAddresses::new(&config.url);
+    host.changed(&server).await;
+
+    loop {
+        if host.changed(&server).await {
+            tracing::info!(%source_id, "Pi-hole host address changed, reconnecting");
+            client = server.reconnect_http_client(config.bind.as_ref(), None);
+        }
+
+        let records = match fetch_records(&source_id, &client, &config).await {
+            Ok(records) => records,
+            Err(e) => {
+                tracing::error!(%source_id, error = %e, "Failed to fetch records from Pi-hole");
+                return LoopResult::Backoff(format!("Failed to fetch records from Pi-hole: {e}"));
+            }
+        };
+
+        server
+            .add_source_records(interval.records(&source_id, records))
+            .await;
+
+        sleep(interval.duration()).await;
+    }
+}
+
+impl SourceConfig for PiholeConfig {
+    fn source_type() -> SourceType {
+        SourceType::Pihole
+    }
+
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        Some(&self.overrides)
+    }
+
+    fn interval_ms(&self) -> Option<u64> {
+        Some(self.interval_ms.unwrap_or(POLL_INTERVAL_MS))
+    }
+
+    fn without_live_settings(&self) -> Self {
+        Self {
+            interval_ms: None,
+            overrides: Default::default(),
+            ..self.clone()
+        }
+    }
+
+    #[instrument(fields(%source_id), skip(self, server))]
+    async fn spawn<S: RecordServer>(
+        self,
+        source_id: SourceId,
+        server: &S,
+    ) -> Result<SourceHandle<S>, Error> {
+        tracing::trace!("Adding source");
+
+        spawn_polling(self, source_id, server, pihole_loop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    use crate::{
+        dns::RData,
+        sources::{pihole::PiholeConfig, SourceConfig, SourceId},
+        test::{fqdn, name, SingleSourceServer},
+    };
+
+    fn config(url: &str, password: Option<&str>) -> PiholeConfig {
+        PiholeConfig {
+            url: url.parse().unwrap(),
+            password: password.map(str::to_owned),
+            zone: Some(fqdn("home.local")),
+            interval_ms: Some(100),
+            bind: None,
+            overrides: Default::default(),
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn api_url() {
+        for url in [
+            "http://pi.hole",
+            "http://pi.hole/",
+            "http://pi.hole/admin",
+            "http://pi.hole/admin/",
+        ] {
+            assert_eq!(
+                config(url, None).api_url("api/auth").unwrap().as_str(),
+                "http://pi.hole/api/auth"
+            );
+        }
+
+        assert_eq!(
+            config("http://proxy.home.local/pihole/admin/", None)
+                .api_url("api/auth")
+                .unwrap()
+                .as_str(),
+            "http://proxy.home.local/pihole/api/auth"
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn generate_records() {
+        let hosts = vec![
+            "10.10.4.6 nas.home.local storage".to_owned(),
+            "fd00::4:6 nas.home.local".to_owned(),
+            "not-an-address printer".to_owned(),
+        ];
+        let cnames = vec![
+            "files.home.local,nas.home.local,300".to_owned(),
+            "backup,nas".to_owned(),
+            "invalid".to_owned(),
+        ];
+
+        let records = super::generate_records(&config("http://pi.hole/", None), &hosts, &cnames);
+
+        assert_eq!(records.len(), 5);
+        assert!(records.contains(
+            &fqdn("nas.home.local"),
+            &RData::A("10.10.4.6".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("nas.home.local"),
+            &RData::Aaaa("fd00::4:6".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("storage.home.local"),
+            &RData::A("10.10.4.6".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("files.home.local"),
+            &RData::Cname(fqdn("nas.home.local"))
+        ));
+        assert!(records.contains(
+            &fqdn("backup.home.local"),
+            &RData::Cname(fqdn("nas.home.local"))
+        ));
+        assert!(!records.has_name(&name("printer.home.local.")));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mock_api() {
+        let logouts = web::Data::new(AtomicUsize::new(0));
+        let server_logouts = logouts.clone();
+
+        let server = HttpServer::new(move || {
+            let authorized = |request: &HttpRequest| {
+                request
+                    .headers()
+                    .get("X-FTL-SID")
+                    .and_then(|value| value.to_str().ok())
+                    == Some("session")
+            };
+
+            App::new()
+                .app_data(server_logouts.clone())
+                .route(
+                    "/api/auth",
+                    web::post().to(|body: web::Json<Value>| async move {
+                        if body["password"] == "secret" {
+                            HttpResponse::Ok()
+                                .json(json!({ "session": { "valid": true, "sid": "session" } }))
+                        } else {
+                            HttpResponse::Unauthorized().finish()
+                        }
+                    }),
+                )
+                .route(
+                    "/api/auth",
+                    web::delete().to(|logouts: web::Data<AtomicUsize>| async move {
+                        logouts.fetch_add(1, Ordering::SeqCst);
+                        HttpResponse::NoContent().finish()
+                    }),
+                )
+                .route(
+                    "/api/config/dns/hosts",
+                    web::get().to(move |request: HttpRequest| async move {
+                        if authorized(&request) {
+                            HttpResponse::Ok().json(
+                                json!({ "config": { "dns": { "hosts": ["10.10.4.6 nas"] } } }),
+                            )
+                        } else {
+                            HttpResponse::Unauthorized().finish()
+                        }
+                    }),
+                )
+                .route(
+                    "/api/config/dns/cnameRecords",
+                    web::get().to(move |request: HttpRequest| async move {
+                        if authorized(&request) {
+                            HttpResponse::Ok().json(json!({
+                                "config": { "dns": { "cnameRecords": ["files,nas"] } }
+                            }))
+                        } else {
+                            HttpResponse::Unauthorized().finish()
+                        }
+                    }),
+                )
+        })
+        .disable_signals()
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+
+        let port = server.addrs().first().unwrap().port();
+        let server = server.run();
+        let server_handle = server.handle();
+        tokio::spawn(server);
+
+        let source_id = SourceId::new(&Uuid::new_v4(), PiholeConfig::source_type(), "test");
+        let config = config(&format!("http://127.0.0.1:{port}/"), Some("secret"));
+
+        let mut test_server = SingleSourceServer::new(&source_id);
+        let handle = config.spawn(source_id.clone(), &test_server).await.unwrap();
+
+        let records = test_server
+            .wait_for_records(|records| records.has_name(&name("nas.home.local.")))
+            .await;
+        assert_eq!(records.len(), 2);
+        assert!(records.contains(
+            &fqdn("files.home.local"),
+            &RData::Cname(fqdn("nas.home.local"))
+        ));
+        assert!(logouts.load(Ordering::SeqCst) > 0);
+
+        handle.drop().await;
+        server_handle.stop(true).await;
+    }
+}
@@ -1,59 +1,306 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
 
+use anyhow::bail;
 use chrono::{DateTime, Utc};
-use reqwest::{Client, Url};
-use serde::{de::DeserializeOwned, Deserialize};
-use tokio::{sync::Mutex, task::JoinHandle, time::sleep};
+use reqwest::{Client, Response, StatusCode, Url};
+use serde::Deserialize;
+use tokio::{
+    sync::Mutex,
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
 use tracing::instrument;
+use uuid::Uuid;
 
 use crate::{
-    api::ApiRecords,
+    api::{ApiRecordChanges, ApiRecords},
     config::deserialize_url,
+    dns::{Fqdn, RData, Record},
     run_loop::{Backoff, LoopResult},
-    sources::{SourceConfig, SourceHandle, SourceId, SourceType},
+    sources::{SourceConfig, SourceHandle, SourceId, SourceRecords, SourceType},
+    util::{Bind, ClientTls, HostAddresses},
     Error, RecordServer,
 };
 
 const POLL_INTERVAL_MS: u64 = 15000;
-
-#[derive(Debug, PartialEq, Eq, Deserialize, Clone)]
+/// Records that have been replicated through this many servers are assumed to
+/// be going round a replication loop and are dropped.
+const MAX_HOPS: u32 = 8;
+/// How long to wait for anything on a record stream before giving up on the
+/// connection. The remote server sends keepalives well within this.
+const STREAM_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
 pub(crate) struct RemoteConfig {
     #[serde(deserialize_with = "deserialize_url")]
     url: Url,
     #[serde(default)]
     interval_ms: Option<u64>,
+    #[serde(default)]
+    bind: Option<Bind>,
+    #[serde(default)]
+    tls: Option<ClientTls>,
+    /// Only records in these zones are imported, when any are given.
+    #[serde(default)]
+    include_zones: Vec<Fqdn>,
+    #[serde(default)]
+    exclude_zones: Vec<Fqdn>,
+}
+
+impl RemoteConfig {
+    /// Replicates from a server discovered through the mesh.
+    pub(super) fn peer(url: Url, bind: Option<Bind>, tls: Option<ClientTls>) -> Self {
+        Self {
+            url,
+            interval_ms: None,
+            bind,
+            tls,
+            include_zones: Vec::new(),
+            exclude_zones: Vec::new(),
+        }
+    }
+
+    /// Whether a record from the remote server is in the zones this source
+    /// imports. Reverse records are judged by the name that they point to.
+    fn includes(&self, record: &Record) -> bool {
+        let name = match record.rdata() {
+            RData::Ptr(target) => target,
+            _ => record.name(),
+        };
+
+        (self.include_zones.is_empty() || self.include_zones.iter().any(|zone| zone.zone_of(name)))
+            && !self.exclude_zones.iter().any(|zone| zone.zone_of(name))
+    }
+}
+
+/// What came of polling the remote server.
+enum Fetched {
+    Records(ApiRecords),
+    Unchanged,
+    /// The remote server is too old to support the request.
+    Unsupported,
 }
 
+/// Polls for all of the remote server's records. Only servers too old to send
+/// changes are polled this way and they don't send an ETag, so every record is
+/// fetched each time.
 #[instrument(fields(%source_id, %base_url), skip(client))]
-async fn api_call<T>(
+async fn fetch_records(
     source_id: &SourceId,
     client: &Client,
     base_url: &Url,
-    method: &str,
-) -> Result<T, LoopResult>
-where
-    T: DeserializeOwned,
-{
-    let target = base_url.join(method).map_err(|e| {
+) -> Result<Fetched, LoopResult> {
+    let target = base_url.join("v2/records").map_err(|e| {
         tracing::error!("Unable to generate API URL: {}", e);
-        LoopResult::Quit
+        LoopResult::Quit(format!("Unable to generate API URL: {e}"))
     })?;
 
     match client.get(target).send().await {
-        Ok(response) => match response.json::<T>().await {
-            Ok(result) => Ok(result),
+        Ok(response) => match response.json::<ApiRecords>().await {
+            Ok(result) => Ok(Fetched::Records(result)),
             Err(e) => {
                 tracing::error!(error = %e, "Failed to parse response from server");
-                Err(LoopResult::Backoff)
+                Err(LoopResult::Backoff(format!(
+                    "Failed to parse response from server: {e}"
+                )))
             }
         },
         Err(e) => {
             tracing::error!(error = %e, "Failed to connect to server");
-            Err(LoopResult::Backoff)
+            Err(LoopResult::Backoff(format!(
+                "Failed to connect to server: {e}"
+            )))
+        }
+    }
+}
+
+/// A copy of the remote server's records, kept up to date from the changes
+/// that it sends so that unchanged records aren't fetched again.
+#[derive(Default)]
+struct Replica {
+    /// The epoch and generation of the remote server's records that the copy
+    /// matches.
+    position: Option<(Uuid, u64)>,
+    sources: HashMap<SourceId, SourceRecords>,
+}
+
+impl Replica {
+    /// Polls for the changes since the copy was last updated.
+    #[instrument(fields(%source_id, %base_url), skip(self, client))]
+    async fn fetch(
+        &mut self,
+        source_id: &SourceId,
+        client: &Client,
+        base_url: &Url,
+    ) -> Result<Fetched, LoopResult> {
+        let mut target = base_url.join("v3/records").map_err(|e| {
+            tracing::error!("Unable to generate API URL: {}", e);
+            LoopResult::Quit(format!("Unable to generate API URL: {e}"))
+        })?;
+
+        if let Some((epoch, generation)) = self.position {
+            target
+                .query_pairs_mut()
+                .append_pair("epoch", &epoch.to_string())
+                .append_pair("since", &generation.to_string());
         }
+
+        let response = client.get(target).send().await.map_err(|e| {
+            tracing::error!(error = %e, "Failed to connect to server");
+            LoopResult::Backoff(format!("Failed to connect to server: {e}"))
+        })?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Fetched::Unsupported);
+        }
+
+        let changes = response.json::<ApiRecordChanges>().await.map_err(|e| {
+            tracing::error!(error = %e, "Failed to parse response from server");
+            LoopResult::Backoff(format!("Failed to parse response from server: {e}"))
+        })?;
+
+        self.position = Some((changes.epoch, changes.generation));
+
+        if let Some(snapshot) = changes.snapshot {
+            tracing::trace!("Remote server sent all of its records");
+            self.sources = snapshot
+                .into_iter()
+                .map(|source| (source.source_id.clone(), source))
+                .collect();
+        } else if changes.changes.is_empty() {
+            return Ok(Fetched::Unchanged);
+        } else {
+            for change in changes.changes {
+                change.apply(&mut self.sources);
+            }
+        }
+
+        Ok(Fetched::Records(ApiRecords {
+            server_id: changes.server_id,
+            timestamp: Utc::now(),
+            source_records: self.sources.values().cloned().collect(),
+            next_offset: None,
+        }))
     }
 }
 
+/// The records pushed by a remote server as server-sent events.
+struct RecordStream {
+    response: Response,
+    buffer: Vec<u8>,
+}
+
+impl RecordStream {
+    /// Opens the stream, returns `None` for servers too old to support it.
+    async fn connect(client: &Client, base_url: &Url) -> Result<Option<Self>, Error> {
+        let response = client
+            .get(base_url.join("v2/records/stream")?)
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            response: response.error_for_status()?,
+            buffer: Vec::new(),
+        }))
+    }
+
+    /// Waits for the next records, returns `None` once the server closes the
+    /// stream.
+    async fn next(&mut self) -> Result<Option<ApiRecords>, Error> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+                let event: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                let data = String::from_utf8(event)?
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("data:"))
+                    .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                    .collect::<Vec<&str>>()
+                    .join("\n");
+
+                // Keepalives and other events without data are skipped.
+                if !data.is_empty() {
+                    return Ok(Some(serde_json::from_str(&data)?));
+                }
+
+                continue;
+            }
+
+            let Ok(chunk) = timeout(STREAM_TIMEOUT, self.response.chunk()).await else {
+                bail!("Timed out waiting for the remote server");
+            };
+
+            match chunk? {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Replaces the records previously seen from the remote server with new ones.
+async fn apply_records<S: RecordServer>(
+    server: &S,
+    source_id: &SourceId,
+    remote_config: &RemoteConfig,
+    mut api_records: ApiRecords,
+    previous_sources: &mut HashMap<SourceId, DateTime<Utc>>,
+    seen_sources: &Mutex<HashMap<SourceId, DateTime<Utc>>>,
+) {
+    // The remote server may pass back records that it got from this server.
+    // Those are ignored so that they can't outlive the local source, as are
+    // records that look to be going round a replication loop.
+    api_records.source_records.retain_mut(|source_records| {
+        source_records.hops += 1;
+
+        source_records.source_id.server_id != source_id.server_id && source_records.hops < MAX_HOPS
+    });
+
+    let mut record_count = 0;
+    let old_sources = std::mem::replace(
+        previous_sources,
+        api_records
+            .source_records
+            .iter()
+            .map(|sr| (sr.source_id.clone(), sr.timestamp))
+            .collect(),
+    );
+
+    {
+        let _guard = server.start_batch_update().await;
+        for (old_source, timestamp) in old_sources {
+            if !previous_sources.contains_key(&old_source) {
+                server.clear_source_records(&old_source, timestamp).await;
+            }
+        }
+
+        for mut source_records in api_records.source_records {
+            if !remote_config.include_zones.is_empty() || !remote_config.exclude_zones.is_empty() {
+                source_records.records = source_records
+                    .records
+                    .into_iter()
+                    .filter(|record| remote_config.includes(record))
+                    .collect();
+            }
+
+            record_count += source_records.records.len();
+
+            server.add_source_records(source_records).await;
+        }
+    }
+
+    seen_sources.lock().await.clone_from(previous_sources);
+
+    tracing::trace!(
+        %source_id,
+        record_count,
+        "Retrieved remote records",
+    );
+}
+
 async fn remote_loop<S: RecordServer>(
     server: S,
     source_id: SourceId,
@@ -68,78 +315,176 @@ async fn remote_loop<S: RecordServer>(
         "Attempting to connect to remote server",
     );
 
-    let client = server.http_client();
+    let mut client = server.http_client(remote_config.bind.as_ref(), remote_config.tls.as_ref());
+    let mut host = HostAddresses::new(&remote_config.url);
+    host.changed(&server).await;
 
     let mut previous_sources: HashMap<SourceId, DateTime<Utc>> = HashMap::new();
+    let mut streaming = true;
+    let mut replicating = true;
+    let mut replica = Replica::default();
 
     loop {
-        let api_records =
-            match api_call::<ApiRecords>(&source_id, &client, &remote_config.url, "v2/records")
-                .await
-            {
-                Ok(r) => {
+        if host.changed(&server).await {
+            tracing::info!(%source_id, "Remote host address changed, reconnecting");
+            client = server
+                .reconnect_http_client(remote_config.bind.as_ref(), remote_config.tls.as_ref());
+        }
+
+        // Changes are pushed over a stream for as long as it stays open. Polling
+        // below picks up from wherever the stream left off.
+        if streaming {
+            match RecordStream::connect(&client, &remote_config.url).await {
+                Ok(Some(mut stream)) => {
+                    tracing::trace!(%source_id, "Streaming records from remote server");
                     backoff.reset();
-                    r
-                }
-                Err(e) => {
-                    {
-                        let _guard = server.start_batch_update().await;
-                        for (source_id, timestamp) in previous_sources.drain() {
-                            server.clear_source_records(&source_id, timestamp).await;
+
+                    loop {
+                        match stream.next().await {
+                            Ok(Some(api_records)) => {
+                                apply_records(
+                                    &server,
+                                    &source_id,
+                                    &remote_config,
+                                    api_records,
+                                    &mut previous_sources,
+                                    &seen_sources,
+                                )
+                                .await;
+                            }
+                            Ok(None) => {
+                                tracing::debug!(%source_id, "Remote server closed the record stream");
+                                break;
+                            }
+                            Err(e) => {
+                                tracing::warn!(%source_id, error = %e, "Record stream from remote server failed");
+                                break;
+                            }
                         }
                     }
 
-                    seen_sources.lock().await.clear();
+                    // The stream may have moved past the copy's records.
+                    replica = Replica::default();
+                }
+                Ok(None) => {
+                    tracing::info!(
+                        %source_id,
+                        "Remote server cannot stream records, polling instead"
+                    );
+                    streaming = false;
+                }
+                Err(e) => {
+                    tracing::debug!(%source_id, error = %e, "Failed to open record stream");
+                }
+            }
+        }
+
+        let mut fetched = if replicating {
+            replica.fetch(&source_id, &client, &remote_config.url).await
+        } else {
+            Ok(Fetched::Unsupported)
+        };
 
-                    match e {
-                        LoopResult::Quit => {
-                            return;
-                        }
-                        LoopResult::Sleep => {
-                            backoff.reset();
-                        }
-                        LoopResult::Backoff => {
-                            backoff.backoff();
-                        }
-                    }
+        if let Ok(Fetched::Unsupported) = fetched {
+            if replicating {
+                tracing::info!(
+                    %source_id,
+                    "Remote server cannot send changes, fetching every record instead"
+                );
+                replicating = false;
+            }
 
-                    sleep(backoff.duration()).await;
+            fetched = fetch_records(&source_id, &client, &remote_config.url).await;
+        }
+
+        let api_records = match fetched {
+            Ok(Fetched::Records(r)) => {
+                backoff.reset();
+                r
+            }
+            Ok(Fetched::Unchanged | Fetched::Unsupported) => {
+                tracing::trace!(%source_id, "Remote records are unchanged");
+                backoff.reset();
+                sleep(backoff.duration()).await;
+                continue;
+            }
+            Err(e) => {
+                // The records are cleared below so must be fetched again.
+                replica = Replica::default();
+
+                // Connections to a host that has just moved fail so retry
+                // straight away rather than backing off.
+                if host.moved(&server).await {
+                    tracing::info!(%source_id, "Remote host address changed, reconnecting");
+                    client = server.reconnect_http_client(
+                        remote_config.bind.as_ref(),
+                        remote_config.tls.as_ref(),
+                    );
+                    backoff.reset();
                     continue;
                 }
-            };
 
-        let mut record_count = 0;
-        let old_sources = previous_sources;
-        previous_sources = api_records
-            .source_records
-            .iter()
-            .map(|sr| (sr.source_id.clone(), sr.timestamp))
-            .collect();
+                {
+                    let _guard = server.start_batch_update().await;
+                    for (source_id, timestamp) in previous_sources.drain() {
+                        server.clear_source_records(&source_id, timestamp).await;
+                    }
+                }
 
-        {
-            let _guard = server.start_batch_update().await;
-            for (old_source, timestamp) in old_sources {
-                if !previous_sources.contains_key(&old_source) {
-                    server.clear_source_records(&old_source, timestamp).await;
+                seen_sources.lock().await.clear();
+
+                match e {
+                    LoopResult::Quit(_) => {
+                        return;
+                    }
+                    LoopResult::Sleep => {
+                        backoff.reset();
+                    }
+                    LoopResult::Backoff(_) => {
+                        backoff.backoff();
+                    }
                 }
+
+                sleep(backoff.duration()).await;
+                continue;
             }
+        };
 
-            for source_records in api_records.source_records {
-                record_count += source_records.records.len();
+        apply_records(
+            &server,
+            &source_id,
+            &remote_config,
+            api_records,
+            &mut previous_sources,
+            &seen_sources,
+        )
+        .await;
 
-                server.add_source_records(source_records).await;
-            }
-        }
+        sleep(backoff.duration()).await;
+    }
+}
 
-        seen_sources.lock().await.clone_from(&previous_sources);
+impl RemoteConfig {
+    /// Starts replicating the remote server's records.
+    pub(super) fn start<S: RecordServer>(
+        self,
+        source_id: SourceId,
+        server: &S,
+    ) -> RemoteRecords<S> {
+        let seen_sources = Arc::new(Mutex::new(HashMap::new()));
 
-        tracing::trace!(
-            %source_id,
-            record_count,
-            "Retrieved remote records",
-        );
+        let handle = tokio::spawn(remote_loop(
+            server.clone(),
+            source_id,
+            self,
+            seen_sources.clone(),
+        ));
 
-        sleep(backoff.duration()).await;
+        RemoteRecords {
+            server: server.clone(),
+            handle,
+            seen_sources,
+        }
     }
 }
 
@@ -169,6 +514,10 @@ impl SourceConfig for RemoteConfig {
         SourceType::Remote
     }
 
+    fn required_files(&self) -> Vec<PathBuf> {
+        self.tls.as_ref().map(ClientTls::files).unwrap_or_default()
+    }
+
     #[instrument(fields(%source_id), skip(self, server))]
     async fn spawn<S: RecordServer>(
         self,
@@ -177,25 +526,7 @@ impl SourceConfig for RemoteConfig {
     ) -> Result<SourceHandle<S>, Error> {
         tracing::trace!("Adding source");
 
-        let seen_sources = Arc::new(Mutex::new(HashMap::new()));
-
-        let handle = {
-            let config = self.clone();
-
-            tokio::spawn(remote_loop(
-                server.clone(),
-                source_id,
-                config.clone(),
-                seen_sources.clone(),
-            ))
-        };
-
-        Ok(RemoteRecords {
-            server: server.clone(),
-            handle,
-            seen_sources,
-        }
-        .into())
+        Ok(self.start(source_id, server).into())
     }
 }
 
@@ -208,16 +539,22 @@ mod tests {
         sync::Arc,
     };
 
+    use actix_web::{web, App, HttpServer};
     use chrono::Utc;
-    use tokio::sync::Mutex;
+    use reqwest::Url;
+    use tokio::sync::{watch, Mutex};
     use uuid::Uuid;
 
     use crate::{
-        api::{ApiConfig, ApiServer},
+        api::{ApiConfig, ApiRecords, ApiServer},
         config::Config,
-        dns::{Fqdn, RData, Record, RecordSet},
-        sources::{remote::RemoteConfig, SourceConfig, SourceId, SourceRecords, SourceType},
+        dns::{Fqdn, RData, Record, RecordSet, ServerState},
+        sources::{
+            remote::{Fetched, RemoteConfig, Replica},
+            SourceConfig, SourceId, SourceRecords, SourceType,
+        },
         test::{fqdn, name, MultiSourceServer},
+        util::Bind,
         ServerId, ServerInner,
     };
 
@@ -225,7 +562,9 @@ mod tests {
         inner: &mut ServerInner,
         records: [(&SourceId, &[(Fqdn, RData)]); N],
     ) {
-        inner.records.clear();
+        for (_, old) in inner.records.drain() {
+            inner.replication.removed(&old);
+        }
 
         for (source_id, record_list) in records {
             let mut records = RecordSet::default();
@@ -236,11 +575,17 @@ mod tests {
             let source_records = SourceRecords {
                 source_id: source_id.clone(),
                 timestamp: Utc::now(),
+                hops: 0,
+                lease: None,
                 records,
+                reported: None,
             };
 
+            inner.replication.changed(None, &source_records);
             inner.records.insert(source_id.clone(), source_records);
         }
+
+        inner.records_changed.send_replace(());
     }
 
     #[tracing_test::traced_test]
@@ -263,6 +608,14 @@ mod tests {
         let mut inner = ServerInner {
             config: Config::default(),
             records: HashMap::new(),
+            changes: Default::default(),
+            replication: Default::default(),
+            mesh_peers: Default::default(),
+            source_errors: HashMap::new(),
+            source_status: HashMap::new(),
+            source_reverse_zones: HashMap::new(),
+            config_error: None,
+            records_changed: watch::Sender::new(()),
         };
 
         build_records(
@@ -285,12 +638,23 @@ mod tests {
             ],
         );
 
+        let server_state = ServerState::new(inner.records(), inner.config.zones.clone());
         let server_inner = Arc::new(Mutex::new(inner));
         let api_config = ApiConfig {
             address: SocketAddr::new(Ipv4Addr::from_str("0.0.0.0").unwrap().into(), 0),
+            token: None,
+            tokens: HashMap::new(),
+            records_file: None,
+            tls: None,
         };
 
-        let api = ApiServer::new(&api_config, local_server, server_inner.clone()).unwrap();
+        let api = ApiServer::new(
+            &api_config,
+            local_server,
+            server_inner.clone(),
+            server_state,
+        )
+        .unwrap();
 
         let mut test_server = MultiSourceServer::new();
 
@@ -302,7 +666,12 @@ mod tests {
 
         let config = RemoteConfig {
             url: format!("http://localhost:{}/", api.port).parse().unwrap(),
-            interval_ms: Some(100),
+            // Long enough that changes can only arrive through the record stream.
+            interval_ms: Some(60000),
+            bind: Some(Bind::Address(Ipv4Addr::LOCALHOST.into())),
+            tls: None,
+            include_zones: Vec::new(),
+            exclude_zones: Vec::new(),
         };
 
         let handle = config.spawn(source_id.clone(), &test_server).await.unwrap();
@@ -412,4 +781,248 @@ mod tests {
         tracing::trace!("Shutting down");
         api.shutdown().await;
     }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn polling_fallback() {
+        let remote_source = SourceId {
+            server_id: ServerId::new_v4(),
+            source_type: SourceType::File,
+            source_name: "test".to_string(),
+        };
+
+        let mut inner = ServerInner {
+            config: Config::default(),
+            records: HashMap::new(),
+            changes: Default::default(),
+            replication: Default::default(),
+            mesh_peers: Default::default(),
+            source_errors: HashMap::new(),
+            source_status: HashMap::new(),
+            source_reverse_zones: HashMap::new(),
+            config_error: None,
+            records_changed: watch::Sender::new(()),
+        };
+
+        build_records(
+            &mut inner,
+            [(
+                &remote_source,
+                &[(
+                    fqdn("old.test.local"),
+                    RData::A("10.5.23.43".parse().unwrap()),
+                )],
+            )],
+        );
+
+        // Mimics an older server that only supports polling for records.
+        let server_inner = Arc::new(Mutex::new(inner));
+        let data = web::Data::new(server_inner.clone());
+        let server = HttpServer::new(move || {
+            App::new().app_data(data.clone()).route(
+                "/v2/records",
+                web::get().to(|inner: web::Data<Arc<Mutex<ServerInner>>>| async move {
+                    web::Json(ApiRecords {
+                        server_id: ServerId::new_v4(),
+                        timestamp: Utc::now(),
+                        source_records: inner.lock().await.records.values().cloned().collect(),
+                        next_offset: None,
+                    })
+                }),
+            )
+        })
+        .disable_signals()
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+
+        let port = server.addrs().first().unwrap().port();
+        let server = server.run();
+        let server_handle = server.handle();
+        tokio::spawn(server);
+
+        let mut test_server = MultiSourceServer::new();
+
+        let source_id = SourceId {
+            server_id: Uuid::new_v4(),
+            source_type: RemoteConfig::source_type(),
+            source_name: "test".to_string(),
+        };
+
+        let config = RemoteConfig {
+            url: format!("http://localhost:{port}/").parse().unwrap(),
+            interval_ms: Some(100),
+            bind: None,
+            tls: None,
+            include_zones: Vec::new(),
+            exclude_zones: Vec::new(),
+        };
+
+        let handle = config.spawn(source_id.clone(), &test_server).await.unwrap();
+
+        test_server
+            .wait_for_records(|records| records.has_name(&name("old.test.local.")))
+            .await;
+
+        {
+            let mut inner = server_inner.lock().await;
+            build_records(
+                &mut inner,
+                [(
+                    &remote_source,
+                    &[(
+                        fqdn("new.test.local"),
+                        RData::A("10.5.23.44".parse().unwrap()),
+                    )],
+                )],
+            );
+        }
+
+        let records = test_server
+            .wait_for_records(|records| records.has_name(&name("new.test.local.")))
+            .await;
+        assert_eq!(records.get(&remote_source).unwrap().len(), 1);
+
+        handle.drop().await;
+        server_handle.stop(false).await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replica() {
+        let remote_source = SourceId {
+            server_id: ServerId::new_v4(),
+            source_type: SourceType::File,
+            source_name: "test".to_string(),
+        };
+
+        let mut inner = ServerInner {
+            config: Config::default(),
+            records: HashMap::new(),
+            changes: Default::default(),
+            replication: Default::default(),
+            mesh_peers: Default::default(),
+            source_errors: HashMap::new(),
+            source_status: HashMap::new(),
+            source_reverse_zones: HashMap::new(),
+            config_error: None,
+            records_changed: watch::Sender::new(()),
+        };
+
+        build_records(
+            &mut inner,
+            [(
+                &remote_source,
+                &[
+                    (
+                        fqdn("www.test.local"),
+                        RData::A("10.5.23.43".parse().unwrap()),
+                    ),
+                    (
+                        fqdn("db.test.local"),
+                        RData::A("10.5.23.44".parse().unwrap()),
+                    ),
+                ],
+            )],
+        );
+
+        let server_state = ServerState::new(inner.records(), inner.config.zones.clone());
+        let server_inner = Arc::new(Mutex::new(inner));
+        let api_config = ApiConfig {
+            address: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0),
+            token: None,
+            tokens: HashMap::new(),
+            records_file: None,
+            tls: None,
+        };
+
+        let api = ApiServer::new(
+            &api_config,
+            ServerId::new_v4(),
+            server_inner.clone(),
+            server_state,
+        )
+        .unwrap();
+
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::Remote, "test");
+        let client = reqwest::Client::new();
+        let url: Url = format!("http://localhost:{}/", api.port).parse().unwrap();
+        let mut replica = Replica::default();
+
+        let Ok(Fetched::Records(records)) = replica.fetch(&source_id, &client, &url).await else {
+            panic!("Expected the remote records");
+        };
+        assert_eq!(records.source_records.len(), 1);
+        assert_eq!(records.source_records[0].records.len(), 2);
+
+        assert!(matches!(
+            replica.fetch(&source_id, &client, &url).await,
+            Ok(Fetched::Unchanged)
+        ));
+
+        {
+            let mut inner = server_inner.lock().await;
+            let mut source = inner.records.get(&remote_source).unwrap().clone();
+            let old = source.records.clone();
+            source.records = old
+                .records()
+                .filter(|record| record.name() != &fqdn("db.test.local"))
+                .cloned()
+                .chain([Record::new(
+                    fqdn("new.test.local"),
+                    RData::A("10.5.23.45".parse().unwrap()),
+                )])
+                .collect();
+
+            inner.replication.changed(Some(&old), &source);
+            inner.records.insert(remote_source.clone(), source);
+        }
+
+        let Ok(Fetched::Records(records)) = replica.fetch(&source_id, &client, &url).await else {
+            panic!("Expected the changed records");
+        };
+        let records = &records.source_records[0].records;
+        assert_eq!(records.len(), 2);
+        assert!(records.has_name(&name("www.test.local.")));
+        assert!(records.has_name(&name("new.test.local.")));
+        assert!(!records.has_name(&name("db.test.local.")));
+
+        // A restarted server can't send changes from before it restarted.
+        let (_, generation) = replica.position.unwrap();
+        replica.position = Some((Uuid::new_v4(), generation));
+        let Ok(Fetched::Records(records)) = replica.fetch(&source_id, &client, &url).await else {
+            panic!("Expected all of the remote records");
+        };
+        assert_eq!(records.source_records[0].records.len(), 2);
+
+        api.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn zone_filters() {
+        let mut config = RemoteConfig {
+            url: "http://localhost/".parse().unwrap(),
+            interval_ms: None,
+            bind: None,
+            tls: None,
+            include_zones: vec![fqdn("branch.local")],
+            exclude_zones: vec![fqdn("private.branch.local")],
+        };
+
+        let address = |name: &str| Record::new(fqdn(name), RData::A("10.5.1.2".parse().unwrap()));
+        let pointer =
+            |target: &str| Record::new(fqdn("2.1.5.10.in-addr.arpa"), RData::Ptr(fqdn(target)));
+
+        assert!(config.includes(&address("branch.local")));
+        assert!(config.includes(&address("www.branch.local")));
+        assert!(!config.includes(&address("www.head.local")));
+        assert!(!config.includes(&address("db.private.branch.local")));
+        assert!(config.includes(&pointer("www.branch.local")));
+        assert!(!config.includes(&pointer("www.head.local")));
+
+        config.include_zones.clear();
+        assert!(config.includes(&address("www.head.local")));
+        assert!(!config.includes(&address("db.private.branch.local")));
+    }
 }
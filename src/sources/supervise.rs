@@ -0,0 +1,135 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{TimeDelta, Utc};
+use tokio::{task::JoinHandle, time::sleep};
+
+use crate::{
+    sources::{SourceConfig, SourceHandle, SourceId, SourceStatus},
+    RecordServer,
+};
+
+/// The delay before the first retry, doubled after each failure.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+/// How many times to try starting a source before giving up.
+const SPAWN_ATTEMPTS: u32 = 10;
+
+async fn retry_loop<S: RecordServer, C: SourceConfig>(
+    config: C,
+    source_id: SourceId,
+    server: S,
+    source: Arc<Mutex<Option<SourceHandle<S>>>>,
+) {
+    let mut delay = RETRY_DELAY;
+
+    // The first attempt has already failed.
+    for attempts in 1..SPAWN_ATTEMPTS {
+        let retry_at = Utc::now() + TimeDelta::from_std(delay).unwrap_or_default();
+        server
+            .source_status(&source_id, SourceStatus::Retrying { attempts, retry_at })
+            .await;
+
+        sleep(delay).await;
+
+        match config.clone().spawn(source_id.clone(), &server).await {
+            Ok(handle) => {
+                // Stored before anything else is awaited so the handle can't be
+                // lost if the source is dropped.
+                *source.lock().unwrap() = Some(handle);
+
+                tracing::info!(source = %source_id, attempts, "Started source after retrying");
+                server.source_status(&source_id, SourceStatus::Ok).await;
+                return;
+            }
+            Err(e) => {
+                tracing::warn!(source = %source_id, error = %e, attempts, "Failed retrying source");
+                server
+                    .source_failed(&source_id, format!("Failed adding source: {e}"))
+                    .await;
+            }
+        }
+
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+    }
+
+    tracing::error!(
+        source = %source_id,
+        attempts = SPAWN_ATTEMPTS,
+        "Giving up on source",
+    );
+    server.source_status(&source_id, SourceStatus::Failed).await;
+}
+
+/// Keeps trying to start a source that failed to start.
+pub(super) struct Supervisor<S: RecordServer> {
+    handle: JoinHandle<()>,
+    source: Arc<Mutex<Option<SourceHandle<S>>>>,
+}
+
+impl<S: RecordServer> Supervisor<S> {
+    pub(super) fn start<C: SourceConfig>(config: C, source_id: SourceId, server: &S) -> Self {
+        let source = Arc::new(Mutex::new(None));
+
+        let handle = tokio::spawn(retry_loop(
+            config,
+            source_id,
+            server.clone(),
+            source.clone(),
+        ));
+
+        Self { handle, source }
+    }
+
+    pub(super) async fn drop(&self) {
+        self.handle.abort();
+
+        let source = self.source.lock().unwrap().take();
+        if let Some(source) = source {
+            // Boxed as the handle may itself be supervised.
+            Box::pin(source.drop()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    use crate::{
+        sources::{file::ApiRecordsConfig, supervise::Supervisor, SourceId, SourceType},
+        test::MultiSourceServer,
+    };
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn retry() {
+        let temp = TempDir::new().unwrap();
+        let records_file = temp.path().join("missing").join("records.zone");
+
+        let mut test_server = MultiSourceServer::new();
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::Api, "api");
+
+        // The records file can't be created until its directory exists.
+        let supervisor = Supervisor::start(
+            ApiRecordsConfig {
+                records_file: records_file.clone(),
+            },
+            source_id.clone(),
+            &test_server,
+        );
+
+        std::fs::create_dir(records_file.parent().unwrap()).unwrap();
+
+        let records = test_server
+            .wait_for_state(|records| records.contains_key(&source_id))
+            .await;
+        assert!(records.get(&source_id).unwrap().is_empty());
+        assert!(records_file.exists());
+
+        supervisor.drop().await;
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str,
+};
+
+use anyhow::{bail, Context};
+use figment::value::magic::RelativePathBuf;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixStream,
+    time::sleep,
+};
+use tracing::instrument;
+
+use crate::{
+    dns::{Fqdn, RData, Record, RecordSet},
+    run_loop::{LoopResult, PollInterval},
+    sources::{spawn_polling, RecordOverrides, SourceConfig, SourceHandle, SourceId, SourceType},
+    Error, RecordServer,
+};
+
+const POLL_INTERVAL_MS: u64 = 15000;
+const DEFAULT_SOCKET: &str = "/var/run/tailscale/tailscaled.sock";
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub(crate) struct TailscaleConfig {
+    zone: Fqdn,
+    #[serde(default)]
+    socket: Option<RelativePathBuf>,
+    #[serde(default)]
+    interval_ms: Option<u64>,
+    #[serde(flatten)]
+    overrides: RecordOverrides,
+}
+
+impl TailscaleConfig {
+    fn socket(&self) -> PathBuf {
+        self.socket
+            .as_ref()
+            .map(|s| s.relative())
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET))
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct PeerStatus {
+    #[serde(rename = "HostName", default)]
+    host_name: String,
+    #[serde(rename = "DNSName", default)]
+    dns_name: String,
+    #[serde(rename = "TailscaleIPs", default)]
+    tailscale_ips: Option<Vec<IpAddr>>,
+}
+
+impl PeerStatus {
+    /// The MagicDNS name is the first label of the fully qualified DNS name. Fall
+    /// back to a cleaned up version of the hostname for older clients.
+    fn label(&self) -> Option<String> {
+        let label = match self.dns_name.split('.').next() {
+            Some(label) if !label.is_empty() => label.to_owned(),
+            _ => self
+                .host_name
+                .to_lowercase()
+                .chars()
+                .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '-' })
+                .collect::<String>()
+                .trim_matches('-')
+                .to_owned(),
+        };
+
+        if label.is_empty() {
+            None
+        } else {
+            Some(label)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct Status {
+    #[serde(rename = "Self", default)]
+    self_status: Option<PeerStatus>,
+    #[serde(rename = "Peer", default)]
+    peers: Option<HashMap<String, PeerStatus>>,
+}
+
+/// Performs a GET request against tailscaled's local API. The API is only
+/// available over a unix socket which reqwest cannot talk to so this speaks just
+/// enough HTTP/1.0 to get a response.
+async fn local_api_call(socket: &Path, method: &str) -> Result<Vec<u8>, Error> {
+    let mut stream = UnixStream::connect(socket).await?;
+
+    stream
+        .write_all(
+            format!("GET /localapi/v0/{method} HTTP/1.0\r\nHost: local-tailscaled.sock\r\n\r\n")
+                .as_bytes(),
+        )
+        .await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .context("Incomplete response from tailscaled")?;
+
+    let headers = str::from_utf8(&response[0..header_end])?;
+    let status = headers
+        .lines()
+        .next()
+        .and_then(|line| line.split_ascii_whitespace().nth(1))
+        .context("Invalid response from tailscaled")?;
+
+    if status != "200" {
+        bail!("tailscaled returned status {status}");
+    }
+
+    Ok(response.split_off(header_end + 4))
+}
+
+#[instrument(fields(%source_id, socket = %socket.display()))]
+async fn fetch_status(source_id: &SourceId, socket: &Path) -> Result<Status, LoopResult> {
+    let body = match local_api_call(socket, "status").await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to connect to tailscaled");
+            return Err(LoopResult::Backoff(format!(
+                "Failed to connect to tailscaled: {e}"
+            )));
+        }
+    };
+
+    serde_json::from_slice(&body).map_err(|e| {
+        tracing::error!(error = %e, "Failed to parse response from tailscaled");
+        LoopResult::Backoff(format!("Failed to parse response from tailscaled: {e}"))
+    })
+}
+
+#[instrument(fields(%source_id), skip(status))]
+fn generate_records(source_id: &SourceId, zone: &Fqdn, status: Status) -> RecordSet {
+    let mut records = RecordSet::new();
+
+    let peers = status
+        .self_status
+        .into_iter()
+        .chain(status.peers.unwrap_or_default().into_values());
+
+    for peer in peers {
+        let Some(label) = peer.label() else {
+            continue;
+        };
+
+        let name = match zone.child(label.as_str()) {
+            Ok(n) => n,
+            Err(e) => {
+                tracing::warn!(error = %e, hostname = label, "Invalid tailscale hostname");
+                continue;
+            }
+        };
+
+        for ip in peer.tailscale_ips.unwrap_or_default() {
+            records.insert(Record::new(name.clone(), RData::from(ip)));
+        }
+    }
+
+    records
+}
+
+async fn tailscale_loop<S: RecordServer>(
+    server: S,
+    source_id: SourceId,
+    tailscale_config: TailscaleConfig,
+    interval: PollInterval,
+) -> LoopResult {
+    let socket = tailscale_config.socket();
+
+    loop {
+        let status = match fetch_status(&source_id, &socket).await {
+            Ok(s) => s,
+            Err(result) => return result,
+        };
+
+        let records = generate_records(&source_id, &tailscale_config.zone, status);
+        server
+            .add_source_records(interval.records(&source_id, records))
+            .await;
+
+        sleep(interval.duration()).await;
+    }
+}
+
+impl SourceConfig for TailscaleConfig {
+    fn source_type() -> SourceType {
+        SourceType::Tailscale
+    }
+
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        Some(&self.overrides)
+    }
+
+    fn interval_ms(&self) -> Option<u64> {
+        Some(self.interval_ms.unwrap_or(POLL_INTERVAL_MS))
+    }
+
+    fn without_live_settings(&self) -> Self {
+        Self {
+            interval_ms: None,
+            overrides: Default::default(),
+            ..self.clone()
+        }
+    }
+
+    #[instrument(fields(%source_id), skip(self, server))]
+    async fn spawn<S: RecordServer>(
+        self,
+        source_id: SourceId,
+        server: &S,
+    ) -> Result<SourceHandle<S>, Error> {
+        tracing::trace!("Adding source");
+
+        spawn_polling(self, source_id, server, tailscale_loop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::{
+        dns::RData,
+        sources::{tailscale::Status, SourceId, SourceType},
+        test::{fqdn, name},
+    };
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn parse_status() {
+        let status: Status = serde_json::from_str(
+            r#"{
+  "Version": "1.56.1",
+  "BackendState": "Running",
+  "Self": {
+    "HostName": "router",
+    "DNSName": "router.tail1234.ts.net.",
+    "TailscaleIPs": ["100.101.102.1", "fd7a:115c:a1e0::1"]
+  },
+  "Peer": {
+    "nodekey:abc": {
+      "HostName": "Dave's Laptop",
+      "DNSName": "",
+      "TailscaleIPs": ["100.101.102.2"]
+    },
+    "nodekey:def": {
+      "HostName": "phone",
+      "DNSName": "pixel.tail1234.ts.net.",
+      "TailscaleIPs": null
+    },
+    "nodekey:ghi": {
+      "HostName": "nas",
+      "DNSName": "nas.tail1234.ts.net.",
+      "TailscaleIPs": ["100.101.102.3"]
+    }
+  }
+}"#,
+        )
+        .unwrap();
+
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::Tailscale, "test");
+        let records = super::generate_records(&source_id, &fqdn("ts.home.local"), status);
+
+        assert_eq!(records.len(), 4);
+
+        assert!(records.contains(
+            &fqdn("router.ts.home.local"),
+            &RData::A("100.101.102.1".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("router.ts.home.local"),
+            &RData::Aaaa("fd7a:115c:a1e0::1".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("dave-s-laptop.ts.home.local"),
+            &RData::A("100.101.102.2".parse().unwrap())
+        ));
+        assert!(records.contains(
+            &fqdn("nas.ts.home.local"),
+            &RData::A("100.101.102.3".parse().unwrap())
+        ));
+        assert!(!records.has_name(&name("pixel.ts.home.local.")));
+    }
+}
@@ -1,6 +1,5 @@
-use std::time::Duration;
-
 use anyhow::bail;
+use regex::Regex;
 use reqwest::{Client, Url};
 use serde::{de::DeserializeOwned, Deserialize};
 use tokio::time::sleep;
@@ -9,13 +8,77 @@ use tracing::instrument;
 use crate::{
     config::deserialize_url,
     dns::{Fqdn, RData, Record, RecordSet},
-    run_loop::{LoopResult, RunLoop},
-    sources::{SourceConfig, SourceHandle, SourceId, SourceType},
-    Error, RecordServer, SourceRecords,
+    run_loop::{LoopResult, PollInterval},
+    sources::{spawn_polling, RecordOverrides, SourceConfig, SourceHandle, SourceId, SourceType},
+    util::{Bind, HostAddresses},
+    Error, RecordServer,
 };
 
 const POLL_INTERVAL_MS: u64 = 15000;
 
+/// A regular expression matched against the full name of a router.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(try_from = "String")]
+struct RouterPattern(Regex);
+
+impl PartialEq for RouterPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for RouterPattern {}
+
+impl TryFrom<String> for RouterPattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Ok(RouterPattern(Regex::new(&pattern)?))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Clone)]
+struct RouterFilter {
+    #[serde(default)]
+    include_providers: Option<Vec<String>>,
+    #[serde(default)]
+    exclude_providers: Vec<String>,
+    #[serde(default)]
+    include_routers: Option<RouterPattern>,
+    #[serde(default)]
+    exclude_routers: Option<RouterPattern>,
+}
+
+impl RouterFilter {
+    fn includes(&self, router: &ApiRouter) -> bool {
+        let provider = router.provider();
+
+        if let Some(ref providers) = self.include_providers {
+            if !providers.iter().any(|p| p == provider) {
+                return false;
+            }
+        }
+
+        if self.exclude_providers.iter().any(|p| p == provider) {
+            return false;
+        }
+
+        if let Some(RouterPattern(ref pattern)) = self.include_routers {
+            if !pattern.is_match(&router.name) {
+                return false;
+            }
+        }
+
+        if let Some(RouterPattern(ref pattern)) = self.exclude_routers {
+            if pattern.is_match(&router.name) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Deserialize, Clone)]
 pub(crate) struct TraefikConfig {
     #[serde(deserialize_with = "deserialize_url")]
@@ -23,12 +86,35 @@ pub(crate) struct TraefikConfig {
     address: Option<RData>,
     #[serde(default)]
     interval_ms: Option<u64>,
+    #[serde(default)]
+    bind: Option<Bind>,
+    #[serde(flatten)]
+    filter: RouterFilter,
+    #[serde(flatten)]
+    overrides: RecordOverrides,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 struct ApiRouter {
     name: String,
     rule: String,
+    #[serde(default)]
+    provider: Option<String>,
+}
+
+impl ApiRouter {
+    /// Older versions of traefik don't include the provider but it is always the
+    /// suffix of the router's name.
+    fn provider(&self) -> &str {
+        match self.provider {
+            Some(ref provider) => provider,
+            None => self
+                .name
+                .rsplit_once('@')
+                .map(|(_, provider)| provider)
+                .unwrap_or_default(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -51,7 +137,7 @@ where
 {
     let target = base_url.join(method).map_err(|e| {
         tracing::error!(error = %e, "Unable to generate API URL");
-        LoopResult::Quit
+        LoopResult::Quit(format!("Unable to generate API URL: {e}"))
     })?;
 
     match client.get(target).send().await {
@@ -59,12 +145,16 @@ where
             Ok(result) => Ok(result),
             Err(e) => {
                 tracing::error!(error = %e, "Failed to parse response from traefik");
-                Err(LoopResult::Backoff)
+                Err(LoopResult::Backoff(format!(
+                    "Failed to parse response from traefik: {e}"
+                )))
             }
         },
         Err(e) => {
             tracing::error!(error = %e, "Failed to connect to traefik");
-            Err(LoopResult::Backoff)
+            Err(LoopResult::Backoff(format!(
+                "Failed to connect to traefik: {e}"
+            )))
         }
     }
 }
@@ -174,6 +264,13 @@ fn generate_records(
 
     let mut names: Vec<Fqdn> = routers
         .iter()
+        .filter(|r| {
+            let included = traefik_config.filter.includes(r);
+            if !included {
+                tracing::trace!(router = r.name, "Skipping filtered router");
+            }
+            included
+        })
         .filter_map(|r| match parse_hosts(&r.rule) {
             Ok(hosts) => Some(hosts),
             Err(e) => {
@@ -198,13 +295,16 @@ async fn traefik_loop<S: RecordServer>(
     server: S,
     source_id: SourceId,
     traefik_config: TraefikConfig,
+    interval: PollInterval,
 ) -> LoopResult {
     tracing::trace!(
         %source_id,
         "Attempting to connect to traefik API",
     );
 
-    let client = server.http_client();
+    let mut client = server.http_client(traefik_config.bind.as_ref(), None);
+    let mut host = HostAddresses::new(&traefik_config.url);
+    host.changed(&server).await;
 
     let version =
         match api_call::<ApiVersion>(&source_id, &client, &traefik_config.url, "version").await {
@@ -219,6 +319,11 @@ async fn traefik_loop<S: RecordServer>(
     );
 
     loop {
+        if host.changed(&server).await {
+            tracing::info!(%source_id, "Traefik host address changed, reconnecting");
+            client = server.reconnect_http_client(traefik_config.bind.as_ref(), None);
+        }
+
         let routers = match api_call::<Vec<ApiRouter>>(
             &source_id,
             &client,
@@ -228,18 +333,25 @@ async fn traefik_loop<S: RecordServer>(
         .await
         {
             Ok(r) => r,
-            Err(result) => return result,
+            Err(result) => {
+                // Connections to a host that has just moved fail so retry straight
+                // away rather than backing off.
+                if host.moved(&server).await {
+                    tracing::info!(%source_id, "Traefik host address changed, reconnecting");
+                    client = server.reconnect_http_client(traefik_config.bind.as_ref(), None);
+                    continue;
+                }
+
+                return result;
+            }
         };
 
         let records = generate_records(&source_id, &traefik_config, routers);
         server
-            .add_source_records(SourceRecords::new(&source_id, None, records))
+            .add_source_records(interval.records(&source_id, records))
             .await;
 
-        sleep(Duration::from_millis(
-            traefik_config.interval_ms.unwrap_or(POLL_INTERVAL_MS),
-        ))
-        .await;
+        sleep(interval.duration()).await;
     }
 }
 
@@ -248,24 +360,29 @@ impl SourceConfig for TraefikConfig {
         SourceType::Traefik
     }
 
+    fn overrides(&self) -> Option<&RecordOverrides> {
+        Some(&self.overrides)
+    }
+
+    fn interval_ms(&self) -> Option<u64> {
+        Some(self.interval_ms.unwrap_or(POLL_INTERVAL_MS))
+    }
+
+    fn without_live_settings(&self) -> Self {
+        Self {
+            interval_ms: None,
+            overrides: Default::default(),
+            ..self.clone()
+        }
+    }
+
     #[instrument(fields(%source_id), skip(self, server))]
     async fn spawn<S: RecordServer>(
         self,
         source_id: SourceId,
         server: &S,
     ) -> Result<SourceHandle<S>, Error> {
-        let handle = {
-            let backoff = RunLoop::new(self.interval_ms.unwrap_or(POLL_INTERVAL_MS));
-            let config = self.clone();
-
-            tokio::spawn(
-                backoff.run(server.clone(), source_id, move |server, source_id| {
-                    traefik_loop(server, source_id, config.clone())
-                }),
-            )
-        };
-
-        Ok(handle.into())
+        spawn_polling(self, source_id, server, traefik_loop)
     }
 }
 
@@ -275,10 +392,62 @@ mod tests {
 
     use crate::{
         dns::RData,
-        sources::{traefik::TraefikConfig, SourceConfig, SourceId},
-        test::{fqdn, name, traefik_container, SingleSourceServer},
+        sources::{
+            traefik::{ApiRouter, TraefikConfig},
+            SourceConfig, SourceId, SourceType,
+        },
+        test::{fqdn, mock_traefik, name, traefik_container, SingleSourceServer},
     };
 
+    #[tracing_test::traced_test]
+    #[test]
+    fn filter_routers() {
+        let routers: Vec<ApiRouter> = serde_json::from_str(
+            r#"[
+  { "name": "api@internal", "rule": "Host(`traefik.home.local`)", "provider": "internal" },
+  { "name": "web@docker", "rule": "Host(`www.home.local`)", "provider": "docker" },
+  { "name": "admin-web@docker", "rule": "Host(`admin.home.local`)", "provider": "docker" },
+  { "name": "files@file", "rule": "Host(`files.home.local`)" }
+]"#,
+        )
+        .unwrap();
+
+        let source_id = SourceId::new(&Uuid::new_v4(), SourceType::Traefik, "test");
+
+        let generate = |config: &str| {
+            let config: TraefikConfig = serde_json::from_str(config).unwrap();
+            super::generate_records(&source_id, &config, routers.clone())
+        };
+
+        let records = generate(r#"{ "url": "http://10.10.1.5/api/" }"#);
+        assert_eq!(records.len(), 4);
+
+        let records = generate(
+            r#"{ "url": "http://10.10.1.5/api/", "include_providers": ["docker", "file"] }"#,
+        );
+        assert_eq!(records.len(), 3);
+        assert!(!records.has_name(&name("traefik.home.local.")));
+
+        let records = generate(
+            r#"{ "url": "http://10.10.1.5/api/", "exclude_providers": ["internal"], "exclude_routers": "^admin-" }"#,
+        );
+        assert_eq!(records.len(), 2);
+        assert!(records.has_name(&name("www.home.local.")));
+        assert!(records.has_name(&name("files.home.local.")));
+
+        let records = generate(
+            r#"{ "url": "http://10.10.1.5/api/", "include_routers": "@(file|internal)$" }"#,
+        );
+        assert_eq!(records.len(), 2);
+        assert!(records.has_name(&name("traefik.home.local.")));
+        assert!(records.has_name(&name("files.home.local.")));
+
+        assert!(serde_json::from_str::<TraefikConfig>(
+            r#"{ "url": "http://10.10.1.5/api/", "include_routers": "(" }"#
+        )
+        .is_err());
+    }
+
     #[tracing_test::traced_test]
     #[test]
     fn parse_hosts() {
@@ -359,9 +528,22 @@ mod tests {
     #[tracing_test::traced_test]
     #[tokio::test]
     async fn integration() {
-        let (handle, mut test_server) = {
-            let traefik = traefik_container(
-                r#"http:
+        let traefik = mock_traefik(&[
+            ("api@internal", "Host(`localhost`)"),
+            ("test-router@file", "Host(`test.example.org`)"),
+        ])
+        .await;
+        let port = traefik.port();
+
+        check_integration(traefik, port).await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    #[cfg_attr(not(feature = "container-tests"), ignore = "requires docker")]
+    async fn container_integration() {
+        let traefik = traefik_container(
+            r#"http:
   routers:
     test-router:
       entryPoints:
@@ -375,10 +557,17 @@ mod tests {
         servers:
         - url: http://foo.bar.com/
 "#,
-            )
-            .await;
-            let port = traefik.get_tcp_port(80).await;
+        )
+        .await;
+        let port = traefik.get_tcp_port(80).await;
+
+        check_integration(traefik, port).await;
+    }
 
+    /// Checks the source against a running traefik API. Records should be
+    /// cleared once `traefik` is dropped and the API disappears.
+    async fn check_integration<T>(traefik: T, port: u16) {
+        let (handle, mut test_server) = {
             let source_id = SourceId {
                 server_id: Uuid::new_v4(),
                 source_type: TraefikConfig::source_type(),
@@ -389,6 +578,9 @@ mod tests {
                 url: format!("http://localhost:{port}/api/").parse().unwrap(),
                 address: None,
                 interval_ms: Some(100),
+                bind: None,
+                filter: Default::default(),
+                overrides: Default::default(),
             };
 
             let mut test_server = SingleSourceServer::new(&source_id);
@@ -409,6 +601,9 @@ mod tests {
                 url: format!("http://localhost:{port}/api/").parse().unwrap(),
                 address: Some(RData::A("10.10.15.23".parse().unwrap())),
                 interval_ms: Some(100),
+                bind: None,
+                filter: Default::default(),
+                overrides: Default::default(),
             };
 
             let mut test_server = SingleSourceServer::new(&source_id);
@@ -431,6 +626,8 @@ mod tests {
                 &RData::A("10.10.15.23".parse().unwrap())
             ));
 
+            drop(traefik);
+
             (handle, test_server)
         };
 
@@ -1,16 +1,27 @@
 use std::{
     collections::{HashMap, HashSet},
     future::IntoFuture,
-    net::Ipv4Addr,
+    net::{IpAddr, Ipv4Addr},
     path::Path,
     str::FromStr,
     sync::{Arc, Mutex as SyncMutex},
     time::Duration,
 };
 
+use actix_web::{dev, web, App, HttpServer};
 use chrono::{DateTime, Utc};
-use hickory_server::proto::rr::{domain::Name, rdata, RData};
+use hickory_server::{
+    authority::MessageResponseBuilder,
+    proto::{
+        op::{Header, ResponseCode},
+        rr::{self, domain::Name, rdata, RData, RecordType},
+        serialize::txt::Parser,
+    },
+    server::{Request, RequestHandler, ResponseHandler, ResponseInfo},
+    ServerFuture,
+};
 use reqwest::{header::HeaderValue, Client};
+use serde_json::{json, Value};
 use tempfile::{tempdir, TempDir};
 use testcontainers::{
     core::{wait::HttpWaitStrategy, ContainerPort, Mount, WaitFor},
@@ -20,6 +31,7 @@ use testcontainers::{
 use tokio::{
     fs,
     io::AsyncWriteExt,
+    net::{TcpListener, UdpSocket},
     sync::{watch, Mutex},
     time,
 };
@@ -27,7 +39,8 @@ use tracing::trace;
 
 use crate::{
     dns::{Fqdn, RecordSet},
-    sources::{SourceId, SourceRecords},
+    sources::{mesh::MeshPeer, SourceId, SourceRecords, SourceStatus},
+    util::{Bind, ClientTls, Subnet},
     RecordServer,
 };
 
@@ -172,8 +185,16 @@ impl SingleSourceServer {
 impl RecordServer for SingleSourceServer {
     type UpdateGuard = <MultiSourceServer as RecordServer>::UpdateGuard;
 
-    fn http_client(&self) -> Client {
-        self.inner.http_client()
+    fn http_client(&self, bind: Option<&Bind>, tls: Option<&ClientTls>) -> Client {
+        self.inner.http_client(bind, tls)
+    }
+
+    fn reconnect_http_client(&self, bind: Option<&Bind>, tls: Option<&ClientTls>) -> Client {
+        self.inner.reconnect_http_client(bind, tls)
+    }
+
+    async fn resolve_host(&self, host: &str) -> Vec<IpAddr> {
+        self.inner.resolve_host(host).await
     }
 
     async fn start_batch_update(&self) -> Self::UpdateGuard {
@@ -185,6 +206,16 @@ impl RecordServer for SingleSourceServer {
         self.inner.add_source_records(new_records).await;
     }
 
+    async fn source_failed(&self, source_id: &SourceId, error: String) {
+        assert_eq!(source_id, &self.source_id);
+        self.inner.source_failed(source_id, error).await;
+    }
+
+    async fn source_status(&self, source_id: &SourceId, status: SourceStatus) {
+        assert_eq!(source_id, &self.source_id);
+        self.inner.source_status(source_id, status).await;
+    }
+
     async fn clear_source_records(&self, source_id: &SourceId, timestamp: DateTime<Utc>) {
         assert_eq!(source_id, &self.source_id);
         self.inner.clear_source_records(source_id, timestamp).await;
@@ -193,13 +224,42 @@ impl RecordServer for SingleSourceServer {
     async fn prune_sources(&self, keep: &HashSet<SourceId>) {
         self.inner.prune_sources(keep).await;
     }
+
+    async fn set_reverse_zones(&self, source_id: &SourceId, subnets: Vec<Subnet>) {
+        assert_eq!(source_id, &self.source_id);
+        self.inner.set_reverse_zones(source_id, subnets).await;
+    }
+
+    async fn mesh_peers(&self) -> Vec<MeshPeer> {
+        self.inner.mesh_peers().await
+    }
 }
 
 impl RecordServer for MultiSourceServer {
     type UpdateGuard = BatchGuard;
 
-    fn http_client(&self) -> Client {
-        self.http_client.clone()
+    fn http_client(&self, bind: Option<&Bind>, tls: Option<&ClientTls>) -> Client {
+        if bind.is_none() && tls.is_none() {
+            return self.http_client.clone();
+        }
+
+        let mut builder = Client::builder();
+        if let Some(bind) = bind {
+            builder = bind.apply(builder).unwrap();
+        }
+        if let Some(tls) = tls {
+            builder = tls.apply(builder).unwrap();
+        }
+
+        builder.build().unwrap()
+    }
+
+    fn reconnect_http_client(&self, bind: Option<&Bind>, tls: Option<&ClientTls>) -> Client {
+        self.http_client(bind, tls)
+    }
+
+    async fn resolve_host(&self, _host: &str) -> Vec<IpAddr> {
+        Vec::new()
     }
 
     async fn start_batch_update(&self) -> Self::UpdateGuard {
@@ -229,6 +289,10 @@ impl RecordServer for MultiSourceServer {
         }
     }
 
+    async fn source_failed(&self, _source_id: &SourceId, _error: String) {}
+
+    async fn source_status(&self, _source_id: &SourceId, _status: SourceStatus) {}
+
     async fn clear_source_records(&self, source_id: &SourceId, timestamp: DateTime<Utc>) {
         trace!(
             source = %source_id,
@@ -261,6 +325,12 @@ impl RecordServer for MultiSourceServer {
             self.sender.send(records.clone()).unwrap();
         }
     }
+
+    async fn set_reverse_zones(&self, _source_id: &SourceId, _subnets: Vec<Subnet>) {}
+
+    async fn mesh_peers(&self) -> Vec<MeshPeer> {
+        Vec::new()
+    }
 }
 
 pub(crate) fn name(n: &str) -> Name {
@@ -370,13 +440,193 @@ pub(crate) async fn coredns_container(zone: &str, zonefile: &str) -> Container {
     }
 }
 
+/// A minimal authoritative DNS server that answers from a zone file, following
+/// CNAMEs within the zone the same way CoreDNS does.
+#[derive(Clone)]
+struct MockZone {
+    soa: Vec<rr::Record>,
+    records: Arc<Vec<rr::Record>>,
+}
+
+impl MockZone {
+    fn lookup(&self, name: &Name, query_type: RecordType) -> (ResponseCode, Vec<rr::Record>) {
+        let mut answers = Vec::new();
+        let mut name = name.clone();
+
+        loop {
+            let known: Vec<&rr::Record> =
+                self.records.iter().filter(|r| r.name() == &name).collect();
+
+            if known.is_empty() {
+                return (ResponseCode::NXDomain, answers);
+            }
+
+            if query_type != RecordType::CNAME {
+                if let Some(RData::CNAME(target)) = known
+                    .iter()
+                    .find(|r| r.record_type() == RecordType::CNAME)
+                    .and_then(|r| r.data())
+                {
+                    answers.extend(
+                        known
+                            .iter()
+                            .filter(|r| r.record_type() == RecordType::CNAME)
+                            .map(|r| (*r).clone()),
+                    );
+                    name = target.0.clone();
+                    continue;
+                }
+            }
+
+            answers.extend(
+                known
+                    .into_iter()
+                    .filter(|r| r.record_type() == query_type)
+                    .cloned(),
+            );
+
+            return (ResponseCode::NoError, answers);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RequestHandler for MockZone {
+    async fn handle_request<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        mut response_handle: R,
+    ) -> ResponseInfo {
+        let query = request.query().original();
+        let (response_code, answers) = self.lookup(query.name(), query.query_type());
+
+        let mut header = Header::response_from_request(request.header());
+        header.set_authoritative(true);
+        header.set_response_code(response_code);
+
+        let soa: &[rr::Record] = if answers.is_empty() { &self.soa } else { &[] };
+
+        let builder = MessageResponseBuilder::from_message_request(request);
+        response_handle
+            .send_response(builder.build(header, answers.iter(), [], soa.iter(), []))
+            .await
+            .unwrap()
+    }
+}
+
+pub(crate) struct MockDns {
+    port: u16,
+    server: ServerFuture<MockZone>,
+}
+
+impl MockDns {
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Stops answering queries, as if the server had gone away.
+    pub(crate) async fn shutdown(&mut self) {
+        self.server.shutdown_gracefully().await.unwrap();
+    }
+}
+
+/// Starts an in-process DNS server on a random local port serving the given
+/// zone. Used in place of a CoreDNS container.
+pub(crate) async fn mock_dns(zone: &str, zonefile: &str) -> MockDns {
+    let (_, record_sets) = Parser::new(zonefile, None, Some(name(zone)))
+        .parse()
+        .unwrap();
+
+    let mut soa = Vec::new();
+    let mut records = Vec::new();
+    for record_set in record_sets.into_values() {
+        for record in record_set.records_without_rrsigs() {
+            if record.record_type() == RecordType::SOA {
+                soa.push(record.clone());
+            } else {
+                records.push(record.clone());
+            }
+        }
+    }
+
+    let handler = MockZone {
+        soa,
+        records: Arc::new(records),
+    };
+
+    let socket = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+
+    let mut server = ServerFuture::new(handler);
+    server.register_socket(socket);
+    server.register_listener(listener, Duration::from_millis(500));
+
+    MockDns { port, server }
+}
+
+pub(crate) struct MockTraefik {
+    port: u16,
+    handle: dev::ServerHandle,
+}
+
+impl MockTraefik {
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for MockTraefik {
+    fn drop(&mut self) {
+        // The stop command is sent immediately, there is no need to wait for it.
+        drop(self.handle.stop(false));
+    }
+}
+
+/// Starts an in-process server that mimics the parts of the traefik API used by
+/// the traefik source. Routers are given as pairs of names and rules.
+pub(crate) async fn mock_traefik(routers: &[(&str, &str)]) -> MockTraefik {
+    let routers: Vec<Value> = routers
+        .iter()
+        .map(|(name, rule)| json!({ "name": name, "rule": rule }))
+        .collect();
+    let routers = web::Data::new(routers);
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(routers.clone())
+            .route(
+                "/api/version",
+                web::get()
+                    .to(|| async { web::Json(json!({ "Version": "3.0.0", "Codename": "mock" })) }),
+            )
+            .route(
+                "/api/http/routers",
+                web::get().to(|routers: web::Data<Vec<Value>>| async move {
+                    web::Json(routers.get_ref().clone())
+                }),
+            )
+    })
+    .disable_signals()
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+
+    let port = server.addrs().first().unwrap().port();
+    let server = server.run();
+    let handle = server.handle();
+    tokio::spawn(server);
+
+    MockTraefik { port, handle }
+}
+
 mod integration {
     use std::{net::SocketAddr, path::PathBuf};
 
     use futures::StreamExt;
     use hickory_client::{
         client::AsyncClient,
-        op::{DnsResponse, Query, ResponseCode},
+        op::{DnsResponse, Message, Query, ResponseCode},
         proto::xfer::{DnsHandle, DnsRequestOptions},
         rr::{self, Name, RecordType},
         udp::UdpClientStream,
@@ -384,7 +634,7 @@ mod integration {
     use tokio::{net::UdpSocket, time::sleep};
 
     use super::*;
-    use crate::Server;
+    use crate::{api::ApiStatus, Server};
 
     async fn lookup(
         address: &str,
@@ -458,6 +708,7 @@ mod integration {
 
     #[tracing_test::traced_test]
     #[tokio::test(flavor = "multi_thread")]
+    #[cfg_attr(not(feature = "container-tests"), ignore = "requires docker")]
     async fn coredns_compare() {
         let temp_dir = TempDir::new().unwrap();
         let config_file = temp_dir.path().join("config.yml");
@@ -621,6 +872,7 @@ zones:
 
     #[tracing_test::traced_test]
     #[tokio::test(flavor = "multi_thread")]
+    #[cfg_attr(not(feature = "container-tests"), ignore = "requires docker")]
     async fn http_resolve() {
         let traefik = traefik_container(
             r#"http:
@@ -701,4 +953,342 @@ sources:
 
         server.shutdown().await;
     }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mock_http_resolve() {
+        let traefik = mock_traefik(&[
+            ("test-router@file", "Host(`test.example.org`)"),
+            ("api2@file", "Host(`traefik.home.local`)"),
+        ])
+        .await;
+        let traefik_port = traefik.port();
+
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &temp_dir.path().join("file1.yml"),
+            "traefik.home.local: 127.0.0.1".to_string(),
+        )
+        .await;
+
+        write_file(
+            &config_file,
+            format!(
+                r#"
+server:
+  port: 53545
+
+sources:
+  file:
+    file1: file1.yml
+  traefik:
+    traefik1:
+      url: 'http://traefik.home.local:{traefik_port}/api/'
+      interval_ms: 100
+"#,
+            ),
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let localns_address = "127.0.0.1:53545";
+
+        wait_for_response(localns_address, &name("test.example.org."), RecordType::A).await;
+
+        let response = lookup(
+            localns_address,
+            &name("test.example.org."),
+            RecordType::A,
+            true,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        let mut answers = response.answers().to_vec();
+        answers.sort();
+        assert_eq!(answers.len(), 2);
+
+        let answer = answers.first().unwrap();
+        assert_eq!(answer.name(), &name("traefik.home.local."));
+        assert_eq!(answer.data().unwrap(), &rdata_a("127.0.0.1"));
+
+        let answer = answers.get(1).unwrap();
+        assert_eq!(answer.name(), &name("test.example.org."));
+        assert_eq!(answer.data().unwrap(), &rdata_cname("traefik.home.local"));
+
+        server.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn dashboard() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &temp_dir.path().join("zone.yml"),
+            "www.home.local: 10.10.10.5",
+        )
+        .await;
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53536
+  capture_queries: 10
+
+api:
+  address: 127.0.0.1:0
+  token: capture-secret
+
+sources:
+  file:
+    zone: zone.yml
+
+zones:
+  home.local:
+    authoritative: true
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let port = server.api_server.inner.lock().await.as_ref().unwrap().port;
+        let localns_address = "127.0.0.1:53536";
+
+        let response = reqwest::get(format!("http://localhost:{port}/"))
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert!(response.text().await.unwrap().contains("<html"));
+
+        wait_for_response(localns_address, &name("www.home.local."), RecordType::A).await;
+
+        let status: ApiStatus = reqwest::get(format!("http://localhost:{port}/v2/status"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(status.config_error.is_none());
+        assert!(status.upstreams.is_empty());
+
+        let query = status.recent_queries.first().unwrap();
+        assert_eq!(query.name, "www.home.local.");
+        assert_eq!(query.record_type, "A");
+        assert_eq!(query.response_code, "No Error");
+        assert_eq!(query.answers, 1);
+        assert_eq!(query.client, IpAddr::from_str("127.0.0.1").unwrap());
+
+        let capture_url = format!("http://localhost:{port}/v2/capture");
+        let response = reqwest::get(&capture_url).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let response = reqwest::Client::new()
+            .get(&capture_url)
+            .bearer_auth("capture-secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "application/vnd.tcpdump.pcap"
+        );
+        let pcap = response.bytes().await.unwrap();
+
+        // The last packet is the response to the most recent query.
+        let mut offset = 24;
+        let mut last = None;
+        while offset < pcap.len() {
+            let length = u32::from_le_bytes(pcap[offset + 8..offset + 12].try_into().unwrap());
+            last = Some(&pcap[offset + 16 + 28..offset + 16 + length as usize]);
+            offset += 16 + length as usize;
+        }
+        let message = Message::from_vec(last.unwrap()).unwrap();
+        assert_eq!(message.answers().len(), 1);
+        assert_eq!(message.answers()[0].name(), &name("www.home.local."));
+
+        write_file(&config_file, "server: [").await;
+        assert!(server.reload_config().await.is_err());
+
+        let status: ApiStatus = reqwest::get(format!("http://localhost:{port}/v2/status"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(status.config_error.is_some());
+
+        server.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mutual_tls() {
+        let tls_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_resources/tls");
+        let tls_file = |name: &str| tls_dir.join(name).display().to_string();
+
+        let remote_dir = TempDir::new().unwrap();
+        let remote_config = remote_dir.path().join("config.yml");
+
+        write_file(
+            &remote_dir.path().join("zone.yml"),
+            "www.remote.local: 10.10.10.5",
+        )
+        .await;
+
+        write_file(
+            &remote_config,
+            format!(
+                r#"
+server:
+  port: 53537
+
+api:
+  address: 127.0.0.1:0
+  tls:
+    certificate: {}
+    key: {}
+    client_ca: {}
+
+sources:
+  file:
+    zone: zone.yml
+"#,
+                tls_file("server.pem"),
+                tls_file("server-key.pem"),
+                tls_file("ca.pem"),
+            ),
+        )
+        .await;
+
+        let remote = Server::new(&remote_config).await.unwrap();
+        let port = remote.api_server.inner.lock().await.as_ref().unwrap().port;
+        let url = format!("https://localhost:{port}/v2/records");
+
+        let client = |identity: Option<(&str, &str)>| {
+            let ca = std::fs::read(tls_file("ca.pem")).unwrap();
+            let mut builder = reqwest::Client::builder()
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(reqwest::Certificate::from_pem(&ca).unwrap());
+
+            if let Some((certificate, key)) = identity {
+                let mut pem = std::fs::read(tls_file(certificate)).unwrap();
+                pem.extend(std::fs::read(tls_file(key)).unwrap());
+                builder = builder.identity(reqwest::Identity::from_pem(&pem).unwrap());
+            }
+
+            builder.build().unwrap()
+        };
+
+        assert!(client(None).get(&url).send().await.is_err());
+        assert!(
+            client(Some(("untrusted-client.pem", "untrusted-client-key.pem")))
+                .get(&url)
+                .send()
+                .await
+                .is_err()
+        );
+        assert!(client(Some(("client.pem", "client-key.pem")))
+            .get(&url)
+            .send()
+            .await
+            .unwrap()
+            .status()
+            .is_success());
+
+        let local_dir = TempDir::new().unwrap();
+        let local_config = local_dir.path().join("config.yml");
+
+        write_file(
+            &local_config,
+            format!(
+                r#"
+server:
+  port: 53538
+
+sources:
+  remote:
+    remote:
+      url: https://127.0.0.1:{port}/
+      tls:
+        ca: {}
+        certificate: {}
+        key: {}
+"#,
+                tls_file("ca.pem"),
+                tls_file("client.pem"),
+                tls_file("client-key.pem"),
+            ),
+        )
+        .await;
+
+        let local = Server::new(&local_config).await.unwrap();
+
+        timeout(async {
+            while !local.records().await.has_name(&name("www.remote.local.")) {
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await;
+
+        local.shutdown().await;
+        remote.shutdown().await;
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn udp_workers() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_file = temp_dir.path().join("config.yml");
+
+        write_file(
+            &temp_dir.path().join("zone.yml"),
+            "www.home.local: 10.10.10.5",
+        )
+        .await;
+
+        write_file(
+            &config_file,
+            r#"
+server:
+  port: 53544
+  listen:
+    - 127.0.0.1
+  udp_workers: 4
+
+sources:
+  file:
+    zone: zone.yml
+"#,
+        )
+        .await;
+
+        let server = Server::new(&config_file).await.unwrap();
+        let localns_address = "127.0.0.1:53544";
+
+        wait_for_response(localns_address, &name("www.home.local."), RecordType::A).await;
+
+        // Each lookup comes from a new client port so may reach any of the
+        // sockets, all of them answer.
+        for _ in 0..20 {
+            let response = lookup(
+                localns_address,
+                &name("www.home.local."),
+                RecordType::A,
+                true,
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(response.response_code(), ResponseCode::NoError);
+            assert_eq!(response.answers().len(), 1);
+        }
+
+        server.shutdown().await;
+    }
 }
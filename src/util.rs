@@ -1,12 +1,21 @@
 use std::{
+    collections::HashSet,
     fmt::{self, Display},
+    fs,
     hash::Hash,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
     str::FromStr,
+    time::{Duration, Instant},
 };
 
+use anyhow::{anyhow, bail, Context};
+use figment::value::magic::RelativePathBuf;
+use reqwest::{Certificate, ClientBuilder, Identity, Url};
 use serde::Deserialize;
 
+use crate::{dns::Fqdn, Error, RecordServer};
+
 pub(crate) type Host = IpAddr;
 
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Hash)]
@@ -17,8 +26,9 @@ pub(crate) struct Address {
 }
 
 impl Address {
+    #[cfg_attr(not(feature = "docker"), allow(dead_code))]
     pub(crate) fn address(&self, default_port: u16) -> String {
-        format!("{}:{}", self.host, self.port.unwrap_or(default_port))
+        self.to_socket_address(default_port).to_string()
     }
 
     pub(crate) fn to_socket_address(&self, default_port: u16) -> SocketAddr {
@@ -28,42 +38,302 @@ impl Address {
 
 impl Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if let Some(port) = self.port {
-            f.pad(&format!("{}:{}", self.host, port))
-        } else {
-            f.pad(&self.host.to_string())
+        match (self.host, self.port) {
+            (host, None) => f.pad(&host.to_string()),
+            (Host::V4(host), Some(port)) => f.pad(&format!("{host}:{port}")),
+            (Host::V6(host), Some(port)) => f.pad(&format!("[{host}]:{port}")),
         }
     }
 }
 
-impl TryFrom<String> for Address {
-    type Error = <SocketAddr as FromStr>::Err;
-
-    fn try_from(host: String) -> Result<Self, Self::Error> {
-        match SocketAddr::from_str(&host) {
-            Ok(addr) => Ok(Self {
-                host: addr.ip(),
-                port: Some(addr.port()),
-            }),
-            Err(e) => {
-                if let Ok(ip) = host.parse::<Ipv4Addr>() {
-                    Ok(Host::from(ip).into())
-                } else if let Ok(ip) = host.parse::<Ipv6Addr>() {
-                    Ok(Host::from(ip).into())
-                } else {
-                    Err(e)
-                }
+fn parse_port(port: &str, address: &str) -> Result<u16, Error> {
+    match port.parse::<u16>() {
+        Ok(0) | Err(_) => bail!("Invalid port '{port}' in address '{address}'"),
+        Ok(port) => Ok(port),
+    }
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    /// Accepts a bare IPv4 or IPv6 address, `ipv4:port` or `[ipv6]:port`. An IPv6
+    /// address with a port must use brackets as otherwise the port is ambiguous.
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        let address = address.trim();
+
+        if let Some(bracketed) = address.strip_prefix('[') {
+            let Some((host, rest)) = bracketed.split_once(']') else {
+                bail!("Missing ']' in address '{address}'");
+            };
+
+            let host = Ipv6Addr::from_str(host)
+                .map_err(|_| anyhow!("Invalid IPv6 address '{host}' in address '{address}'"))?;
+
+            let port = match rest {
+                "" => None,
+                _ => match rest.strip_prefix(':') {
+                    Some(port) => Some(parse_port(port, address)?),
+                    None => bail!("Unexpected '{rest}' after host in address '{address}'"),
+                },
+            };
+
+            return Ok(Self {
+                host: host.into(),
+                port,
+            });
+        }
+
+        if let Ok(host) = Host::from_str(address) {
+            return Ok(host.into());
+        }
+
+        match address.split_once(':') {
+            Some((host, port)) if !port.contains(':') => {
+                let host = Ipv4Addr::from_str(host)
+                    .map_err(|_| anyhow!("Invalid host '{host}' in address '{address}'"))?;
+
+                Ok(Self {
+                    host: host.into(),
+                    port: Some(parse_port(port, address)?),
+                })
             }
+            _ => bail!("Invalid address '{address}'"),
         }
     }
 }
 
+impl TryFrom<String> for Address {
+    type Error = Error;
+
+    fn try_from(address: String) -> Result<Self, Self::Error> {
+        Address::from_str(&address)
+    }
+}
+
 impl From<Host> for Address {
     fn from(host: Host) -> Self {
         Self { host, port: None }
     }
 }
 
+/// A range of addresses given as `address/prefix`. A bare address is a subnet
+/// containing just that address.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Hash)]
+#[serde(try_from = "String")]
+pub(crate) struct Subnet {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    fn bits(ip: IpAddr) -> (u128, u8) {
+        match ip {
+            IpAddr::V4(ip) => (u32::from(ip).into(), 32),
+            IpAddr::V6(ip) => (ip.into(), 128),
+        }
+    }
+
+    fn mask(prefix: u8, width: u8) -> u128 {
+        let host_bits = u32::from(width - prefix);
+        (u128::MAX.checked_shl(host_bits).unwrap_or(0)) & (u128::MAX >> (128 - width))
+    }
+
+    /// The subnet of the given length containing an address.
+    pub(crate) fn new(address: IpAddr, prefix: u8) -> Self {
+        let (bits, width) = Self::bits(address);
+        let prefix = prefix.min(width);
+
+        let masked = bits & Self::mask(prefix, width);
+        let network = match address {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(masked as u32)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(masked)),
+        };
+
+        Self { network, prefix }
+    }
+
+    pub(crate) fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    pub(crate) fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub(crate) fn contains(&self, ip: &IpAddr) -> bool {
+        if ip.is_ipv4() != self.network.is_ipv4() {
+            return false;
+        }
+
+        let (network, width) = Self::bits(self.network);
+        let (ip, _) = Self::bits(*ip);
+        let mask = Self::mask(self.prefix, width);
+
+        ip & mask == network
+    }
+
+    /// The reverse lookup zones that together cover the subnet. Zones can only
+    /// be split on octets for IPv4 and nibbles for IPv6 so a subnet that ends
+    /// elsewhere is covered by several zones.
+    pub(crate) fn reverse_zones(&self) -> Vec<Fqdn> {
+        let (network, width) = Self::bits(self.network);
+        let (unit, suffix) = match self.network {
+            IpAddr::V4(_) => (8, "in-addr.arpa"),
+            IpAddr::V6(_) => (4, "ip6.arpa"),
+        };
+
+        let labels = self.prefix.div_ceil(unit);
+        let zone_prefix = labels * unit;
+
+        (0..1u128 << (zone_prefix - self.prefix))
+            .map(|index| {
+                let zone = network
+                    | index
+                        .checked_shl(u32::from(width - zone_prefix))
+                        .unwrap_or(0);
+
+                let mut parts: Vec<String> = (0..labels)
+                    .rev()
+                    .map(|label| {
+                        let digit = (zone >> (width - (label + 1) * unit)) & ((1 << unit) - 1);
+                        match self.network {
+                            IpAddr::V4(_) => digit.to_string(),
+                            IpAddr::V6(_) => format!("{digit:x}"),
+                        }
+                    })
+                    .collect();
+                parts.push(suffix.to_owned());
+
+                Fqdn::try_from(parts.join(".")).unwrap()
+            })
+            .collect()
+    }
+}
+
+impl Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&format!("{}/{}", self.network, self.prefix))
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = Error;
+
+    fn from_str(subnet: &str) -> Result<Self, Self::Err> {
+        let subnet = subnet.trim();
+        let (address, prefix) = match subnet.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (subnet, None),
+        };
+
+        let address = Host::from_str(address)
+            .map_err(|_| anyhow!("Invalid address '{address}' in subnet '{subnet}'"))?;
+        let (_, width) = Self::bits(address);
+
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= width => prefix,
+                _ => bail!("Invalid prefix length '{prefix}' in subnet '{subnet}'"),
+            },
+            None => width,
+        };
+
+        Ok(Self::new(address, prefix))
+    }
+}
+
+impl TryFrom<String> for Subnet {
+    type Error = Error;
+
+    fn try_from(subnet: String) -> Result<Self, Self::Error> {
+        Subnet::from_str(&subnet)
+    }
+}
+
+/// Where a source's outbound connections are made from, either a local address
+/// or the name of a network interface.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Bind {
+    Address(IpAddr),
+    Interface(String),
+}
+
+impl Bind {
+    pub(crate) fn apply(&self, builder: ClientBuilder) -> Result<ClientBuilder, Error> {
+        match self {
+            Bind::Address(address) => Ok(builder.local_address(*address)),
+            #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+            Bind::Interface(interface) => Ok(builder.interface(interface)),
+            #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+            Bind::Interface(interface) => {
+                bail!("Binding to interface {interface} is not supported on this platform")
+            }
+        }
+    }
+}
+
+/// TLS settings for connecting to servers that use a private CA or require
+/// clients to present a certificate.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub(crate) struct ClientTls {
+    /// When given only servers with certificates signed by this CA are trusted.
+    #[serde(default)]
+    ca: Option<RelativePathBuf>,
+    #[serde(default)]
+    certificate: Option<RelativePathBuf>,
+    #[serde(default)]
+    key: Option<RelativePathBuf>,
+}
+
+impl ClientTls {
+    pub(crate) fn files(&self) -> Vec<PathBuf> {
+        [&self.ca, &self.certificate, &self.key]
+            .into_iter()
+            .flatten()
+            .map(RelativePathBuf::relative)
+            .collect()
+    }
+
+    /// Reads the certificates and adds them to the builder.
+    pub(crate) fn apply(&self, mut builder: ClientBuilder) -> Result<ClientBuilder, Error> {
+        let read = |path: &RelativePathBuf| {
+            let path = path.relative();
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))
+        };
+
+        if let Some(ref ca) = self.ca {
+            builder = builder
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(Certificate::from_pem(&read(ca)?)?);
+        }
+
+        match (&self.certificate, &self.key) {
+            (Some(certificate), Some(key)) => {
+                let mut pem = read(certificate)?;
+                pem.push(b'\n');
+                pem.extend(read(key)?);
+                builder = builder.identity(Identity::from_pem(&pem)?);
+            }
+            (None, None) => {}
+            _ => bail!("A client certificate and key must be given together"),
+        }
+
+        Ok(builder)
+    }
+}
+
+/// Compares secrets in a time that only depends on their length so that the
+/// comparison doesn't reveal how much of a guess was correct.
+pub(crate) fn secrets_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
 #[macro_export]
 macro_rules! event_lvl {
     ($lvl:ident, $($arg:tt)+) => {
@@ -76,3 +346,233 @@ macro_rules! event_lvl {
         }
     };
 }
+
+/// How long the addresses a host resolved to are used before resolving it again.
+const HOST_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Tracks the addresses that the host of a URL resolves to so that a source can
+/// notice when the server it talks to has moved.
+pub(crate) struct HostAddresses {
+    host: Option<String>,
+    addresses: Option<HashSet<IpAddr>>,
+    resolved: Option<Instant>,
+}
+
+impl HostAddresses {
+    pub(crate) fn new(url: &Url) -> Self {
+        Self {
+            host: url.domain().map(|domain| domain.to_owned()),
+            addresses: None,
+            resolved: None,
+        }
+    }
+
+    /// Resolves the host again if it hasn't been recently, returning true if its
+    /// addresses have changed since the last time they were seen.
+    pub(crate) async fn changed<S: RecordServer>(&mut self, server: &S) -> bool {
+        if self
+            .resolved
+            .is_some_and(|resolved| resolved.elapsed() < HOST_RECHECK_INTERVAL)
+        {
+            return false;
+        }
+
+        self.moved(server).await
+    }
+
+    /// Resolves the host straight away, for when connecting to it has failed.
+    /// Failed lookups are ignored.
+    pub(crate) async fn moved<S: RecordServer>(&mut self, server: &S) -> bool {
+        let Some(ref host) = self.host else {
+            return false;
+        };
+
+        let addresses = server.resolve_host(host).await;
+        self.resolved = Some(Instant::now());
+        self.update(addresses.into_iter().collect())
+    }
+
+    fn update(&mut self, addresses: HashSet<IpAddr>) -> bool {
+        if addresses.is_empty() {
+            return false;
+        }
+
+        match self.addresses.replace(addresses) {
+            Some(previous) => Some(&previous) != self.addresses.as_ref(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr};
+
+    use proptest::prelude::*;
+    use reqwest::Url;
+
+    use crate::{
+        test::fqdn,
+        util::{secrets_match, Address, Bind, HostAddresses, Subnet},
+    };
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn host_addresses() {
+        let addresses = |ips: &[&str]| ips.iter().map(|ip| ip.parse().unwrap()).collect();
+
+        let mut host = HostAddresses::new(&Url::parse("http://traefik.home.local:8080/").unwrap());
+        assert_eq!(host.host.as_deref(), Some("traefik.home.local"));
+
+        assert!(!host.update(addresses(&["10.10.1.5"])));
+        assert!(!host.update(addresses(&["10.10.1.5"])));
+        assert!(!host.update(addresses(&[])));
+        assert!(host.update(addresses(&["10.10.1.6", "fd00::6"])));
+        assert!(!host.update(addresses(&["fd00::6", "10.10.1.6"])));
+
+        let host = HostAddresses::new(&Url::parse("http://10.10.1.5:8080/").unwrap());
+        assert!(host.host.is_none());
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn secrets() {
+        assert!(secrets_match("secret", "secret"));
+        assert!(!secrets_match("secreT", "secret"));
+        assert!(!secrets_match("secret!", "secret"));
+        assert!(!secrets_match("", "secret"));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn bind() {
+        let parse = |value: &str| serde_yaml::from_str::<Bind>(value).unwrap();
+
+        assert_eq!(
+            parse("10.10.1.2"),
+            Bind::Address(IpAddr::from_str("10.10.1.2").unwrap())
+        );
+        assert_eq!(
+            parse("fd00::2"),
+            Bind::Address(IpAddr::from_str("fd00::2").unwrap())
+        );
+        assert_eq!(parse("eth1"), Bind::Interface("eth1".to_owned()));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn parse() {
+        let check = |input: &str, host: &str, port: Option<u16>| {
+            let address = Address::from_str(input).unwrap();
+            assert_eq!(address.host, IpAddr::from_str(host).unwrap(), "{input}");
+            assert_eq!(address.port, port, "{input}");
+        };
+
+        check("10.10.15.3", "10.10.15.3", None);
+        check("10.10.15.3:5353", "10.10.15.3", Some(5353));
+        check(" 10.10.15.3:53 ", "10.10.15.3", Some(53));
+        check("::1", "::1", None);
+        check("fe80::1:53", "fe80::1:53", None);
+        check("[::1]", "::1", None);
+        check("[::1]:5353", "::1", Some(5353));
+        check(
+            "[2b02:c7a:7e12:5b00:1::26b7]:53",
+            "2b02:c7a:7e12:5b00:1::26b7",
+            Some(53),
+        );
+
+        for bad in [
+            "",
+            "localhost",
+            "localhost:53",
+            "10.10.15.3:",
+            "10.10.15.3:0",
+            "10.10.15.3:65536",
+            "10.10.15.3:dns",
+            "10.10.15:53",
+            "[::1",
+            "[::1]:",
+            "[::1]53",
+            "[::1]:99999",
+            "[10.10.15.3]:53",
+            "::1::53",
+        ] {
+            assert!(Address::from_str(bad).is_err(), "{bad}");
+        }
+
+        let error = Address::from_str("10.10.15.3:99999").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid port '99999' in address '10.10.15.3:99999'"
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn subnet() {
+        let subnet = |value: &str| Subnet::from_str(value).unwrap();
+        let ip = |value: &str| IpAddr::from_str(value).unwrap();
+
+        assert_eq!(subnet("10.10.3.4/16").to_string(), "10.10.0.0/16");
+        assert_eq!(subnet("10.10.3.4").to_string(), "10.10.3.4/32");
+        assert_eq!(subnet("fd00:1:2::7/48").to_string(), "fd00:1:2::/48");
+
+        assert!(subnet("10.10.0.0/16").contains(&ip("10.10.200.1")));
+        assert!(!subnet("10.10.0.0/16").contains(&ip("10.11.0.1")));
+        assert!(!subnet("10.10.0.0/16").contains(&ip("::ffff:10.10.0.1")));
+        assert!(subnet("0.0.0.0/0").contains(&ip("192.168.1.1")));
+        assert!(subnet("fd00::/8").contains(&ip("fdff::1")));
+        assert!(!subnet("fd00::/8").contains(&ip("fe80::1")));
+
+        assert_eq!(
+            subnet("10.10.0.0/16").reverse_zones(),
+            vec![fqdn("10.10.in-addr.arpa.")]
+        );
+        assert_eq!(
+            subnet("172.16.0.0/15").reverse_zones(),
+            vec![fqdn("16.172.in-addr.arpa."), fqdn("17.172.in-addr.arpa.")]
+        );
+        assert_eq!(
+            subnet("0.0.0.0/0").reverse_zones(),
+            vec![fqdn("in-addr.arpa.")]
+        );
+        assert_eq!(
+            subnet("fd00:12::/32").reverse_zones(),
+            vec![fqdn("2.1.0.0.0.0.d.f.ip6.arpa.")]
+        );
+        assert_eq!(subnet("fd00::/7").reverse_zones().len(), 2);
+
+        for bad in ["", "10.10.0.0/", "10.10.0.0/33", "fd00::/129", "home/24"] {
+            assert!(Subnet::from_str(bad).is_err(), "{bad}");
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip(host in any::<IpAddr>(), port in proptest::option::of(1..=u16::MAX)) {
+            let address = Address { host, port };
+            prop_assert_eq!(Address::from_str(&address.to_string()).unwrap(), address);
+        }
+
+        #[test]
+        fn socket_address(host in any::<IpAddr>(), port in 1..=u16::MAX) {
+            let address = Address::from(host);
+            let parsed = Address::from_str(&address.address(port)).unwrap();
+            prop_assert_eq!(parsed.to_socket_address(0), address.to_socket_address(port));
+        }
+
+        #[test]
+        fn never_panics(input in "\\PC*") {
+            let _ = Address::from_str(&input);
+        }
+
+        #[test]
+        fn rejects_bad_ports(host in any::<IpAddr>(), port in 65536u32..) {
+            let input = match host {
+                IpAddr::V4(host) => format!("{host}:{port}"),
+                IpAddr::V6(host) => format!("[{host}]:{port}"),
+            };
+            prop_assert!(Address::from_str(&input).is_err());
+        }
+    }
+}
@@ -4,10 +4,138 @@ use std::{
     time::Duration,
 };
 
+use notify::Notifier;
 use sha2::{Digest, Sha256};
-use tokio::{fs::File, io::AsyncReadExt, task::JoinHandle, time::sleep};
+use tokio::{
+    fs::{metadata, read_dir, File},
+    io::AsyncReadExt,
+    select,
+    task::JoinHandle,
+    time::sleep,
+};
+
+use crate::{config::matches_wildcard, Error};
 
-use crate::Error;
+/// How often files are checked when the OS doesn't tell us about changes.
+const POLL_INTERVAL: Duration = if cfg!(test) {
+    Duration::from_millis(50)
+} else {
+    Duration::from_millis(500)
+};
+
+/// Change notifications aren't delivered for files on network filesystems so
+/// files are still checked occasionally when notifications are available.
+const NOTIFY_POLL_INTERVAL: Duration = if cfg!(test) {
+    Duration::from_secs(60)
+} else {
+    Duration::from_secs(10)
+};
+
+#[cfg(target_os = "linux")]
+mod notify {
+    use std::{
+        ffi::OsString,
+        io,
+        os::fd::{AsFd, AsRawFd, RawFd},
+        path::Path,
+    };
+
+    use anyhow::Context;
+    use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+    use tokio::io::unix::AsyncFd;
+
+    use crate::Error;
+
+    struct InotifyFd(Inotify);
+
+    impl AsRawFd for InotifyFd {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0.as_fd().as_raw_fd()
+        }
+    }
+
+    /// Wakes the watcher when a file is replaced or finishes being written. The
+    /// parent directory is watched so that files being created, deleted or
+    /// renamed into place are seen.
+    pub(super) struct Notifier {
+        fd: AsyncFd<InotifyFd>,
+        /// Only events for this name are of interest, when watching a file.
+        name: Option<OsString>,
+    }
+
+    impl Notifier {
+        pub(super) fn new(path: &Path) -> Result<Self, Error> {
+            let (directory, name) = if path.is_dir() {
+                (path, None)
+            } else {
+                let parent = path
+                    .parent()
+                    .filter(|parent| !parent.as_os_str().is_empty())
+                    .unwrap_or(Path::new("."));
+                (parent, path.file_name().map(ToOwned::to_owned))
+            };
+
+            let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+            inotify
+                .add_watch(
+                    directory,
+                    AddWatchFlags::IN_CLOSE_WRITE
+                        | AddWatchFlags::IN_CREATE
+                        | AddWatchFlags::IN_DELETE
+                        | AddWatchFlags::IN_MOVED_FROM
+                        | AddWatchFlags::IN_MOVED_TO,
+                )
+                .with_context(|| format!("Failed to watch {}", directory.display()))?;
+
+            Ok(Self {
+                fd: AsyncFd::new(InotifyFd(inotify))?,
+                name,
+            })
+        }
+
+        pub(super) async fn changed(&self) -> Result<(), Error> {
+            loop {
+                let mut guard = self.fd.readable().await?;
+
+                let events = match guard
+                    .try_io(|fd| fd.get_ref().0.read_events().map_err(io::Error::from))
+                {
+                    Ok(events) => events?,
+                    Err(_) => continue,
+                };
+
+                if events.iter().any(|event| {
+                    self.name.is_none() || event.name.is_none() || event.name == self.name
+                }) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod notify {
+    use std::path::Path;
+
+    use anyhow::bail;
+
+    use crate::Error;
+
+    /// Change notifications are only supported on Linux, files are polled
+    /// elsewhere.
+    pub(super) struct Notifier;
+
+    impl Notifier {
+        pub(super) fn new(_: &Path) -> Result<Self, Error> {
+            bail!("File change notifications are not supported on this platform")
+        }
+
+        pub(super) async fn changed(&self) -> Result<(), Error> {
+            std::future::pending().await
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub(crate) enum FileEvent {
@@ -35,8 +163,65 @@ impl Drop for Watcher {
     }
 }
 
+/// Splits a path whose file name contains `*` wildcards into the directory to
+/// watch and the pattern that the names of interesting files match.
+fn split_pattern(path: &Path) -> Option<(&Path, &str)> {
+    let pattern = path.file_name()?.to_str()?;
+    if !pattern.contains('*') {
+        return None;
+    }
+
+    let directory = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    Some((directory, pattern))
+}
+
 impl Watcher {
+    /// A directory's state is the names of the files within it, so only adding or
+    /// removing files counts as a change. When there is a pattern only files with
+    /// matching names are considered.
+    async fn fetch_directory_state(path: &Path, pattern: Option<&str>) -> Option<[u8; 32]> {
+        let mut entries = read_dir(path).await.ok()?;
+
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await.ok()? {
+            let name = entry.file_name();
+            if pattern.is_some_and(|pattern| {
+                !name
+                    .to_str()
+                    .is_some_and(|name| matches_wildcard(pattern, name))
+            }) {
+                continue;
+            }
+
+            names.push(name);
+        }
+        names.sort();
+
+        let mut hasher = Sha256::new();
+        for name in names {
+            hasher.update(name.as_encoded_bytes());
+            hasher.update([0]);
+        }
+
+        let mut output = [0_u8; 32];
+        output.copy_from_slice(hasher.finalize().as_slice());
+
+        Some(output)
+    }
+
     async fn fetch_state(path: &Path) -> Option<[u8; 32]> {
+        if let Some((directory, pattern)) = split_pattern(path) {
+            return Watcher::fetch_directory_state(directory, Some(pattern)).await;
+        }
+
+        if metadata(path).await.ok()?.is_dir() {
+            return Watcher::fetch_directory_state(path, None).await;
+        }
+
         let mut file = File::open(path).await.ok()?;
         let mut buffer = [0_u8; 65536];
 
@@ -58,14 +243,36 @@ impl Watcher {
         Some(output)
     }
 
+    /// Waits until the file may have changed.
+    async fn wait(path: &Path, notifier: &mut Option<Notifier>) {
+        let Some(current) = notifier else {
+            sleep(POLL_INTERVAL).await;
+            return;
+        };
+
+        select! {
+            result = current.changed() => {
+                if let Err(e) = result {
+                    tracing::warn!(
+                        path = %path.display(),
+                        error = %e,
+                        "Lost change notifications, falling back to polling"
+                    );
+                    *notifier = None;
+                }
+            }
+            _ = sleep(NOTIFY_POLL_INTERVAL) => {}
+        }
+    }
+
     async fn watch_loop<L: WatchListener>(
         path: PathBuf,
-        interval: Duration,
+        mut notifier: Option<Notifier>,
         mut state: Option<[u8; 32]>,
         mut listener: L,
     ) {
         loop {
-            sleep(interval).await;
+            Watcher::wait(&path, &mut notifier).await;
 
             let new_state = Watcher::fetch_state(&path).await;
 
@@ -84,20 +291,28 @@ impl Watcher {
     }
 }
 
+/// Watches a file or directory for changes. A file name containing `*`
+/// wildcards watches for files with matching names being added to or removed
+/// from the directory.
 pub(crate) async fn watch<L: WatchListener>(path: &Path, listener: L) -> Result<Watcher, Error> {
     tracing::trace!(path = %path.display(), "Starting file watcher");
 
-    let initial_state = Watcher::fetch_state(path).await;
+    let notify_path = split_pattern(path).map_or(path, |(directory, _)| directory);
 
-    let interval = if cfg!(test) {
-        Duration::from_millis(50)
-    } else {
-        Duration::from_millis(500)
+    // Set up before reading the initial state so no change can be missed.
+    let notifier = match Notifier::new(notify_path) {
+        Ok(notifier) => Some(notifier),
+        Err(e) => {
+            tracing::debug!(path = %path.display(), error = %e, "Polling for file changes");
+            None
+        }
     };
 
+    let initial_state = Watcher::fetch_state(path).await;
+
     let handle = tokio::spawn(Watcher::watch_loop(
         path.to_owned(),
-        interval,
+        notifier,
         initial_state,
         listener,
     ));
@@ -111,7 +326,7 @@ pub(crate) async fn watch<L: WatchListener>(path: &Path, listener: L) -> Result<
 #[cfg(test)]
 mod tests {
     use std::{
-        fs::{remove_file, File},
+        fs::{create_dir, remove_file, File},
         io::Write,
     };
 
@@ -180,4 +395,56 @@ mod tests {
         let event = timeout(receiver.recv()).await;
         assert_eq!(event, None);
     }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn directory_watcher() {
+        let (sender, mut receiver) = unbounded_channel();
+
+        let temp = TempDir::new().unwrap();
+        let target = temp.path().join("conf.d");
+        create_dir(&target).unwrap();
+
+        let _watcher = watch(&target, sender).await.unwrap();
+
+        File::create(target.join("one.yaml")).unwrap();
+
+        let event = timeout(receiver.recv()).await;
+        assert_eq!(event, Some(FileEvent::Change));
+
+        remove_file(target.join("one.yaml")).unwrap();
+
+        let event = timeout(receiver.recv()).await;
+        assert_eq!(event, Some(FileEvent::Change));
+
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pattern_watcher() {
+        let (sender, mut receiver) = unbounded_channel();
+
+        let temp = TempDir::new().unwrap();
+        let target = temp.path().join("extra-*.yml");
+
+        let _watcher = watch(&target, sender).await.unwrap();
+
+        // Files that don't match the pattern are ignored.
+        File::create(temp.path().join("notes.txt")).unwrap();
+        File::create(temp.path().join("extra.yml")).unwrap();
+
+        File::create(temp.path().join("extra-zones.yml")).unwrap();
+
+        let event = timeout(receiver.recv()).await;
+        assert_eq!(event, Some(FileEvent::Change));
+
+        remove_file(temp.path().join("notes.txt")).unwrap();
+        remove_file(temp.path().join("extra-zones.yml")).unwrap();
+
+        let event = timeout(receiver.recv()).await;
+        assert_eq!(event, Some(FileEvent::Change));
+
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    }
 }